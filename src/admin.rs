@@ -0,0 +1,176 @@
+use futures::{stream, StreamExt};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::cert_manager::{CertificateInfo, CertificateManager};
+use crate::cert_monitor::CertificateMonitor;
+use crate::proto::admin::{
+    node_admin_server::NodeAdmin, RenewCertificatesRequest, RenewCertificatesResponse, RenewalResult,
+};
+use crate::proto::certservice::RevocationReason;
+
+/// Certificates renewed in parallel by one RenewCertificates call
+const RENEWAL_CONCURRENCY: usize = 4;
+
+/// Admin operations on the certificates of this node, for `cacsictl`
+///
+/// Served on a socket only root on the node can reach, so there is no further
+/// authorization.
+pub struct AdminService {
+    cert_manager: CertificateManager,
+    monitor: Arc<CertificateMonitor>,
+}
+
+impl AdminService {
+    pub fn new(cert_manager: CertificateManager, monitor: Arc<CertificateMonitor>) -> Self {
+        Self { cert_manager, monitor }
+    }
+
+    /// Revoke a certificate if requested, then replace it
+    async fn renew(&self, cert_info: CertificateInfo, revoke: bool) -> RenewalResult {
+        let mut result = RenewalResult {
+            certificate_id: cert_info.cert_id.clone(),
+            mount_path: cert_info.mount_path.clone(),
+            pod: format!("{}/{}", cert_info.pod.namespace, cert_info.pod.name),
+            ..Default::default()
+        };
+
+        // Certificates signed on the node are unknown to the certificate service
+        if revoke && cert_info.local_request.is_none() {
+            if let Err(e) = self
+                .cert_manager
+                .revoke_certificate(&cert_info.cert_id, RevocationReason::KeyCompromise)
+                .await
+            {
+                result.error = format!("Failed to revoke the current certificate: {:#}", e);
+                return result;
+            }
+        }
+
+        match self.monitor.renew_certificate(&cert_info, true).await {
+            Ok(()) => {
+                result.renewed = true;
+                result.not_after = self
+                    .cert_manager
+                    .get_certificate(&cert_info.cert_id)
+                    .map_or(0, |renewed| renewed.not_after);
+            }
+            Err(e) => result.error = format!("{:#}", e),
+        }
+        result
+    }
+}
+
+#[tonic::async_trait]
+impl NodeAdmin for AdminService {
+    async fn renew_certificates(
+        &self,
+        request: Request<RenewCertificatesRequest>,
+    ) -> Result<Response<RenewCertificatesResponse>, Status> {
+        let req = request.into_inner();
+        if req.all != req.targets.is_empty() {
+            return Err(Status::invalid_argument("Give either targets or all, not both"));
+        }
+
+        let selected = select_certificates(&self.cert_manager.get_all_certificates(), &req.targets, req.all)
+            .map_err(|unknown| Status::not_found(format!("No certificates match {}", unknown.join(", "))))?;
+
+        warn!(
+            "Renewing {} certificates on request of an administrator{}",
+            selected.len(),
+            if req.revoke { ", revoking the current ones" } else { "" }
+        );
+        let results: Vec<RenewalResult> = stream::iter(selected)
+            .map(|cert_info| self.renew(cert_info, req.revoke))
+            .buffer_unordered(RENEWAL_CONCURRENCY)
+            .collect()
+            .await;
+
+        for result in &results {
+            match result.renewed {
+                true => info!("Certificate {} renewed on request of an administrator", result.certificate_id),
+                false => warn!("Forced renewal of certificate {} failed: {}", result.certificate_id, result.error),
+            }
+        }
+
+        Ok(Response::new(RenewCertificatesResponse { results }))
+    }
+}
+
+/// Registered certificates matching `targets`, or the targets that match none
+///
+/// A target is a certificate ID, a mount path or `<namespace>/<pod>`, which selects all
+/// volumes of the pod; with `all`, every registered certificate is selected.
+pub fn select_certificates(
+    registered: &[CertificateInfo],
+    targets: &[String],
+    all: bool,
+) -> Result<Vec<CertificateInfo>, Vec<String>> {
+    if all {
+        return Ok(registered.to_vec());
+    }
+
+    let mut selected = BTreeMap::new();
+    let mut unknown = Vec::new();
+    for target in targets {
+        let matches: Vec<&CertificateInfo> = registered
+            .iter()
+            .filter(|cert_info| {
+                cert_info.cert_id == *target
+                    || cert_info.mount_path == *target
+                    || format!("{}/{}", cert_info.pod.namespace, cert_info.pod.name) == *target
+            })
+            .collect();
+        if matches.is_empty() {
+            unknown.push(target.clone());
+        }
+        for cert_info in matches {
+            selected.insert(cert_info.cert_id.clone(), cert_info.clone());
+        }
+    }
+
+    match unknown.is_empty() {
+        true => Ok(selected.into_values().collect()),
+        false => Err(unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::k8s_client::PodRef;
+    use crate::reload::ReloadStrategy;
+
+    #[test]
+    fn test_select_certificates() {
+        let registered = |cert_id: &str, pod: &str| CertificateInfo {
+            cert_id: cert_id.to_string(),
+            mount_path: format!("/pods/{}/volumes/mount", cert_id),
+            pod: PodRef { namespace: "default".to_string(), name: pod.to_string(), uid: None },
+            reload_strategy: ReloadStrategy::None,
+            not_before: 0,
+            not_after: 0,
+            last_renewal_error: None,
+            local_request: None,
+        };
+        let certificates = [
+            registered("default-web-csi-a", "web"),
+            registered("default-web-csi-b", "web"),
+            registered("default-api-csi-c", "api"),
+        ];
+        let ids = |selected: Vec<CertificateInfo>| -> Vec<String> {
+            selected.into_iter().map(|cert_info| cert_info.cert_id).collect()
+        };
+
+        let targets = ["default/web".to_string(), "default-web-csi-a".to_string()];
+        assert_eq!(ids(select_certificates(&certificates, &targets, false).unwrap()), ["default-web-csi-a", "default-web-csi-b"]);
+        let targets = ["/pods/default-api-csi-c/volumes/mount".to_string()];
+        assert_eq!(ids(select_certificates(&certificates, &targets, false).unwrap()), ["default-api-csi-c"]);
+        assert_eq!(select_certificates(&certificates, &[], true).unwrap().len(), 3);
+
+        let targets = ["default/web".to_string(), "other/web".to_string()];
+        assert_eq!(select_certificates(&certificates, &targets, false).err(), Some(vec!["other/web".to_string()]));
+    }
+}
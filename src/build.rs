@@ -1,5 +1,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Use environment variable if protoc is installed, otherwise provide helpful message
+    // Use environment variable if protoc is installed, otherwise the vendored binary if enabled
+    if std::env::var("PROTOC").is_err() {
+        #[cfg(feature = "vendored-protoc")]
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
     if std::env::var("PROTOC").is_err() {
         eprintln!("PROTOC environment variable not set.");
         eprintln!("Please install protoc or set PROTOC to the path of the protoc binary.");
@@ -9,24 +13,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("            Extract and add to PATH, or set PROTOC=path\\to\\protoc.exe");
         eprintln!("  - Linux: apt-get install protobuf-compiler");
         eprintln!("  - macOS: brew install protobuf");
+        eprintln!("  - Any: cargo build --features vendored-protoc (uses a bundled protoc)");
         eprintln!("");
         eprintln!("For Docker builds, this is handled by the Dockerfile.");
         return Err("protoc not found".into());
     }
 
-    // Compile CSI protobuf definitions
-    tonic_build::configure()
-        .build_server(true)
-        .build_client(true)
-        .compile(
-            &["proto/csi.proto"],
-            &["proto/"],
-        )?;
+    // Descriptor sets are embedded for gRPC server reflection
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+
+    // Compile CSI protobuf definitions (not needed by the client-only build)
+    if std::env::var_os("CARGO_FEATURE_SERVER").is_some() {
+        tonic_build::configure()
+            .build_server(true)
+            .build_client(true)
+            .file_descriptor_set_path(out_dir.join("csi_descriptor.bin"))
+            .compile(
+                &["proto/csi.proto"],
+                &["proto/"],
+            )?;
+
+        // Kubelet plugin registration, served in place of the node-driver-registrar sidecar
+        tonic_build::configure()
+            .build_server(true)
+            .build_client(true)
+            .compile(
+                &["proto/pluginregistration.proto"],
+                &["proto/"],
+            )?;
+
+        // Node admin operations, served to cacsictl on a node-local socket
+        tonic_build::configure()
+            .build_server(true)
+            .build_client(true)
+            .compile(
+                &["proto/admin.proto"],
+                &["proto/"],
+            )?;
+    }
 
     // Compile certificate service protobuf definitions
+    // Certificate records embed these; the Postgres store keeps records as JSON
+    let serde = r#"#[cfg_attr(feature = "postgres", derive(serde::Serialize, serde::Deserialize))]"#;
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
+        .type_attribute("certservice.v1.Subject", serde)
+        .type_attribute("certservice.v1.Extension", serde)
+        .file_descriptor_set_path(out_dir.join("cert_service_descriptor.bin"))
         .compile(
             &["proto/cert_service.proto"],
             &["proto/"],
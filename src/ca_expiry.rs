@@ -0,0 +1,171 @@
+use chrono::{Duration, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::ca_manager::CaManager;
+use crate::cert_manager::certificate_validity;
+use crate::metrics::{self, Metrics};
+
+/// Default window before CA expiry in which warnings are logged
+pub const DEFAULT_CA_EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// How often the CA expiry is re-evaluated
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// While only expiring (not yet critical), the warning is repeated this often
+const WARNING_REPEAT: Duration = Duration::hours(24);
+
+/// Below this remaining validity, every check logs an error
+const CRITICAL_REMAINING: Duration = Duration::days(7);
+
+/// How close the CA is to expiring
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ExpiryStage {
+    Valid,
+    /// Within the warning window
+    Expiring,
+    /// Within a week, or within the last quarter of the warning window if that is shorter
+    Critical,
+    Expired,
+}
+
+fn expiry_stage(remaining: Duration, warning_window: Duration) -> ExpiryStage {
+    if remaining <= Duration::zero() {
+        ExpiryStage::Expired
+    } else if remaining <= CRITICAL_REMAINING.min(warning_window / 4) {
+        ExpiryStage::Critical
+    } else if remaining <= warning_window {
+        ExpiryStage::Expiring
+    } else {
+        ExpiryStage::Valid
+    }
+}
+
+/// Tracks the expiry of the CA certificate
+///
+/// Exports it as `cacsi_ca_expiry_timestamp_seconds` and logs warnings that grow
+/// more frequent and severe as the CA approaches its notAfter, so an expiring CA
+/// is noticed before every issued certificate stops verifying.
+#[derive(Clone)]
+pub struct CaExpiryMonitor {
+    ca_manager: CaManager,
+    warning_window: Duration,
+    /// Window in which Probe reports the driver as not ready
+    probe_window: Option<Duration>,
+    /// notAfter of the current CA; 0 until known
+    not_after: Arc<AtomicI64>,
+    metrics: Metrics,
+}
+
+impl CaExpiryMonitor {
+    pub fn new(ca_manager: CaManager, warning_window: Duration, metrics: Metrics) -> Self {
+        Self {
+            ca_manager,
+            warning_window,
+            probe_window: None,
+            not_after: Arc::new(AtomicI64::new(0)),
+            metrics,
+        }
+    }
+
+    /// Have Probe report the driver as not ready when the CA expires within `window`
+    pub fn with_probe_window(mut self, window: Duration) -> Self {
+        self.probe_window = Some(window);
+        self
+    }
+
+    /// Check the CA expiry hourly and whenever the CA changes
+    ///
+    /// Runs until the process exits.
+    pub async fn run(&self) {
+        let mut ca_changes = self.ca_manager.subscribe();
+        let mut last_warning: Option<(ExpiryStage, chrono::DateTime<Utc>)> = None;
+
+        loop {
+            self.check(&mut last_warning).await;
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                changed = ca_changes.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    // A rotated CA starts its warnings afresh
+                    last_warning = None;
+                }
+            }
+        }
+    }
+
+    async fn check(&self, last_warning: &mut Option<(ExpiryStage, chrono::DateTime<Utc>)>) {
+        let not_after = match self.ca_manager.get_ca_cert().await.and_then(|pem| certificate_validity(&pem)) {
+            Ok((_, not_after)) => not_after,
+            Err(e) => {
+                warn!("Failed to read CA certificate expiry: {:#}", e);
+                return;
+            }
+        };
+        if self.not_after.swap(not_after, Ordering::Relaxed) != not_after {
+            info!("CA certificate expires at {}", not_after);
+        }
+        self.metrics.set(&metrics::CA_EXPIRY, &[], not_after as f64);
+
+        let now = Utc::now();
+        let remaining = Duration::seconds(not_after - now.timestamp());
+        let stage = expiry_stage(remaining, self.warning_window);
+
+        let repeat = match last_warning {
+            Some((last_stage, at)) => stage > *last_stage || stage > ExpiryStage::Expiring || now - *at >= WARNING_REPEAT,
+            None => true,
+        };
+        if stage == ExpiryStage::Valid || !repeat {
+            return;
+        }
+        *last_warning = Some((stage, now));
+
+        match stage {
+            ExpiryStage::Valid => {}
+            ExpiryStage::Expiring => warn!(
+                "CA certificate expires in {} days (at {}); rotate the CA before issued certificates stop verifying",
+                remaining.num_days(), not_after
+            ),
+            ExpiryStage::Critical => error!(
+                "CA certificate expires in {} hours (at {}); rotate the CA now",
+                remaining.num_hours(), not_after
+            ),
+            ExpiryStage::Expired => error!(
+                "CA certificate expired at {}; certificates issued by it no longer verify",
+                not_after
+            ),
+        }
+    }
+
+    /// Whether the CA expires within the probe window
+    pub fn is_degraded(&self) -> bool {
+        let not_after = self.not_after.load(Ordering::Relaxed);
+        match self.probe_window {
+            Some(window) if not_after > 0 => not_after - Utc::now().timestamp() <= window.num_seconds(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry_stage() {
+        let window = Duration::days(30);
+        assert_eq!(expiry_stage(Duration::days(90), window), ExpiryStage::Valid);
+        assert_eq!(expiry_stage(Duration::days(20), window), ExpiryStage::Expiring);
+        assert_eq!(expiry_stage(Duration::days(3), window), ExpiryStage::Critical);
+        assert_eq!(expiry_stage(Duration::seconds(-1), window), ExpiryStage::Expired);
+
+        // Short windows escalate in their last quarter
+        let window = Duration::days(4);
+        assert_eq!(expiry_stage(Duration::days(3), window), ExpiryStage::Expiring);
+        assert_eq!(expiry_stage(Duration::hours(12), window), ExpiryStage::Critical);
+    }
+}
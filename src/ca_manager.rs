@@ -1,79 +1,364 @@
 use anyhow::{Result, Context};
-use kube::{Api, Client};
-use k8s_openapi::api::core::v1::Secret;
+use futures::{StreamExt, TryStreamExt};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Resource};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::info;
+use tokio::sync::{watch, RwLock};
+use rcgen::KeyPair;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use crate::cert_service::signer::{load_passphrase, parse_ca_key, parse_ca_secret};
+use crate::tenancy::Tenancy;
+
+/// Key in the trust bundle ConfigMap holding the CA certificate
+const TRUST_BUNDLE_KEY: &str = "ca.crt";
+
+/// Where the CA is read from
+#[derive(Clone, Debug)]
+enum CaSource {
+    /// Secret with the CA certificate and key (`tls.crt`, `tls.key`)
+    Secret,
+    /// ConfigMap with only the CA certificate (`ca.crt`), so the key never reaches the node
+    TrustBundle,
+    /// Fixed CA handed in at startup (dev mode); nothing to load or watch
+    InMemory,
+}
 
 /// Manages the CA certificate and key retrieved from Kubernetes secret
 /// The CA never leaves the node and is stored in memory
 #[derive(Clone)]
 pub struct CaManager {
+    source: CaSource,
     secret_name: String,
     secret_namespace: String,
+    /// Secret (next to the CA secret) with the passphrase of an encrypted CA key
+    passphrase_secret: Option<String>,
     ca_cert: Arc<RwLock<Option<String>>>,
-    ca_key: Arc<RwLock<Option<String>>>,
+    ca_key: Arc<RwLock<Option<Zeroizing<String>>>>,
+    /// Passphrase `ca_key` was checked to decrypt with, re-read with every change of the CA secret
+    passphrase: Arc<RwLock<Option<Zeroizing<String>>>>,
+    /// Bumped every time the CA certificate or key changes, or one of the namespace or tenant CAs
+    generation: Arc<watch::Sender<u64>>,
+    /// CAs of namespaces that do not use this one, e.g. tenants with their own CA
+    namespace_cas: Arc<HashMap<String, CaManager>>,
+    tenancy: Option<Arc<Tenancy>>,
+    /// CAs of tenants with their own CA, by CA secret
+    tenant_cas: Arc<HashMap<String, CaManager>>,
 }
 
 impl CaManager {
-    pub async fn new(secret_name: String, secret_namespace: String) -> Result<Self> {
+    /// Load the CA from a secret, decrypting an encrypted key with the passphrase in `passphrase_secret`
+    pub async fn new(secret_name: String, secret_namespace: String, passphrase_secret: Option<String>) -> Result<Self> {
         let manager = Self {
+            passphrase_secret,
+            ..Self::empty(CaSource::Secret, secret_name, secret_namespace)
+        };
+        manager.load_ca().await?;
+        Ok(manager)
+    }
+
+    /// Only load the CA certificate, from the `ca.crt` key of a ConfigMap
+    ///
+    /// The node can then validate certificates but never holds the CA key;
+    /// all signing is left to the certificate service.
+    pub async fn trust_only(configmap_name: String, configmap_namespace: String) -> Result<Self> {
+        let manager = Self::empty(CaSource::TrustBundle, configmap_name, configmap_namespace);
+        manager.load_ca().await?;
+        Ok(manager)
+    }
+
+    /// Use a fixed CA certificate and key without reading anything from Kubernetes (dev mode)
+    pub fn in_memory(ca_cert: String, ca_key: Zeroizing<String>) -> Self {
+        Self {
+            source: CaSource::InMemory,
+            secret_name: String::new(),
+            secret_namespace: String::new(),
+            passphrase_secret: None,
+            ca_cert: Arc::new(RwLock::new(Some(ca_cert))),
+            ca_key: Arc::new(RwLock::new(Some(ca_key))),
+            passphrase: Arc::new(RwLock::new(None)),
+            generation: Arc::new(watch::Sender::new(0)),
+            namespace_cas: Arc::new(HashMap::new()),
+            tenancy: None,
+            tenant_cas: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Load the CA of each namespace in `secrets` from the named secret, next to the CA secret
+    ///
+    /// Certificates of pods in these namespaces are checked against, and signed on the node
+    /// with, their namespace's CA only (see [`for_namespace`](Self::for_namespace)). A trust
+    /// bundle is expected to hold every CA, so trust-only managers load nothing.
+    pub async fn with_namespace_cas(mut self, secrets: &BTreeMap<String, String>) -> Result<Self> {
+        match self.source {
+            CaSource::Secret => {}
+            CaSource::TrustBundle => return Ok(self),
+            CaSource::InMemory if secrets.is_empty() => return Ok(self),
+            CaSource::InMemory => anyhow::bail!("Namespace CAs need Kubernetes and cannot be used with an in-memory CA"),
+        }
+
+        let mut namespace_cas = HashMap::new();
+        for (namespace, secret_name) in secrets {
+            let manager = Self {
+                passphrase_secret: self.passphrase_secret.clone(),
+                generation: self.generation.clone(),
+                ..Self::empty(CaSource::Secret, secret_name.clone(), self.secret_namespace.clone())
+            };
+            manager.load_ca().await.context(format!("Failed to load the CA of namespace {}", namespace))?;
+            namespace_cas.insert(namespace.clone(), manager);
+        }
+        self.namespace_cas = Arc::new(namespace_cas);
+        Ok(self)
+    }
+
+    /// Load the CA of every tenant with its own CA secret, next to the CA secret
+    ///
+    /// Certificates of pods in a tenant's namespaces are then checked against, and signed
+    /// on the node with, the tenant's CA (see [`for_namespace`](Self::for_namespace)). Tenants
+    /// added later with a new CA secret need a restart. Trust-only managers load nothing.
+    pub async fn with_tenancy(mut self, tenancy: Option<Arc<Tenancy>>) -> Result<Self> {
+        let Some(tenancy) = tenancy else {
+            return Ok(self);
+        };
+        match self.source {
+            CaSource::Secret => {}
+            CaSource::TrustBundle => return Ok(self),
+            CaSource::InMemory => anyhow::bail!("Tenants need Kubernetes and cannot be used with an in-memory CA"),
+        }
+
+        let mut tenant_cas = HashMap::new();
+        for tenant in tenancy.tenants().await {
+            let Some(secret_name) = &tenant.ca_secret else {
+                continue;
+            };
+            if tenant_cas.contains_key(secret_name) {
+                continue;
+            }
+            let manager = Self {
+                passphrase_secret: self.passphrase_secret.clone(),
+                generation: self.generation.clone(),
+                ..Self::empty(CaSource::Secret, secret_name.clone(), self.secret_namespace.clone())
+            };
+            manager.load_ca().await.context(format!("Failed to load the CA of tenant {}", tenant.name))?;
+            tenant_cas.insert(secret_name.clone(), manager);
+        }
+        self.tenancy = Some(tenancy);
+        self.tenant_cas = Arc::new(tenant_cas);
+        Ok(self)
+    }
+
+    /// The CA of certificates for pods in `namespace`: its own CA if it has one, else its
+    /// tenant's if that has one, else this one
+    pub async fn for_namespace(&self, namespace: &str) -> Result<&CaManager> {
+        if let Some(manager) = self.namespace_cas.get(namespace) {
+            return Ok(manager);
+        }
+        let Some(tenancy) = &self.tenancy else {
+            return Ok(self);
+        };
+
+        let tenant = tenancy.tenant_of(namespace).await?;
+        match tenant.as_ref().and_then(|tenant| tenant.ca_secret.as_ref().map(|secret| (tenant, secret))) {
+            Some((tenant, secret)) => self.tenant_cas.get(secret).ok_or_else(|| anyhow::anyhow!(
+                "CA secret {} of tenant {} was not loaded at startup; restart the driver to load it", secret, tenant.name
+            )),
+            None => Ok(self),
+        }
+    }
+
+    /// Whether certificates for pods in `namespace` use a CA other than this one
+    pub async fn has_namespace_ca(&self, namespace: &str) -> Result<bool> {
+        Ok(!std::ptr::eq(self.for_namespace(namespace).await?, self))
+    }
+
+    /// Namespaces with a CA of their own and their CAs
+    pub fn namespace_cas(&self) -> impl Iterator<Item = (&String, &CaManager)> {
+        self.namespace_cas.iter()
+    }
+
+    /// CA secrets of tenants with their own CA and their CAs
+    pub fn tenant_cas(&self) -> impl Iterator<Item = (&String, &CaManager)> {
+        self.tenant_cas.iter()
+    }
+
+    /// Tenants of the namespaces, if any are configured
+    pub fn tenancy(&self) -> Option<&Arc<Tenancy>> {
+        self.tenancy.as_ref()
+    }
+
+    /// This CA and the namespace and tenant CAs
+    fn all_cas(&self) -> impl Iterator<Item = &CaManager> {
+        std::iter::once(self).chain(self.namespace_cas.values()).chain(self.tenant_cas.values())
+    }
+
+    fn empty(source: CaSource, secret_name: String, secret_namespace: String) -> Self {
+        Self {
+            source,
             secret_name,
             secret_namespace,
+            passphrase_secret: None,
             ca_cert: Arc::new(RwLock::new(None)),
             ca_key: Arc::new(RwLock::new(None)),
+            passphrase: Arc::new(RwLock::new(None)),
+            generation: Arc::new(watch::Sender::new(0)),
+            namespace_cas: Arc::new(HashMap::new()),
+            tenancy: None,
+            tenant_cas: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Load CA certificate and key from Kubernetes secret, or only the certificate from the trust bundle
+    async fn load_ca(&self) -> Result<()> {
+        if matches!(self.source, CaSource::InMemory) {
+            return Ok(());
+        }
+
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let (ca_cert, ca_key) = match self.source {
+            CaSource::Secret => {
+                info!("Loading CA from secret: {}/{}", self.secret_namespace, self.secret_name);
+                let secrets: Api<Secret> = Api::namespaced(client, &self.secret_namespace);
+                let secret = crate::k8s_client::with_retries(|| secrets.get(&self.secret_name))
+                    .await
+                    .context("Failed to get CA secret")?;
+                let (ca_cert, ca_key) = parse_ca_secret(secret)?;
+                (ca_cert, Some(ca_key))
+            }
+            CaSource::TrustBundle => {
+                info!("Loading CA certificate from ConfigMap: {}/{}", self.secret_namespace, self.secret_name);
+                let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.secret_namespace);
+                let configmap = crate::k8s_client::with_retries(|| configmaps.get(&self.secret_name))
+                    .await
+                    .context("Failed to get CA trust bundle ConfigMap")?;
+                (parse_trust_bundle(configmap)?, None)
+            }
+            CaSource::InMemory => unreachable!("in-memory CAs are never loaded"),
         };
+        self.store(ca_cert, ca_key).await?;
 
-        // Load CA from Kubernetes secret
-        manager.load_ca().await?;
+        info!("CA loaded successfully");
 
-        Ok(manager)
+        Ok(())
     }
 
-    /// Load CA certificate and key from Kubernetes secret
-    async fn load_ca(&self) -> Result<()> {
-        info!("Loading CA from secret: {}/{}", self.secret_namespace, self.secret_name);
+    /// Keep the CA and the namespace and tenant CAs in sync with their secrets, so a rotated
+    /// CA is picked up without a restart
+    ///
+    /// Runs until the process exits. Invalid updates are logged and ignored; the
+    /// previous CA stays active.
+    pub async fn watch_secret(&self) -> Result<()> {
+        let watches = self.all_cas().map(|manager| manager.watch_own_secret());
+        futures::future::try_join_all(watches).await?;
+        Ok(())
+    }
+
+    async fn watch_own_secret(&self) -> Result<()> {
+        if matches!(self.source, CaSource::InMemory) {
+            return Ok(());
+        }
 
-        let client = Client::try_default()
+        let client = crate::k8s_client::get_client()
             .await
             .context("Failed to create Kubernetes client")?;
 
-        let secrets: Api<Secret> = Api::namespaced(client, &self.secret_namespace);
+        match self.source {
+            CaSource::Secret => {
+                let secrets: Api<Secret> = Api::namespaced(client, &self.secret_namespace);
+                self.watch(secrets, |secret| parse_ca_secret(secret).map(|(cert, key)| (cert, Some(key)))).await
+            }
+            CaSource::TrustBundle => {
+                let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.secret_namespace);
+                self.watch(configmaps, |configmap| parse_trust_bundle(configmap).map(|cert| (cert, None))).await
+            }
+            CaSource::InMemory => Ok(()),
+        }
+    }
 
-        let secret = secrets
-            .get(&self.secret_name)
-            .await
-            .context("Failed to get CA secret")?;
-
-        let data = secret
-            .data
-            .ok_or_else(|| anyhow::anyhow!("Secret has no data"))?;
-
-        // Extract CA certificate
-        let ca_cert_bytes = data
-            .get("tls.crt")
-            .ok_or_else(|| anyhow::anyhow!("Secret missing tls.crt"))?;
-        
-        let ca_cert = String::from_utf8(ca_cert_bytes.0.clone())
-            .context("Invalid UTF-8 in CA certificate")?;
-
-        // Extract CA key
-        let ca_key_bytes = data
-            .get("tls.key")
-            .ok_or_else(|| anyhow::anyhow!("Secret missing tls.key"))?;
-        
-        let ca_key = String::from_utf8(ca_key_bytes.0.clone())
-            .context("Invalid UTF-8 in CA key")?;
+    async fn watch<K>(
+        &self,
+        api: Api<K>,
+        parse: impl Fn(K) -> Result<(String, Option<Zeroizing<String>>)>,
+    ) -> Result<()>
+    where
+        K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+    {
+        let config = watcher::Config::default().fields(&format!("metadata.name={}", self.secret_name));
 
-        // Store in memory (never written to disk)
-        *self.ca_cert.write().await = Some(ca_cert);
-        *self.ca_key.write().await = Some(ca_key);
+        let mut updates = watcher(api, config)
+            .default_backoff()
+            .applied_objects()
+            .boxed();
+
+        loop {
+            match updates.try_next().await {
+                Ok(Some(object)) => {
+                    let stored = match parse(object) {
+                        Ok((ca_cert, ca_key)) => self.store(ca_cert, ca_key).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = stored {
+                        warn!("Ignoring invalid CA update, keeping current CA: {:#}", e);
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => warn!("Error watching CA {}/{}: {}", self.secret_namespace, self.secret_name, e),
+            }
+        }
+    }
+
+    /// Store a CA, notifying subscribers if it differs from the current one
+    ///
+    /// A key must decrypt with the current passphrase, which is usually rotated together with it.
+    async fn store(&self, ca_cert: String, ca_key: Option<Zeroizing<String>>) -> Result<()> {
+        let passphrase = match (&ca_key, &self.passphrase_secret) {
+            (Some(_), Some(name)) => {
+                let client = crate::k8s_client::get_client()
+                    .await
+                    .context("Failed to create Kubernetes client")?;
+                let secrets: Api<Secret> = Api::namespaced(client, &self.secret_namespace);
+                Some(load_passphrase(&secrets, &self.secret_namespace, name).await?)
+            }
+            _ => None,
+        };
+        if let Some(ca_key) = &ca_key {
+            parse_ca_key(ca_key, passphrase.as_deref().map(String::as_str))?;
+        }
+
+        let mut current_cert = self.ca_cert.write().await;
+        let mut current_key = self.ca_key.write().await;
+        let mut current_passphrase = self.passphrase.write().await;
+
+        if current_cert.as_deref() == Some(ca_cert.as_str()) && *current_key == ca_key && *current_passphrase == passphrase {
+            return Ok(());
+        }
+
+        let rotated = current_cert.is_some();
 
-        info!("CA loaded successfully from secret");
+        // Store in memory (never written to disk)
+        *current_cert = Some(ca_cert);
+        *current_key = ca_key;
+        *current_passphrase = passphrase;
 
+        if rotated {
+            info!("CA {}/{} changed, CA reloaded", self.secret_namespace, self.secret_name);
+        }
+        self.generation.send_modify(|generation| *generation += 1);
         Ok(())
     }
 
+    /// Subscribe to CA changes; the receiver is notified whenever the CA or a namespace or tenant CA is rotated
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.generation.subscribe()
+    }
+
     /// Get CA certificate (PEM format)
     pub async fn get_ca_cert(&self) -> Result<String> {
         self.ca_cert
@@ -83,23 +368,78 @@ impl CaManager {
             .ok_or_else(|| anyhow::anyhow!("CA certificate not loaded"))
     }
 
-    /// Get CA private key (PEM format)
-    pub async fn get_ca_key(&self) -> Result<String> {
-        self.ca_key
-            .read()
-            .await
-            .clone()
-            .ok_or_else(|| anyhow::anyhow!("CA key not loaded"))
+    /// Get the CA key pair, decrypted with the passphrase if the key is encrypted
+    pub async fn get_ca_key(&self) -> Result<Zeroizing<KeyPair>> {
+        if matches!(self.source, CaSource::TrustBundle) {
+            return Err(anyhow::anyhow!("CA key is not available on nodes in trust-only mode"));
+        }
+
+        let ca_key = self.ca_key.read().await;
+        let ca_key = ca_key.as_ref().ok_or_else(|| anyhow::anyhow!("CA key not loaded"))?;
+        parse_ca_key(ca_key, self.passphrase.read().await.as_deref().map(String::as_str))
     }
 
-    /// Reload CA from Kubernetes secret (for rotation scenarios)
+    /// Reload CA and the namespace and tenant CAs from Kubernetes secrets (for rotation scenarios)
     pub async fn reload_ca(&self) -> Result<()> {
+        if matches!(self.source, CaSource::InMemory) {
+            return Ok(());
+        }
+
         info!("Reloading CA from secret");
-        self.load_ca().await
+        self.load_ca().await?;
+        for (namespace, manager) in self.namespace_cas.iter() {
+            manager.load_ca().await.context(format!("Failed to reload the CA of namespace {}", namespace))?;
+        }
+        for (secret, manager) in self.tenant_cas.iter() {
+            manager.load_ca().await.context(format!("Failed to reload tenant CA {}", secret))?;
+        }
+        Ok(())
     }
 
     /// Check if CA is loaded
     pub async fn is_loaded(&self) -> bool {
-        self.ca_cert.read().await.is_some() && self.ca_key.read().await.is_some()
+        for manager in self.all_cas() {
+            let loaded = manager.ca_cert.read().await.is_some()
+                && (matches!(manager.source, CaSource::TrustBundle) || manager.ca_key.read().await.is_some());
+            if !loaded {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Extract the CA certificate (PEM) from the trust bundle ConfigMap
+fn parse_trust_bundle(configmap: ConfigMap) -> Result<String> {
+    let ca_cert = configmap
+        .data
+        .and_then(|mut data| data.remove(TRUST_BUNDLE_KEY))
+        .ok_or_else(|| anyhow::anyhow!("ConfigMap missing {}", TRUST_BUNDLE_KEY))?;
+
+    if !ca_cert.contains("-----BEGIN CERTIFICATE-----") {
+        return Err(anyhow::anyhow!("{} does not contain a PEM certificate", TRUST_BUNDLE_KEY));
+    }
+
+    Ok(ca_cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_parse_trust_bundle() {
+        let ca = rcgen::CertificateParams::default()
+            .self_signed(&rcgen::KeyPair::generate().unwrap())
+            .unwrap()
+            .pem();
+        let configmap = ConfigMap {
+            data: Some(BTreeMap::from([(TRUST_BUNDLE_KEY.to_string(), ca.clone())])),
+            ..Default::default()
+        };
+        assert_eq!(parse_trust_bundle(configmap).unwrap(), ca);
+
+        assert!(parse_trust_bundle(ConfigMap::default()).is_err());
     }
 }
@@ -0,0 +1,79 @@
+use anyhow::{Result, Context};
+use clap::{Parser, Subcommand};
+use tonic::transport::{Endpoint, Uri};
+
+use cacsi_driver::config::DEFAULT_ADMIN_SOCKET;
+use cacsi_driver::proto::admin::node_admin_client::NodeAdminClient;
+use cacsi_driver::proto::admin::RenewCertificatesRequest;
+
+/// Administer the certificates of a node through the CSI driver's admin socket
+#[derive(Parser)]
+#[command(name = "cacsictl")]
+struct Cli {
+    /// Admin socket of the CSI driver
+    #[arg(long, env = "ADMIN_SOCKET", default_value = DEFAULT_ADMIN_SOCKET)]
+    socket: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replace certificates right away instead of at their renewal time
+    Renew {
+        /// Certificate IDs, volume mount paths or <namespace>/<pod> names
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        targets: Vec<String>,
+
+        /// Renew every certificate on the node
+        #[arg(long)]
+        all: bool,
+
+        /// Revoke the current certificates as compromised before replacing them
+        #[arg(long)]
+        revoke: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // The URI is a placeholder; every connection dials the socket
+    let socket = cli.socket.clone();
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(tower::service_fn(move |_: Uri| tokio::net::UnixStream::connect(socket.clone())))
+        .await
+        .context(format!("Failed to connect to the CSI driver at {}", cli.socket))?;
+    let mut client = NodeAdminClient::new(channel);
+
+    match cli.command {
+        Command::Renew { targets, all, revoke } => {
+            let response = client
+                .renew_certificates(RenewCertificatesRequest { targets, all, revoke })
+                .await
+                .context("Failed to renew certificates")?
+                .into_inner();
+
+            let mut failed = 0;
+            for result in &response.results {
+                if result.renewed {
+                    let not_after = chrono::DateTime::from_timestamp(result.not_after, 0)
+                        .map(|dt| dt.to_rfc3339())
+                        .unwrap_or_else(|| "unknown".to_string());
+                    println!("renewed  {}  {}  expires {}", result.certificate_id, result.pod, not_after);
+                } else {
+                    failed += 1;
+                    println!("failed   {}  {}  {}", result.certificate_id, result.pod, result.error);
+                }
+            }
+            println!("{} renewed, {} failed", response.results.len() - failed, failed);
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
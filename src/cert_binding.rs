@@ -0,0 +1,278 @@
+use anyhow::{Result, Context};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{Api, DeleteParams, Patch, PatchParams};
+use kube::CustomResource;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::{debug, warn};
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::parse_x509_pem;
+
+use crate::k8s_client::PodRef;
+
+/// Field manager of the bindings written by the driver
+const FIELD_MANAGER: &str = "cacsi-driver";
+
+/// Label naming the node whose driver owns a binding
+pub const NODE_LABEL: &str = "cacsi.k8s.io/node";
+
+/// Longest Kubernetes object name
+const MAX_NAME_LENGTH: usize = 253;
+
+/// Certificate of one published volume, created by the node driver in the pod's namespace
+///
+/// `kubectl get certificatebindings -A` lists every mounted certificate with its expiry
+/// and the result of its last renewal.
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[kube(
+    group = "cacsi.k8s.io",
+    version = "v1alpha1",
+    kind = "CertificateBinding",
+    namespaced,
+    status = "CertificateBindingStatus",
+    shortname = "certbinding",
+    schema = "disabled"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateBindingSpec {
+    pub pod_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_uid: Option<String>,
+    pub node_name: String,
+    pub certificate_id: String,
+}
+
+/// What the certificate on the volume looks like, and how its last renewal went
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateBindingStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
+    #[serde(default)]
+    pub dns_names: Vec<String>,
+    #[serde(default)]
+    pub ip_addresses: Vec<String>,
+    #[serde(default)]
+    pub uris: Vec<String>,
+    /// Lowercase hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<String>,
+    /// RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<String>,
+    /// `Issued`, `Renewed` or `Failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_renewal_result: Option<String>,
+    /// RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_renewal_time: Option<String>,
+    /// Error of the last renewal, cleared when one succeeds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_renewal_error: Option<String>,
+}
+
+/// Keeps a CertificateBinding per published volume in sync with its certificate
+///
+/// Like pod annotations, bindings are best effort and never fail issuance or renewal.
+/// Bindings are owned by their pod when its UID is known, so Kubernetes deletes them
+/// together with the pod.
+#[derive(Clone)]
+pub struct BindingRecorder {
+    enabled: bool,
+    node_id: String,
+}
+
+impl BindingRecorder {
+    pub fn new(enabled: bool, node_id: String) -> Self {
+        Self { enabled, node_id }
+    }
+
+    /// Record `cert_pem`, just issued (`renewal: false`) or renewed, as the volume's certificate
+    pub async fn record_certificate(&self, pod: &PodRef, cert_id: &str, cert_pem: &str, renewal: bool) {
+        if !self.enabled {
+            return;
+        }
+
+        let result = async {
+            let mut status = status_from_pem(cert_pem)?;
+            status.last_renewal_result = Some(if renewal { "Renewed" } else { "Issued" }.to_string());
+            status.last_renewal_time = Some(chrono::Utc::now().to_rfc3339());
+            self.apply(pod, cert_id).await?;
+            // Explicit nulls remove a previous error in the merge patch
+            let mut status = serde_json::to_value(status)?;
+            status["lastRenewalError"] = serde_json::Value::Null;
+            self.patch_status(pod, cert_id, status).await
+        }.await;
+
+        match result {
+            Ok(()) => debug!("Updated CertificateBinding of {}", cert_id),
+            Err(e) => warn!("Failed to update CertificateBinding of {}: {:#}", cert_id, e),
+        }
+    }
+
+    /// Record a failed renewal; the certificate fields keep describing the current certificate
+    pub async fn record_renewal_failure(&self, pod: &PodRef, cert_id: &str, error: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let status = serde_json::json!({
+            "lastRenewalResult": "Failed",
+            "lastRenewalTime": chrono::Utc::now().to_rfc3339(),
+            "lastRenewalError": error,
+        });
+        if let Err(e) = self.patch_status(pod, cert_id, status).await {
+            warn!("Failed to update CertificateBinding of {}: {:#}", cert_id, e);
+        }
+    }
+
+    /// Delete the binding of an unpublished volume
+    pub async fn remove(&self, pod: &PodRef, cert_id: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let result = async {
+            let bindings = Self::api(pod).await?;
+            match bindings.delete(&binding_name(pod, cert_id), &DeleteParams::default()).await {
+                Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(()),
+                Err(e) => Err(e).context("Failed to delete CertificateBinding"),
+            }
+        }.await;
+
+        if let Err(e) = result {
+            warn!("Failed to delete CertificateBinding of {}: {:#}", cert_id, e);
+        }
+    }
+
+    async fn api(pod: &PodRef) -> Result<Api<CertificateBinding>> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+        Ok(Api::namespaced(client, &pod.namespace))
+    }
+
+    /// Create the binding, or bring its spec up to date
+    async fn apply(&self, pod: &PodRef, cert_id: &str) -> Result<()> {
+        let name = binding_name(pod, cert_id);
+        let mut binding = CertificateBinding::new(&name, CertificateBindingSpec {
+            pod_name: pod.name.clone(),
+            pod_uid: pod.uid.clone(),
+            node_name: self.node_id.clone(),
+            certificate_id: cert_id.to_string(),
+        });
+        binding.metadata.namespace = Some(pod.namespace.clone());
+        binding.metadata.labels = Some(BTreeMap::from([(NODE_LABEL.to_string(), self.node_id.clone())]));
+        binding.metadata.owner_references = pod.uid.as_ref().map(|uid| vec![OwnerReference {
+            api_version: "v1".to_string(),
+            kind: "Pod".to_string(),
+            name: pod.name.clone(),
+            uid: uid.clone(),
+            ..Default::default()
+        }]);
+
+        Self::api(pod).await?
+            .patch(&name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(&binding))
+            .await
+            .context("Failed to apply CertificateBinding")?;
+        Ok(())
+    }
+
+    async fn patch_status(&self, pod: &PodRef, cert_id: &str, status: serde_json::Value) -> Result<()> {
+        let patch = serde_json::json!({ "status": status });
+        Self::api(pod).await?
+            .patch_status(&binding_name(pod, cert_id), &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context("Failed to patch CertificateBinding status")?;
+        Ok(())
+    }
+}
+
+/// Name of the binding of a certificate, unique within the pod's namespace
+///
+/// The certificate ID without the namespace; too long IDs are cut and end in a hash
+/// of the full ID instead.
+pub fn binding_name(pod: &PodRef, cert_id: &str) -> String {
+    let name = cert_id
+        .strip_prefix(&format!("{}-", pod.namespace))
+        .unwrap_or(cert_id)
+        .to_lowercase();
+    if name.len() <= MAX_NAME_LENGTH {
+        return name;
+    }
+
+    let hash: String = Sha256::digest(cert_id.as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    let prefix = name[..MAX_NAME_LENGTH - hash.len() - 1].trim_end_matches(['-', '.']);
+    format!("{}-{}", prefix, hash)
+}
+
+/// Certificate fields of the status, read from a PEM certificate
+pub fn status_from_pem(cert_pem: &str) -> Result<CertificateBindingStatus> {
+    let (_, pem) = parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate PEM: {}", e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))?;
+
+    let mut status = CertificateBindingStatus {
+        common_name: cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok()).map(str::to_string),
+        serial: Some(cert.raw_serial().iter().map(|b| format!("{:02x}", b)).collect()),
+        not_before: chrono::DateTime::from_timestamp(cert.validity().not_before.timestamp(), 0).map(|dt| dt.to_rfc3339()),
+        not_after: chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0).map(|dt| dt.to_rfc3339()),
+        ..Default::default()
+    };
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => status.dns_names.push(dns.to_string()),
+                GeneralName::URI(uri) => status.uris.push(uri.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    let ip = match bytes.len() {
+                        4 => <[u8; 4]>::try_from(*bytes).map(std::net::IpAddr::from).ok(),
+                        16 => <[u8; 16]>::try_from(*bytes).map(std::net::IpAddr::from).ok(),
+                        _ => None,
+                    };
+                    status.ip_addresses.extend(ip.map(|ip| ip.to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DnType, KeyPair, SanType, SerialNumber};
+
+    #[test]
+    fn test_binding_status() {
+        let mut params = CertificateParams::new(vec!["web.default.svc".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "web");
+        params.subject_alt_names.push(SanType::IpAddress("10.0.0.1".parse().unwrap()));
+        params.subject_alt_names.push(SanType::URI("spiffe://cluster.local/ns/default/sa/web".try_into().unwrap()));
+        params.serial_number = Some(SerialNumber::from_slice(&[0x0a, 0xbc]));
+        params.not_after = time::macros::datetime!(2030-01-02 03:04:05 UTC);
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let status = status_from_pem(&cert.pem()).unwrap();
+        assert_eq!(status.common_name.as_deref(), Some("web"));
+        assert_eq!(status.dns_names, ["web.default.svc"]);
+        assert_eq!(status.ip_addresses, ["10.0.0.1"]);
+        assert_eq!(status.uris, ["spiffe://cluster.local/ns/default/sa/web"]);
+        assert_eq!(status.serial.as_deref(), Some("0abc"));
+        assert_eq!(status.not_after.as_deref(), Some("2030-01-02T03:04:05+00:00"));
+
+        let pod = PodRef { namespace: "default".to_string(), name: "web".to_string(), uid: None };
+        assert_eq!(binding_name(&pod, "default-web-csi-abc"), "web-csi-abc");
+        let long = binding_name(&pod, &format!("default-{}-csi-abc", "w".repeat(300)));
+        assert_eq!(long.len(), MAX_NAME_LENGTH);
+        assert_ne!(long, binding_name(&pod, &format!("default-{}-csi-abd", "w".repeat(300))));
+    }
+}
@@ -1,40 +1,253 @@
 use anyhow::{Result, Context};
-use chrono::Utc;
 use dashmap::DashMap;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-use tracing::{info, error};
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info, warn};
+use x509_parser::pem::parse_x509_pem;
+use zeroize::Zeroizing;
 
+use crate::cert_service::validity::days_rounded_up;
+use crate::encoding::{self, Encoding, KeyEncoding};
+use crate::k8s_client::PodRef;
+use crate::key_encryption::{self, KeyEncryptor};
+use crate::keyed_lock::{KeyedLockGuard, KeyedLocks};
+use crate::layout::{Layout, LAYOUT_FILES};
+use crate::local_signing::LocalSigningRequest;
+use crate::reload::ReloadStrategy;
+use crate::cert_validation::{certificate_fingerprint, spki_pins};
+use crate::client::CertServiceClient;
+use crate::error_details::{error_reason, ErrorReason};
+use crate::retry::{CircuitBreaker, RetryPolicy};
+use crate::volume_metadata::{VolumeMetadata, METADATA_FILE};
 use crate::proto::certservice::{
-    certificate_service_client::CertificateServiceClient,
     IssueCertificateRequest, RenewCertificateRequest,
+    GetCertificateInfoRequest, GetCertificateInfoResponse,
+    IssueNodeIntermediateRequest, Subject, Extension, CertificateEvent,
+    RevokeCertificateRequest, RevocationReason,
 };
 
-#[derive(Clone)]
+/// File under the base path holding the registry, so monitoring survives restarts
+const REGISTRY_FILE: &str = "registry.json";
+
+/// Files of a volume holding the certificate and key
+pub const CERTIFICATE_FILES: [&str; 2] = ["tls.crt", "tls.key"];
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CertificateInfo {
     pub cert_id: String,
     pub mount_path: String,
+    pub pod: PodRef,
+    pub reload_strategy: ReloadStrategy,
     pub not_before: i64,
     pub not_after: i64,
+    /// Error of the last renewal attempt, cleared when a renewal succeeds
+    #[serde(default)]
+    pub last_renewal_error: Option<String>,
+    /// Set while the certificate on disk was signed on the node, to sign it there again
+    /// or re-issue it through the service
+    #[serde(default)]
+    pub local_request: Option<LocalSigningRequest>,
+}
+
+/// Fingerprint of the certificate, written to volumes that ask for pins
+pub const FINGERPRINT_FILE: &str = "tls.crt.sha256";
+
+/// SPKI pin of the certificate
+pub const CERTIFICATE_PIN_FILE: &str = "tls.crt.pin";
+
+/// SPKI pins of the CA certificates, one per line
+pub const CA_PIN_FILE: &str = "ca.crt.pin";
+
+/// Previous certificates and keys kept in each volume on renewal, unless set otherwise
+pub const DEFAULT_PREVIOUS_VERSIONS: usize = 1;
+
+/// A certificate file as the driver last wrote it, to restore it if it is deleted or truncated
+#[derive(Clone)]
+pub struct WrittenFile {
+    pub contents: Zeroizing<Vec<u8>>,
+    pub mode: u32,
+    pub gid: u32,
 }
 
 #[derive(Clone)]
 pub struct CertificateManager {
     base_path: PathBuf,
-    cert_service_addr: String,
     certificates: Arc<DashMap<String, CertificateInfo>>,
+    /// Serializes writes of the registry file
+    persist_lock: Arc<Mutex<()>>,
+    client: CertServiceClient,
+    /// Signalled when certificates are registered or unregistered
+    changed: Arc<Notify>,
+    key_encryptor: Option<Arc<KeyEncryptor>>,
+    /// Encrypt keys of volumes that do not set `encrypt_key`
+    encrypt_keys_by_default: bool,
+    /// Held while a certificate is issued or renewed
+    certificate_locks: KeyedLocks,
+    /// Certificate files as last written, by path; kept in memory only
+    written_files: Arc<DashMap<PathBuf, WrittenFile>>,
+    /// Previous certificates and keys kept as `tls.crt.old`, `tls.crt.old.1`, ...
+    previous_versions: usize,
 }
 
 impl CertificateManager {
     pub fn new(base_path: PathBuf, cert_service_addr: String) -> Self {
         Self {
             base_path,
-            cert_service_addr,
             certificates: Arc::new(DashMap::new()),
+            persist_lock: Arc::new(Mutex::new(())),
+            client: CertServiceClient::new(cert_service_addr),
+            changed: Arc::new(Notify::new()),
+            key_encryptor: None,
+            encrypt_keys_by_default: false,
+            certificate_locks: KeyedLocks::new(),
+            written_files: Arc::new(DashMap::new()),
+            previous_versions: DEFAULT_PREVIOUS_VERSIONS,
+        }
+    }
+
+    /// Keep this many previous certificates and keys in each volume on renewal, 0 for none
+    pub fn with_previous_versions(mut self, previous_versions: usize) -> Self {
+        self.previous_versions = previous_versions;
+        self
+    }
+
+    /// Allow private keys to be encrypted on disk, for all volumes if `by_default` is set
+    pub fn with_key_encryption(mut self, encryptor: KeyEncryptor, by_default: bool) -> Self {
+        self.key_encryptor = Some(Arc::new(encryptor));
+        self.encrypt_keys_by_default = by_default;
+        self
+    }
+
+    /// Whether keys are encrypted for volumes that do not set `encrypt_key`
+    pub fn encrypt_keys_by_default(&self) -> bool {
+        self.encrypt_keys_by_default
+    }
+
+    /// Whether a key encryption passphrase is configured
+    pub fn can_encrypt_keys(&self) -> bool {
+        self.key_encryptor.is_some()
+    }
+
+    /// Encode a private key for writing to a volume, encrypting it if requested
+    pub fn encode_private_key(&self, key_pem: &str, encrypt: bool) -> Result<Zeroizing<String>> {
+        if !encrypt {
+            return Ok(Zeroizing::new(key_pem.to_string()));
+        }
+
+        self.key_encryptor
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Key encryption requested but no KEY_ENCRYPTION_SECRET is configured"))?
+            .encrypt(key_pem)
+    }
+
+    /// Retry certificate service calls that fail with transient errors
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.client = self.client.with_retry_policy(retry_policy);
+        self
+    }
+
+    /// Fail certificate service calls fast after repeated connection failures
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.client = self.client.with_circuit_breaker(circuit_breaker);
+        self
+    }
+
+    /// Authenticate to the certificate service with the service account token in `token_file`
+    pub fn with_token_file(mut self, token_file: PathBuf) -> Self {
+        self.client = self.client.with_token_file(token_file);
+        self
+    }
+
+    /// Verify the certificate service's TLS certificate against `ca_pem`
+    pub fn with_tls_ca(mut self, ca_pem: String) -> Self {
+        self.client = self.client.with_tls_ca(ca_pem);
+        self
+    }
+
+    /// Load the registry persisted by a previous run
+    ///
+    /// Entries whose mount path no longer exists (the volume was unpublished while
+    /// the driver was down) are dropped.
+    pub async fn load_registry(&self) -> Result<usize> {
+        let path = self.base_path.join(REGISTRY_FILE);
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context(format!("Failed to read registry {}", path.display())),
+        };
+
+        let entries: Vec<CertificateInfo> = serde_json::from_str(&contents)
+            .context(format!("Invalid registry {}", path.display()))?;
+
+        let mut loaded = 0;
+        for info in entries {
+            if !Path::new(&info.mount_path).exists() {
+                info!("Dropping stale registry entry {}: {} no longer exists", info.cert_id, info.mount_path);
+                continue;
+            }
+            self.certificates.insert(info.cert_id.clone(), info);
+            loaded += 1;
+        }
+
+        self.persist_registry().await;
+
+        Ok(loaded)
+    }
+
+    /// Write the registry to disk now, e.g. before exiting
+    pub async fn save_registry(&self) -> Result<()> {
+        let _guard = self.persist_lock.lock().await;
+        self.write_registry().await
+    }
+
+    /// Write the registry to disk, replacing the previous file atomically
+    async fn persist_registry(&self) {
+        let _guard = self.persist_lock.lock().await;
+
+        if let Err(e) = self.write_registry().await {
+            warn!("Failed to persist certificate registry: {}", e);
         }
     }
 
+    async fn write_registry(&self) -> Result<()> {
+        let entries = self.get_all_certificates();
+        let contents = serde_json::to_vec_pretty(&entries)
+            .context("Failed to serialize registry")?;
+
+        tokio::fs::create_dir_all(&self.base_path)
+            .await
+            .context(format!("Failed to create {}", self.base_path.display()))?;
+
+        let path = self.base_path.join(REGISTRY_FILE);
+        let tmp_path = self.base_path.join(format!("{}.tmp", REGISTRY_FILE));
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .context(format!("Failed to write {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .context(format!("Failed to replace {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Get a registered certificate
+    pub fn get_certificate(&self, cert_id: &str) -> Option<CertificateInfo> {
+        self.certificates.get(cert_id).map(|entry| entry.value().clone())
+    }
+
+    /// Wait until no one else issues or renews `cert_id`, and keep others out until dropped
+    ///
+    /// Kubelet retries NodePublishVolume while a slow call is still running. Holding the
+    /// lock for the whole publish makes the retry find the certificate the first call
+    /// issued, instead of issuing a second one and key for the same volume.
+    pub async fn lock_certificate(&self, cert_id: &str) -> KeyedLockGuard<()> {
+        self.certificate_locks.lock(cert_id).await
+    }
+
     /// Issue a new certificate via the certificate service
     pub async fn issue_certificate(
         &self,
@@ -42,23 +255,18 @@ impl CertificateManager {
         common_name: &str,
         dns_names: Vec<String>,
         ip_addresses: Vec<String>,
+        uris: Vec<String>,
         organizational_units: Vec<String>,
-        validity_days: i64,
-    ) -> Result<(String, String, i64, i64)> {
+        subject: Subject,
+        key_usages: Vec<String>,
+        extended_key_usages: Vec<String>,
+        extensions: Vec<Extension>,
+        validity_seconds: i64,
+        metadata: HashMap<String, String>,
+        profile: Option<String>,
+        key_encoding: KeyEncoding,
+    ) -> Result<(String, Zeroizing<String>, i64, i64)> {
         info!("Issuing certificate for: {}", cert_id);
-        
-        // Ensure the address has a proper scheme
-        let addr = if !self.cert_service_addr.starts_with("http://") && !self.cert_service_addr.starts_with("https://") {
-            format!("http://{}", self.cert_service_addr)
-        } else {
-            self.cert_service_addr.clone()
-        };
-        
-        info!("Connecting to certificate service at: {}", addr);
-
-        let mut client = CertificateServiceClient::connect(addr.clone())
-            .await
-            .context(format!("Failed to connect to certificate service at {}", addr))?;
 
         // Build request for certificate issuance
         let request = IssueCertificateRequest {
@@ -66,73 +274,133 @@ impl CertificateManager {
             common_name: common_name.to_string(),
             dns_names,
             ip_addresses,
-            validity_days,
-            metadata: std::collections::HashMap::new(),
+            uris,
+            // Services that predate validity_seconds only read whole days
+            validity_days: days_rounded_up(validity_seconds),
+            validity_seconds,
+            metadata,
             organizational_units,
+            profile: profile.unwrap_or_default(),
+            subject: Some(subject),
+            key_usages,
+            extended_key_usages,
+            extensions,
+            // Set by the client, shared by its retries
+            idempotency_key: String::new(),
+            // The node only issues when the volume has no usable certificate, e.g. after a publish
+            // failed past issuance or a reboot wiped the tmpfs, so the one the service holds is unused
+            replace_existing: true,
+            // Converted by the caller; the service rejects key types without this encoding
+            key_encoding: key_encoding.as_str().to_string(),
         };
 
-        let response = client
-            .issue_certificate(request)
-            .await
-            .context("Failed to issue certificate")?
-            .into_inner();
+        let response = self.client.issue_certificate(request).await?;
 
         info!("Certificate issued: {}", response.certificate_id);
 
         Ok((
             response.certificate_pem,
-            response.private_key_pem,
+            Zeroizing::new(response.private_key_pem),
             response.not_before,
             response.not_after,
         ))
     }
 
     /// Renew an existing certificate
+    ///
+    /// A `validity_seconds` of 0 keeps the validity requested at issuance. With
+    /// `replace_revoked` a revoked certificate is replaced with a new key and serial;
+    /// otherwise renewing it fails.
+    ///
+    /// Signers that re-enroll with the existing identity (EST) need the certificate and key
+    /// in `mount_path`. They are only sent when the service asks for them, over TLS or a Unix
+    /// socket, and never to replace a revoked certificate, whose key may be compromised; keys
+    /// stored encrypted cannot be sent, as the node has no way to decrypt them.
     pub async fn renew_certificate(
         &self,
         cert_id: &str,
-        validity_days: i64,
-    ) -> Result<(String, String, i64, i64)> {
+        mount_path: &str,
+        validity_seconds: i64,
+        replace_revoked: bool,
+    ) -> Result<(String, Zeroizing<String>, i64, i64)> {
         info!("Renewing certificate: {}", cert_id);
-        
-        // Ensure the address has a proper scheme
-        let addr = if !self.cert_service_addr.starts_with("http://") && !self.cert_service_addr.starts_with("https://") {
-            format!("http://{}", self.cert_service_addr)
-        } else {
-            self.cert_service_addr.clone()
+
+        let mut request = RenewCertificateRequest {
+            certificate_id: cert_id.to_string(),
+            validity_days: days_rounded_up(validity_seconds),
+            validity_seconds,
+            replace_revoked,
+            ..Default::default()
         };
-        
-        info!("Connecting to certificate service at: {}", addr);
-
-        let endpoint = tonic::transport::Endpoint::from_shared(addr.clone())
-            .context("Invalid endpoint URL")?
-            .timeout(Duration::from_secs(10))
-            .connect_timeout(Duration::from_secs(5));
-
-        let mut client = match CertificateServiceClient::connect(endpoint).await {
-            Ok(client) => client,
-            Err(e) => {
-                error!("Connection error details: {:?}", e);
-                return Err(anyhow::anyhow!("Failed to connect to certificate service at {}: {}", addr, e));
+
+        let response = match self.client.renew_certificate(request.clone()).await {
+            Err(e) if error_reason(&e) == Some(ErrorReason::CurrentKeyRequired) && !replace_revoked => {
+                if !self.client.is_confidential() {
+                    return Err(e.context("Not sending the key of the certificate being renewed over a plaintext connection"));
+                }
+                let mut current_key = encoding::read_key(mount_path)
+                    .await
+                    .context(format!("Failed to read the key of certificate {}", cert_id))?;
+                if key_encryption::is_encrypted(&current_key) {
+                    return Err(e.context("The key of the certificate being renewed is stored encrypted and cannot be sent"));
+                }
+                request.current_certificate_pem = encoding::read_certificate(mount_path)
+                    .await
+                    .context(format!("Failed to read certificate {}", cert_id))?;
+                request.current_private_key_pem = std::mem::take(&mut *current_key);
+
+                debug!("Renewing certificate {} again with its current key, which the signer re-enrolls with", cert_id);
+                self.client.renew_certificate(request).await?
             }
+            response => response?,
+        };
+
+        info!("Certificate renewed: {}", cert_id);
+
+        Ok((
+            response.certificate_pem,
+            Zeroizing::new(response.private_key_pem),
+            response.not_before,
+            response.not_after,
+        ))
+    }
+
+    /// Get what the certificate service knows about a certificate, including its revocation status
+    pub async fn get_certificate_info(&self, cert_id: &str) -> Result<GetCertificateInfoResponse> {
+        let request = GetCertificateInfoRequest {
+            certificate_id: cert_id.to_string(),
         };
 
-        let request = RenewCertificateRequest {
+        self.client.get_certificate_info(request).await
+    }
+
+    /// Have the certificate service revoke a certificate, e.g. of a deleted pod
+    pub async fn revoke_certificate(&self, cert_id: &str, reason: RevocationReason) -> Result<()> {
+        let request = RevokeCertificateRequest {
             certificate_id: cert_id.to_string(),
-            validity_days,
+            reason: reason.into(),
         };
 
-        let response = client
-            .renew_certificate(request)
-            .await
-            .context("Failed to renew certificate")?
-            .into_inner();
+        self.client.revoke_certificate(request).await?;
+        Ok(())
+    }
 
-        info!("Certificate renewed: {}", cert_id);
+    /// Subscribe to events the certificate service pushes for `cert_ids`, and to CA rotations
+    pub async fn watch_certificates(&self, cert_ids: Vec<String>) -> Result<tonic::Streaming<CertificateEvent>> {
+        self.client.watch_certificates(cert_ids).await
+    }
+
+    /// Get an intermediate CA for this node to sign certificates with
+    pub async fn issue_node_intermediate(&self, node_id: &str) -> Result<(String, Zeroizing<String>, i64, i64)> {
+        let request = IssueNodeIntermediateRequest {
+            node_id: node_id.to_string(),
+        };
+
+        let response = self.client.issue_node_intermediate(request).await?;
 
         Ok((
             response.certificate_pem,
-            response.private_key_pem,
+            Zeroizing::new(response.private_key_pem),
             response.not_before,
             response.not_after,
         ))
@@ -143,24 +411,109 @@ impl CertificateManager {
         &self,
         cert_id: String,
         mount_path: String,
+        pod: PodRef,
+        reload_strategy: ReloadStrategy,
         not_before: i64,
         not_after: i64,
+        local_request: Option<LocalSigningRequest>,
     ) {
         let info = CertificateInfo {
             cert_id: cert_id.clone(),
             mount_path,
+            pod,
+            reload_strategy,
             not_before,
             not_after,
+            last_renewal_error: None,
+            local_request,
         };
 
         self.certificates.insert(cert_id.clone(), info);
         info!("Registered certificate for monitoring: {}", cert_id);
+
+        self.persist_registry().await;
+        self.changed.notify_one();
+    }
+
+    /// Unregister the certificate mounted at `mount_path` from monitoring, and those in
+    /// its subdirectories (`mode: dual`)
+    ///
+    /// NodeUnpublishVolume only carries the volume ID and target path, not the pod
+    /// information the cert_id is derived from, so entries are matched by path.
+    /// Returns the unregistered certificates.
+    pub async fn unregister_certificate(&self, mount_path: &str) -> Vec<CertificateInfo> {
+        let cert_ids: Vec<String> = self.certificates
+            .iter()
+            .filter(|entry| Path::new(&entry.value().mount_path).starts_with(mount_path))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut unregistered = Vec::new();
+        self.forget_certificate_files(mount_path);
+        for cert_id in &cert_ids {
+            if let Some((_, cert_info)) = self.certificates.remove(cert_id) {
+                self.forget_certificate_files(&cert_info.mount_path);
+                unregistered.push(cert_info);
+            }
+            info!("Unregistered certificate: {}", cert_id);
+        }
+
+        if !cert_ids.is_empty() {
+            self.persist_registry().await;
+            self.changed.notify_one();
+        }
+
+        unregistered
+    }
+
+    /// Wait until certificates are registered or unregistered
+    pub async fn changed(&self) {
+        self.changed.notified().await;
+    }
+
+    /// Record a failed renewal, reported as an abnormal volume condition until a renewal succeeds
+    pub async fn record_renewal_failure(&self, cert_id: &str, error: String) {
+        let mount_path = if let Some(mut info) = self.certificates.get_mut(cert_id) {
+            info.last_renewal_error = Some(error.clone());
+            info.mount_path.clone()
+        } else {
+            return;
+        };
+
+        self.persist_registry().await;
+
+        // The certificate fields keep describing the certificate still on the volume
+        let result = async {
+            let mut metadata = VolumeMetadata::read(&mount_path).await?;
+            metadata.certificate.last_renewal_result = Some("Failed".to_string());
+            metadata.certificate.last_renewal_time = Some(chrono::Utc::now().to_rfc3339());
+            metadata.certificate.last_renewal_error = Some(error);
+            metadata.write(&mount_path).await
+        }.await;
+        if let Err(e) = result {
+            warn!("Failed to update {} of {}: {:#}", METADATA_FILE, cert_id, e);
+        }
+    }
+
+    /// Describe the certificate just written to a volume in its `metadata.json`
+    ///
+    /// Best effort, like pod annotations: failures are logged and never fail issuance
+    /// or renewal.
+    pub async fn write_metadata(&self, mount_path: &str, cert_id: &str, cert_pem: &str, renewal: bool) {
+        let result = async {
+            VolumeMetadata::from_pem(cert_id, cert_pem, renewal)?.write(mount_path).await
+        }.await;
+        if let Err(e) = result {
+            warn!("Failed to write {} of {}: {:#}", METADATA_FILE, cert_id, e);
+        }
     }
 
-    /// Unregister a certificate from monitoring
-    pub async fn unregister_certificate(&self, cert_id: &str) {
-        self.certificates.remove(cert_id);
-        info!("Unregistered certificate: {}", cert_id);
+    /// Find the certificate mounted at `mount_path`
+    pub fn find_by_mount_path(&self, mount_path: &str) -> Option<CertificateInfo> {
+        self.certificates
+            .iter()
+            .find(|entry| entry.value().mount_path == mount_path)
+            .map(|entry| entry.value().clone())
     }
 
     /// Get all registered certificates
@@ -181,30 +534,264 @@ impl CertificateManager {
         let cert_path = std::path::Path::new(mount_path).join("tls.crt");
         let key_path = std::path::Path::new(mount_path).join("tls.key");
 
+        // Keep the encoding and key format the volume was published with; only PEM
+        // PKCS#8 keys are ever encrypted
+        let encoding = Encoding::detect(mount_path).await;
+        let key_encoding = KeyEncoding::detect(mount_path).await;
+        let encrypted = tokio::fs::read_to_string(&key_path)
+            .await
+            .map(|existing| key_encryption::is_encrypted(&Zeroizing::new(existing)))
+            .unwrap_or(false);
+        let key_pem = self.encode_private_key(&key_encoding.encode(key_pem)?, encrypted)?;
+
+        self.keep_previous_versions(mount_path).await?;
+
         // Write new certificate
-        tokio::fs::write(&cert_path, cert_pem)
+        tokio::fs::write(&cert_path, encoding.encode_certificate(cert_pem)?)
             .await
             .context("Failed to write certificate")?;
 
         // Write new key
-        tokio::fs::write(&key_path, key_pem)
+        tokio::fs::write(&key_path, encoding.encode_key(&key_pem)?.as_slice())
             .await
             .context("Failed to write key")?;
 
+        // Keep the layout the volume was published with
+        let (layout, owner) = Layout::detect(mount_path).await;
+        layout.write(mount_path, cert_pem, &key_pem, owner).await?;
+
+        self.remember_certificate_files(mount_path).await;
         info!("Updated certificate files at: {}", mount_path);
 
         Ok(())
     }
 
-    /// Check if a certificate needs renewal (renew if < 20% of lifetime remaining)
-    pub fn needs_renewal(&self, not_before: i64, not_after: i64) -> bool {
-        let now = Utc::now().timestamp();
-        let lifetime = not_after - not_before;
-        let remaining = not_after - now;
-        
-        // Renew if less than 20% of lifetime remains
-        let threshold = (lifetime as f64 * 0.2) as i64;
-        
-        remaining < threshold
+    /// Remove the certificate and key, their previous versions, layout copies and the
+    /// metadata from a volume, leaving the CA bundle in place
+    pub async fn remove_certificate_files(&self, mount_path: &str) -> Result<()> {
+        self.forget_certificate_files(mount_path);
+        let previous_versions = CERTIFICATE_FILES
+            .into_iter()
+            .flat_map(|file| (0..self.previous_versions).map(move |version| previous_version_path(mount_path, file, version)));
+        let files = CERTIFICATE_FILES
+            .into_iter()
+            .chain([METADATA_FILE, FINGERPRINT_FILE, CERTIFICATE_PIN_FILE])
+            .chain(LAYOUT_FILES);
+        for path in files.map(|file| Path::new(mount_path).join(file)).chain(previous_versions) {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).context(format!("Failed to remove {}", path.display())),
+            }
+        }
+
+        info!("Removed certificate files at: {}", mount_path);
+
+        Ok(())
+    }
+
+    /// Write the fingerprint and SPKI pin of the certificate, and the SPKI pins of the CA
+    /// certificates, next to the certificate of a volume
+    pub async fn write_pin_files(&self, mount_path: &str, cert_pem: &str, ca_pem: &str) -> Result<()> {
+        let certificate_pin = spki_pins(cert_pem)?.into_iter().next().context("No certificate in PEM")?;
+        let files = [
+            (FINGERPRINT_FILE, certificate_fingerprint(cert_pem)?),
+            (CERTIFICATE_PIN_FILE, certificate_pin),
+            (CA_PIN_FILE, spki_pins(ca_pem)?.join("\n")),
+        ];
+        for (file, contents) in files {
+            let path = Path::new(mount_path).join(file);
+            tokio::fs::write(&path, format!("{}\n", contents))
+                .await
+                .context(format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Whether a volume was published with pin files, to write them again on renewal
+    pub async fn has_pin_files(&self, mount_path: &str) -> bool {
+        tokio::fs::try_exists(Path::new(mount_path).join(FINGERPRINT_FILE)).await.unwrap_or(false)
+    }
+
+    /// Copy the current certificate and key of a volume to `tls.crt.old` and `tls.key.old`,
+    /// shifting older copies to `.old.1`, `.old.2`, ... and dropping the oldest
+    ///
+    /// The files are copied rather than moved so the workload never sees them missing.
+    async fn keep_previous_versions(&self, mount_path: &str) -> Result<()> {
+        if self.previous_versions == 0 {
+            return Ok(());
+        }
+
+        for file in CERTIFICATE_FILES {
+            let current = Path::new(mount_path).join(file);
+            let Ok(metadata) = tokio::fs::metadata(&current).await else {
+                continue;
+            };
+            for version in (1..self.previous_versions).rev() {
+                let older = previous_version_path(mount_path, file, version - 1);
+                match tokio::fs::rename(&older, previous_version_path(mount_path, file, version)).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e).context(format!("Failed to move {}", older.display())),
+                }
+            }
+
+            let previous = previous_version_path(mount_path, file, 0);
+            tokio::fs::copy(&current, &previous)
+                .await
+                .context(format!("Failed to keep the previous version of {}", current.display()))?;
+            std::os::unix::fs::chown(&previous, None, Some(metadata.gid()))
+                .context(format!("Failed to set the group of {}", previous.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep the certificate files of a volume as they are on disk now, with their mode
+    /// and group, so they can be restored; call after writing them
+    pub async fn remember_certificate_files(&self, mount_path: &str) {
+        for file in CERTIFICATE_FILES {
+            let path = Path::new(mount_path).join(file);
+            match (tokio::fs::read(&path).await, tokio::fs::metadata(&path).await) {
+                (Ok(contents), Ok(metadata)) => {
+                    let written = WrittenFile {
+                        contents: Zeroizing::new(contents),
+                        mode: metadata.permissions().mode(),
+                        gid: metadata.gid(),
+                    };
+                    self.written_files.insert(path, written);
+                }
+                _ => {
+                    self.written_files.remove(&path);
+                }
+            }
+        }
+    }
+
+    /// The file at `path` as the driver last wrote it, unless written before a restart
+    pub fn written_file(&self, path: &Path) -> Option<WrittenFile> {
+        self.written_files.get(path).map(|entry| entry.value().clone())
+    }
+
+    fn forget_certificate_files(&self, mount_path: &str) {
+        for file in CERTIFICATE_FILES {
+            self.written_files.remove(&Path::new(mount_path).join(file));
+        }
+    }
+}
+
+/// Path of the `version`th previous copy of `file`, 0 being the most recent
+fn previous_version_path(mount_path: &str, file: &str, version: usize) -> PathBuf {
+    match version {
+        0 => Path::new(mount_path).join(format!("{}.old", file)),
+        _ => Path::new(mount_path).join(format!("{}.old.{}", file, version)),
+    }
+}
+
+/// Read the validity period (unix timestamps) from a PEM certificate
+pub fn certificate_validity(cert_pem: &str) -> Result<(i64, i64)> {
+    let (_, pem) = parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate PEM: {}", e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))?;
+
+    Ok((cert.validity().not_before.timestamp(), cert.validity().not_after.timestamp()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_registry_survives_restart() {
+        let base_path = TempDir::new("registry").unwrap();
+        let mount_path = base_path.join("mount");
+        std::fs::create_dir_all(&mount_path).unwrap();
+        let mount_path = mount_path.to_string_lossy().to_string();
+
+        let manager = CertificateManager::new(base_path.to_path_buf(), String::new());
+        let pod = PodRef { namespace: "default".to_string(), name: "web".to_string(), uid: None };
+        manager.register_certificate("default-web-vol".to_string(), mount_path.clone(), pod.clone(), ReloadStrategy::Sentinel, 1, 2, None).await;
+
+        let restarted = CertificateManager::new(base_path.to_path_buf(), String::new());
+        assert_eq!(restarted.load_registry().await.unwrap(), 1);
+        let info = &restarted.get_all_certificates()[0];
+        assert_eq!(info.cert_id, "default-web-vol");
+        assert_eq!(info.reload_strategy, ReloadStrategy::Sentinel);
+
+        restarted.unregister_certificate(&mount_path).await;
+        let restarted_again = CertificateManager::new(base_path.to_path_buf(), String::new());
+        assert_eq!(restarted_again.load_registry().await.unwrap(), 0);
+
+        // The certificates of a dual volume go with it, those of a neighbouring volume stay
+        let server_path = format!("{}/server", mount_path);
+        manager.register_certificate("default-web-vol-server".to_string(), server_path, pod.clone(), ReloadStrategy::None, 1, 2, None).await;
+        manager.register_certificate("default-web-vol2".to_string(), format!("{}2", mount_path), pod, ReloadStrategy::None, 1, 2, None).await;
+        assert_eq!(manager.unregister_certificate(&mount_path).await.len(), 2);
+        assert!(manager.get_certificate("default-web-vol2").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_previous_versions_kept_on_update() {
+        let base_path = TempDir::new("versions").unwrap();
+        let mount_path = base_path.to_string_lossy().to_string();
+        let read = |file: &str| std::fs::read_to_string(base_path.join(file)).unwrap();
+
+        let manager = CertificateManager::new(base_path.to_path_buf(), String::new()).with_previous_versions(2);
+        for version in 1..=3 {
+            manager
+                .update_certificate_files(&mount_path, &format!("cert{}", version), &format!("key{}", version))
+                .await
+                .unwrap();
+        }
+        assert_eq!(read("tls.crt"), "cert3");
+        assert_eq!(read("tls.crt.old"), "cert2");
+        assert_eq!(read("tls.key.old.1"), "key1");
+        assert!(!base_path.join("tls.key.old.2").exists());
+
+        manager.remove_certificate_files(&mount_path).await.unwrap();
+        assert_eq!(std::fs::read_dir(&base_path).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_current_key_sent_only_when_asked_over_a_confidential_channel() {
+        let base_path = TempDir::new("current-key").unwrap();
+        let mount_path = base_path.to_string_lossy().to_string();
+
+        let mock = crate::testing::MockCertService::new().unwrap().with_current_key_required();
+        let (addr, tcp_server) = mock.serve().await.unwrap();
+        let socket_path = base_path.join("cert-service.sock");
+        let unix_server = mock.serve_unix(&socket_path).unwrap();
+
+        let manager = CertificateManager::new(base_path.to_path_buf(), format!("unix://{}", socket_path.display()));
+        manager
+            .client
+            .issue_certificate(crate::proto::certservice::IssueCertificateRequest {
+                certificate_id: "web".to_string(),
+                common_name: "web".to_string(),
+                validity_seconds: 3600,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        manager.update_certificate_files(&mount_path, "cert", "key").await.unwrap();
+
+        // Asked for it over a Unix socket, the node retries once with the key
+        manager.renew_certificate("web", &mount_path, 0, false).await.unwrap();
+        assert_eq!((mock.renew_calls(), mock.renewals_with_key()), (2, 1));
+
+        // A revoked certificate is replaced without it
+        manager.renew_certificate("web", &mount_path, 0, true).await.unwrap();
+        assert_eq!((mock.renew_calls(), mock.renewals_with_key()), (3, 1));
+
+        // Over plaintext TCP the node refuses to send it
+        let plaintext = CertificateManager::new(base_path.to_path_buf(), format!("http://{}", addr));
+        let err = plaintext.renew_certificate("web", &mount_path, 0, false).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("plaintext"), "{:#}", err);
+        assert_eq!((mock.renew_calls(), mock.renewals_with_key()), (4, 1));
+
+        tcp_server.abort();
+        unix_server.abort();
     }
 }
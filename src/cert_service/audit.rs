@@ -0,0 +1,219 @@
+use anyhow::{Result, Context};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, error, warn};
+
+/// Signing operation recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    Issue,
+    Renew,
+    Revoke,
+    /// Issuance of a node intermediate CA
+    #[serde(rename = "issue_node_intermediate")]
+    IssueNodeIntermediate,
+}
+
+/// Result of an audited operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    /// Rejected by the namespace policy, a profile or a policy rule
+    Denied,
+    Failed,
+}
+
+/// Who asked for the operation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Requester {
+    /// Address of the gRPC peer (the node's CSI driver)
+    pub peer: Option<String>,
+    pub namespace: Option<String>,
+    pub pod: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<String>,
+    /// Pod labels the node driver is configured to record (`METADATA_LABELS`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Requester {
+    /// Fill in the workload from the metadata the node driver sent with the request
+    pub fn with_metadata(mut self, metadata: &HashMap<String, String>) -> Self {
+        self.namespace = metadata.get("namespace").cloned();
+        self.pod = metadata.get("pod").cloned();
+        self.pod_uid = metadata.get("pod_uid").cloned();
+        self.service_account = metadata.get("service_account").cloned();
+        self.labels = metadata
+            .iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix("label.")?.to_string(), value.clone())))
+            .collect();
+        self
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub operation: AuditOperation,
+    pub outcome: AuditOutcome,
+    pub certificate_id: String,
+    pub requester: Requester,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns_names: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ip_addresses: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Serial number of the issued certificate (lowercase hex)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(operation: AuditOperation, certificate_id: &str, peer: Option<SocketAddr>) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339(),
+            operation,
+            outcome: AuditOutcome::Success,
+            certificate_id: certificate_id.to_string(),
+            requester: Requester {
+                peer: peer.map(|addr| addr.to_string()),
+                ..Default::default()
+            },
+            common_name: None,
+            dns_names: vec![],
+            ip_addresses: vec![],
+            profile: None,
+            serial: None,
+            not_after: None,
+            revocation_reason: None,
+            error: None,
+        }
+    }
+
+    /// Set the outcome from the gRPC status the operation ended with
+    pub fn with_status(mut self, status: Option<&tonic::Status>) -> Self {
+        if let Some(status) = status {
+            self.outcome = match status.code() {
+                tonic::Code::PermissionDenied => AuditOutcome::Denied,
+                _ => AuditOutcome::Failed,
+            };
+            self.error = Some(status.message().to_string());
+        }
+        self
+    }
+}
+
+/// Append-only record of every signing operation, for compliance evidence
+///
+/// Records are written as JSON lines to a file and/or posted to a webhook. A record
+/// that cannot be written is logged as an error but does not fail the operation.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    webhook: Option<(reqwest::Client, String)>,
+}
+
+impl AuditLog {
+    pub async fn new(file_path: Option<String>, webhook_url: Option<String>) -> Result<Self> {
+        let file = match &file_path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .context(format!("Failed to open audit log {}", path))?;
+                info!("Writing audit log to {}", path);
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+
+        let webhook = match webhook_url {
+            Some(url) => {
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .connect_timeout(Duration::from_secs(5))
+                    .build()
+                    .context("Failed to build audit webhook client")?;
+                info!("Posting audit records to {}", url);
+                Some((client, url))
+            }
+            None => None,
+        };
+
+        Ok(Self { file, webhook })
+    }
+
+    /// Record an operation
+    pub async fn record(&self, record: AuditRecord) {
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit record for {}: {}", record.certificate_id, e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().await;
+            let written = async {
+                file.write_all(line.as_bytes()).await?;
+                file.sync_data().await
+            }.await;
+            if let Err(e) = written {
+                error!("Failed to write audit record for {}: {}", record.certificate_id, e);
+            }
+        }
+
+        if let Some((client, url)) = &self.webhook {
+            // Posted in the background so a slow sink does not delay issuance
+            let request = client.post(url).json(&record);
+            tokio::spawn(async move {
+                match request.send().await.and_then(|response| response.error_for_status()) {
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to post audit record for {}: {}", record.certificate_id, e),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_outcome_from_status() {
+        let denied = AuditRecord::new(AuditOperation::Issue, "ns-pod-vol", None)
+            .with_status(Some(&tonic::Status::permission_denied("namespace not allowed")));
+        assert_eq!(denied.outcome, AuditOutcome::Denied);
+
+        let json = serde_json::to_value(&denied).unwrap();
+        assert_eq!(json["operation"], "issue");
+        assert_eq!(json["outcome"], "denied");
+        assert_eq!(json["error"], "namespace not allowed");
+        assert!(json.get("serial").is_none());
+    }
+}
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use k8s_openapi::api::authentication::v1::{TokenReview, TokenReviewSpec};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, PostParams};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+use tracing::{debug, warn};
+
+use crate::error_details::ErrorReason;
+
+/// Audience of the service account tokens callers present, unless configured otherwise
+pub const DEFAULT_TOKEN_AUDIENCE: &str = "cacsi-service";
+
+/// Extra of bound service account tokens naming the node of the pod they were issued to (Kubernetes 1.30+)
+const NODE_NAME_EXTRA: &str = "authentication.kubernetes.io/node-name";
+
+/// How long the outcome of a token review is reused
+const REVIEW_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Outcome of a token review and when it was made
+type Review = (Option<Caller>, Instant);
+
+/// A caller authenticated by its service account token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    /// Kubernetes user name, e.g. `system:serviceaccount:cacsi:cacsi-driver`
+    pub username: String,
+    /// Node of the pod the token is bound to, if it is bound to one
+    pub node: Option<String>,
+}
+
+/// The Kubernetes API calls authentication makes, replaced in tests
+#[async_trait]
+pub trait ClusterApi: Send + Sync {
+    /// Review `token` for `audience`; `None` when it does not authenticate anyone
+    async fn review_token(&self, token: &str, audience: &str) -> Result<Option<Caller>>;
+
+    /// Node the pod `namespace/name` is scheduled on; `None` when there is no such pod or it is not scheduled
+    async fn pod_node(&self, namespace: &str, name: &str) -> Result<Option<String>>;
+}
+
+/// [`ClusterApi`] backed by the Kubernetes API server
+pub struct KubernetesApi;
+
+#[async_trait]
+impl ClusterApi for KubernetesApi {
+    async fn review_token(&self, token: &str, audience: &str) -> Result<Option<Caller>> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+        let reviews: Api<TokenReview> = Api::all(client);
+        let review = TokenReview {
+            spec: TokenReviewSpec {
+                token: Some(token.to_string()),
+                audiences: Some(vec![audience.to_string()]),
+            },
+            ..Default::default()
+        };
+        let params = PostParams::default();
+        let review = crate::k8s_client::with_retries(|| reviews.create(&params, &review))
+            .await
+            .context("Failed to review service account token")?;
+
+        let Some(status) = review.status.filter(|status| status.authenticated == Some(true)) else {
+            return Ok(None);
+        };
+        if !status.audiences.iter().flatten().any(|a| a == audience) {
+            return Ok(None);
+        }
+        let Some(user) = status.user else {
+            return Ok(None);
+        };
+        let node = user
+            .extra
+            .as_ref()
+            .and_then(|extra| extra.get(NODE_NAME_EXTRA))
+            .and_then(|nodes| nodes.first().cloned());
+        Ok(user.username.map(|username| Caller { username, node }))
+    }
+
+    async fn pod_node(&self, namespace: &str, name: &str) -> Result<Option<String>> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+        let pods: Api<Pod> = Api::namespaced(client, namespace);
+        let pod = crate::k8s_client::with_retries(|| pods.get_opt(name))
+            .await
+            .context(format!("Failed to get pod {}/{}", namespace, name))?;
+        Ok(pod.and_then(|pod| pod.spec).and_then(|spec| spec.node_name))
+    }
+}
+
+/// Authenticates callers of the certificate service by Kubernetes service account tokens
+///
+/// Callers send a token for the configured audience, e.g. a projected service account
+/// token, as `authorization: Bearer <token>` metadata; the API server reviews it. Node
+/// drivers run as one of `node_service_accounts`, and their token must be bound to a pod,
+/// which names the node the driver runs on. They act for the pods on that node; any other
+/// service account only for its own namespace. Only `admin_service_accounts` may make
+/// administrative calls, which span namespaces.
+pub struct Authenticator {
+    api: Arc<dyn ClusterApi>,
+    audience: String,
+    /// User names of the node driver service accounts
+    node_service_accounts: Vec<String>,
+    /// User names of the service accounts allowed to make administrative calls
+    admin_service_accounts: Vec<String>,
+    /// Review outcomes by SHA-256 of the token
+    reviews: Mutex<HashMap<[u8; 32], Review>>,
+}
+
+impl Authenticator {
+    pub fn new(api: Arc<dyn ClusterApi>, audience: String, node_service_accounts: Vec<String>) -> Self {
+        Self {
+            api,
+            audience,
+            node_service_accounts,
+            admin_service_accounts: Vec::new(),
+            reviews: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allow the service accounts with user names `admin_service_accounts` to make administrative calls
+    pub fn with_admins(mut self, admin_service_accounts: Vec<String>) -> Self {
+        self.admin_service_accounts = admin_service_accounts;
+        self
+    }
+
+    /// The caller presenting the token in `metadata`; UNAUTHENTICATED without a valid one
+    pub async fn caller(&self, metadata: &MetadataMap) -> Result<Caller, Status> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| unauthenticated("Call carries no bearer token"))?;
+
+        let key: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        if let Some((caller, _)) = self.reviews.lock().unwrap().get(&key).filter(|(_, at)| at.elapsed() < REVIEW_CACHE_TTL) {
+            return caller.clone().ok_or_else(|| unauthenticated("Token is not valid for the certificate service"));
+        }
+
+        let caller = self.api.review_token(token, &self.audience).await.map_err(|e| {
+            warn!("Failed to authenticate caller: {:#}", e);
+            Status::unavailable(format!("Failed to authenticate caller: {}", e))
+        })?;
+        debug!("Token review: {:?}", caller);
+
+        let mut reviews = self.reviews.lock().unwrap();
+        reviews.retain(|_, (_, at)| at.elapsed() < REVIEW_CACHE_TTL);
+        reviews.insert(key, (caller.clone(), Instant::now()));
+        caller.ok_or_else(|| unauthenticated("Token is not valid for the certificate service"))
+    }
+
+    /// The node of the node driver making the call; PERMISSION_DENIED for other callers
+    pub async fn node(&self, metadata: &MetadataMap) -> Result<String, Status> {
+        let caller = self.caller(metadata).await?;
+        if !self.node_service_accounts.contains(&caller.username) {
+            return Err(not_allowed(format!("{} is not a node driver", caller.username)));
+        }
+        caller.node.clone().ok_or_else(|| unbound(&caller))
+    }
+
+    /// The user name of the administrator making the call; PERMISSION_DENIED for other callers
+    pub async fn admin(&self, metadata: &MetadataMap) -> Result<String, Status> {
+        let caller = self.caller(metadata).await?;
+        if !self.admin_service_accounts.contains(&caller.username) {
+            return Err(not_allowed(format!("{} is not an administrator", caller.username)));
+        }
+        Ok(caller.username)
+    }
+
+    /// Whether a node driver makes the call and the pod `namespace/pod` runs on no node
+    ///
+    /// Node drivers revoke the certificates of deleted pods, which the pod check of
+    /// [`Authenticator::namespace`] no longer lets them call for.
+    pub async fn is_node_driver_for_gone_pod(&self, metadata: &MetadataMap, namespace: &str, pod: &str) -> bool {
+        if namespace.is_empty() || pod.is_empty() || self.node(metadata).await.is_err() {
+            return false;
+        }
+        matches!(self.api.pod_node(namespace, pod).await, Ok(None))
+    }
+
+    /// The namespace a call for the pod `namespace/pod` is made in, as far as the caller may make it
+    ///
+    /// A node driver may call for pods scheduled on its node. Any other service account
+    /// calls for its own namespace, which an empty `namespace` is taken to be.
+    pub async fn namespace(&self, metadata: &MetadataMap, namespace: &str, pod: &str) -> Result<String, Status> {
+        let caller = self.caller(metadata).await?;
+        if self.node_service_accounts.contains(&caller.username) {
+            if namespace.is_empty() || pod.is_empty() {
+                return Err(not_allowed(format!("{} did not name the pod it calls for", caller.username)));
+            }
+            let node = caller.node.clone().ok_or_else(|| unbound(&caller))?;
+            let scheduled = self.api.pod_node(namespace, pod).await.map_err(|e| {
+                warn!("Failed to look up the node of pod {}/{}: {:#}", namespace, pod, e);
+                Status::unavailable(format!("Failed to look up the node of pod {}/{}: {}", namespace, pod, e))
+            })?;
+            if scheduled.as_deref() != Some(node.as_str()) {
+                warn!("Driver on node {} called for pod {}/{} on node {:?}", node, namespace, pod, scheduled);
+                return Err(not_allowed(format!("Pod {}/{} does not run on node {}", namespace, pod, node)));
+            }
+            return Ok(namespace.to_string());
+        }
+
+        let own = caller
+            .username
+            .strip_prefix("system:serviceaccount:")
+            .and_then(|rest| rest.split_once(':'))
+            .map(|(own, _)| own)
+            .ok_or_else(|| not_allowed(format!("{} is not a service account", caller.username)))?;
+        if !namespace.is_empty() && namespace != own {
+            return Err(not_allowed(format!("{} may not call for namespace {}", caller.username, namespace)));
+        }
+        Ok(own.to_string())
+    }
+}
+
+/// PERMISSION_DENIED for a node driver whose token names no node
+fn unbound(caller: &Caller) -> Status {
+    not_allowed(format!("Token of {} is not bound to a pod on a node", caller.username))
+}
+
+/// Kubernetes user names of `namespace/name` service accounts
+pub fn service_account_usernames(service_accounts: &[String]) -> Result<Vec<String>> {
+    service_accounts
+        .iter()
+        .map(|entry| match entry.trim().split_once('/') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Ok(format!("system:serviceaccount:{}:{}", namespace, name))
+            }
+            _ => Err(anyhow::anyhow!("Invalid service account '{}', expected <namespace>/<name>", entry)),
+        })
+        .collect()
+}
+
+fn unauthenticated(message: &str) -> Status {
+    ErrorReason::CallerNotAllowed.status(Code::Unauthenticated, message)
+}
+
+fn not_allowed(message: String) -> Status {
+    ErrorReason::CallerNotAllowed.status(Code::PermissionDenied, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bearer_metadata, MockClusterApi};
+
+    #[tokio::test]
+    async fn test_node_callers() {
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("node-token", driver, Some("node-1"))
+                .with_token("unbound-token", driver, None)
+                .with_token("app-token", "system:serviceaccount:default:web", Some("node-1")),
+        );
+        let authenticator = Authenticator::new(api.clone(), DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()]);
+
+        assert_eq!(authenticator.node(&bearer_metadata("node-token")).await.unwrap(), "node-1");
+        assert_eq!(authenticator.node(&bearer_metadata("node-token")).await.unwrap(), "node-1");
+        assert_eq!(api.reviews(), 1);
+
+        let code = |result: Result<String, Status>| result.unwrap_err().code();
+        assert_eq!(code(authenticator.node(&MetadataMap::new()).await), Code::Unauthenticated);
+        assert_eq!(code(authenticator.node(&bearer_metadata("forged")).await), Code::Unauthenticated);
+        assert_eq!(code(authenticator.node(&bearer_metadata("app-token")).await), Code::PermissionDenied);
+        assert_eq!(code(authenticator.node(&bearer_metadata("unbound-token")).await), Code::PermissionDenied);
+
+        assert_eq!(api.reviews(), 4);
+        assert_eq!(
+            service_account_usernames(&["cacsi/cacsi-driver".to_string()]).unwrap(),
+            vec![driver]
+        );
+        assert!(service_account_usernames(&["cacsi-driver".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_caller_namespaces() {
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("node-token", driver, Some("node-1"))
+                .with_token("app-token", "system:serviceaccount:team-a:web", None)
+                .with_token("user-token", "alice", None)
+                .with_pod("team-a", "web-0", "node-1")
+                .with_pod("team-b", "api-0", "node-2"),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()]);
+        let namespace = |token: &str, namespace: &str, pod: &str| {
+            let metadata = bearer_metadata(token);
+            let (namespace, pod) = (namespace.to_string(), pod.to_string());
+            let authenticator = &authenticator;
+            async move { authenticator.namespace(&metadata, &namespace, &pod).await }
+        };
+
+        // Drivers call for the pods on their node
+        assert_eq!(namespace("node-token", "team-a", "web-0").await.unwrap(), "team-a");
+        assert_eq!(namespace("node-token", "team-b", "api-0").await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(namespace("node-token", "team-a", "gone-0").await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(namespace("node-token", "", "").await.unwrap_err().code(), Code::PermissionDenied);
+
+        // Other service accounts only for their own namespace, whatever they claim
+        assert_eq!(namespace("app-token", "", "").await.unwrap(), "team-a");
+        assert_eq!(namespace("app-token", "team-a", "web-0").await.unwrap(), "team-a");
+        assert_eq!(namespace("app-token", "team-b", "api-0").await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(namespace("user-token", "team-a", "").await.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(namespace("forged", "team-a", "web-0").await.unwrap_err().code(), Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use rcgen::{CertificateParams, CrlDistributionPoint, CustomExtension};
+use yasna::models::ObjectIdentifier;
+
+/// Authority Information Access extension (RFC 5280 4.2.2.1)
+const AUTHORITY_INFO_ACCESS_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+
+/// AIA access method for OCSP responders
+const OCSP_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1];
+
+/// AIA access method for the issuing CA certificate
+const CA_ISSUERS_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 2];
+
+/// Where relying parties find revocation information and the issuing CA
+///
+/// Embedded in every issued certificate as the CRL distribution points and
+/// Authority Information Access extensions.
+#[derive(Debug, Clone, Default)]
+pub struct IssuerUrls {
+    pub crl: Vec<String>,
+    pub ocsp: Vec<String>,
+    pub ca_issuers: Vec<String>,
+}
+
+impl IssuerUrls {
+    /// Parse comma-separated URL lists, rejecting anything that is not an absolute URL
+    pub fn parse(crl: &str, ocsp: &str, ca_issuers: &str) -> Result<Self> {
+        fn urls(value: &str) -> Result<Vec<String>> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|u| !u.is_empty())
+                .map(|u| {
+                    reqwest::Url::parse(u)
+                        .map(|_| u.to_string())
+                        .map_err(|e| anyhow::anyhow!("Invalid URL '{}': {}", u, e))
+                })
+                .collect()
+        }
+
+        Ok(Self {
+            crl: urls(crl)?,
+            ocsp: urls(ocsp)?,
+            ca_issuers: urls(ca_issuers)?,
+        })
+    }
+
+    /// Add the CRL distribution points and AIA extensions to `params`
+    pub fn apply(&self, params: &mut CertificateParams) {
+        if !self.crl.is_empty() {
+            // A single distribution point; its URIs are alternative locations of the same CRL
+            params.crl_distribution_points = vec![CrlDistributionPoint { uris: self.crl.clone() }];
+        }
+
+        if self.ocsp.is_empty() && self.ca_issuers.is_empty() {
+            return;
+        }
+
+        let access_descriptions = self.ocsp
+            .iter()
+            .map(|url| (OCSP_OID, url))
+            .chain(self.ca_issuers.iter().map(|url| (CA_ISSUERS_OID, url)));
+
+        let content = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                for (method, url) in access_descriptions {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_oid(&ObjectIdentifier::from_slice(method));
+                        // GeneralName uniformResourceIdentifier [6] IA5String
+                        writer.next().write_tagged_implicit(yasna::Tag::context(6), |writer| {
+                            writer.write_ia5_string(url);
+                        });
+                    });
+                }
+            });
+        });
+
+        params.custom_extensions.push(CustomExtension::from_oid_content(AUTHORITY_INFO_ACCESS_OID, content));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::extensions::{DistributionPointName, GeneralName, ParsedExtension};
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    #[test]
+    fn test_issuer_urls_are_embedded() {
+        let urls = IssuerUrls::parse(
+            "http://ca.example.com/ca.crl",
+            "http://ca.example.com/ocsp",
+            "http://ca.example.com/ca.crt",
+        ).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        urls.apply(&mut params);
+        let cert = params.self_signed(&key).unwrap();
+        let (_, parsed) = X509Certificate::from_der(cert.der()).unwrap();
+
+        let mut found = (false, false);
+        for extension in parsed.extensions() {
+            match extension.parsed_extension() {
+                ParsedExtension::AuthorityInfoAccess(aia) => {
+                    let locations: Vec<_> = aia.accessdescs.iter().map(|desc| &desc.access_location).collect();
+                    assert!(matches!(locations[..], [
+                        GeneralName::URI("http://ca.example.com/ocsp"),
+                        GeneralName::URI("http://ca.example.com/ca.crt"),
+                    ]));
+                    found.0 = true;
+                }
+                ParsedExtension::CRLDistributionPoints(points) => {
+                    assert!(matches!(
+                        &points.points[0].distribution_point,
+                        Some(DistributionPointName::FullName(names))
+                            if matches!(names[..], [GeneralName::URI("http://ca.example.com/ca.crl")])
+                    ));
+                    found.1 = true;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(found, (true, true));
+
+        assert!(IssuerUrls::parse("ca.example.com/ca.crl", "", "").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Result, Context};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use kube::api::PostParams;
+use kube::Api;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How long a leader holds the lease without renewing it, as in client-go
+pub const DEFAULT_LEASE_DURATION_SECONDS: u64 = 15;
+
+/// Lease-based leader election between certificate service replicas
+///
+/// The certificate inventory lives in memory, so only one replica serves at a time;
+/// the others stand by and take over once the leader's lease expires.
+#[derive(Clone)]
+pub struct LeaderElection {
+    leases: Api<Lease>,
+    lease_name: String,
+    identity: String,
+    lease_duration: Duration,
+    leader: Arc<watch::Sender<bool>>,
+    /// The lease spec as last read, and when it last changed on the local clock
+    observed: Arc<Mutex<Option<(LeaseSpec, Instant)>>>,
+}
+
+impl LeaderElection {
+    pub async fn new(lease_name: String, namespace: String, identity: String, lease_duration: Duration) -> Result<Self> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        Ok(Self {
+            leases: Api::namespaced(client, &namespace),
+            lease_name,
+            identity,
+            lease_duration,
+            leader: Arc::new(watch::Sender::new(false)),
+            observed: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Whether this replica currently holds the lease
+    pub fn is_leader(&self) -> bool {
+        *self.leader.borrow()
+    }
+
+    /// Receiver notified when this replica gains or loses the lease
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.leader.subscribe()
+    }
+
+    /// Acquire and renew the lease until `shutdown` is cancelled, then release it
+    ///
+    /// Attempts run every third of the lease duration. A leader that could not renew
+    /// for two thirds of it steps down before anyone else may take over.
+    pub async fn run(&self, shutdown: CancellationToken) {
+        let retry_period = self.lease_duration / 3;
+        let renew_deadline = self.lease_duration * 2 / 3;
+        let mut last_renewed: Option<Instant> = None;
+
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => last_renewed = Some(Instant::now()),
+                Ok(false) => last_renewed = None,
+                Err(e) => warn!("Failed to update leader lease {}: {:#}", self.lease_name, e),
+            }
+            let leader = last_renewed.is_some_and(|renewed| renewed.elapsed() < renew_deadline);
+            self.set_leader(leader);
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(retry_period) => {}
+            }
+        }
+
+        // Hand over right away instead of letting the standby wait for the lease to expire
+        if self.is_leader() {
+            self.set_leader(false);
+            match self.release().await {
+                Ok(()) => info!("Released leader lease {}", self.lease_name),
+                Err(e) => warn!("Failed to release leader lease {}: {:#}", self.lease_name, e),
+            }
+        }
+    }
+
+    fn set_leader(&self, leader: bool) {
+        let changed = self.leader.send_if_modified(|current| std::mem::replace(current, leader) != leader);
+        if changed && leader {
+            info!("Acquired leader lease {} as {}; serving requests", self.lease_name, self.identity);
+        } else if changed {
+            warn!("Lost leader lease {}; standing by", self.lease_name);
+        }
+    }
+
+    /// Take or renew the lease, returning whether this replica holds it
+    ///
+    /// Writes carry the lease's resourceVersion, so of two replicas racing for an
+    /// expired lease only one succeeds.
+    async fn try_acquire_or_renew(&self) -> Result<bool> {
+        let now = Utc::now();
+
+        let Some(mut lease) = self.leases.get_opt(&self.lease_name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.lease_name.clone()),
+                    ..Default::default()
+                },
+                spec: next_spec(None, &self.identity, now, self.lease_duration, Duration::ZERO),
+            };
+            return match self.leases.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(true),
+                Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+                Err(e) => Err(e.into()),
+            };
+        };
+
+        let unchanged_for = self.observe(lease.spec.as_ref());
+        let Some(spec) = next_spec(lease.spec.as_ref(), &self.identity, now, self.lease_duration, unchanged_for) else {
+            return Ok(false);
+        };
+        lease.spec = Some(spec);
+
+        match self.leases.replace(&self.lease_name, &PostParams::default(), &lease).await {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// How long the lease has been read with `spec` unchanged, on the local clock
+    ///
+    /// Another holder's renewTime is written with its own clock, so expiry is judged by
+    /// when this replica last saw the lease change instead, as client-go does.
+    fn observe(&self, spec: Option<&LeaseSpec>) -> Duration {
+        let spec = spec.cloned().unwrap_or_default();
+        let mut observed = self.observed.lock().unwrap();
+        match observed.as_ref() {
+            Some((last, at)) if *last == spec => at.elapsed(),
+            _ => {
+                *observed = Some((spec, Instant::now()));
+                Duration::ZERO
+            }
+        }
+    }
+
+    /// Give up the lease if this replica still holds it
+    async fn release(&self) -> Result<()> {
+        let mut lease = self.leases.get(&self.lease_name).await?;
+        let Some(spec) = lease.spec.as_mut() else {
+            return Ok(());
+        };
+        if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return Ok(());
+        }
+
+        spec.holder_identity = None;
+        spec.renew_time = None;
+        self.leases.replace(&self.lease_name, &PostParams::default(), &lease).await?;
+        Ok(())
+    }
+}
+
+/// The lease spec after `identity` takes or renews the lease at `now`, or `None` while
+/// another holder's lease is still valid
+///
+/// Another holder's lease expires once it has gone `unchanged_for` its duration without
+/// a renewal; its renewTime is never compared with the local clock.
+fn next_spec(
+    current: Option<&LeaseSpec>,
+    identity: &str,
+    now: DateTime<Utc>,
+    lease_duration: Duration,
+    unchanged_for: Duration,
+) -> Option<LeaseSpec> {
+    let lease_duration_seconds = lease_duration.as_secs() as i32;
+    let mut spec = current.cloned().unwrap_or_default();
+
+    let held_by_us = spec.holder_identity.as_deref() == Some(identity);
+    if !held_by_us {
+        let expired = match (&spec.holder_identity, &spec.renew_time) {
+            (Some(_), Some(_)) => {
+                let duration = spec.lease_duration_seconds.unwrap_or(lease_duration_seconds);
+                unchanged_for.as_secs() >= duration.max(0) as u64
+            }
+            _ => true,
+        };
+        if !expired {
+            return None;
+        }
+
+        spec.holder_identity = Some(identity.to_string());
+        spec.acquire_time = Some(MicroTime(now));
+        spec.lease_transitions = Some(spec.lease_transitions.unwrap_or(0) + 1);
+    }
+
+    spec.lease_duration_seconds = Some(lease_duration_seconds);
+    spec.renew_time = Some(MicroTime(now));
+    Some(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_spec_respects_live_leases() {
+        let duration = Duration::from_secs(15);
+        let start = Utc::now();
+
+        let ours = next_spec(None, "a", start, duration, Duration::ZERO).unwrap();
+        assert_eq!(ours.holder_identity.as_deref(), Some("a"));
+        assert_eq!(ours.lease_transitions, Some(1));
+
+        // Others wait for the lease to expire; the holder renews it
+        let seen = Duration::from_secs(10);
+        assert!(next_spec(Some(&ours), "b", start + chrono::Duration::seconds(10), duration, seen).is_none());
+        let renewed = next_spec(Some(&ours), "a", start + chrono::Duration::seconds(10), duration, seen).unwrap();
+        assert_eq!(renewed.lease_transitions, Some(1));
+        assert_eq!(renewed.acquire_time, ours.acquire_time);
+
+        let taken = next_spec(Some(&renewed), "b", start + chrono::Duration::seconds(25), duration, duration).unwrap();
+        assert_eq!(taken.holder_identity.as_deref(), Some("b"));
+        assert_eq!(taken.lease_transitions, Some(2));
+
+        // A released lease is free right away
+        let released = LeaseSpec { holder_identity: None, renew_time: None, ..taken };
+        assert_eq!(next_spec(Some(&released), "a", start, duration, Duration::ZERO).unwrap().holder_identity.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_next_spec_ignores_the_holders_clock() {
+        let duration = Duration::from_secs(15);
+        let start = Utc::now();
+        let ours = next_spec(None, "a", start, duration, Duration::ZERO).unwrap();
+
+        // A holder whose clock runs an hour behind still holds a lease it keeps renewing
+        let an_hour_later = start + chrono::Duration::hours(1);
+        assert!(next_spec(Some(&ours), "b", an_hour_later, duration, Duration::from_secs(5)).is_none());
+        // One running ahead loses it once it stops renewing
+        let an_hour_earlier = start - chrono::Duration::hours(1);
+        assert!(next_spec(Some(&ours), "b", an_hour_earlier, duration, duration).is_some());
+    }
+}
@@ -1,67 +1,276 @@
-use anyhow::Result;
-use std::env;
+use anyhow::{Result, Context};
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::signal;
-use tonic::transport::Server;
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tracing::{info, error, warn};
 
-mod service;
+use cacsi_driver::cert_service::{auth, leader_election, request_log, signer};
+use cacsi_driver::config::{self, LogFilter, ServiceConfig};
+use cacsi_driver::shutdown::shutdown_signal;
+use cacsi_driver::{dev_ca, k8s_client, metrics, proto};
 
-// Include generated protobuf code
-pub mod proto {
-    pub mod certservice {
-        tonic::include_proto!("certservice.v1");
-    }
-}
+/// gRPC service name reported through the health service
+const CERTIFICATE_SERVICE_NAME: &str = "certservice.v1.CertificateService";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // Flags override environment variables, which override the config file
+    let mut config: ServiceConfig = config::load()?;
+    config.validate()?;
+    let log_filter = LogFilter::init(config.log_level.as_deref())?;
 
     info!("Starting Certificate Service");
 
-    // Get configuration from environment variables
-    let listen_addr = env::var("LISTEN_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:50051".to_string());
-    let ca_secret_name = env::var("CA_SECRET_NAME")
-        .unwrap_or_else(|_| "csi-ca-secret".to_string());
-    let ca_secret_namespace = env::var("CA_SECRET_NAMESPACE")
-        .unwrap_or_else(|_| "kube-system".to_string());
+    let dev_mode = config.dev_mode;
+    let listen_addr = config.listen_addr();
+    let leader_election_namespace = config.leader_election_namespace();
+    let pod_name = config.pod_name();
+    let metrics_addr = config.metrics_addr.clone();
+    let namespace_ca_secrets = config.namespace_ca_secrets()?;
+    let node_service_accounts = config.node_service_accounts()?;
+    let admin_service_accounts = config.admin_service_accounts()?;
+    let tls_files = config.tls_cert_file.clone().zip(config.tls_key_file.clone());
+    let token_audience = config.token_audience.clone();
+    // Compared with reloaded configurations
+    let initial_config = config.clone();
+    let dev_ca_dir = config.dev_ca_dir;
+    let ca_secret_name = config.ca_secret_name;
+    let ca_secret_namespace = config.ca_secret_namespace;
+    let ca_key_passphrase_secret = config.ca_key_passphrase_secret;
+    let signer_backend = config.signer_backend;
+    let settings = config.settings;
+    let leader_election_enabled = config.leader_election;
+    let leader_election_lease = config.leader_election_lease_name;
+    let leader_election_lease_duration = std::time::Duration::from_secs(config.leader_election_lease_duration_seconds);
+    let kube_api = config.kube_api.api_settings();
+    k8s_client::configure(kube_api);
 
     info!("Configuration:");
     info!("  Listen Address: {}", listen_addr);
-    info!("  CA Secret: {}/{}", ca_secret_namespace, ca_secret_name);
+    if let Some(addr) = &metrics_addr {
+        info!("  Metrics Address: {}", addr);
+    }
+    if dev_mode {
+        info!("  Dev Mode: enabled (no Kubernetes), CA: {}", dev_ca_dir.as_deref().unwrap_or("in memory"));
+    } else {
+        info!("  CA Secret: {}/{}", ca_secret_namespace, ca_secret_name);
+        info!(
+            "  Kubernetes API: timeout {:?}, {} QPS (burst {}), {} attempts",
+            kube_api.timeout, kube_api.qps, kube_api.burst, kube_api.retry.max_attempts
+        );
+    }
+    for (namespace, secret) in &namespace_ca_secrets {
+        info!("  CA Secret of namespace {}: {}/{}", namespace, ca_secret_namespace, secret);
+    }
+    if let Some(name) = &ca_key_passphrase_secret {
+        info!("  CA Key Passphrase Secret: {}/{}", ca_secret_namespace, name);
+    }
+    info!("  Signer Backend: {}", signer_backend);
+    if let Some((cert_file, _)) = &tls_files {
+        info!("  TLS Certificate: {}", cert_file.display());
+    }
+    if !node_service_accounts.is_empty() {
+        info!("  Node Service Accounts: {} (token audience {})", node_service_accounts.join(", "), token_audience);
+    }
+    if !admin_service_accounts.is_empty() {
+        info!("  Admin Service Accounts: {}", admin_service_accounts.join(", "));
+    }
+    settings.log();
+    if leader_election_enabled {
+        info!(
+            "  Leader Election: lease {}/{} ({:?}) as {}",
+            leader_election_namespace, leader_election_lease, leader_election_lease_duration, pod_name
+        );
+    }
+
+    // Checked by ServiceConfig::validate
+    let addr: SocketAddr = listen_addr.parse()?;
+
+    // Stop on SIGTERM or Ctrl-C
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // Create the signing backend
+    let signer: Arc<dyn signer::Signer> = match signer_backend.as_str() {
+        "local" if dev_mode => {
+            let (ca_cert, ca_key) = dev_ca::dev_ca(dev_ca_dir.as_deref().map(std::path::Path::new))?;
+            Arc::new(signer::LocalSigner::in_memory(ca_cert, &ca_key)?)
+        }
+        "local" => local_signer(ca_secret_name, &ca_secret_namespace, ca_key_passphrase_secret.clone()).await?,
+        "step-ca" => Arc::new(signer::StepCaSigner::new(signer::StepCaConfig::from_env()?)?),
+        "est" => Arc::new(signer::EstSigner::new(signer::EstConfig::from_env()?)?),
+        "kms" => Arc::new(signer::KmsSigner::new(signer::KmsConfig::from_env()?).await?),
+        other => unreachable!("SIGNER_BACKEND '{}' passed validation", other),
+    };
 
-    // Parse listen address
-    let addr: SocketAddr = listen_addr
-        .parse()
-        .expect("Invalid listen address");
+    // Namespaces with their own CA secret get their own local signer
+    let mut namespace_signers: HashMap<String, Arc<dyn signer::Signer>> = HashMap::new();
+    for (namespace, secret) in namespace_ca_secrets {
+        let signer = local_signer(secret, &ca_secret_namespace, ca_key_passphrase_secret.clone())
+            .await
+            .context(format!("Failed to load the CA of namespace {}", namespace))?;
+        namespace_signers.insert(namespace, signer);
+    }
+
+    // Tenants with their own CA secret get a local signer per secret; tenants added
+    // later with a new CA secret need a restart
+    let tenancy = settings.tenancy().await?;
+    let mut tenant_signers: HashMap<String, Arc<dyn signer::Signer>> = HashMap::new();
+    if let Some(tenancy) = &tenancy {
+        for tenant in tenancy.tenants().await {
+            let Some(secret) = &tenant.ca_secret else {
+                continue;
+            };
+            if signer_backend != "local" {
+                anyhow::bail!("Tenant {} has its own CA secret, which requires SIGNER_BACKEND=local", tenant.name);
+            }
+            if !tenant_signers.contains_key(secret) {
+                let signer = local_signer(secret.clone(), &ca_secret_namespace, ca_key_passphrase_secret.clone())
+                    .await
+                    .context(format!("Failed to load the CA of tenant {}", tenant.name))?;
+                tenant_signers.insert(secret.clone(), signer);
+            }
+        }
+    }
 
     // Create certificate service
-    let cert_service = service::CertificateServiceImpl::new(
-        ca_secret_name,
-        ca_secret_namespace,
-    ).await?;
+    let mut cert_service = settings.build(signer, namespace_signers, tenancy, tenant_signers).await?;
+    if !node_service_accounts.is_empty() {
+        let authenticator = auth::Authenticator::new(Arc::new(auth::KubernetesApi), token_audience, node_service_accounts)
+            .with_admins(admin_service_accounts);
+        cert_service = cert_service.with_authenticator(Arc::new(authenticator));
+    }
+    if let Some(addr) = &metrics_addr {
+        // Checked by ServiceConfig::validate
+        let addr: SocketAddr = addr.parse()?;
+        let metrics = metrics::Metrics::new();
+        cert_service = cert_service.with_request_metrics(metrics.clone());
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(addr).await {
+                error!("Metrics endpoint error: {:#}", e);
+            }
+        });
+    }
+
+    // Apply changes of the config file or on SIGHUP that do not need a restart
+    tokio::spawn({
+        let mut current = initial_config;
+        let issuance_settings = cert_service.settings_handle();
+        config::watch(move |mut config: ServiceConfig| {
+            config.validate()?;
+            issuance_settings.send_replace(config.settings.issuance_settings()?);
+            log_filter.set(config.log_level.as_deref())?;
+            if config.needs_restart(&current) {
+                warn!("Configuration reloaded; changed settings other than the log level and issuance settings take effect after a restart");
+            } else {
+                info!("Configuration reloaded");
+            }
+            current = config;
+            Ok(())
+        })
+    });
+
+    // Standby replicas report not serving, so the Service only routes to the leader
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    let election = match leader_election_enabled {
+        true => Some(leader_election::LeaderElection::new(
+            leader_election_lease,
+            leader_election_namespace,
+            pod_name,
+            leader_election_lease_duration,
+        ).await?),
+        false => None,
+    };
+    let election_handle = election.clone().map(|election| {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { election.run(shutdown).await })
+    });
+    let leader = election.as_ref().map(|election| election.subscribe());
+    if let Some(mut leader) = leader.clone() {
+        tokio::spawn(async move {
+            loop {
+                let status = match *leader.borrow_and_update() {
+                    true => tonic_health::ServingStatus::Serving,
+                    false => tonic_health::ServingStatus::NotServing,
+                };
+                health_reporter.set_service_status("", status).await;
+                health_reporter.set_service_status(CERTIFICATE_SERVICE_NAME, status).await;
+                if leader.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    } else {
+        health_reporter.set_service_status(CERTIFICATE_SERVICE_NAME, tonic_health::ServingStatus::Serving).await;
+    }
 
     info!("Certificate service listening on {}", addr);
 
     // Start gRPC server
-    Server::builder()
-        .add_service(proto::certservice::certificate_service_server::CertificateServiceServer::new(cert_service))
-        .serve_with_shutdown(addr, async {
-            signal::ctrl_c().await.ok();
-            info!("Received shutdown signal");
-        })
+    // Lets grpcurl discover the API without the proto files
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::certservice::FILE_DESCRIPTOR_SET)
+        .build()
+        .context("Failed to build gRPC reflection service")?;
+
+    // Requests reaching a standby replica are retried by the client, which reconnects
+    let certificate_service = proto::certservice::certificate_service_server::CertificateServiceServer::with_interceptor(
+        cert_service,
+        move |request: tonic::Request<()>| match &leader {
+            Some(leader) if !*leader.borrow() => Err(tonic::Status::unavailable(
+                "This certificate service replica is not the leader",
+            )),
+            _ => request_log::intercept(request),
+        },
+    );
+
+    let mut server = Server::builder();
+    if let Some((cert_file, key_file)) = &tls_files {
+        let cert = std::fs::read(cert_file).context(format!("Failed to read TLS_CERT_FILE {}", cert_file.display()))?;
+        let key = std::fs::read(key_file).context(format!("Failed to read TLS_KEY_FILE {}", key_file.display()))?;
+        server = server
+            .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+            .context("Invalid TLS certificate or key")?;
+    }
+
+    server
+        .add_service(reflection)
+        .add_service(health_service)
+        .add_service(certificate_service)
+        .serve_with_shutdown(addr, shutdown.clone().cancelled_owned())
         .await?;
 
+    // Release the lease so a standby takes over right away
+    if let Some(handle) = election_handle {
+        handle.await.ok();
+    }
+
     info!("Certificate service shutdown complete");
     Ok(())
 }
+
+/// Sign with the CA from `secret_name`, picking up CA rotations without a restart
+async fn local_signer(
+    secret_name: String,
+    secret_namespace: &str,
+    passphrase_secret: Option<String>,
+) -> Result<Arc<dyn signer::Signer>> {
+    let local = Arc::new(signer::LocalSigner::new(secret_name, secret_namespace.to_string(), passphrase_secret).await?);
+
+    let watched = local.clone();
+    tokio::spawn(async move {
+        if let Err(e) = watched.watch_secret().await {
+            error!("CA secret watch error: {}", e);
+        }
+    });
+
+    Ok(local)
+}
@@ -1 +1,20 @@
+pub mod audit;
+pub mod auth;
+pub mod issuer_urls;
+pub mod leader_election;
+pub mod name_constraints;
+pub mod namespace_policy;
+pub mod node_intermediates;
+pub mod policy;
+pub mod profiles;
+pub mod request_log;
 pub mod service;
+pub mod settings;
+pub mod signer;
+pub mod store;
+pub mod validation;
+pub mod validity;
+pub mod watch;
+
+// Generated protobuf code, shared with the CSI driver
+pub use crate::proto;
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use std::net::IpAddr;
+use x509_parser::extensions::{GeneralName, GeneralSubtree, ParsedExtension};
+use x509_parser::pem::parse_x509_pem;
+
+/// DNS and IP address name constraints of the issuing CA (RFC 5280 4.2.1.10)
+///
+/// Other constraint types (email, URI, directory name) are not checked here; the
+/// names checked are the common name, DNS names and IP addresses.
+#[derive(Debug, Clone, Default)]
+pub struct NameConstraints {
+    permitted_dns: Vec<String>,
+    excluded_dns: Vec<String>,
+    permitted_ip: Vec<Vec<u8>>,
+    excluded_ip: Vec<Vec<u8>>,
+}
+
+impl NameConstraints {
+    /// Read the name constraints of a CA certificate; `None` when it has none
+    pub fn from_ca_pem(ca_cert_pem: &str) -> Result<Option<Self>> {
+        let (_, pem) = parse_x509_pem(ca_cert_pem.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate PEM: {}", e))?;
+        let cert = pem
+            .parse_x509()
+            .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate: {}", e))?;
+
+        let Some(constraints) = cert.extensions().iter().find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::NameConstraints(constraints) => Some(constraints),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+
+        let mut parsed = Self::default();
+        for subtree in constraints.permitted_subtrees.iter().flatten() {
+            parsed.add(subtree, true);
+        }
+        for subtree in constraints.excluded_subtrees.iter().flatten() {
+            parsed.add(subtree, false);
+        }
+        Ok(Some(parsed))
+    }
+
+    fn add(&mut self, subtree: &GeneralSubtree, permitted: bool) {
+        match (&subtree.base, permitted) {
+            (GeneralName::DNSName(name), true) => self.permitted_dns.push(name.to_ascii_lowercase()),
+            (GeneralName::DNSName(name), false) => self.excluded_dns.push(name.to_ascii_lowercase()),
+            (GeneralName::IPAddress(range), true) => self.permitted_ip.push(range.to_vec()),
+            (GeneralName::IPAddress(range), false) => self.excluded_ip.push(range.to_vec()),
+            _ => {}
+        }
+    }
+
+    /// Permitted dNSName subtrees; empty when DNS names are unconstrained
+    pub fn permitted_dns(&self) -> &[String] {
+        &self.permitted_dns
+    }
+
+    /// Check the common name, DNS names and IP addresses against the permitted and excluded subtrees
+    ///
+    /// A name type with no permitted subtrees is unconstrained; excluded subtrees always apply.
+    /// The common name is only checked when it is a qualified hostname, as verifiers only
+    /// apply dNSName constraints to such a CN; DNS names are always checked.
+    pub fn check(&self, common_name: &str, dns_names: &[String], ip_addresses: &[String]) -> Result<(), String> {
+        let hostname_cn = Some(common_name).filter(|cn| is_hostname(cn));
+        for name in dns_names.iter().map(String::as_str).chain(hostname_cn) {
+            let name = name.to_ascii_lowercase();
+            if let Some(excluded) = self.excluded_dns.iter().find(|c| dns_name_matches(c, &name)) {
+                return Err(format!("DNS name '{}' is in the excluded subtree '{}'", name, excluded));
+            }
+            if !self.permitted_dns.is_empty() && !self.permitted_dns.iter().any(|c| dns_name_matches(c, &name)) {
+                return Err(format!(
+                    "DNS name '{}' is outside the permitted subtrees ({})", name, self.permitted_dns.join(", ")
+                ));
+            }
+        }
+
+        for ip in ip_addresses {
+            let addr: IpAddr = ip.parse().map_err(|_| format!("Invalid IP address '{}'", ip))?;
+            if self.excluded_ip.iter().any(|range| ip_matches(range, &addr)) {
+                return Err(format!("IP address {} is in an excluded subtree", addr));
+            }
+            if !self.permitted_ip.is_empty() && !self.permitted_ip.iter().any(|range| ip_matches(range, &addr)) {
+                return Err(format!("IP address {} is outside the permitted subtrees", addr));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `name` is a qualified hostname, with at most a `*` as its leftmost label
+fn is_hostname(name: &str) -> bool {
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    name.contains('.')
+        && name
+            .trim_end_matches('.')
+            .split('.')
+            .all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')))
+}
+
+/// Whether `name` is within the dNSName constraint `constraint`
+///
+/// `example.com` matches the domain and all its subdomains; `.example.com` only subdomains.
+fn dns_name_matches(constraint: &str, name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    if constraint.is_empty() {
+        return true;
+    }
+    if constraint.starts_with('.') {
+        return name.ends_with(constraint);
+    }
+    name == constraint || name.strip_suffix(constraint).map(|prefix| prefix.ends_with('.')).unwrap_or(false)
+}
+
+/// Whether `addr` is within an iPAddress constraint (address followed by mask)
+fn ip_matches(range: &[u8], addr: &IpAddr) -> bool {
+    let octets = match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    if range.len() != octets.len() * 2 {
+        return false;
+    }
+    let (network, mask) = range.split_at(octets.len());
+    octets
+        .iter()
+        .zip(network.iter().zip(mask))
+        .all(|(octet, (network, mask))| octet & mask == network & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, CidrSubnet, GeneralSubtree, IsCa, BasicConstraints, KeyPair};
+
+    #[test]
+    fn test_constraints_from_ca() {
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.name_constraints = Some(rcgen::NameConstraints {
+            permitted_subtrees: vec![
+                GeneralSubtree::DnsName("svc.cluster.local".to_string()),
+                GeneralSubtree::IpAddress("10.0.0.0/8".parse::<CidrSubnet>().unwrap()),
+            ],
+            excluded_subtrees: vec![GeneralSubtree::DnsName("kube-system.svc.cluster.local".to_string())],
+        });
+        let ca = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let constraints = NameConstraints::from_ca_pem(&ca.pem()).unwrap().unwrap();
+
+        let dns = |name: &str| vec![name.to_string()];
+        assert!(constraints.check("web", &dns("web.default.svc.cluster.local"), &["10.1.2.3".to_string()]).is_ok());
+        assert!(constraints.check("svc.cluster.local", &[], &[]).is_ok());
+        assert!(constraints.check("web", &dns("web.example.com"), &[]).is_err());
+        assert!(constraints.check("web.example.com", &[], &[]).is_err());
+        assert!(constraints.check("web", &dns("evilsvc.cluster.local"), &[]).is_err());
+        assert!(constraints.check("web", &dns("dns.kube-system.svc.cluster.local"), &[]).is_err());
+        assert!(constraints.check("web", &[], &["192.168.0.1".to_string()]).is_err());
+        assert!(constraints.check("Payments Service", &[], &[]).is_ok());
+        // Unqualified SANs are checked like any other; an unqualified CN or a bare `*` is not a hostname
+        assert!(constraints.check("web", &dns("web"), &[]).is_err());
+        assert!(constraints.check("*", &[], &[]).is_ok());
+        assert!(constraints.check("*.example.com", &[], &[]).is_err());
+
+        let unconstrained = CertificateParams::default().self_signed(&KeyPair::generate().unwrap()).unwrap();
+        assert!(NameConstraints::from_ca_pem(&unconstrained.pem()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::Result;
+
+/// Namespaces denied by default so system workloads cannot mint arbitrary identities
+pub const DEFAULT_DENIED_NAMESPACES: &str = "kube-system";
+
+/// Which namespaces may request certificates, and for which names
+///
+/// Namespace patterns match exactly, or by prefix when they end in `*` (e.g. `team-*`).
+/// The deny list wins over the allow list; an empty allow list allows every namespace
+/// that is not denied. Wildcard DNS names are only allowed in the namespaces listed for them.
+#[derive(Debug, Clone)]
+pub struct NamespacePolicy {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    /// Namespace pattern -> DNS suffixes the CN and DNS SANs must end with
+    name_suffixes: Vec<(String, Vec<String>)>,
+    /// Namespaces that may request wildcard DNS names such as `*.web.svc`
+    wildcard_namespaces: Vec<String>,
+}
+
+impl NamespacePolicy {
+    /// Parse comma-separated namespace lists and a suffix spec of the form
+    /// `ns=.suffix-a|.suffix-b, other-ns=.suffix-c`, where suffixes may contain `{namespace}`
+    pub fn parse(allowed: &str, denied: &str, name_suffixes: &str, wildcard_namespaces: &str) -> Result<Self> {
+        let mut suffix_rules = Vec::new();
+        for entry in split_list(name_suffixes) {
+            let (pattern, suffixes) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid NAMESPACE_NAME_SUFFIXES entry '{}', expected ns=suffix", entry))?;
+            let suffixes: Vec<String> = suffixes
+                .split('|')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if suffixes.is_empty() {
+                return Err(anyhow::anyhow!("NAMESPACE_NAME_SUFFIXES entry '{}' has no suffixes", entry));
+            }
+            suffix_rules.push((pattern.trim().to_string(), suffixes));
+        }
+
+        Ok(Self {
+            allowed: split_list(allowed),
+            denied: split_list(denied),
+            name_suffixes: suffix_rules,
+            wildcard_namespaces: split_list(wildcard_namespaces),
+        })
+    }
+
+    /// Whether `namespace` may request wildcard DNS names, unless its profile decides otherwise
+    pub fn allows_wildcards(&self, namespace: &str) -> bool {
+        !namespace.is_empty() && self.wildcard_namespaces.iter().any(|p| matches_pattern(p, namespace))
+    }
+
+    /// Check whether `namespace` may obtain a certificate for `names` (CN and DNS SANs)
+    pub fn check(&self, namespace: &str, names: &[&str]) -> Result<(), String> {
+        if namespace.is_empty() {
+            // Requests without a namespace only pass when no namespace rule is configured
+            if !self.allowed.is_empty() || !self.denied.is_empty() || !self.name_suffixes.is_empty() {
+                return Err("request does not identify its namespace".to_string());
+            }
+            return Ok(());
+        }
+
+        if self.denied.iter().any(|p| matches_pattern(p, namespace)) {
+            return Err(format!("namespace '{}' is not allowed to request certificates", namespace));
+        }
+
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|p| matches_pattern(p, namespace)) {
+            return Err(format!("namespace '{}' is not in the list of allowed namespaces", namespace));
+        }
+
+        // The most specific rule wins: an exact match over a wildcard pattern
+        let rule = self.name_suffixes
+            .iter()
+            .find(|(p, _)| p == namespace)
+            .or_else(|| self.name_suffixes.iter().find(|(p, _)| matches_pattern(p, namespace)));
+
+        if let Some((_, suffixes)) = rule {
+            // A suffix matches whole labels, with or without its leading dot
+            let suffixes: Vec<String> = suffixes
+                .iter()
+                .map(|s| s.trim_start_matches('.').replace("{namespace}", namespace))
+                .collect();
+
+            for name in names {
+                let permitted = suffixes.iter().any(|suffix| {
+                    *name == suffix.as_str() || name.ends_with(&format!(".{suffix}"))
+                });
+                if !permitted {
+                    return Err(format!(
+                        "name '{}' is not permitted for namespace '{}' (allowed suffixes: {})",
+                        name, namespace, suffixes.join(", ")
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn matches_pattern(pattern: &str, namespace: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => namespace.starts_with(prefix),
+        None => pattern == namespace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kube_system_denied_by_default() {
+        let policy = NamespacePolicy::parse("", DEFAULT_DENIED_NAMESPACES, "", "").unwrap();
+        assert!(policy.check("kube-system", &["coredns"]).is_err());
+        assert!(policy.check("default", &["web"]).is_ok());
+        // A request without a namespace could be from anywhere, kube-system included
+        assert!(policy.check("", &["coredns"]).is_err());
+        assert!(NamespacePolicy::parse("", "", "", "").unwrap().check("", &["web"]).is_ok());
+    }
+
+    #[test]
+    fn test_allow_list_with_prefix_pattern() {
+        let policy = NamespacePolicy::parse("team-*, shared", "", "", "").unwrap();
+        assert!(policy.check("team-a", &["web"]).is_ok());
+        assert!(policy.check("shared", &["web"]).is_ok());
+        assert!(policy.check("other", &["web"]).is_err());
+        assert!(policy.check("", &["web"]).is_err());
+    }
+
+    #[test]
+    fn test_name_suffixes() {
+        let policy = NamespacePolicy::parse(
+            "",
+            "",
+            "*=.{namespace}.svc.cluster.local, payments=.payments.example.com",
+            "",
+        ).unwrap();
+
+        assert!(policy.check("team-a", &["web.team-a.svc.cluster.local"]).is_ok());
+        assert!(policy.check("team-a", &["web.team-b.svc.cluster.local"]).is_err());
+        assert!(policy.check("payments", &["api.payments.example.com", "payments.example.com"]).is_ok());
+        assert!(policy.check("payments", &["api.payments.svc.cluster.local"]).is_err());
+        assert!(policy.check("", &["api.payments.example.com"]).is_err());
+
+        // Suffixes match whole labels, with or without the leading dot
+        let policy = NamespacePolicy::parse("", "", "shop=example.com", "").unwrap();
+        assert!(policy.check("shop", &["example.com", "api.example.com"]).is_ok());
+        assert!(policy.check("shop", &["evilexample.com"]).is_err());
+        assert!(policy.check("payments", &["api.evilpayments.example.com"]).is_ok());
+        let policy = NamespacePolicy::parse("", "", "payments=.payments.example.com", "").unwrap();
+        assert!(policy.check("payments", &["api.evilpayments.example.com"]).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_namespaces() {
+        assert!(!NamespacePolicy::parse("", "", "", "").unwrap().allows_wildcards("ingress"));
+
+        let policy = NamespacePolicy::parse("", "", "", "ingress, edge-*").unwrap();
+        assert!(policy.allows_wildcards("ingress"));
+        assert!(policy.allows_wildcards("edge-eu"));
+        assert!(!policy.allows_wildcards("default"));
+        assert!(!policy.allows_wildcards(""));
+    }
+}
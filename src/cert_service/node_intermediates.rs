@@ -0,0 +1,178 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rcgen::{BasicConstraints, CertificateParams, CustomExtension, DnType, IsCa, KeyUsagePurpose, SerialNumber};
+use std::time::SystemTime;
+use yasna::Tag;
+
+use super::signer::{random_serial, SubjectName};
+
+/// Name constraints extension (RFC 5280 4.2.1.10)
+const NAME_CONSTRAINTS_OID: &[u64] = &[2, 5, 29, 30];
+
+/// Host of the only URI and email domain node intermediates may sign for; `.invalid` never resolves (RFC 2606)
+const NO_HOST: &str = "invalid";
+
+/// Default validity of node intermediates
+pub const DEFAULT_NODE_INTERMEDIATE_VALIDITY_SECONDS: i64 = 86400;
+
+/// Short-lived intermediate CAs issued to node drivers, so they can sign workload certificates themselves
+///
+/// Every intermediate has a path length of 0 and is name-constrained to DNS names in
+/// `permitted_dns`: IP addresses are excluded and URIs and email addresses are limited to
+/// the `invalid` domain, which limits what a compromised node can sign for. Directory
+/// names are left unconstrained, as rustls rejects every certificate below a CA with a
+/// directoryName constraint.
+#[derive(Debug, Clone)]
+pub struct NodeIntermediates {
+    pub validity_seconds: i64,
+    pub permitted_dns: Vec<String>,
+}
+
+impl NodeIntermediates {
+    /// Parse the comma-separated permitted DNS domains; at least one is required
+    pub fn parse(validity_seconds: i64, permitted_dns: &str) -> Result<Self> {
+        if validity_seconds < 300 {
+            return Err(anyhow::anyhow!("Node intermediate validity must be at least 300s, got {}s", validity_seconds));
+        }
+
+        let permitted_dns: Vec<String> = permitted_dns
+            .split(',')
+            .map(|domain| domain.trim().to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        if permitted_dns.is_empty() {
+            return Err(anyhow::anyhow!("Node intermediates need at least one permitted DNS domain"));
+        }
+        if let Some(invalid) = permitted_dns
+            .iter()
+            .find(|domain| !domain.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.')))
+        {
+            return Err(anyhow::anyhow!("Invalid permitted DNS domain '{}'", invalid));
+        }
+
+        Ok(Self { validity_seconds, permitted_dns })
+    }
+
+    /// Certificate parameters and subject of the intermediate for `node_id`
+    pub fn params(
+        &self,
+        node_id: &str,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> (CertificateParams, SubjectName) {
+        let mut params = CertificateParams::default();
+        let mut subject = SubjectName::new();
+        subject.push(DnType::CommonName, format!("cacsi node {}", node_id));
+        params.distinguished_name = subject.to_distinguished_name();
+
+        // May sign leaf certificates only, and only for the permitted domains
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        params.custom_extensions.push(self.name_constraints());
+        params.use_authority_key_identifier_extension = true;
+
+        params.serial_number = Some(SerialNumber::from_slice(&random_serial()));
+
+        params.not_before = time::OffsetDateTime::from(SystemTime::from(not_before));
+        params.not_after = time::OffsetDateTime::from(SystemTime::from(not_after));
+
+        (params, subject)
+    }
+
+    /// Critical name constraints extension: DNS names within `permitted_dns` only
+    ///
+    /// rcgen cannot encode URI subtrees, so the extension is written here.
+    fn name_constraints(&self) -> CustomExtension {
+        let content = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                // permittedSubtrees [0]: dNSName [2], uniformResourceIdentifier [6] and rfc822Name [1]
+                writer.next().write_tagged_implicit(Tag::context(0), |writer| {
+                    writer.write_sequence(|writer| {
+                        let names = self.permitted_dns
+                            .iter()
+                            .map(|domain| (2, domain.as_str()))
+                            .chain([(6, NO_HOST), (1, NO_HOST)]);
+                        for (tag, name) in names {
+                            writer.next().write_sequence(|writer| {
+                                writer.next().write_tagged_implicit(Tag::context(tag), |writer| {
+                                    writer.write_ia5_string(name);
+                                });
+                            });
+                        }
+                    });
+                });
+                // excludedSubtrees [1]: every IPv4 and IPv6 address as iPAddress [7]
+                writer.next().write_tagged_implicit(Tag::context(1), |writer| {
+                    writer.write_sequence(|writer| {
+                        for range in [&[0u8; 8][..], &[0u8; 32][..]] {
+                            writer.next().write_sequence(|writer| {
+                                writer.next().write_tagged_implicit(Tag::context(7), |writer| {
+                                    writer.write_bytes(range);
+                                });
+                            });
+                        }
+                    });
+                });
+            });
+        });
+
+        let mut extension = CustomExtension::from_oid_content(NAME_CONSTRAINTS_OID, content);
+        extension.set_criticality(true);
+        extension
+    }
+}
+
+/// Whether `node_id` is a valid Kubernetes node name (a DNS subdomain)
+pub fn is_valid_node_id(node_id: &str) -> bool {
+    !node_id.is_empty()
+        && node_id.len() <= 253
+        && node_id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::name_constraints;
+    use rcgen::{Issuer, KeyPair};
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    #[test]
+    fn test_intermediate_is_constrained() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::default();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let issuer = Issuer::new(ca_params, &ca_key);
+
+        let intermediates = NodeIntermediates::parse(3600, "svc.cluster.local, example.com").unwrap();
+        let now = Utc::now();
+        let (params, _) = intermediates.params("node-1", now, now + chrono::Duration::seconds(3600));
+        let cert = params.signed_by(&KeyPair::generate().unwrap(), &issuer).unwrap();
+
+        let (_, parsed) = X509Certificate::from_der(cert.der()).unwrap();
+        let constraints = parsed.basic_constraints().unwrap().unwrap().value;
+        assert!(constraints.ca);
+        assert_eq!(constraints.path_len_constraint, Some(0));
+
+        let names = name_constraints::NameConstraints::from_ca_pem(&cert.pem()).unwrap().unwrap();
+        assert!(names.check("web", &["web.default.svc.cluster.local".to_string()], &[]).is_ok());
+        assert!(names.check("web", &["web.internal.corp".to_string()], &[]).is_err());
+        assert!(names.check("web", &[], &["10.0.0.1".to_string()]).is_err());
+        assert!(names.check("web", &[], &["fd00::1".to_string()]).is_err());
+
+        let extension = parsed.name_constraints().unwrap().unwrap();
+        assert!(extension.critical);
+        let permitted = extension.value.permitted_subtrees.as_ref().unwrap();
+        assert!(permitted.iter().any(|subtree| matches!(subtree.base, GeneralName::URI("invalid"))));
+        assert!(permitted.iter().any(|subtree| matches!(subtree.base, GeneralName::RFC822Name("invalid"))));
+
+        assert!(NodeIntermediates::parse(3600, "").is_err());
+        assert!(NodeIntermediates::parse(60, "cluster.local").is_err());
+        assert!(is_valid_node_id("ip-10-0-0-1.ec2.internal"));
+        assert!(!is_valid_node_id("../etc"));
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{Result, Context as _};
+use cel_interpreter::{Context, Program, Value};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::Api;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tracing::{info, debug, warn};
+
+use super::profiles::{ExtendedKeyUsage, KeyUsage};
+
+/// ConfigMap key holding the policy rules
+const RULES_KEY: &str = "rules.yaml";
+
+/// A single policy rule as written in the ConfigMap
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyRuleSpec {
+    /// Rule name, reported back to callers when the rule denies a request
+    pub name: String,
+    /// CEL expression that must evaluate to `true` for the request to be allowed
+    pub expression: String,
+    /// Human readable explanation returned when the rule denies a request
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+struct CompiledRule {
+    spec: PolicyRuleSpec,
+    program: Program,
+}
+
+/// The issuance request as exposed to policy expressions under the `request` variable
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyRequest {
+    pub certificate_id: String,
+    pub common_name: String,
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<String>,
+    pub uris: Vec<String>,
+    pub organizational_units: Vec<String>,
+    pub key_usages: Vec<KeyUsage>,
+    pub extended_key_usages: Vec<ExtendedKeyUsage>,
+    /// OIDs of the requested custom extensions
+    pub extension_oids: Vec<String>,
+    pub namespace: String,
+    /// Validity to be issued, rounded up to whole days
+    pub validity_days: i64,
+    pub validity_seconds: i64,
+    pub metadata: HashMap<String, String>,
+    pub profile: String,
+    pub renewal: bool,
+}
+
+/// A request rejected by policy
+#[derive(Debug, Clone)]
+pub struct PolicyViolation {
+    pub rule: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "denied by policy rule '{}': {}", self.rule, self.message)
+    }
+}
+
+/// Evaluates CEL rules loaded from a ConfigMap against issuance requests
+///
+/// Every rule must evaluate to `true`; the first rule that evaluates to `false`
+/// (or fails to evaluate) denies the request.
+pub struct PolicyEngine {
+    configmap_name: String,
+    configmap_namespace: String,
+    rules: RwLock<Vec<CompiledRule>>,
+}
+
+impl PolicyEngine {
+    pub async fn new(configmap_name: String, configmap_namespace: String) -> Result<Self> {
+        let engine = Self {
+            configmap_name,
+            configmap_namespace,
+            rules: RwLock::new(Vec::new()),
+        };
+
+        engine.reload().await?;
+
+        Ok(engine)
+    }
+
+    /// Re-read the rules from the ConfigMap, keeping the current rules if any fail to compile
+    pub async fn reload(&self) -> Result<()> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.configmap_namespace);
+
+        let configmap = crate::k8s_client::with_retries(|| configmaps.get(&self.configmap_name))
+            .await
+            .context(format!("Failed to get policy ConfigMap {}/{}", self.configmap_namespace, self.configmap_name))?;
+
+        let rules_yaml = configmap
+            .data
+            .as_ref()
+            .and_then(|data| data.get(RULES_KEY))
+            .ok_or_else(|| anyhow::anyhow!("Policy ConfigMap missing {}", RULES_KEY))?;
+
+        let rules = compile_rules(rules_yaml)?;
+
+        info!("Loaded {} policy rules from {}/{}", rules.len(), self.configmap_namespace, self.configmap_name);
+        *self.rules.write().await = rules;
+
+        Ok(())
+    }
+
+    /// Evaluate all rules against `request`
+    pub async fn evaluate(&self, request: &PolicyRequest) -> Result<(), PolicyViolation> {
+        let rules = self.rules.read().await;
+        evaluate_rules(&rules, request)
+    }
+}
+
+/// Policy rules defined outside the policy ConfigMap, e.g. those of a tenant
+#[derive(Default)]
+pub struct PolicyRules(Vec<CompiledRule>);
+
+impl PolicyRules {
+    pub fn compile(specs: Vec<PolicyRuleSpec>) -> Result<Self> {
+        compile_specs(specs).map(Self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Evaluate all rules against `request`
+    pub fn evaluate(&self, request: &PolicyRequest) -> Result<(), PolicyViolation> {
+        evaluate_rules(&self.0, request)
+    }
+}
+
+fn compile_rules(rules_yaml: &str) -> Result<Vec<CompiledRule>> {
+    let specs: Vec<PolicyRuleSpec> = serde_yaml::from_str(rules_yaml)
+        .context("Invalid policy rules YAML")?;
+    compile_specs(specs)
+}
+
+fn compile_specs(specs: Vec<PolicyRuleSpec>) -> Result<Vec<CompiledRule>> {
+    specs
+        .into_iter()
+        .map(|spec| {
+            let program = Program::compile(&spec.expression)
+                .map_err(|e| anyhow::anyhow!("Policy rule '{}' does not compile: {}", spec.name, e))?;
+            Ok(CompiledRule { spec, program })
+        })
+        .collect()
+}
+
+fn evaluate_rules(rules: &[CompiledRule], request: &PolicyRequest) -> Result<(), PolicyViolation> {
+    let mut context = Context::default();
+    context.add_variable("request", request).map_err(|e| PolicyViolation {
+        rule: "<context>".to_string(),
+        message: format!("failed to build policy context: {}", e),
+    })?;
+
+    for rule in rules {
+        let denied = |message: String| PolicyViolation {
+            rule: rule.spec.name.clone(),
+            message,
+        };
+
+        match rule.program.execute(&context) {
+            Ok(Value::Bool(true)) => {
+                debug!("Policy rule '{}' allowed {}", rule.spec.name, request.certificate_id);
+            }
+            Ok(Value::Bool(false)) => {
+                let message = rule.spec.message.clone()
+                    .unwrap_or_else(|| format!("expression `{}` evaluated to false", rule.spec.expression));
+                return Err(denied(message));
+            }
+            Ok(other) => {
+                warn!("Policy rule '{}' returned a non-boolean value: {:?}", rule.spec.name, other);
+                return Err(denied("rule did not evaluate to a boolean".to_string()));
+            }
+            Err(e) => {
+                warn!("Policy rule '{}' failed to evaluate: {}", rule.spec.name, e);
+                return Err(denied(format!("rule failed to evaluate: {}", e)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(common_name: &str, dns_names: &[&str]) -> PolicyRequest {
+        PolicyRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: common_name.to_string(),
+            dns_names: dns_names.iter().map(|name| name.to_string()).collect(),
+            ip_addresses: vec![],
+            uris: vec![],
+            organizational_units: vec![],
+            key_usages: vec![],
+            extended_key_usages: vec![],
+            extension_oids: vec![],
+            namespace: "default".to_string(),
+            validity_days: 30,
+            validity_seconds: 30 * 86400,
+            metadata: HashMap::new(),
+            profile: String::new(),
+            renewal: false,
+        }
+    }
+
+    const RULES: &str = r#"
+- name: short-validity
+  expression: request.validity_days <= 90
+  message: Certificates are valid for at most 90 days
+- name: no-wildcards
+  expression: "!request.dns_names.exists(n, n.startsWith('*.'))"
+- name: no-admin
+  expression: request.common_name != "admin"
+  message: admin is reserved
+"#;
+
+    #[test]
+    fn test_rules_allow_and_deny() {
+        let rules = compile_rules(RULES).unwrap();
+        assert!(evaluate_rules(&rules, &request("web", &["web.default.svc"])).is_ok());
+        assert!(evaluate_rules(&[], &request("admin", &["*.default.svc"])).is_ok());
+
+        let denied = evaluate_rules(&rules, &request("admin", &["web.default.svc"])).unwrap_err();
+        assert_eq!((denied.rule.as_str(), denied.message.as_str()), ("no-admin", "admin is reserved"));
+        // Without a message the expression is reported
+        let denied = evaluate_rules(&rules, &request("web", &["*.default.svc"])).unwrap_err();
+        assert_eq!(denied.rule, "no-wildcards");
+        assert!(denied.message.contains("startsWith"), "{}", denied.message);
+    }
+
+    #[test]
+    fn test_first_denying_rule_is_reported() {
+        let rules = compile_rules(RULES).unwrap();
+        let mut denied_by_all = request("admin", &["*.default.svc"]);
+        denied_by_all.validity_days = 365;
+        assert_eq!(evaluate_rules(&rules, &denied_by_all).unwrap_err().rule, "short-validity");
+        denied_by_all.validity_days = 90;
+        assert_eq!(evaluate_rules(&rules, &denied_by_all).unwrap_err().rule, "no-wildcards");
+
+        // A rule that does not evaluate to true denies too, before the rules after it
+        let rules = compile_rules("- name: typo\n  expression: request.common_nam == 'web'\n- name: number\n  expression: \"1\"\n").unwrap();
+        let denied = evaluate_rules(&rules, &request("web", &[])).unwrap_err();
+        assert_eq!(denied.rule, "typo");
+        assert!(denied.message.starts_with("rule failed to evaluate"), "{}", denied.message);
+        let rules = PolicyRules::compile(vec![PolicyRuleSpec {
+            name: "number".to_string(),
+            expression: "1".to_string(),
+            message: None,
+        }]).unwrap();
+        let denied = rules.evaluate(&request("web", &[])).unwrap_err();
+        assert_eq!((denied.rule.as_str(), denied.message.as_str()), ("number", "rule did not evaluate to a boolean"));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let error = compile_rules("- name: broken\n  expression: request.common_name ==\n").err().unwrap();
+        assert!(error.to_string().contains("'broken'"), "{}", error);
+        assert!(compile_rules("name: not-a-list\n").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::encoding::KeyEncoding;
+use anyhow::{Result, Context as _};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::Api;
+use rcgen::{ExtendedKeyUsagePurpose, KeyPair, KeyUsagePurpose, SignatureAlgorithm};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// ConfigMap key holding the profile definitions
+const PROFILES_KEY: &str = "profiles.yaml";
+
+/// Key algorithm used for the certificate key pair
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    #[default]
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyType {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        match self {
+            KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            KeyType::Ed25519 => &rcgen::PKCS_ED25519,
+        }
+    }
+
+    /// Generate a key pair of this type
+    pub fn generate(&self) -> Result<KeyPair> {
+        KeyPair::generate_for(self.algorithm())
+            .map_err(|e| anyhow::anyhow!("Failed to generate {:?} key pair: {}", self, e))
+    }
+
+    /// Whether keys of this type can be written in `encoding`
+    ///
+    /// SEC1 only holds EC keys and PKCS#1 only RSA keys, which are never generated.
+    pub fn has_encoding(&self, encoding: KeyEncoding) -> bool {
+        match encoding {
+            KeyEncoding::Pkcs8 => true,
+            KeyEncoding::Sec1 => matches!(self, KeyType::EcdsaP256 | KeyType::EcdsaP384),
+            KeyEncoding::Pkcs1 => false,
+        }
+    }
+}
+
+/// Key usages a leaf certificate may be issued with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyUsage {
+    DigitalSignature,
+    ContentCommitment,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+}
+
+impl KeyUsage {
+    pub fn purpose(&self) -> KeyUsagePurpose {
+        match self {
+            KeyUsage::DigitalSignature => KeyUsagePurpose::DigitalSignature,
+            KeyUsage::ContentCommitment => KeyUsagePurpose::ContentCommitment,
+            KeyUsage::KeyEncipherment => KeyUsagePurpose::KeyEncipherment,
+            KeyUsage::DataEncipherment => KeyUsagePurpose::DataEncipherment,
+            KeyUsage::KeyAgreement => KeyUsagePurpose::KeyAgreement,
+        }
+    }
+}
+
+impl FromStr for KeyUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "digital_signature" => Ok(Self::DigitalSignature),
+            "content_commitment" => Ok(Self::ContentCommitment),
+            "key_encipherment" => Ok(Self::KeyEncipherment),
+            "data_encipherment" => Ok(Self::DataEncipherment),
+            "key_agreement" => Ok(Self::KeyAgreement),
+            other => Err(format!(
+                "Invalid key usage '{}' (expected digital_signature, content_commitment, key_encipherment, data_encipherment or key_agreement)",
+                other
+            )),
+        }
+    }
+}
+
+/// Key usages of certificates that do not request any
+pub fn default_key_usages() -> Vec<KeyUsage> {
+    vec![KeyUsage::DigitalSignature, KeyUsage::KeyEncipherment, KeyUsage::KeyAgreement]
+}
+
+/// Extended key usages a profile may grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtendedKeyUsage {
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+    EmailProtection,
+}
+
+impl ExtendedKeyUsage {
+    pub fn purpose(&self) -> ExtendedKeyUsagePurpose {
+        match self {
+            ExtendedKeyUsage::ServerAuth => ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsage::ClientAuth => ExtendedKeyUsagePurpose::ClientAuth,
+            ExtendedKeyUsage::CodeSigning => ExtendedKeyUsagePurpose::CodeSigning,
+            ExtendedKeyUsage::EmailProtection => ExtendedKeyUsagePurpose::EmailProtection,
+        }
+    }
+}
+
+impl FromStr for ExtendedKeyUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "server_auth" => Ok(Self::ServerAuth),
+            "client_auth" => Ok(Self::ClientAuth),
+            "code_signing" => Ok(Self::CodeSigning),
+            "email_protection" => Ok(Self::EmailProtection),
+            other => Err(format!(
+                "Invalid extended key usage '{}' (expected server_auth, client_auth, code_signing or email_protection)",
+                other
+            )),
+        }
+    }
+}
+
+/// Extended key usages of certificates that request none and have no profile
+pub fn default_extended_key_usages() -> Vec<ExtendedKeyUsage> {
+    vec![ExtendedKeyUsage::ServerAuth, ExtendedKeyUsage::ClientAuth]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Subject fields set by the profile; values may use `{namespace}` and `{pod}`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SubjectTemplate {
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub organization: Option<String>,
+    /// Replaces the organizational units requested by the volume when set
+    #[serde(default)]
+    pub organizational_units: Option<Vec<String>>,
+}
+
+/// A named set of issuance settings, selected by the `profile` volume attribute
+#[derive(Debug, Clone, Deserialize)]
+pub struct CertificateProfile {
+    #[serde(default)]
+    pub key_type: KeyType,
+    #[serde(default = "default_extended_key_usages")]
+    pub extended_key_usages: Vec<ExtendedKeyUsage>,
+    /// Validity used when the volume does not request one
+    #[serde(default)]
+    pub validity_days: Option<i64>,
+    /// Upper bound on the validity of certificates issued with this profile
+    #[serde(default)]
+    pub max_validity_days: Option<i64>,
+    #[serde(default)]
+    pub subject: SubjectTemplate,
+    /// Patterns the CN and DNS SANs must match (`*.example.com` matches any subdomain);
+    /// an empty list allows any name
+    #[serde(default)]
+    pub allowed_dns_names: Vec<String>,
+    #[serde(default = "default_true")]
+    pub allow_ip_addresses: bool,
+    /// Whether wildcard DNS names may be requested, overriding `WILDCARD_NAMESPACES`
+    #[serde(default)]
+    pub allow_wildcards: Option<bool>,
+}
+
+impl CertificateProfile {
+    /// Check the requested names against the profile's SAN rules
+    pub fn check_names(&self, names: &[&str], ip_addresses: &[String], namespace: &str) -> Result<(), String> {
+        if !self.allow_ip_addresses && !ip_addresses.is_empty() {
+            return Err("IP address SANs are not allowed by this profile".to_string());
+        }
+
+        if self.allowed_dns_names.is_empty() {
+            return Ok(());
+        }
+
+        let patterns: Vec<String> = self.allowed_dns_names
+            .iter()
+            .map(|p| p.replace("{namespace}", namespace))
+            .collect();
+
+        for name in names {
+            if !patterns.iter().any(|pattern| matches_dns_pattern(pattern, name)) {
+                return Err(format!(
+                    "name '{}' is not allowed by this profile (allowed: {})",
+                    name, patterns.join(", ")
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolve `{namespace}` and `{pod}` placeholders in a subject template value
+pub fn expand_subject_value(value: &str, metadata: &HashMap<String, String>) -> String {
+    let namespace = metadata.get("namespace").map(String::as_str).unwrap_or_default();
+    let pod = metadata.get("pod").map(String::as_str).unwrap_or_default();
+    value.replace("{namespace}", namespace).replace("{pod}", pod)
+}
+
+fn matches_dns_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => name
+            .strip_suffix(domain)
+            .and_then(|prefix| prefix.strip_suffix('.'))
+            .map(|label| !label.is_empty())
+            .unwrap_or(false),
+        None => pattern == name,
+    }
+}
+
+/// Certificate profiles loaded from a ConfigMap
+pub struct ProfileStore {
+    configmap_name: String,
+    configmap_namespace: String,
+    profiles: RwLock<HashMap<String, CertificateProfile>>,
+}
+
+impl ProfileStore {
+    pub async fn new(configmap_name: String, configmap_namespace: String) -> Result<Self> {
+        let store = Self {
+            configmap_name,
+            configmap_namespace,
+            profiles: RwLock::new(HashMap::new()),
+        };
+
+        store.reload().await?;
+
+        Ok(store)
+    }
+
+    /// Re-read the profiles from the ConfigMap, keeping the current ones if the new ones are invalid
+    pub async fn reload(&self) -> Result<()> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.configmap_namespace);
+
+        let configmap = crate::k8s_client::with_retries(|| configmaps.get(&self.configmap_name))
+            .await
+            .context(format!("Failed to get profiles ConfigMap {}/{}", self.configmap_namespace, self.configmap_name))?;
+
+        let profiles_yaml = configmap
+            .data
+            .as_ref()
+            .and_then(|data| data.get(PROFILES_KEY))
+            .ok_or_else(|| anyhow::anyhow!("Profiles ConfigMap missing {}", PROFILES_KEY))?;
+
+        let profiles = parse_profiles(profiles_yaml)?;
+
+        info!("Loaded {} certificate profiles from {}/{}", profiles.len(), self.configmap_namespace, self.configmap_name);
+        *self.profiles.write().await = profiles;
+
+        Ok(())
+    }
+
+    /// Look up a profile by name
+    pub async fn get(&self, name: &str) -> Option<CertificateProfile> {
+        self.profiles.read().await.get(name).cloned()
+    }
+}
+
+fn parse_profiles(profiles_yaml: &str) -> Result<HashMap<String, CertificateProfile>> {
+    let profiles: HashMap<String, CertificateProfile> = serde_yaml::from_str(profiles_yaml)
+        .context("Invalid certificate profiles YAML")?;
+
+    for (name, profile) in &profiles {
+        for (field, days) in [("validity_days", profile.validity_days), ("max_validity_days", profile.max_validity_days)] {
+            if matches!(days, Some(d) if d <= 0) {
+                return Err(anyhow::anyhow!("Profile '{}' has a non-positive {}", name, field));
+            }
+        }
+        if profile.extended_key_usages.is_empty() {
+            return Err(anyhow::anyhow!("Profile '{}' grants no extended key usages", name));
+        }
+    }
+
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+web:
+  key_type: ecdsa-p384
+  extended_key_usages: [server_auth]
+  max_validity_days: 30
+  subject:
+    organization: Example
+    organizational_units: ["ns:{namespace}"]
+  allowed_dns_names: ["*.{namespace}.svc.cluster.local"]
+  allow_ip_addresses: false
+client: {}
+"#;
+
+    #[test]
+    fn test_parse_profiles_with_defaults() {
+        let profiles = parse_profiles(PROFILES).unwrap();
+
+        let web = &profiles["web"];
+        assert_eq!(web.key_type, KeyType::EcdsaP384);
+        assert_eq!(web.extended_key_usages, vec![ExtendedKeyUsage::ServerAuth]);
+        assert_eq!(web.max_validity_days, Some(30));
+
+        let client = &profiles["client"];
+        assert_eq!(client.key_type, KeyType::EcdsaP256);
+        assert_eq!(client.extended_key_usages, default_extended_key_usages());
+        assert!(client.allow_ip_addresses);
+    }
+
+    #[test]
+    fn test_profile_name_rules() {
+        let profiles = parse_profiles(PROFILES).unwrap();
+        let web = &profiles["web"];
+
+        assert!(web.check_names(&["api.team-a.svc.cluster.local"], &[], "team-a").is_ok());
+        assert!(web.check_names(&["team-a.svc.cluster.local"], &[], "team-a").is_err());
+        assert!(web.check_names(&["api.team-b.svc.cluster.local"], &[], "team-a").is_err());
+        assert!(web.check_names(&[], &["10.0.0.1".to_string()], "team-a").is_err());
+        assert!(profiles["client"].check_names(&["anything"], &[], "team-a").is_ok());
+    }
+
+    #[test]
+    fn test_key_type_encodings() {
+        assert!(KeyType::EcdsaP384.has_encoding(KeyEncoding::Sec1));
+        assert!(KeyType::Ed25519.has_encoding(KeyEncoding::Pkcs8));
+        assert!(!KeyType::Ed25519.has_encoding(KeyEncoding::Sec1));
+        assert!(!KeyType::EcdsaP256.has_encoding(KeyEncoding::Pkcs1));
+    }
+
+    #[test]
+    fn test_invalid_profiles_rejected() {
+        assert!(parse_profiles("bad:\n  max_validity_days: 0\n").is_err());
+        assert!(parse_profiles("bad:\n  extended_key_usages: []\n").is_err());
+        assert!(parse_profiles("bad:\n  key_type: rsa-1024\n").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tonic::{Code, Request, Status};
+use tracing::info;
+
+use crate::error_details::ErrorInfo;
+use crate::metrics::{self, Metrics};
+
+/// When and from where a call arrived, stamped on the request by [`intercept`]
+#[derive(Debug, Clone)]
+pub struct CallContext {
+    pub received: Instant,
+    pub peer: Option<SocketAddr>,
+    /// `user-agent` the client sent, naming its gRPC library and version
+    pub user_agent: Option<String>,
+}
+
+impl CallContext {
+    fn of<T>(request: &Request<T>) -> Self {
+        Self {
+            received: Instant::now(),
+            peer: request.remote_addr(),
+            user_agent: request
+                .metadata()
+                .get("user-agent")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Interceptor of the certificate service stamping each call with its [`CallContext`]
+///
+/// Runs before the request is decoded, so the latency logged includes decoding and any
+/// time spent waiting for the service.
+pub fn intercept(mut request: Request<()>) -> Result<Request<()>, Status> {
+    let context = CallContext::of(&request);
+    request.extensions_mut().insert(context);
+    Ok(request)
+}
+
+/// One call to the certificate service, logged by [`RequestLog::finish`] once answered
+#[derive(Debug, Clone)]
+pub struct Call {
+    method: &'static str,
+    context: CallContext,
+    certificate_id: Option<String>,
+    /// `<namespace>/<pod>` the node driver asked for
+    workload: Option<String>,
+}
+
+impl Call {
+    /// Start logging a call to `method`, with the context [`intercept`] stamped on `request`
+    ///
+    /// Calls that did not pass the interceptor, as in tests, are timed from here.
+    pub fn new<T>(method: &'static str, request: &Request<T>) -> Self {
+        let context = request
+            .extensions()
+            .get::<CallContext>()
+            .cloned()
+            .unwrap_or_else(|| CallContext::of(request));
+        Self { method, context, certificate_id: None, workload: None }
+    }
+
+    pub fn with_certificate_id(mut self, certificate_id: &str) -> Self {
+        self.certificate_id = Some(certificate_id.to_string());
+        self
+    }
+
+    /// Take the workload from the metadata the node driver sent with the request
+    pub fn with_metadata(mut self, metadata: &HashMap<String, String>) -> Self {
+        self.workload = match (metadata.get("namespace"), metadata.get("pod")) {
+            (Some(namespace), Some(pod)) => Some(format!("{}/{}", namespace, pod)),
+            (Some(namespace), None) => Some(namespace.clone()),
+            _ => None,
+        };
+        self
+    }
+}
+
+/// Logs every call to the certificate service in one structured line and counts it
+///
+/// The line carries the method, peer, client, certificate ID, workload, latency and
+/// status code, plus the `ErrorInfo` reason of failed calls.
+#[derive(Clone, Default)]
+pub struct RequestLog {
+    metrics: Option<Metrics>,
+}
+
+impl RequestLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also count calls and their latency by method in `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Log the outcome of `call`
+    pub fn finish(&self, call: Call, outcome: Result<(), &Status>) {
+        let latency = call.context.received.elapsed();
+        let code = outcome.err().map_or(Code::Ok, Status::code);
+        let reason = outcome
+            .err()
+            .and_then(ErrorInfo::from_status)
+            .map(|info| info.reason)
+            .unwrap_or_default();
+
+        info!(
+            method = call.method,
+            peer = %call.context.peer.map(|peer| peer.to_string()).unwrap_or_else(|| "local".to_string()),
+            user_agent = call.context.user_agent.as_deref().unwrap_or_default(),
+            certificate_id = call.certificate_id.as_deref().unwrap_or_default(),
+            workload = call.workload.as_deref().unwrap_or_default(),
+            latency_ms = latency.as_millis() as u64,
+            code = ?code,
+            reason = reason.as_str(),
+            "{} {:?}",
+            call.method,
+            code
+        );
+
+        if let Some(metrics) = &self.metrics {
+            let code = format!("{:?}", code);
+            metrics.inc(&metrics::CERT_SERVICE_REQUESTS, &[("method", call.method), ("code", &code)]);
+            metrics.add(&metrics::CERT_SERVICE_REQUEST_SECONDS, &[("method", call.method)], latency.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_details::ErrorReason;
+
+    #[test]
+    fn test_request_log_counts_calls() {
+        let metrics = Metrics::new();
+        let log = RequestLog::new().with_metrics(metrics.clone());
+
+        let mut request = Request::new(());
+        request.metadata_mut().insert("user-agent", "tonic/0.11".parse().unwrap());
+        let request = intercept(request).unwrap();
+        let call = Call::new("IssueCertificate", &request)
+            .with_certificate_id("default-web-csi-abc")
+            .with_metadata(&HashMap::from([
+                ("namespace".to_string(), "default".to_string()),
+                ("pod".to_string(), "web".to_string()),
+            ]));
+        assert_eq!(call.context.user_agent.as_deref(), Some("tonic/0.11"));
+        assert_eq!(call.workload.as_deref(), Some("default/web"));
+
+        log.finish(call.clone(), Ok(()));
+        let denied = ErrorReason::PolicyDenied.status(Code::PermissionDenied, "denied");
+        log.finish(call, Err(&denied));
+        log.finish(Call::new("ListCertificates", &Request::new(())), Ok(()));
+
+        let output = metrics.render();
+        assert!(output.contains("cacsi_cert_service_requests_total{method=\"IssueCertificate\",code=\"Ok\"} 1"));
+        assert!(output.contains("cacsi_cert_service_requests_total{method=\"IssueCertificate\",code=\"PermissionDenied\"} 1"));
+        assert!(output.contains("cacsi_cert_service_requests_total{method=\"ListCertificates\",code=\"Ok\"} 1"));
+        assert!(output.contains("cacsi_cert_service_request_duration_seconds_total{method=\"IssueCertificate\"}"));
+    }
+}
@@ -1,186 +1,752 @@
-use anyhow::{Result, Context};
+use anyhow::Result;
 use chrono::{Duration, Utc};
-use dashmap::DashMap;
-use kube::{Api, Client};
-use k8s_openapi::api::core::v1::Secret;
 use rcgen::{
-    CertificateParams, KeyPair, DistinguishedName,
-    SanType, ExtendedKeyUsagePurpose,
-    KeyUsagePurpose, DnType, CustomExtension,
+    CertificateParams, CustomExtension, KeyPair,
+    SanType, DnType, SerialNumber,
 };
-use rustls_pki_types::CertificateDer;
-use std::sync::Arc;
-use tonic::{Request, Response, Status};
-use tracing::{info, error, debug};
-use x509_parser::prelude::{X509Certificate, FromDer};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request, Response, Status};
+use tracing::{info, error, debug, warn};
+use x509_parser::pem::parse_x509_pem;
+use zeroize::Zeroizing;
 
+use super::audit::{AuditLog, AuditOperation, AuditRecord};
+use super::auth::Authenticator;
+use super::issuer_urls::IssuerUrls;
+use super::name_constraints::NameConstraints;
+use super::namespace_policy::NamespacePolicy;
+use super::node_intermediates::{is_valid_node_id, NodeIntermediates};
+use super::policy::{PolicyEngine, PolicyRequest};
+use super::request_log::{Call, RequestLog};
+use super::profiles::{
+    default_extended_key_usages, default_key_usages, expand_subject_value,
+    CertificateProfile, ExtendedKeyUsage, KeyUsage, ProfileStore,
+};
+use super::signer::{random_serial, CurrentCertificate, SignPurpose, Signer, SubjectName};
+use super::store::{CertificateRecord, CertificateStore, ListFilter, MemoryStore, Revocation};
+use super::watch::{CertificateEventStream, CertificateEvents, MAX_WATCHED_CERTIFICATES};
+use super::validation::{parse_oid, requested_validity_seconds, validate_issue_request};
+use super::validity::{days_rounded_up, format_validity, ValidityLimit, ValidityMode, SECONDS_PER_DAY};
+use crate::encoding::KeyEncoding;
+use crate::error_details::{ErrorInfo, ErrorReason};
+use crate::keyed_lock::KeyedLocks;
+use crate::metrics::Metrics;
+use crate::tenancy::{Tenancy, Tenant};
 use super::proto::certservice::{
     certificate_service_server::CertificateService,
     IssueCertificateRequest, IssueCertificateResponse,
     RenewCertificateRequest, RenewCertificateResponse,
     RevokeCertificateRequest, RevokeCertificateResponse,
     GetCertificateInfoRequest, GetCertificateInfoResponse,
+    ListCertificatesRequest, ListCertificatesResponse,
+    IssueNodeIntermediateRequest, IssueNodeIntermediateResponse,
+    WatchCertificatesRequest,
+    RevocationFilter, RevocationReason, Subject, Extension,
 };
 
-#[derive(Clone)]
-struct CertificateRecord {
-    certificate_id: String,
-    common_name: String,
-    dns_names: Vec<String>,
-    organizational_units: Vec<String>,
+/// A freshly signed certificate and its private key
+struct IssuedCertificate {
+    cert_pem: String,
+    key_pem: Zeroizing<String>,
+    serial: String,
     not_before: i64,
     not_after: i64,
-    metadata: std::collections::HashMap<String, String>,
+}
+
+/// X.520 serialNumber attribute type
+const SERIAL_NUMBER_OID: &[u64] = &[2, 5, 4, 5];
+
+/// Page size of ListCertificates when the request does not set one
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest page ListCertificates returns
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// How long an issued certificate and key are kept for retries repeating its idempotency key,
+/// longer than a client's retries of one call take
+const IDEMPOTENCY_WINDOW: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Most issuances kept for retries at once; the oldest is dropped for a new one
+const MAX_RECENT_ISSUES: usize = 1024;
+
+/// Validity used when neither the request nor its profile specifies one
+const DEFAULT_VALIDITY_DAYS: i64 = 7;
+
+/// How far notBefore is set in the past by default, for peers whose clocks lag behind
+pub const DEFAULT_NOT_BEFORE_BACKDATE_SECONDS: i64 = 300;
+
+/// Settings applied to every request that take effect without a restart
+#[derive(Debug, Clone)]
+pub struct IssuanceSettings {
+    pub namespace_policy: Option<NamespacePolicy>,
+    pub max_validity_days: Option<i64>,
+    pub validity_mode: ValidityMode,
+    pub issuer_urls: IssuerUrls,
+    pub not_before_backdate: Duration,
+}
+
+impl Default for IssuanceSettings {
+    fn default() -> Self {
+        Self {
+            namespace_policy: None,
+            max_validity_days: None,
+            validity_mode: ValidityMode::Clamp,
+            issuer_urls: IssuerUrls::default(),
+            not_before_backdate: Duration::seconds(DEFAULT_NOT_BEFORE_BACKDATE_SECONDS),
+        }
+    }
 }
 
 pub struct CertificateServiceImpl {
-    ca_secret_name: String,
-    ca_secret_namespace: String,
-    ca_key: Arc<tokio::sync::RwLock<Option<KeyPair>>>,
-    ca_cert_pem: Arc<tokio::sync::RwLock<Option<String>>>,
-    certificates: Arc<DashMap<String, CertificateRecord>>,
+    signer: Arc<dyn Signer>,
+    /// Signers of namespaces with their own CA, used instead of `signer`
+    namespace_signers: HashMap<String, Arc<dyn Signer>>,
+    tenancy: Option<Arc<Tenancy>>,
+    /// Signers of the tenant CAs, by CA secret
+    tenant_signers: HashMap<String, Arc<dyn Signer>>,
+    settings: Arc<tokio::sync::watch::Sender<IssuanceSettings>>,
+    policy: Option<Arc<PolicyEngine>>,
+    profiles: Option<Arc<ProfileStore>>,
+    default_profile: Option<String>,
+    store: Arc<dyn CertificateStore>,
+    audit_log: Option<Arc<AuditLog>>,
+    request_log: RequestLog,
+    node_intermediates: Option<NodeIntermediates>,
+    /// Authenticates callers of node-only calls; without it they are refused
+    authenticator: Option<Arc<Authenticator>>,
+    /// Whether every caller may make administrative calls, as on a private socket
+    trusted_callers: bool,
+    /// Parsed details of the current CA certificate of each signer, by namespace ("" for `signer`,
+    /// "tenant/<secret>" for tenant CAs)
+    ca_details: Mutex<HashMap<String, Arc<CaDetails>>>,
+    events: CertificateEvents,
+    /// Held while a certificate ID is issued; keeps the response for identical requests
+    /// that waited for it
+    issue_locks: KeyedLocks<Option<(IssueCertificateRequest, IssueCertificateResponse)>>,
+    /// Last issuance of each certificate ID that carried an idempotency key, with its private
+    /// key, for up to [`IDEMPOTENCY_WINDOW`] and [`MAX_RECENT_ISSUES`]
+    recent_issues: Mutex<HashMap<String, RecentIssue>>,
+}
+
+/// An issuance repeated to retries of the same request
+///
+/// The private key is the only one the service holds on to; it lives in a zeroizing
+/// buffer and is dropped when the entry expires or is displaced.
+struct RecentIssue {
+    request: IssueCertificateRequest,
+    issued_at: std::time::Instant,
+    serial: String,
+    cert_pem: String,
+    key_pem: Zeroizing<String>,
+    not_before: i64,
+    not_after: i64,
+}
+
+/// What issuance reads from the CA certificate, parsed once per CA instead of per request
+struct CaDetails {
+    cert_pem: String,
+    organization: Option<String>,
+    country: Option<String>,
+    name_constraints: Option<NameConstraints>,
+}
+
+impl CaDetails {
+    fn parse(cert_pem: String) -> Result<Self> {
+        let (organization, country) = ca_subject_fields(&cert_pem)?;
+        let name_constraints = NameConstraints::from_ca_pem(&cert_pem)?;
+        Ok(Self { cert_pem, organization, country, name_constraints })
+    }
 }
 
 impl CertificateServiceImpl {
-    pub async fn new(ca_secret_name: String, ca_secret_namespace: String) -> Result<Self> {
-        let service = Self {
-            ca_secret_name,
-            ca_secret_namespace,
-            ca_key: Arc::new(tokio::sync::RwLock::new(None)),
-            ca_cert_pem: Arc::new(tokio::sync::RwLock::new(None)),
-            certificates: Arc::new(DashMap::new()),
+    pub fn new(signer: Arc<dyn Signer>) -> Self {
+        info!("Using {} signer backend", signer.name());
+
+        Self {
+            signer,
+            namespace_signers: HashMap::new(),
+            tenancy: None,
+            tenant_signers: HashMap::new(),
+            settings: Arc::new(tokio::sync::watch::Sender::new(IssuanceSettings::default())),
+            policy: None,
+            profiles: None,
+            default_profile: None,
+            store: Arc::new(MemoryStore::new()),
+            audit_log: None,
+            request_log: RequestLog::new(),
+            node_intermediates: None,
+            authenticator: None,
+            trusted_callers: false,
+            ca_details: Mutex::new(HashMap::new()),
+            events: CertificateEvents::new(),
+            issue_locks: KeyedLocks::new(),
+            recent_issues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Restrict which namespaces may request certificates, and for which names
+    pub fn with_namespace_policy(self, namespace_policy: NamespacePolicy) -> Self {
+        self.settings.send_modify(|settings| settings.namespace_policy = Some(namespace_policy));
+        self
+    }
+
+    /// Enforce CEL policy rules on every issuance and renewal
+    pub fn with_policy(mut self, policy: Arc<PolicyEngine>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Cap the validity of issued certificates instead of trusting what the node asks for
+    ///
+    /// `mode` also applies to the per-profile `max_validity_days`.
+    pub fn with_max_validity(self, max_validity_days: Option<i64>, mode: ValidityMode) -> Self {
+        self.settings.send_modify(|settings| {
+            settings.max_validity_days = max_validity_days;
+            settings.validity_mode = mode;
+        });
+        self
+    }
+
+    /// Issue certificates according to named profiles, using `default_profile` when none is requested
+    pub fn with_profiles(mut self, profiles: Arc<ProfileStore>, default_profile: Option<String>) -> Self {
+        self.profiles = Some(profiles);
+        self.default_profile = default_profile;
+        self
+    }
+
+    /// Record every issuance, renewal and revocation in `audit_log`
+    pub fn with_audit_log(mut self, audit_log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Count the calls the service answers, and their latency, in `metrics`
+    pub fn with_request_metrics(mut self, metrics: Metrics) -> Self {
+        self.request_log = self.request_log.with_metrics(metrics);
+        self
+    }
+
+    /// Point issued certificates at the CRL, OCSP responder and CA certificate
+    pub fn with_issuer_urls(self, issuer_urls: IssuerUrls) -> Self {
+        self.settings.send_modify(|settings| settings.issuer_urls = issuer_urls);
+        self
+    }
+
+    /// Start the validity of issued certificates this long before the time of issuance
+    pub fn with_not_before_backdate(self, backdate: Duration) -> Self {
+        self.settings.send_modify(|settings| settings.not_before_backdate = backdate);
+        self
+    }
+
+    /// Apply `settings` to every request, replacing those of the other builders
+    pub fn with_issuance_settings(self, settings: IssuanceSettings) -> Self {
+        self.settings.send_replace(settings);
+        self
+    }
+
+    /// Sender for new issuance settings, applied to requests from then on
+    pub fn settings_handle(&self) -> Arc<tokio::sync::watch::Sender<IssuanceSettings>> {
+        self.settings.clone()
+    }
+
+    fn settings(&self) -> IssuanceSettings {
+        self.settings.borrow().clone()
+    }
+
+    /// Keep issued certificates and revocations in `store` instead of in memory
+    pub fn with_store(mut self, store: Arc<dyn CertificateStore>) -> Self {
+        info!("Using {} certificate store", store.name());
+        self.store = store;
+        self
+    }
+
+    /// Sign certificates of pods in the given namespaces with their own signer, e.g. a tenant CA
+    ///
+    /// Certificates of other namespaces, and node intermediates, are signed by the default signer.
+    pub fn with_namespace_signers(mut self, namespace_signers: HashMap<String, Arc<dyn Signer>>) -> Self {
+        for (namespace, signer) in &namespace_signers {
+            info!("Using {} signer backend with its own CA for namespace {}", signer.name(), namespace);
+        }
+        self.namespace_signers = namespace_signers;
+        self
+    }
+
+    /// Isolate tenants: each gets its own CA from `tenant_signers` (by CA secret), serials,
+    /// quota and policy rules, and may not request names or certificates of another tenant
+    pub fn with_tenancy(mut self, tenancy: Arc<Tenancy>, tenant_signers: HashMap<String, Arc<dyn Signer>>) -> Self {
+        for (secret, signer) in &tenant_signers {
+            info!("Using {} signer backend with tenant CA {}", signer.name(), secret);
+        }
+        self.tenancy = Some(tenancy);
+        self.tenant_signers = tenant_signers;
+        self
+    }
+
+    /// The signer of certificates for pods in `namespace`, belonging to `tenant`, with its
+    /// key in the CA details cache
+    ///
+    /// A namespace's own CA takes precedence over its tenant's.
+    fn signer(&self, namespace: &str, tenant: Option<&Tenant>) -> Result<(&Arc<dyn Signer>, String)> {
+        if let Some(signer) = self.namespace_signers.get(namespace) {
+            return Ok((signer, namespace.to_string()));
+        }
+        if let Some((tenant, secret)) = tenant.and_then(|t| t.ca_secret.as_ref().map(|secret| (t, secret))) {
+            let signer = self.tenant_signers.get(secret).ok_or_else(|| anyhow::anyhow!(
+                "CA secret {} of tenant {} is not loaded; tenant CAs need the local signer backend and are loaded at startup",
+                secret, tenant.name
+            ))?;
+            return Ok((signer, format!("tenant/{}", secret)));
+        }
+        Ok((&self.signer, String::new()))
+    }
+
+    /// The tenant of `namespace`, if tenants are configured and it belongs to one
+    ///
+    /// Requests without a namespace are refused while tenants are configured, rather than
+    /// getting the default CA and policy.
+    async fn tenant_of(&self, namespace: &str) -> Result<Option<Arc<Tenant>>, Status> {
+        let Some(tenancy) = &self.tenancy else {
+            return Ok(None);
         };
-        
-        service.load_ca().await?;
-        
-        Ok(service)
+        if namespace.is_empty() {
+            return Err(ErrorReason::TenantUnresolved.status(
+                Code::FailedPrecondition,
+                "Certificate request names no namespace, so its tenant cannot be determined",
+            ));
+        }
+        tenancy.tenant_of(namespace).await.map_err(|e| {
+            warn!("Failed to determine the tenant of namespace {}: {:#}", namespace, e);
+            ErrorReason::TenantUnresolved.status(
+                Code::FailedPrecondition,
+                format!("Failed to determine the tenant of namespace {}: {:#}", namespace, e),
+            )
+        })
     }
 
-    async fn load_ca(&self) -> Result<()> {
-        let client = Client::try_default()
-            .await
-            .context("Failed to create Kubernetes client")?;
+    /// Reject a new certificate of `tenant` if it already holds its quota of live certificates
+    ///
+    /// Re-issuing a certificate ID the tenant already holds does not count against the quota.
+    /// This saves signing certificates that cannot be handed out; the quota is enforced when
+    /// the certificate is stored.
+    async fn check_quota(&self, tenant: &Tenant, certificate_id: &str, existing: Option<&CertificateRecord>) -> Result<(), Status> {
+        let Some(max_certificates) = tenant.max_certificates else {
+            return Ok(());
+        };
+        if existing.is_some_and(|record| record.tenant() == Some(tenant.name.as_str())) {
+            return Ok(());
+        }
 
-        let secrets: Api<Secret> = Api::namespaced(client, &self.ca_secret_namespace);
+        let filter = ListFilter {
+            tenant: Some(tenant.name.clone()),
+            expires_after: Some(Utc::now().timestamp()),
+            revocation: RevocationFilter::NotRevoked,
+            ..Default::default()
+        };
+        let held = self.store.list(&filter, max_certificates).await.map_err(store_error)?.len();
+        if held >= max_certificates {
+            warn!("Certificate {} denied: tenant {} holds {} certificates", certificate_id, tenant.name, held);
+            return Err(quota_exceeded(tenant, max_certificates));
+        }
+        Ok(())
+    }
 
-        let secret = secrets
-            .get(&self.ca_secret_name)
+    /// Store a newly issued certificate, within the quota of its tenant if it has one
+    async fn store_issued(&self, record: CertificateRecord, tenant: Option<&Tenant>) -> Result<(), Status> {
+        let Some((tenant, max_certificates)) = tenant.and_then(|t| t.max_certificates.map(|max| (t, max))) else {
+            return self.store.put(record).await.map_err(store_error);
+        };
+        let certificate_id = record.certificate_id.clone();
+        let stored = self
+            .store
+            .put_within_quota(record, &tenant.name, max_certificates, Utc::now().timestamp())
             .await
-            .context("Failed to get CA secret")?;
+            .map_err(store_error)?;
+        if !stored {
+            warn!("Certificate {} discarded: tenant {} reached its quota while it was signed", certificate_id, tenant.name);
+            return Err(quota_exceeded(tenant, max_certificates));
+        }
+        Ok(())
+    }
 
-        let data = secret
-            .data
-            .ok_or_else(|| anyhow::anyhow!("Secret has no data"))?;
+    /// Issue intermediate CAs to node drivers that ask for one
+    pub fn with_node_intermediates(mut self, node_intermediates: NodeIntermediates) -> Self {
+        self.node_intermediates = Some(node_intermediates);
+        self
+    }
 
-        let ca_cert_pem = data
-            .get("tls.crt")
-            .ok_or_else(|| anyhow::anyhow!("Secret missing tls.crt"))?;
-        
-        let ca_cert_str = String::from_utf8(ca_cert_pem.0.clone())
-            .context("Invalid UTF-8 in CA certificate")?;
+    /// Authenticate callers by their service account token
+    pub fn with_authenticator(mut self, authenticator: Arc<Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
 
-        let ca_key_pem = data
-            .get("tls.key")
-            .ok_or_else(|| anyhow::anyhow!("Secret missing tls.key"))?;
-        
-        let ca_key_str = String::from_utf8(ca_key_pem.0.clone())
-            .context("Invalid UTF-8 in CA key")?;
-        
-        let ca_keypair = KeyPair::from_pem(&ca_key_str)
-            .map_err(|e| anyhow::anyhow!("Failed to parse CA key: {}", e))?;
+    /// Let every caller make administrative calls, for a service only reachable on a private socket
+    pub fn with_trusted_callers(mut self) -> Self {
+        self.trusted_callers = true;
+        self
+    }
+
+    /// Events pushed to WatchCertificates callers, e.g. to announce CA rotations
+    pub fn events(&self) -> CertificateEvents {
+        self.events.clone()
+    }
+
+    /// Write the outcome of an operation to the audit log, if one is configured
+    async fn audit(&self, mut record: AuditRecord, metadata: &HashMap<String, String>, issued: Result<(), &Status>) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+
+        record.requester = record.requester.with_metadata(metadata);
+
+        match issued {
+            Ok(()) => {
+                if let Ok(Some(existing)) = self.store.get(&record.certificate_id).await {
+                    record.profile = Some(existing.profile.clone()).filter(|p| !p.is_empty());
+                    record.serial = Some(existing.serial.clone());
+                    record.not_after = Some(existing.not_after);
+                }
+            }
+            Err(status) => record = record.with_status(Some(status)),
+        }
+
+        audit_log.record(record).await;
+    }
+
+    /// Look up the requested profile, falling back to the default profile
+    async fn resolve_profile(&self, name: &str) -> Result<(String, Option<CertificateProfile>), Status> {
+        let name = match (name, &self.default_profile) {
+            ("", Some(default)) => default.as_str(),
+            ("", None) => return Ok((String::new(), None)),
+            (name, _) => name,
+        };
+
+        let Some(profiles) = &self.profiles else {
+            return Err(ErrorReason::UnknownProfile.status(Code::FailedPrecondition, format!(
+                "Profile '{}' requested but no certificate profiles are configured", name
+            )));
+        };
+
+        match profiles.get(name).await {
+            Some(profile) => Ok((name.to_string(), Some(profile))),
+            None => Err(ErrorReason::UnknownProfile.status(
+                Code::InvalidArgument,
+                format!("Unknown certificate profile '{}'", name),
+            )),
+        }
+    }
+
+    /// Validate the requested validity and apply the configured and profile maximums
+    ///
+    /// A request for 0 seconds uses the profile's validity, or the service default.
+    fn effective_validity(
+        &self,
+        certificate_id: &str,
+        requested_seconds: i64,
+        profile: Option<&CertificateProfile>,
+    ) -> Result<i64, Status> {
+        let requested_seconds = match requested_seconds {
+            0 => profile.and_then(|p| p.validity_days).unwrap_or(DEFAULT_VALIDITY_DAYS) * SECONDS_PER_DAY,
+            seconds => seconds,
+        };
+
+        let settings = self.settings();
+        let limits = [settings.max_validity_days, profile.and_then(|p| p.max_validity_days)];
+        let mut seconds = requested_seconds;
+        for max_days in limits.into_iter().flatten() {
+            let limit = ValidityLimit { max_days, mode: settings.validity_mode };
+            seconds = limit.apply(seconds).map_err(|reason| {
+                warn!("Certificate {} denied: {}", certificate_id, reason);
+                ErrorReason::InvalidValidity.status(Code::InvalidArgument, reason)
+            })?;
+        }
+
+        if seconds != requested_seconds {
+            info!(
+                "Clamped validity of {} from {} to {}",
+                certificate_id, format_validity(requested_seconds), format_validity(seconds)
+            );
+        }
+
+        Ok(seconds)
+    }
+
+    /// Parse the requested key usages and EKUs, falling back to the profile's or the defaults
+    ///
+    /// A profile's extended key usages are the most a certificate issued with it may carry.
+    fn effective_usages(
+        key_usages: &[String],
+        extended_key_usages: &[String],
+        profile: Option<&CertificateProfile>,
+    ) -> Result<(Vec<KeyUsage>, Vec<ExtendedKeyUsage>), Status> {
+        let key_usages = match key_usages {
+            [] => default_key_usages(),
+            names => names
+                .iter()
+                .map(|name| name.parse::<KeyUsage>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| ErrorReason::InvalidKeyUsage.status(Code::InvalidArgument, e))?,
+        };
+
+        let allowed = profile.map(|p| p.extended_key_usages.clone());
+        let extended_key_usages = match extended_key_usages {
+            [] => allowed.unwrap_or_else(default_extended_key_usages),
+            names => {
+                let requested: Vec<ExtendedKeyUsage> = names
+                    .iter()
+                    .map(|name| name.parse::<ExtendedKeyUsage>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| ErrorReason::InvalidKeyUsage.status(Code::InvalidArgument, e))?;
+                if let Some(allowed) = allowed {
+                    if let Some(eku) = requested.iter().find(|eku| !allowed.contains(eku)) {
+                        return Err(policy_denied("profile", format!(
+                            "Extended key usage {:?} is not allowed by the certificate profile", eku
+                        )));
+                    }
+                }
+                requested
+            }
+        };
+
+        Ok((key_usages, extended_key_usages))
+    }
+
+    /// Reject the request if the namespace policy, the CA's name constraints, the profile's SAN rules,
+    /// any CEL policy rule or the tenant's rules deny it, or it asks for names of another tenant
+    /// or for wildcard names its profile or namespace does not allow
+    async fn authorize(
+        &self,
+        request: &PolicyRequest,
+        profile: Option<&CertificateProfile>,
+        tenant: Option<&Tenant>,
+    ) -> Result<(), Status> {
+        let names: Vec<&str> = std::iter::once(request.common_name.as_str())
+            .chain(request.dns_names.iter().map(String::as_str))
+            .collect();
+
+        let settings = self.settings();
+        if let Some(namespace_policy) = &settings.namespace_policy {
+            namespace_policy.check(&request.namespace, &names).map_err(|reason| {
+                warn!("Certificate {} denied: {}", request.certificate_id, reason);
+                policy_denied("namespace", format!("Certificate request denied: {}", reason))
+            })?;
+        }
+
+        // A wildcard certificate identifies whatever serves the domain, not one workload
+        if let Some(wildcard) = names.iter().find(|name| name.contains('*')) {
+            let allowed = match profile.and_then(|p| p.allow_wildcards) {
+                Some(allowed) => allowed,
+                None => settings.namespace_policy.as_ref().is_some_and(|p| p.allows_wildcards(&request.namespace)),
+            };
+            if !allowed {
+                warn!("Certificate {} denied: wildcard name {}", request.certificate_id, wildcard);
+                return Err(policy_denied("wildcard", format!(
+                    "Certificate request denied: wildcard name '{}' is not allowed for namespace '{}'",
+                    wildcard, request.namespace
+                )));
+            }
+        }
+
+        if let Some(tenancy) = &self.tenancy {
+            for other in tenancy.tenants().await {
+                if tenant.is_some_and(|tenant| tenant.name == other.name) {
+                    continue;
+                }
+                let owned = names.iter().copied().find(|name| other.owns_name(name))
+                    .or_else(|| request.uris.iter().map(String::as_str).find(|uri| other.owns_uri(uri)));
+                if let Some(name) = owned {
+                    warn!("Certificate {} denied: {} belongs to tenant {}", request.certificate_id, name, other.name);
+                    return Err(policy_denied("tenant", format!(
+                        "Certificate request denied: {} is in a DNS domain of another tenant", name
+                    )));
+                }
+            }
+        }
+
+        // A constrained CA would issue certificates that verifiers reject; refuse them up front
+        let ca_details = self.ca_details(&request.namespace, tenant).await
+            .map_err(|e| ErrorReason::CaNotLoaded.status(
+                Code::FailedPrecondition,
+                format!("Failed to read CA name constraints: {}", e),
+            ))?;
+        if let Some(ca_details) = ca_details {
+            if let Some(constraints) = &ca_details.name_constraints {
+                constraints.check(&request.common_name, &request.dns_names, &request.ip_addresses).map_err(|reason| {
+                    warn!("Certificate {} denied by CA name constraints: {}", request.certificate_id, reason);
+                    policy_denied("nameConstraints", format!("Certificate request denied by CA name constraints: {}", reason))
+                })?;
+            }
+        }
+
+        if let Some(profile) = profile {
+            profile.check_names(&names, &request.ip_addresses, &request.namespace).map_err(|reason| {
+                warn!("Certificate {} denied by profile '{}': {}", request.certificate_id, request.profile, reason);
+                policy_denied("profile", format!("Certificate request denied by profile '{}': {}", request.profile, reason))
+            })?;
+        }
 
-        *self.ca_key.write().await = Some(ca_keypair);
-        *self.ca_cert_pem.write().await = Some(ca_cert_str);
+        if let Some(policy) = &self.policy {
+            policy.evaluate(request).await.map_err(|violation| {
+                warn!("Certificate {} {}", request.certificate_id, violation);
+                policy_denied("cel", format!("Certificate request {}", violation))
+            })?;
+        }
 
-        info!("CA loaded successfully from secret");
+        if let Some(tenant) = tenant {
+            tenant.rules.evaluate(request).map_err(|violation| {
+                warn!("Certificate {} of tenant {} {}", request.certificate_id, tenant.name, violation);
+                policy_denied("tenant", format!("Certificate request of tenant {} {}", tenant.name, violation))
+            })?;
+        }
 
         Ok(())
     }
 
+    /// Details of the CA certificate signing for `namespace` of `tenant`, if it is known locally
+    ///
+    /// Parsed again only when the signer's CA certificate changes, e.g. after a CA rotation.
+    async fn ca_details(&self, namespace: &str, tenant: Option<&Tenant>) -> Result<Option<Arc<CaDetails>>> {
+        let (signer, key) = self.signer(namespace, tenant)?;
+        let Some(cert_pem) = signer.ca_certificate().await else {
+            return Ok(None);
+        };
+
+        let mut cached = self.ca_details.lock().unwrap();
+        if let Some(details) = cached.get(&key).filter(|details| details.cert_pem == cert_pem) {
+            return Ok(Some(details.clone()));
+        }
+
+        let details = Arc::new(CaDetails::parse(cert_pem)?);
+        cached.insert(key, details.clone());
+        Ok(Some(details))
+    }
+
     async fn generate_certificate(
         &self,
         common_name: &str,
         dns_names: Vec<String>,
         ip_addresses: Vec<String>,
+        uris: Vec<String>,
         organizational_units: Vec<String>,
-        validity_days: i64,
-    ) -> Result<(String, String, i64, i64)> {
-        let ca_key_lock = self.ca_key.read().await;
-        let ca_key = ca_key_lock
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("CA key not loaded"))?;
-        
-        let ca_pem_lock = self.ca_cert_pem.read().await;
-        let ca_cert_pem_str = ca_pem_lock
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("CA certificate PEM not loaded"))?;
-
-        // Parse CA certificate to extract DN fields
-        let ca_pems = pem::parse_many(ca_cert_pem_str.as_bytes())
-            .map_err(|e| anyhow::anyhow!("Failed to parse CA cert PEM: {}", e))?;
-        let ca_cert_pem = ca_pems.into_iter().next()
-            .ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
-        let ca_cert_der = CertificateDer::from(ca_cert_pem.contents().to_vec());
-        
-        let (_, ca_cert) = X509Certificate::from_der(&ca_cert_der)
-            .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate: {}", e))?;
-        
-        let ca_org = ca_cert.subject().iter_organization().next().and_then(|o| o.as_str().ok()).map(|s| s);
-        let ca_country = ca_cert.subject().iter_country().next().and_then(|c| c.as_str().ok()).map(|s| s);
-
-        let server_kp = KeyPair::generate()
-            .map_err(|e| anyhow::anyhow!("Failed to generate server key pair: {}", e))?;
+        requested_subject: &Subject,
+        key_usages: &[KeyUsage],
+        extended_key_usages: &[ExtendedKeyUsage],
+        extensions: &[Extension],
+        validity_seconds: i64,
+        profile: Option<&CertificateProfile>,
+        metadata: &HashMap<String, String>,
+        tenant: Option<&Tenant>,
+        purpose: SignPurpose<'_>,
+    ) -> Result<IssuedCertificate> {
+        let namespace = metadata.get("namespace").map(String::as_str).unwrap_or_default();
+        let server_kp = Zeroizing::new(match profile {
+            Some(profile) => profile.key_type.generate()?,
+            None => KeyPair::generate()
+                .map_err(|e| anyhow::anyhow!("Failed to generate server key pair: {}", e))?,
+        });
 
         let mut server_params = CertificateParams::default();
+        let mut subject_name = SubjectName::new();
+
+        // C and O come from the profile, then the request, then the CA when it is known locally
+        let non_empty = |value: &str| Some(value.to_string()).filter(|v| !v.is_empty());
+        let (mut country, mut organization) = (non_empty(&requested_subject.country), non_empty(&requested_subject.organization));
+        if country.is_none() || organization.is_none() {
+            if let Some(ca_details) = self.ca_details(namespace, tenant).await? {
+                country = country.or_else(|| ca_details.country.clone());
+                organization = organization.or_else(|| ca_details.organization.clone());
+            }
+        }
+
+        let subject = profile.map(|p| &p.subject);
+        if let Some(value) = subject.and_then(|s| s.country.as_ref()) {
+            country = Some(expand_subject_value(value, metadata));
+        }
+        if let Some(value) = subject.and_then(|s| s.organization.as_ref()) {
+            organization = Some(expand_subject_value(value, metadata));
+        }
 
         // Build DN in standard X.509 order
-        server_params.distinguished_name.push(DnType::CountryName, ca_country.unwrap_or("DK"));
-        server_params.distinguished_name.push(DnType::OrganizationName, ca_org.unwrap_or("Akuzo"));
-        
-        // Handle organizational units
-        // NOTE: rcgen 0.14 has a CRITICAL LIMITATION where DistinguishedName uses a BTreeMap<DnType, DnValue>,
-        // which fundamentally cannot store multiple values for the same key (DnType::OrganizationalUnitName).
-        //
-        // WORKAROUND: Join all OUs into a single OU field separated by " + "
-        // This is a valid X.509 DN representation where multiple values can be combined.
-        // Example: OU=t:tenantid + e:environment + n:sandbox
-        debug!("Processing {} organizational units", organizational_units.len());
-        
-        if !organizational_units.is_empty() {
-            let combined_ou = organizational_units.join(" + ");
-            debug!("Combined OUs into single field: '{}'", combined_ou);
-            server_params.distinguished_name.push(DnType::OrganizationalUnitName, combined_ou.as_str());
-            info!("Added combined OU with {} components: {}", organizational_units.len(), combined_ou);
+        if let Some(country) = country {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(anyhow::anyhow!("country must be a two-letter country code, got '{}'", country));
+            }
+            subject_name.push(DnType::CountryName, country);
+        }
+        if let Some(province) = non_empty(&requested_subject.province) {
+            subject_name.push(DnType::StateOrProvinceName, province);
+        }
+        if let Some(locality) = non_empty(&requested_subject.locality) {
+            subject_name.push(DnType::LocalityName, locality);
+        }
+        if let Some(organization) = organization {
+            subject_name.push(DnType::OrganizationName, organization);
         }
-        
-        server_params.distinguished_name.push(DnType::CommonName, common_name);
 
-        server_params.subject_alt_names = dns_names
-            .iter()
-            .map(|name| SanType::DnsName(rcgen::string::Ia5String::try_from(name.as_str()).unwrap()))
-            .collect();
+        let organizational_units = match subject.and_then(|s| s.organizational_units.as_ref()) {
+            Some(ous) => ous.iter().map(|ou| expand_subject_value(ou, metadata)).collect(),
+            None => organizational_units,
+        };
+
+        // One OU RDN per organizational unit, so relying parties can match them individually.
+        // rcgen keeps one value per attribute type, so the signer encodes the full subject itself.
+        debug!("Adding {} organizational units: {:?}", organizational_units.len(), organizational_units);
+        for ou in organizational_units {
+            subject_name.push(DnType::OrganizationalUnitName, ou);
+        }
+
+        subject_name.push(DnType::CommonName, common_name);
+        if let Some(serial_number) = non_empty(&requested_subject.serial_number) {
+            subject_name.push(DnType::CustomDnType(SERIAL_NUMBER_OID.to_vec()), serial_number);
+        }
+        server_params.distinguished_name = subject_name.to_distinguished_name();
+
+        for name in dns_names {
+            let name = rcgen::string::Ia5String::try_from(name.as_str())
+                .map_err(|_| anyhow::anyhow!("DNS name '{}' is not ASCII", name))?;
+            server_params.subject_alt_names.push(SanType::DnsName(name));
+        }
 
         for ip in ip_addresses {
-            if let Ok(addr) = ip.parse() {
-                server_params.subject_alt_names.push(SanType::IpAddress(addr));
-            }
+            let addr = ip.parse().map_err(|_| anyhow::anyhow!("Invalid IP address '{}'", ip))?;
+            server_params.subject_alt_names.push(SanType::IpAddress(addr));
         }
 
-        server_params.key_usages = vec![
-            KeyUsagePurpose::DigitalSignature,
-            KeyUsagePurpose::KeyEncipherment,
-            KeyUsagePurpose::KeyAgreement,
-        ];
+        for uri in uris {
+            let uri = rcgen::string::Ia5String::try_from(uri.as_str())
+                .map_err(|_| anyhow::anyhow!("URI '{}' is not ASCII", uri))?;
+            server_params.subject_alt_names.push(SanType::URI(uri));
+        }
+
+        server_params.key_usages = key_usages.iter().map(|usage| usage.purpose()).collect();
+        server_params.extended_key_usages = extended_key_usages.iter().map(|eku| eku.purpose()).collect();
+
+        for extension in extensions {
+            let oid = parse_oid(&extension.oid)
+                .ok_or_else(|| anyhow::anyhow!("Invalid extension OID '{}'", extension.oid))?;
+            server_params.custom_extensions.push(CustomExtension::from_oid_content(&oid, extension.value.clone()));
+        }
 
-        server_params.extended_key_usages = vec![
-            ExtendedKeyUsagePurpose::ServerAuth,
-            ExtendedKeyUsagePurpose::ClientAuth,
-        ];
+        // Explicit CA:FALSE basic constraints; this also makes rcgen write the SubjectKeyIdentifier
+        server_params.is_ca = rcgen::IsCa::ExplicitNoCa;
+        server_params.use_authority_key_identifier_extension = true;
+        let settings = self.settings();
+        settings.issuer_urls.apply(&mut server_params);
 
-        server_params.is_ca = rcgen::IsCa::NoCa;
+        let mut serial = random_serial();
+        // Tenants' serials start with their prefix, so each tenant has a serial space of its own
+        if let Some(tenant) = tenant {
+            serial[..tenant.serial_prefix.len()].copy_from_slice(&tenant.serial_prefix);
+        }
+        server_params.serial_number = Some(SerialNumber::from_slice(&serial));
 
-        let not_before = Utc::now();
-        let not_after = not_before + Duration::days(validity_days);
+        // Backdate notBefore so peers with a slightly slow clock accept the certificate right away;
+        // the requested validity still counts from now
+        let now = Utc::now();
+        let not_before = now - settings.not_before_backdate;
+        let not_after = now + Duration::seconds(validity_seconds);
         
         use std::time::SystemTime;
         let not_before_system: SystemTime = not_before.into();
@@ -189,179 +755,1643 @@ impl CertificateServiceImpl {
         server_params.not_before = time::OffsetDateTime::from(not_before_system);
         server_params.not_after = time::OffsetDateTime::from(not_after_system);
 
-        // Sign the server certificate with the CA
-        let ca_issuer = rcgen::Issuer::from_ca_cert_der(&ca_cert_der, ca_key)
-            .map_err(|e| anyhow::anyhow!("Failed to create issuer from CA cert: {}", e))?;
-        let server_cert_signed = server_params.signed_by(&server_kp, &ca_issuer)
-            .map_err(|e| anyhow::anyhow!("Failed to sign certificate with CA: {}", e))?;
-        let server_cert_der: Vec<u8> = server_cert_signed.der().to_vec();
-        
-        let server_cert_pem = pem::encode(&pem::Pem::new("CERTIFICATE", server_cert_der));
-        let server_key_pem = server_kp.serialize_pem();
+        // Sign the server certificate with the backend of the pod's namespace
+        let (signer, _) = self.signer(namespace, tenant)?;
+        let server_cert_pem = signer.sign(server_params, &subject_name, &server_kp, purpose).await?;
+        let server_key_pem = Zeroizing::new(server_kp.serialize_pem());
+
+        // Remote backends may adjust the validity period and assign their own serial,
+        // so report what was actually issued
+        let (serial, not_before, not_after) = certificate_validity(&server_cert_pem)?;
 
         // 02 - bug, do not include CA cert in chain for now
         //let cert_chain = format!("{}\n{}", server_cert_pem.trim(), ca_cert_pem_str.trim());
 
-        Ok((
-            server_cert_pem, //cert_chain,
-            server_key_pem,
-            not_before.timestamp(),
-            not_after.timestamp(),
-        ))
+        Ok(IssuedCertificate {
+            cert_pem: server_cert_pem, //cert_chain,
+            key_pem: server_key_pem,
+            serial,
+            not_before,
+            not_after,
+        })
     }
 }
 
-#[tonic::async_trait]
-impl CertificateService for CertificateServiceImpl {
-    async fn issue_certificate(
-        &self,
-        request: Request<IssueCertificateRequest>,
-    ) -> Result<Response<IssueCertificateResponse>, Status> {
-        let req = request.into_inner();
-        
-        info!("Issuing certificate: {}", req.certificate_id);
+/// `metadata` with the `tenant` entry set to `tenant`, or removed without one
+///
+/// The tenant is always determined by the service, never taken from the request.
+fn with_tenant(mut metadata: HashMap<String, String>, tenant: Option<&Tenant>) -> HashMap<String, String> {
+    match tenant {
+        Some(tenant) => metadata.insert("tenant".to_string(), tenant.name.clone()),
+        None => metadata.remove("tenant"),
+    };
+    metadata
+}
+
+/// Extract the organization and country from the CA certificate subject
+fn ca_subject_fields(ca_cert_pem: &str) -> Result<(Option<String>, Option<String>)> {
+    let (_, pem) = parse_x509_pem(ca_cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse CA cert PEM: {}", e))?;
+    let ca_cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate: {}", e))?;
+
+    let ca_org = ca_cert.subject().iter_organization().next().and_then(|o| o.as_str().ok()).map(String::from);
+    let ca_country = ca_cert.subject().iter_country().next().and_then(|c| c.as_str().ok()).map(String::from);
+
+    Ok((ca_org, ca_country))
+}
+
+/// Read the serial number (lowercase hex) and validity period (unix timestamps) from a PEM certificate
+fn certificate_validity(cert_pem: &str) -> Result<(String, i64, i64)> {
+    let (_, pem) = parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse issued certificate PEM: {}", e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse issued certificate: {}", e))?;
+
+    let serial = cert.raw_serial().iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok((serial, cert.validity().not_before.timestamp(), cert.validity().not_after.timestamp()))
+}
+
+impl CertificateServiceImpl {
+    /// Issue a certificate, once for identical requests that arrive while it is being issued
+    ///
+    /// A node whose call timed out retries it while the first is still signing; the retry
+    /// gets the same certificate and key instead of replacing them with another.
+    async fn issue_coalesced(&self, req: IssueCertificateRequest) -> Result<IssueCertificateResponse, Status> {
+        let mut in_flight = self.issue_locks.lock(&req.certificate_id).await;
+        if let Some(response) = self.replay(&req).await? {
+            return Ok(response);
+        }
+        if let Some((first, response)) = in_flight.as_ref().filter(|(first, _)| *first == req) {
+            info!("Certificate {} was just issued to an identical request, returning it", first.certificate_id);
+            return Ok(response.clone());
+        }
+
+        let result = self.issue(req.clone()).await;
+        if let Ok(response) = &result {
+            self.remember(&req, response);
+        }
+        // Only kept while someone waits, as the lock and the key go away with the last one
+        let share = in_flight.has_waiters();
+        *in_flight = match &result {
+            Ok(response) if share => Some((req, response.clone())),
+            _ => None,
+        };
+        result
+    }
+
+    /// The certificate issued to an earlier request with the same idempotency key, while it
+    /// is still the current one
+    async fn replay(&self, req: &IssueCertificateRequest) -> Result<Option<IssueCertificateResponse>, Status> {
+        if req.idempotency_key.is_empty() {
+            return Ok(None);
+        }
+        let recent = {
+            let recent_issues = self.recent_issues.lock().unwrap();
+            match recent_issues.get(&req.certificate_id) {
+                Some(recent) if recent.request.idempotency_key == req.idempotency_key && recent.issued_at.elapsed() < IDEMPOTENCY_WINDOW => {
+                    // A retry repeats the request; the same key on another one is a client bug
+                    if recent.request != *req {
+                        warn!("Certificate {} requested again with the same idempotency key but other parameters", req.certificate_id);
+                        return Err(Status::invalid_argument(format!(
+                            "Idempotency key of certificate {} was already used for a different request", req.certificate_id
+                        )));
+                    }
+                    (recent.serial.clone(), recent.cert_pem.clone(), recent.key_pem.clone(), recent.not_before, recent.not_after)
+                }
+                _ => return Ok(None),
+            }
+        };
+        let (serial, certificate_pem, key_pem, not_before, not_after) = recent;
+
+        // Renewed or revoked since: the retry must not get a certificate that was replaced
+        let current = self.store.get(&req.certificate_id).await.map_err(store_error)?;
+        if current.is_none_or(|record| record.serial != serial)
+            || self.store.revocation(&serial).await.map_err(store_error)?.is_some()
+        {
+            return Ok(None);
+        }
+
+        info!("Returning certificate {} issued to an earlier request with the same idempotency key", req.certificate_id);
+        Ok(Some(IssueCertificateResponse {
+            certificate_pem,
+            private_key_pem: key_pem.to_string(),
+            certificate_id: req.certificate_id.clone(),
+            not_before,
+            not_after,
+        }))
+    }
+
+    /// Keep an issuance for retries with the same idempotency key
+    fn remember(&self, req: &IssueCertificateRequest, response: &IssueCertificateResponse) {
+        if req.idempotency_key.is_empty() {
+            return;
+        }
+        let Ok((serial, _, _)) = certificate_validity(&response.certificate_pem) else {
+            return;
+        };
+
+        let mut recent_issues = self.recent_issues.lock().unwrap();
+        recent_issues.retain(|_, recent| recent.issued_at.elapsed() < IDEMPOTENCY_WINDOW);
+        if recent_issues.len() >= MAX_RECENT_ISSUES && !recent_issues.contains_key(&req.certificate_id) {
+            let oldest = recent_issues.iter().min_by_key(|(_, recent)| recent.issued_at).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                recent_issues.remove(&oldest);
+            }
+        }
+        recent_issues.insert(req.certificate_id.clone(), RecentIssue {
+            request: req.clone(),
+            issued_at: std::time::Instant::now(),
+            serial,
+            cert_pem: response.certificate_pem.clone(),
+            key_pem: Zeroizing::new(response.private_key_pem.clone()),
+            not_before: response.not_before,
+            not_after: response.not_after,
+        });
+    }
+
+    /// Fail with ALREADY_EXISTS while `certificate_id` has a certificate that is neither expired nor revoked
+    ///
+    /// The service keeps private keys only briefly for retries, so issuing again would silently
+    /// replace the certificate whose key the caller may still be using.
+    async fn check_not_issued(&self, certificate_id: &str) -> Result<(), Status> {
+        let Some(existing) = self.store.get(certificate_id).await.map_err(store_error)? else {
+            return Ok(());
+        };
+        if existing.not_after <= Utc::now().timestamp()
+            || self.store.revocation(&existing.serial).await.map_err(store_error)?.is_some()
+        {
+            return Ok(());
+        }
+
+        warn!("Certificate {} already has valid certificate {}, not replacing it", certificate_id, existing.serial);
+        let not_after = chrono::DateTime::from_timestamp(existing.not_after, 0).unwrap_or_default().to_rfc3339();
+        Err(ErrorInfo::new(ErrorReason::AlreadyIssued)
+            .with_metadata("serial", &existing.serial)
+            .with_metadata("notAfter", &not_after)
+            .into_status(Code::AlreadyExists, format!(
+                "Certificate {} already has a valid certificate (serial {}, expires {}); set replace_existing to replace it",
+                certificate_id, existing.serial, not_after
+            )))
+    }
+
+    async fn issue(&self, req: IssueCertificateRequest) -> Result<IssueCertificateResponse, Status> {
         debug!("Common name: {}", req.common_name);
         debug!("DNS names: {:?}", req.dns_names);
+        debug!("IP addresses: {:?}", req.ip_addresses);
+        debug!("URIs: {:?}", req.uris);
         debug!("Organizational units: {:?}", req.organizational_units);
 
+        validate_issue_request(&req)?;
+        let subject = req.subject.clone().unwrap_or_default();
+
+        let (profile_name, profile) = self.resolve_profile(&req.profile).await?;
+        // Checked before signing, so the client is not left with a key it cannot write
+        if !req.key_encoding.is_empty() {
+            let key_type = profile.as_ref().map(|profile| profile.key_type).unwrap_or_default();
+            let key_encoding = req.key_encoding.parse::<KeyEncoding>()
+                .map_err(|e| ErrorReason::InvalidKeyEncoding.status(Code::InvalidArgument, e))?;
+            if !key_type.has_encoding(key_encoding) {
+                return Err(ErrorReason::InvalidKeyEncoding.status(Code::InvalidArgument, format!(
+                    "{:?} keys cannot be written as {}", key_type, key_encoding.as_str()
+                )));
+            }
+        }
+        let requested_validity = requested_validity_seconds(req.validity_seconds, req.validity_days)?;
+        let validity_seconds = self.effective_validity(&req.certificate_id, requested_validity, profile.as_ref())?;
+        let (key_usages, extended_key_usages) =
+            Self::effective_usages(&req.key_usages, &req.extended_key_usages, profile.as_ref())?;
+
+        let namespace = req.metadata.get("namespace").cloned().unwrap_or_default();
+        let tenant = self.tenant_of(&namespace).await?;
+        let metadata = with_tenant(req.metadata.clone(), tenant.as_deref());
+        if self.tenancy.is_some() {
+            let existing = self.store.get(&req.certificate_id).await.map_err(store_error)?;
+            if let Some(owner) = existing.as_ref().and_then(CertificateRecord::tenant) {
+                if tenant.as_ref().is_none_or(|tenant| tenant.name != owner) {
+                    warn!("Certificate {} denied: it belongs to tenant {}", req.certificate_id, owner);
+                    return Err(policy_denied("tenant", format!(
+                        "Certificate {} belongs to another tenant", req.certificate_id
+                    )));
+                }
+            }
+            if let Some(tenant) = &tenant {
+                self.check_quota(tenant, &req.certificate_id, existing.as_ref()).await?;
+            }
+        }
+
+        self.authorize(&PolicyRequest {
+            certificate_id: req.certificate_id.clone(),
+            common_name: req.common_name.clone(),
+            dns_names: req.dns_names.clone(),
+            ip_addresses: req.ip_addresses.clone(),
+            uris: req.uris.clone(),
+            organizational_units: req.organizational_units.clone(),
+            key_usages: key_usages.clone(),
+            extended_key_usages: extended_key_usages.clone(),
+            extension_oids: req.extensions.iter().map(|e| e.oid.clone()).collect(),
+            namespace,
+            validity_days: days_rounded_up(validity_seconds),
+            validity_seconds,
+            metadata: metadata.clone(),
+            profile: profile_name.clone(),
+            renewal: false,
+        }, profile.as_ref(), tenant.as_deref()).await?;
+
+        // Clients without idempotency keys predate duplicate detection and expect a new certificate
+        if !req.idempotency_key.is_empty() && !req.replace_existing {
+            self.check_not_issued(&req.certificate_id).await?;
+        }
+
         match self
             .generate_certificate(
                 &req.common_name,
                 req.dns_names.clone(),
                 req.ip_addresses.clone(),
+                req.uris.clone(),
                 req.organizational_units.clone(),
-                req.validity_days,
+                &subject,
+                &key_usages,
+                &extended_key_usages,
+                &req.extensions,
+                validity_seconds,
+                profile.as_ref(),
+                &metadata,
+                tenant.as_deref(),
+                SignPurpose::Issue,
             )
             .await
         {
-            Ok((cert_pem, key_pem, not_before, not_after)) => {
+            Ok(mut issued) => {
+                let (not_before, not_after) = (issued.not_before, issued.not_after);
                 let record = CertificateRecord {
                     certificate_id: req.certificate_id.clone(),
                     common_name: req.common_name.clone(),
                     dns_names: req.dns_names.clone(),
+                    ip_addresses: req.ip_addresses.clone(),
+                    uris: req.uris.clone(),
                     organizational_units: req.organizational_units.clone(),
+                    subject,
+                    key_usages: req.key_usages.clone(),
+                    extended_key_usages: req.extended_key_usages.clone(),
+                    extensions: req.extensions.clone(),
+                    validity_seconds: requested_validity,
                     not_before,
                     not_after,
-                    metadata: req.metadata.clone(),
+                    metadata,
+                    profile: profile_name,
+                    serial: issued.serial.clone(),
                 };
 
-                self.certificates.insert(req.certificate_id.clone(), record);
+                self.store_issued(record, tenant.as_deref()).await?;
+                self.events.renewed(&req.certificate_id, &issued.serial, not_after);
 
-                info!("Certificate issued successfully: {}", req.certificate_id);
+                debug!("Certificate {} issued, serial {}", req.certificate_id, issued.serial);
 
                 let response = IssueCertificateResponse {
-                    certificate_pem: cert_pem,
-                    private_key_pem: key_pem,
+                    certificate_pem: issued.cert_pem,
+                    // Hand the buffer over instead of copying it; tonic owns it from here
+                    private_key_pem: std::mem::take(&mut *issued.key_pem),
                     certificate_id: req.certificate_id,
                     not_before,
                     not_after,
                 };
 
-                Ok(Response::new(response))
+                Ok(response)
             }
             Err(e) => {
                 error!("Failed to issue certificate: {}", e);
-                Err(Status::internal(format!("Failed to issue certificate: {}", e)))
+                Err(ErrorReason::SigningFailed.status(Code::Internal, format!("Failed to issue certificate: {}", e)))
             }
         }
     }
 
-    async fn renew_certificate(
-        &self,
-        request: Request<RenewCertificateRequest>,
-    ) -> Result<Response<RenewCertificateResponse>, Status> {
-        let req = request.into_inner();
-        
-        info!("Renewing certificate: {}", req.certificate_id);
-
+    async fn renew(&self, caller: &MetadataMap, mut req: RenewCertificateRequest) -> Result<RenewCertificateResponse, Status> {
         let existing = self
-            .certificates
+            .store
             .get(&req.certificate_id)
+            .await
+            .map_err(store_error)?
             .ok_or_else(|| Status::not_found("Certificate not found"))?;
+        if self.requesting_namespace(caller, &existing.metadata).await? != existing.namespace().unwrap_or_default() {
+            warn!("Renewal of certificate {} denied: the caller may not call for its namespace", req.certificate_id);
+            return Err(ErrorReason::CallerNotAllowed.status(Code::PermissionDenied, format!(
+                "Certificate {} is not of a namespace the caller may call for", req.certificate_id
+            )));
+        }
 
         let common_name = existing.common_name.clone();
         let dns_names = existing.dns_names.clone();
+        let ip_addresses = existing.ip_addresses.clone();
+        let uris = existing.uris.clone();
         let organizational_units = existing.organizational_units.clone();
-        
-        drop(existing);
+        let subject = existing.subject.clone();
+        let requested_key_usages = existing.key_usages.clone();
+        let requested_extended_key_usages = existing.extended_key_usages.clone();
+        let extensions = existing.extensions.clone();
+        let previous_validity = existing.validity_seconds;
+        let namespace = existing.namespace().unwrap_or_default().to_string();
+        let profile_name = existing.profile.clone();
+        let revoked = self.store.revocation(&existing.serial).await.map_err(store_error)?.is_some();
 
-        match self
-            .generate_certificate(
-                &common_name,
-                dns_names.clone(),
-                vec![],
+        // Renewal would silently undo a revocation, so nodes have to ask for a replacement explicitly
+        if revoked && !req.replace_revoked {
+            return Err(ErrorReason::CertificateRevoked.status(Code::FailedPrecondition, format!(
+                "Certificate {} was revoked", req.certificate_id
+            )));
+        }
+        if revoked {
+            self.check_may_replace_revoked(caller, &req.certificate_id).await?;
+        }
+
+        // A namespace that moved to another tenant must not keep certificates of the previous one
+        let tenant = self.tenant_of(&namespace).await?;
+        if let Some(owner) = existing.tenant() {
+            if tenant.as_ref().is_none_or(|tenant| tenant.name != owner) {
+                warn!("Renewal of certificate {} denied: it belongs to tenant {}", req.certificate_id, owner);
+                return Err(policy_denied("tenant", format!(
+                    "Certificate {} belongs to tenant {}, which namespace {} no longer belongs to",
+                    req.certificate_id, owner, namespace
+                )));
+            }
+        }
+        let metadata = with_tenant(existing.metadata.clone(), tenant.as_deref());
+        let current = (!req.current_certificate_pem.is_empty() && !req.current_private_key_pem.is_empty())
+            .then(|| CurrentCertificate {
+                cert_pem: std::mem::take(&mut req.current_certificate_pem),
+                key_pem: Zeroizing::new(std::mem::take(&mut req.current_private_key_pem)),
+            });
+        // A revoked certificate cannot vouch for its replacement, which is enrolled anew
+        let purpose = if revoked { SignPurpose::Issue } else { SignPurpose::Renew(current.as_ref()) };
+        let needs_current = self.signer(&namespace, tenant.as_deref())
+            .is_ok_and(|(signer, _)| signer.needs_current_certificate());
+
+        // Profiles are re-read on renewal so central changes reach existing certificates
+        let (profile_name, profile) = self.resolve_profile(&profile_name).await?;
+        let requested_validity = match requested_validity_seconds(req.validity_seconds, req.validity_days)? {
+            0 => previous_validity,
+            seconds => seconds,
+        };
+        let validity_seconds = self.effective_validity(&req.certificate_id, requested_validity, profile.as_ref())?;
+        let (key_usages, extended_key_usages) =
+            Self::effective_usages(&requested_key_usages, &requested_extended_key_usages, profile.as_ref())?;
+
+        self.authorize(&PolicyRequest {
+            certificate_id: req.certificate_id.clone(),
+            common_name: common_name.clone(),
+            dns_names: dns_names.clone(),
+            ip_addresses: ip_addresses.clone(),
+            uris: uris.clone(),
+            organizational_units: organizational_units.clone(),
+            key_usages: key_usages.clone(),
+            extended_key_usages: extended_key_usages.clone(),
+            extension_oids: extensions.iter().map(|e| e.oid.clone()).collect(),
+            namespace,
+            validity_days: days_rounded_up(validity_seconds),
+            validity_seconds,
+            metadata: metadata.clone(),
+            profile: profile_name,
+            renewal: true,
+        }, profile.as_ref(), tenant.as_deref()).await?;
+
+        // Nodes only send the key of the certificate being renewed when asked for it
+        if needs_current && !revoked && current.is_none() {
+            return Err(ErrorReason::CurrentKeyRequired.status(Code::FailedPrecondition, format!(
+                "The signer re-enrolls certificate {} with its current certificate and key; renew with them", req.certificate_id
+            )));
+        }
+
+        match self
+            .generate_certificate(
+                &common_name,
+                dns_names.clone(),
+                ip_addresses,
+                uris,
                 organizational_units.clone(),
-                req.validity_days,
+                &subject,
+                &key_usages,
+                &extended_key_usages,
+                &extensions,
+                validity_seconds,
+                profile.as_ref(),
+                &metadata,
+                tenant.as_deref(),
+                purpose,
             )
             .await
         {
-            Ok((cert_pem, key_pem, not_before, not_after)) => {
-                if let Some(mut record) = self.certificates.get_mut(&req.certificate_id) {
-                    record.not_before = not_before;
-                    record.not_after = not_after;
-                }
+            Ok(mut issued) => {
+                let (not_before, not_after) = (issued.not_before, issued.not_after);
+                let record = CertificateRecord {
+                    validity_seconds: requested_validity,
+                    not_before,
+                    not_after,
+                    metadata,
+                    serial: issued.serial.clone(),
+                    ..existing
+                };
+                self.store.put(record).await.map_err(store_error)?;
+                self.events.renewed(&req.certificate_id, &issued.serial, not_after);
 
-                info!("Certificate renewed successfully: {}", req.certificate_id);
+                debug!("Certificate {} renewed, serial {}", req.certificate_id, issued.serial);
 
                 let response = RenewCertificateResponse {
-                    certificate_pem: cert_pem,
-                    private_key_pem: key_pem,
+                    certificate_pem: issued.cert_pem,
+                    // Hand the buffer over instead of copying it; tonic owns it from here
+                    private_key_pem: std::mem::take(&mut *issued.key_pem),
                     not_before,
                     not_after,
                 };
 
-                Ok(Response::new(response))
+                Ok(response)
             }
             Err(e) => {
                 error!("Failed to renew certificate: {}", e);
-                Err(Status::internal(format!("Failed to renew certificate: {}", e)))
+                Err(ErrorReason::SigningFailed.status(Code::Internal, format!("Failed to renew certificate: {}", e)))
+            }
+        }
+    }
+
+    async fn revoke(&self, caller: &MetadataMap, req: RevokeCertificateRequest) -> Result<RevokeCertificateResponse, Status> {
+        let reason = RevocationReason::try_from(req.reason)
+            .map_err(|_| Status::invalid_argument(format!("Unknown revocation reason {}", req.reason)))?;
+
+        let record = self
+            .store
+            .get(&req.certificate_id)
+            .await
+            .map_err(store_error)?
+            .ok_or_else(|| Status::not_found("Certificate not found"))?;
+        self.check_may_access(caller, &record, "Revocation", true).await?;
+        let serial = record.serial;
+
+        // Revoking again keeps the original reason and time
+        let revocation = self
+            .store
+            .revoke(&serial, Revocation {
+                certificate_id: req.certificate_id.clone(),
+                reason,
+                revoked_at: Utc::now().timestamp(),
+            })
+            .await
+            .map_err(store_error)?;
+
+        debug!("Certificate {} revoked ({}), serial {}", revocation.certificate_id, revocation.reason.short_name(), serial);
+        self.events.revoked(&revocation.certificate_id, &serial, record.not_after, revocation.reason);
+
+        Ok(RevokeCertificateResponse {
+            success: true,
+            serial,
+            revoked_at: revocation.revoked_at,
+        })
+    }
+
+    /// Look up a certificate and its revocation status
+    async fn get_info(&self, caller: &MetadataMap, certificate_id: &str) -> Result<GetCertificateInfoResponse, Status> {
+        let record = self
+            .store
+            .get(certificate_id)
+            .await
+            .map_err(store_error)?
+            .ok_or_else(|| Status::not_found("Certificate not found"))?;
+        self.check_may_access(caller, &record, "Lookup", false).await?;
+        let revocation = self.store.revocation(&record.serial).await.map_err(store_error)?;
+
+        Ok(Self::certificate_info(&record, revocation.as_ref()))
+    }
+
+    /// Report a certificate record with its revocation status
+    fn certificate_info(record: &CertificateRecord, revocation: Option<&Revocation>) -> GetCertificateInfoResponse {
+        let now = Utc::now().timestamp();
+        let is_valid = revocation.is_none() && now >= record.not_before && now <= record.not_after;
+
+        GetCertificateInfoResponse {
+            certificate_id: record.certificate_id.clone(),
+            common_name: record.common_name.clone(),
+            dns_names: record.dns_names.clone(),
+            not_before: record.not_before,
+            not_after: record.not_after,
+            is_valid,
+            metadata: record.metadata.clone(),
+            serial: record.serial.clone(),
+            revoked: revocation.is_some(),
+            revocation_reason: revocation.map(|r| r.reason as i32).unwrap_or_default(),
+            revoked_at: revocation.map(|r| r.revoked_at).unwrap_or_default(),
+        }
+    }
+
+    /// One page of the certificates matching the request's filters, ordered by certificate ID
+    ///
+    /// The page token is the last certificate ID of the previous page, so paging stays
+    /// consistent while certificates are issued and removed.
+    async fn list(&self, req: ListCertificatesRequest) -> Result<ListCertificatesResponse, Status> {
+        let revocation_filter = RevocationFilter::try_from(req.revocation)
+            .map_err(|_| Status::invalid_argument(format!("Unknown revocation filter {}", req.revocation)))?;
+        if req.page_size < 0 {
+            return Err(Status::invalid_argument("page_size must not be negative"));
+        }
+        if req.expiring_within_seconds < 0 {
+            return Err(Status::invalid_argument("expiring_within_seconds must not be negative"));
+        }
+
+        let page_size = match req.page_size as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+        let expiring_before = (req.expiring_within_seconds > 0)
+            .then(|| Utc::now().timestamp() + req.expiring_within_seconds);
+
+        let filter = ListFilter {
+            after: req.page_token,
+            namespace: Some(req.namespace).filter(|namespace| !namespace.is_empty()),
+            expiring_before,
+            revocation: revocation_filter,
+            ..Default::default()
+        };
+        // One more than the page size tells whether there is a next page
+        let mut matching: Vec<GetCertificateInfoResponse> = self
+            .store
+            .list(&filter, page_size + 1)
+            .await
+            .map_err(store_error)?
+            .iter()
+            .map(|(record, revocation)| Self::certificate_info(record, revocation.as_ref()))
+            .collect();
+
+        let next_page_token = if matching.len() > page_size {
+            matching.truncate(page_size);
+            matching.last().map(|info| info.certificate_id.clone()).unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        Ok(ListCertificatesResponse {
+            certificates: matching,
+            next_page_token,
+        })
+    }
+}
+
+/// Certificate store failures are worth a retry, possibly on another replica
+fn store_error(e: anyhow::Error) -> Status {
+    error!("Certificate store error: {:#}", e);
+    ErrorReason::StoreUnavailable.status(Code::Unavailable, format!("Certificate store unavailable: {}", e))
+}
+
+/// RESOURCE_EXHAUSTED as `tenant` holds its quota of certificates
+fn quota_exceeded(tenant: &Tenant, max_certificates: usize) -> Status {
+    ErrorInfo::new(ErrorReason::QuotaExceeded)
+        .with_metadata("tenant", &tenant.name)
+        .with_metadata("maxCertificates", max_certificates.to_string())
+        .into_status(Code::ResourceExhausted, format!(
+            "Tenant {} already holds its maximum of {} certificates", tenant.name, max_certificates
+        ))
+}
+
+/// PERMISSION_DENIED by `policy`, which the error details name for callers
+fn policy_denied(policy: &str, message: String) -> Status {
+    ErrorInfo::new(ErrorReason::PolicyDenied)
+        .with_metadata("policy", policy)
+        .into_status(Code::PermissionDenied, message)
+}
+
+impl CertificateServiceImpl {
+    /// The namespace a certificate call with request `metadata` is made for
+    ///
+    /// With an authenticator, the namespace the caller may call for (see
+    /// [`Authenticator::namespace`]); without one, as on the private socket of the embedded
+    /// service, the namespace the node put in `metadata`.
+    async fn requesting_namespace(&self, caller: &MetadataMap, metadata: &HashMap<String, String>) -> Result<String, Status> {
+        let namespace = metadata.get("namespace").map(String::as_str).unwrap_or_default();
+        match &self.authenticator {
+            Some(authenticator) => {
+                let pod = metadata.get("pod").map(String::as_str).unwrap_or_default();
+                authenticator.namespace(caller, namespace, pod).await
+            }
+            None => Ok(namespace.to_string()),
+        }
+    }
+
+    /// The node of the node driver making a call, which only authenticated callers have
+    async fn calling_node(&self, metadata: &MetadataMap) -> Result<String, Status> {
+        match &self.authenticator {
+            Some(authenticator) => authenticator.node(metadata).await,
+            None => Err(ErrorReason::CallerNotAllowed.status(
+                Code::Unauthenticated,
+                "The certificate service does not authenticate callers (NODE_SERVICE_ACCOUNTS)",
+            )),
+        }
+    }
+
+    /// Refuse administrative calls, which span namespaces, unless an administrator makes them
+    async fn check_admin(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        if self.trusted_callers {
+            return Ok(());
+        }
+        match &self.authenticator {
+            Some(authenticator) => authenticator.admin(metadata).await.map(|_| ()),
+            None => Err(ErrorReason::CallerNotAllowed.status(
+                Code::Unauthenticated,
+                "The certificate service does not authenticate administrators (ADMIN_SERVICE_ACCOUNTS)",
+            )),
+        }
+    }
+
+    /// Refuse calls about `record` unless its namespace is one the caller may call for, or an administrator makes them
+    ///
+    /// With `gone_pods`, node drivers may also call about the certificates of pods that no
+    /// longer exist, to revoke them on pod deletion. `action` names the call in the log.
+    async fn check_may_access(&self, caller: &MetadataMap, record: &CertificateRecord, action: &str, gone_pods: bool) -> Result<(), Status> {
+        let namespace = self.requesting_namespace(caller, &record.metadata).await;
+        if namespace.is_ok_and(|namespace| namespace == record.namespace().unwrap_or_default()) {
+            return Ok(());
+        }
+        if self.check_admin(caller).await.is_ok() {
+            return Ok(());
+        }
+        if let (true, Some(authenticator)) = (gone_pods, &self.authenticator) {
+            let pod = record.metadata.get("pod").map(String::as_str).unwrap_or_default();
+            if authenticator.is_node_driver_for_gone_pod(caller, record.namespace().unwrap_or_default(), pod).await {
+                return Ok(());
+            }
+        }
+        warn!("{} of certificate {} denied: the caller may not call for its namespace", action, record.certificate_id);
+        Err(ErrorReason::CallerNotAllowed.status(Code::PermissionDenied, format!(
+            "Certificate {} is not of a namespace the caller may call for", record.certificate_id
+        )))
+    }
+
+    /// Refuse replacing the revoked certificate `certificate_id` unless a node driver asks
+    ///
+    /// Node drivers replace the revoked certificates of the pods on their node; the workload
+    /// itself, which may hold the compromised key, must not get its identity back.
+    async fn check_may_replace_revoked(&self, caller: &MetadataMap, certificate_id: &str) -> Result<(), Status> {
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(());
+        };
+        if self.trusted_callers || authenticator.node(caller).await.is_ok() {
+            return Ok(());
+        }
+        warn!("Replacement of revoked certificate {} denied: the caller is not a node driver", certificate_id);
+        Err(ErrorReason::CallerNotAllowed.status(Code::PermissionDenied, format!(
+            "Only node drivers may replace revoked certificate {}", certificate_id
+        )))
+    }
+
+    /// Issue an intermediate CA for `req.node_id` to the driver on `caller_node`
+    async fn issue_node_intermediate(
+        &self,
+        caller_node: Result<String, Status>,
+        req: IssueNodeIntermediateRequest,
+    ) -> Result<IssueNodeIntermediateResponse, Status> {
+        let intermediates = self
+            .node_intermediates
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("Node intermediates are not enabled on the certificate service"))?;
+        if !is_valid_node_id(&req.node_id) {
+            return Err(Status::invalid_argument(format!("Invalid node ID '{}'", req.node_id)));
+        }
+        let caller_node = caller_node?;
+        if caller_node != req.node_id {
+            warn!("Driver on node {} asked for the intermediate CA of node {}", caller_node, req.node_id);
+            return Err(ErrorReason::CallerNotAllowed.status(
+                Code::PermissionDenied,
+                format!("Caller runs on node {}, not {}", caller_node, req.node_id),
+            ));
+        }
+
+        let result: Result<_> = async {
+            let key_pair = Zeroizing::new(
+                KeyPair::generate().map_err(|e| anyhow::anyhow!("Failed to generate intermediate key pair: {}", e))?,
+            );
+
+            let now = Utc::now();
+            let settings = self.settings();
+            let (mut params, subject) = intermediates.params(
+                &req.node_id,
+                now - settings.not_before_backdate,
+                now + Duration::seconds(intermediates.validity_seconds),
+            );
+            settings.issuer_urls.apply(&mut params);
+
+            let cert_pem = self.signer.sign(params, &subject, &key_pair, SignPurpose::Issue).await?;
+            let (_, not_before, not_after) = certificate_validity(&cert_pem)?;
+            Ok((cert_pem, Zeroizing::new(key_pair.serialize_pem()), not_before, not_after))
+        }
+        .await;
+
+        match result {
+            Ok((cert_pem, mut key_pem, not_before, not_after)) => {
+                debug!("Intermediate CA issued for node {}, expires {}", req.node_id, not_after);
+                Ok(IssueNodeIntermediateResponse {
+                    certificate_pem: cert_pem,
+                    private_key_pem: std::mem::take(&mut *key_pem),
+                    not_before,
+                    not_after,
+                })
+            }
+            Err(e) => {
+                error!("Failed to issue intermediate CA for node {}: {}", req.node_id, e);
+                Err(ErrorReason::SigningFailed.status(Code::Internal, format!("Failed to issue intermediate CA: {}", e)))
             }
         }
     }
+}
+
+#[tonic::async_trait]
+impl CertificateService for CertificateServiceImpl {
+    async fn issue_certificate(
+        &self,
+        request: Request<IssueCertificateRequest>,
+    ) -> Result<Response<IssueCertificateResponse>, Status> {
+        let call = Call::new("IssueCertificate", &request);
+        let peer = request.remote_addr();
+        let caller = request.metadata().clone();
+        let mut req = request.into_inner();
+        let call = call.with_certificate_id(&req.certificate_id).with_metadata(&req.metadata);
+
+        let mut audit = AuditRecord::new(AuditOperation::Issue, &req.certificate_id, peer);
+        audit.common_name = Some(req.common_name.clone());
+        audit.dns_names = req.dns_names.clone();
+        audit.ip_addresses = req.ip_addresses.clone();
+
+        // The tenant, CA and policy follow the namespace the caller may call for, not the one it claims
+        let namespace = self.requesting_namespace(&caller, &req.metadata).await;
+        if let Ok(namespace) = &namespace {
+            req.metadata.insert("namespace".to_string(), namespace.clone());
+        }
+        let metadata = req.metadata.clone();
+
+        let result = match namespace {
+            Ok(_) => self.issue_coalesced(req).await,
+            Err(e) => Err(e),
+        };
+        self.audit(audit, &metadata, result.as_ref().map(|_| ())).await;
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
+
+        result.map(Response::new)
+    }
+
+    async fn renew_certificate(
+        &self,
+        request: Request<RenewCertificateRequest>,
+    ) -> Result<Response<RenewCertificateResponse>, Status> {
+        let call = Call::new("RenewCertificate", &request);
+        let peer = request.remote_addr();
+        let caller = request.metadata().clone();
+        let req = request.into_inner();
+
+        let mut audit = AuditRecord::new(AuditOperation::Renew, &req.certificate_id, peer);
+        let metadata = match self.store.get(&req.certificate_id).await.ok().flatten() {
+            Some(existing) => {
+                audit.common_name = Some(existing.common_name.clone());
+                audit.dns_names = existing.dns_names.clone();
+                existing.metadata.clone()
+            }
+            None => HashMap::new(),
+        };
+
+        let call = call.with_certificate_id(&req.certificate_id).with_metadata(&metadata);
+        let result = self.renew(&caller, req).await;
+        self.audit(audit, &metadata, result.as_ref().map(|_| ())).await;
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
+
+        result.map(Response::new)
+    }
 
     async fn revoke_certificate(
         &self,
         request: Request<RevokeCertificateRequest>,
     ) -> Result<Response<RevokeCertificateResponse>, Status> {
+        let call = Call::new("RevokeCertificate", &request);
+        let peer = request.remote_addr();
+        let caller = request.metadata().clone();
         let req = request.into_inner();
-        
-        info!("Revoking certificate: {}", req.certificate_id);
-
-        self.certificates.remove(&req.certificate_id);
 
-        let response = RevokeCertificateResponse {
-            success: true,
+        let mut audit = AuditRecord::new(AuditOperation::Revoke, &req.certificate_id, peer);
+        audit.revocation_reason = RevocationReason::try_from(req.reason).ok().map(|reason| reason.short_name());
+        let metadata = match self.store.get(&req.certificate_id).await.ok().flatten() {
+            Some(existing) => {
+                audit.common_name = Some(existing.common_name.clone());
+                audit.dns_names = existing.dns_names.clone();
+                existing.metadata.clone()
+            }
+            None => HashMap::new(),
         };
 
-        Ok(Response::new(response))
+        let call = call.with_certificate_id(&req.certificate_id).with_metadata(&metadata);
+        let result = self.revoke(&caller, req).await;
+        self.audit(audit, &metadata, result.as_ref().map(|_| ())).await;
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
+
+        result.map(Response::new)
     }
 
     async fn get_certificate_info(
         &self,
         request: Request<GetCertificateInfoRequest>,
     ) -> Result<Response<GetCertificateInfoResponse>, Status> {
+        let call = Call::new("GetCertificateInfo", &request);
+        let caller = request.metadata().clone();
         let req = request.into_inner();
-        
-        debug!("Getting certificate info: {}", req.certificate_id);
+        let call = call.with_certificate_id(&req.certificate_id);
 
-        let record = self
-            .certificates
-            .get(&req.certificate_id)
-            .ok_or_else(|| Status::not_found("Certificate not found"))?;
+        let result = self.get_info(&caller, &req.certificate_id).await;
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
 
-        let now = Utc::now().timestamp();
-        let is_valid = now >= record.not_before && now <= record.not_after;
+        result.map(Response::new)
+    }
+
+    async fn list_certificates(
+        &self,
+        request: Request<ListCertificatesRequest>,
+    ) -> Result<Response<ListCertificatesResponse>, Status> {
+        let call = Call::new("ListCertificates", &request);
+        let admin = self.check_admin(request.metadata()).await;
+        let req = request.into_inner();
 
-        let response = GetCertificateInfoResponse {
+        debug!(
+            "Listing certificates (namespace: {:?}, expiring within: {}s, page token: {:?})",
+            req.namespace, req.expiring_within_seconds, req.page_token
+        );
+
+        let result = match admin {
+            Ok(()) => self.list(req).await,
+            Err(status) => Err(status),
+        };
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
+
+        result.map(Response::new)
+    }
+
+    async fn issue_node_intermediate(
+        &self,
+        request: Request<IssueNodeIntermediateRequest>,
+    ) -> Result<Response<IssueNodeIntermediateResponse>, Status> {
+        let call = Call::new("IssueNodeIntermediate", &request);
+        let peer = request.remote_addr();
+        let caller_node = self.calling_node(request.metadata()).await;
+        let req = request.into_inner();
+
+        let certificate_id = format!("node-intermediate-{}", req.node_id);
+        let call = call.with_certificate_id(&certificate_id);
+        let mut audit = AuditRecord::new(AuditOperation::IssueNodeIntermediate, &certificate_id, peer);
+        let result = self.issue_node_intermediate(caller_node, req).await;
+        if let Ok(response) = &result {
+            if let Ok((serial, _, not_after)) = certificate_validity(&response.certificate_pem) {
+                audit.serial = Some(serial);
+                audit.not_after = Some(not_after);
+            }
+        }
+        self.audit(audit, &HashMap::new(), result.as_ref().map(|_| ())).await;
+        self.request_log.finish(call, result.as_ref().map(|_| ()));
+
+        result.map(Response::new)
+    }
+
+    type WatchCertificatesStream = CertificateEventStream;
+
+    async fn watch_certificates(
+        &self,
+        request: Request<WatchCertificatesRequest>,
+    ) -> Result<Response<Self::WatchCertificatesStream>, Status> {
+        let call = Call::new("WatchCertificates", &request);
+        let req = request.into_inner();
+
+        if req.certificate_ids.len() > MAX_WATCHED_CERTIFICATES {
+            let status = Status::invalid_argument(format!(
+                "Cannot watch more than {} certificates, got {}", MAX_WATCHED_CERTIFICATES, req.certificate_ids.len()
+            ));
+            self.request_log.finish(call, Err(&status));
+            return Err(status);
+        }
+
+        debug!("Watching {} certificates", req.certificate_ids.len());
+        self.request_log.finish(call, Ok(()));
+
+        Ok(Response::new(self.events.subscribe(req.certificate_ids)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::auth::DEFAULT_TOKEN_AUDIENCE;
+    use crate::testing::{bearer_metadata, MockClusterApi};
+    use async_trait::async_trait;
+
+    struct NoSigner;
+
+    #[async_trait]
+    impl Signer for NoSigner {
+        fn name(&self) -> &'static str {
+            "none"
+        }
+
+        async fn sign(&self, _: CertificateParams, _: &SubjectName, _: &KeyPair, _: SignPurpose<'_>) -> Result<String> {
+            Err(anyhow::anyhow!("not signing in tests"))
+        }
+
+        async fn ca_certificate(&self) -> Option<String> {
+            None
+        }
+    }
+
+    /// Signer whose CA certificate can be swapped, as on a CA rotation
+    struct RotatingSigner {
+        ca_cert_pem: Mutex<String>,
+    }
+
+    #[async_trait]
+    impl Signer for RotatingSigner {
+        fn name(&self) -> &'static str {
+            "rotating"
+        }
+
+        async fn sign(&self, _: CertificateParams, _: &SubjectName, _: &KeyPair, _: SignPurpose<'_>) -> Result<String> {
+            Err(anyhow::anyhow!("not signing in tests"))
+        }
+
+        async fn ca_certificate(&self) -> Option<String> {
+            Some(self.ca_cert_pem.lock().unwrap().clone())
+        }
+    }
+
+    /// Local signer that, like EST, re-enrolls with the certificate being renewed
+    struct ReenrollingSigner {
+        inner: super::super::signer::LocalSigner,
+        /// Whether each renewal carried the current key
+        renewals: Mutex<Vec<bool>>,
+    }
+
+    #[async_trait]
+    impl Signer for ReenrollingSigner {
+        fn name(&self) -> &'static str {
+            "reenrolling"
+        }
+
+        async fn sign(&self, params: CertificateParams, subject: &SubjectName, key_pair: &KeyPair, purpose: SignPurpose<'_>) -> Result<String> {
+            if let SignPurpose::Renew(current) = purpose {
+                self.renewals.lock().unwrap().push(current.is_some_and(|current| !current.key_pem.is_empty()));
+            }
+            self.inner.sign(params, subject, key_pair, purpose).await
+        }
+
+        fn needs_current_certificate(&self) -> bool {
+            true
+        }
+
+        async fn ca_certificate(&self) -> Option<String> {
+            self.inner.ca_certificate().await
+        }
+    }
+
+    fn ca_cert_pem(organization: &str) -> String {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::OrganizationName, organization);
+        params.distinguished_name.push(DnType::CountryName, "DE");
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.self_signed(&KeyPair::generate().unwrap()).unwrap().pem()
+    }
+
+    fn record(certificate_id: &str, namespace: &str, not_after: i64) -> CertificateRecord {
+        CertificateRecord {
+            certificate_id: certificate_id.to_string(),
+            common_name: certificate_id.to_string(),
+            dns_names: vec![],
+            ip_addresses: vec![],
+            uris: vec![],
+            organizational_units: vec![],
+            subject: Subject::default(),
+            key_usages: vec![],
+            extended_key_usages: vec![],
+            extensions: vec![],
+            validity_seconds: 0,
+            not_before: 0,
+            not_after,
+            metadata: HashMap::from([("namespace".to_string(), namespace.to_string())]),
+            profile: String::new(),
+            serial: format!("{}-serial", certificate_id),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_filters_and_pages() {
+        let service = CertificateServiceImpl::new(Arc::new(NoSigner));
+        let soon = Utc::now().timestamp() + 60;
+        let later = Utc::now().timestamp() + 86400;
+        for (id, namespace, not_after) in [("a", "prod", soon), ("b", "dev", soon), ("c", "prod", later), ("d", "prod", soon)] {
+            service.store.put(record(id, namespace, not_after)).await.unwrap();
+        }
+        service.store.revoke("d-serial", Revocation {
+            certificate_id: "d".to_string(),
+            reason: RevocationReason::KeyCompromise,
+            revoked_at: 0,
+        }).await.unwrap();
+
+        let ids = |response: &ListCertificatesResponse| -> Vec<String> {
+            response.certificates.iter().map(|c| c.certificate_id.clone()).collect()
+        };
+
+        let first = service.list(ListCertificatesRequest { page_size: 2, ..Default::default() }).await.unwrap();
+        assert_eq!(ids(&first), ["a", "b"]);
+        let second = service.list(ListCertificatesRequest {
+            page_size: 2,
+            page_token: first.next_page_token,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(ids(&second), ["c", "d"]);
+        assert!(second.next_page_token.is_empty());
+
+        let expiring = service.list(ListCertificatesRequest {
+            namespace: "prod".to_string(),
+            expiring_within_seconds: 3600,
+            revocation: RevocationFilter::NotRevoked as i32,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(ids(&expiring), ["a"]);
+
+        let revoked = service.list(ListCertificatesRequest {
+            revocation: RevocationFilter::Revoked as i32,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(ids(&revoked), ["d"]);
+        assert!(!revoked.certificates[0].is_valid);
+        assert_eq!(RevocationReason::KeyCompromise.short_name(), "key_compromise");
+    }
+
+    #[tokio::test]
+    async fn test_revoked_certificate_is_reported() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let service = CertificateServiceImpl::new(signer).with_trusted_callers();
+        let issued = service.issue(IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            ..Default::default()
+        }).await.unwrap();
+        let (serial, _, _) = certificate_validity(&issued.certificate_pem).unwrap();
+
+        let revoke = |reason: RevocationReason| CertificateService::revoke_certificate(&service, Request::new(RevokeCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            reason: reason as i32,
+        }));
+        let revoked = revoke(RevocationReason::KeyCompromise).await.unwrap().into_inner();
+        assert_eq!(revoked.serial, serial);
+        // Revoking again keeps the first reason and time
+        let again = revoke(RevocationReason::Superseded).await.unwrap().into_inner();
+        assert_eq!(again.revoked_at, revoked.revoked_at);
+
+        let info = CertificateService::get_certificate_info(&service, Request::new(GetCertificateInfoRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+        })).await.unwrap().into_inner();
+        assert_eq!(info.serial, serial);
+        assert!(info.revoked && !info.is_valid);
+        assert_eq!(info.revocation_reason(), RevocationReason::KeyCompromise);
+        assert_eq!(info.revoked_at, revoked.revoked_at);
+
+        // What a CRL is built from: the revoked serials with their reason and time
+        let list = |revocation: RevocationFilter| CertificateService::list_certificates(&service, Request::new(ListCertificatesRequest {
+            revocation: revocation as i32,
+            ..Default::default()
+        }));
+        let crl = list(RevocationFilter::Revoked).await.unwrap().into_inner().certificates;
+        assert_eq!(crl.len(), 1);
+        assert_eq!((crl[0].serial.as_str(), crl[0].revocation_reason()), (serial.as_str(), RevocationReason::KeyCompromise));
+        assert!(list(RevocationFilter::NotRevoked).await.unwrap().into_inner().certificates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_leaf_key_identifiers_chain_to_the_ca() {
+        use x509_parser::extensions::ParsedExtension;
+
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert.clone(), &ca_key).unwrap());
+        let service = CertificateServiceImpl::new(signer).with_trusted_callers();
+        let issued = service.issue(IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            ..Default::default()
+        }).await.unwrap();
+
+        // (SubjectKeyIdentifier, AuthorityKeyIdentifier's keyIdentifier) of a certificate
+        let key_identifiers = |cert_pem: &str| {
+            let (_, pem) = parse_x509_pem(cert_pem.as_bytes()).unwrap();
+            let cert = pem.parse_x509().unwrap();
+            let (mut ski, mut aki) = (None, None);
+            for extension in cert.extensions() {
+                match extension.parsed_extension() {
+                    ParsedExtension::SubjectKeyIdentifier(id) => ski = Some(id.0.to_vec()),
+                    ParsedExtension::AuthorityKeyIdentifier(id) => aki = id.key_identifier.as_ref().map(|id| id.0.to_vec()),
+                    _ => {}
+                }
+            }
+            (ski, aki)
+        };
+
+        let (ca_ski, _) = key_identifiers(&ca_cert);
+        let (leaf_ski, leaf_aki) = key_identifiers(&issued.certificate_pem);
+        assert!(ca_ski.is_some());
+        assert!(leaf_ski.is_some());
+        assert_ne!(leaf_ski, ca_ski);
+        assert_eq!(leaf_aki, ca_ski);
+    }
+
+    #[tokio::test]
+    async fn test_current_key_asked_for_by_reenrolling_signers_only() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(ReenrollingSigner {
+            inner: super::super::signer::LocalSigner::in_memory(ca_cert.clone(), &ca_key).unwrap(),
+            renewals: Mutex::new(Vec::new()),
+        });
+        let service = CertificateServiceImpl::new(signer.clone()).with_trusted_callers();
+        let issued = service.issue(IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+        let caller = MetadataMap::new();
+        let renew = |current: Option<(&str, &str)>, replace_revoked: bool| service.renew(&caller, RenewCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            replace_revoked,
+            current_certificate_pem: current.map(|(cert, _)| cert.to_string()).unwrap_or_default(),
+            current_private_key_pem: current.map(|(_, key)| key.to_string()).unwrap_or_default(),
+            ..Default::default()
+        });
+
+        let status = renew(None, false).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(ErrorInfo::from_status(&status).and_then(|info| info.reason()), Some(ErrorReason::CurrentKeyRequired));
+        assert!(signer.renewals.lock().unwrap().is_empty());
+
+        renew(Some((&issued.certificate_pem, &issued.private_key_pem)), false).await.unwrap();
+        assert_eq!(*signer.renewals.lock().unwrap(), vec![true]);
+
+        // A revoked certificate is replaced without its key, by enrolling anew
+        let record = service.store.get("default-web-csi-abc").await.unwrap().unwrap();
+        service.store.revoke(&record.serial, Revocation {
             certificate_id: record.certificate_id.clone(),
-            common_name: record.common_name.clone(),
-            dns_names: record.dns_names.clone(),
-            not_before: record.not_before,
-            not_after: record.not_after,
-            is_valid,
-            metadata: record.metadata.clone(),
+            reason: RevocationReason::KeyCompromise,
+            revoked_at: 0,
+        }).await.unwrap();
+        renew(None, true).await.unwrap();
+        assert_eq!(*signer.renewals.lock().unwrap(), vec![true]);
+
+        // Other signers are never asked for it
+        let local = CertificateServiceImpl::new(Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap()))
+            .with_trusted_callers();
+        local.issue(IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+        local.renew(&MetadataMap::new(), RenewCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            ..Default::default()
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_issuance() {
+        let service = CertificateServiceImpl::new(Arc::new(NoSigner));
+        let later = Utc::now().timestamp() + 86400;
+        service.store.put(record("valid", "prod", later)).await.unwrap();
+        service.store.put(record("expired", "prod", Utc::now().timestamp() - 60)).await.unwrap();
+        service.store.put(record("revoked", "prod", later)).await.unwrap();
+        service.store.revoke("revoked-serial", Revocation {
+            certificate_id: "revoked".to_string(),
+            reason: RevocationReason::Superseded,
+            revoked_at: 0,
+        }).await.unwrap();
+
+        let status = service.check_not_issued("valid").await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        let info = ErrorInfo::from_status(&status).unwrap();
+        assert_eq!(info.reason(), Some(ErrorReason::AlreadyIssued));
+        assert_eq!(info.metadata["serial"], "valid-serial");
+        for certificate_id in ["expired", "revoked", "unknown"] {
+            service.check_not_issued(certificate_id).await.unwrap();
+        }
+
+        // A retry with the same key gets the same certificate while it is current
+        let cert_pem = ca_cert_pem("Acme");
+        let (serial, not_before, not_after) = certificate_validity(&cert_pem).unwrap();
+        service.store.put(CertificateRecord { serial, ..record("web", "prod", not_after) }).await.unwrap();
+        let request = IssueCertificateRequest {
+            certificate_id: "web".to_string(),
+            idempotency_key: "key-1".to_string(),
+            ..Default::default()
+        };
+        service.remember(&request, &IssueCertificateResponse {
+            certificate_pem: cert_pem.clone(),
+            private_key_pem: "key".to_string(),
+            certificate_id: "web".to_string(),
+            not_before,
+            not_after,
+        });
+
+        let replayed = service.replay(&request).await.unwrap().unwrap();
+        assert_eq!((replayed.certificate_pem.as_str(), replayed.private_key_pem.as_str()), (cert_pem.as_str(), "key"));
+        let other_key = IssueCertificateRequest { idempotency_key: "key-2".to_string(), ..request.clone() };
+        assert!(service.replay(&other_key).await.unwrap().is_none());
+        let other_request = IssueCertificateRequest { common_name: "other".to_string(), ..request.clone() };
+        assert_eq!(service.replay(&other_request).await.unwrap_err().code(), Code::InvalidArgument);
+
+        // Only so many keys are held at once, dropping the oldest first
+        for i in 0..MAX_RECENT_ISSUES {
+            let filler = IssueCertificateRequest { certificate_id: format!("filler-{}", i), ..request.clone() };
+            service.remember(&filler, &IssueCertificateResponse { certificate_pem: cert_pem.clone(), ..Default::default() });
+        }
+        {
+            let recent_issues = service.recent_issues.lock().unwrap();
+            assert_eq!(recent_issues.len(), MAX_RECENT_ISSUES);
+            assert!(!recent_issues.contains_key("web"));
+        }
+        service.remember(&request, &IssueCertificateResponse {
+            certificate_pem: cert_pem.clone(),
+            private_key_pem: "key".to_string(),
+            certificate_id: "web".to_string(),
+            not_before,
+            not_after,
+        });
+
+        service.store.put(record("web", "prod", later)).await.unwrap();
+        assert!(service.replay(&request).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ca_details_follow_rotation() {
+        let signer = Arc::new(RotatingSigner { ca_cert_pem: Mutex::new(ca_cert_pem("Acme")) });
+        let service = CertificateServiceImpl::new(signer.clone());
+
+        let first = service.ca_details("", None).await.unwrap().unwrap();
+        assert_eq!(first.organization.as_deref(), Some("Acme"));
+        assert_eq!(first.country.as_deref(), Some("DE"));
+        assert!(first.name_constraints.is_none());
+        assert!(Arc::ptr_eq(&first, &service.ca_details("", None).await.unwrap().unwrap()));
+
+        *signer.ca_cert_pem.lock().unwrap() = ca_cert_pem("Acme Rotated");
+        let rotated = service.ca_details("", None).await.unwrap().unwrap();
+        assert_eq!(rotated.organization.as_deref(), Some("Acme Rotated"));
+    }
+
+    #[tokio::test]
+    async fn test_namespace_signers() {
+        let tenant: Arc<dyn Signer> = Arc::new(RotatingSigner { ca_cert_pem: Mutex::new(ca_cert_pem("Tenant")) });
+        let service = CertificateServiceImpl::new(Arc::new(RotatingSigner { ca_cert_pem: Mutex::new(ca_cert_pem("Acme")) }))
+            .with_namespace_signers(HashMap::from([("tenant".to_string(), tenant)]));
+
+        let tenant_details = service.ca_details("tenant", None).await.unwrap().unwrap();
+        assert_eq!(tenant_details.organization.as_deref(), Some("Tenant"));
+        let default_details = service.ca_details("prod", None).await.unwrap().unwrap();
+        assert_eq!(default_details.organization.as_deref(), Some("Acme"));
+        assert!(Arc::ptr_eq(&default_details, &service.ca_details("", None).await.unwrap().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_node_intermediates_need_the_node_driver() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let intermediates = NodeIntermediates::parse(3600, "svc.cluster.local").unwrap();
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("node-1-token", driver, Some("node-1"))
+                .with_token("app-token", "system:serviceaccount:default:web", Some("node-1")),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()]);
+        let service = CertificateServiceImpl::new(signer.clone())
+            .with_node_intermediates(intermediates.clone())
+            .with_authenticator(Arc::new(authenticator));
+
+        async fn call(service: &CertificateServiceImpl, token: &str, node_id: &str) -> Result<IssueNodeIntermediateResponse, Status> {
+            let mut request = Request::new(IssueNodeIntermediateRequest { node_id: node_id.to_string() });
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::issue_node_intermediate(service, request).await.map(Response::into_inner)
+        }
+
+        let issued = call(&service, "node-1-token", "node-1").await.unwrap();
+        assert!(!issued.private_key_pem.is_empty());
+        let code = |result: Result<IssueNodeIntermediateResponse, Status>| result.unwrap_err().code();
+        assert_eq!(code(call(&service, "node-1-token", "node-2").await), Code::PermissionDenied);
+        assert_eq!(code(call(&service, "app-token", "node-1").await), Code::PermissionDenied);
+        assert_eq!(code(call(&service, "forged", "node-1").await), Code::Unauthenticated);
+
+        // Without an authenticator nobody gets an intermediate
+        let unauthenticated = CertificateServiceImpl::new(signer).with_node_intermediates(intermediates);
+        assert_eq!(code(call(&unauthenticated, "node-1-token", "node-1").await), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_only_admins_list_certificates() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let admin = "system:serviceaccount:cacsi:cacsi-admin";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("admin-token", admin, None)
+                .with_token("node-token", driver, Some("node-1"))
+                .with_token("app-token", "system:serviceaccount:default:web", None),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()])
+            .with_admins(vec![admin.to_string()]);
+        let service = CertificateServiceImpl::new(signer.clone()).with_authenticator(Arc::new(authenticator));
+
+        async fn list(service: &CertificateServiceImpl, token: &str) -> Result<ListCertificatesResponse, Status> {
+            let mut request = Request::new(ListCertificatesRequest::default());
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::list_certificates(service, request).await.map(Response::into_inner)
+        }
+
+        assert!(list(&service, "admin-token").await.is_ok());
+        let code = |result: Result<ListCertificatesResponse, Status>| result.unwrap_err().code();
+        assert_eq!(code(list(&service, "node-token").await), Code::PermissionDenied);
+        assert_eq!(code(list(&service, "app-token").await), Code::PermissionDenied);
+        assert_eq!(code(list(&service, "forged").await), Code::Unauthenticated);
+
+        // Without an authenticator only a service on a private socket lists
+        let unauthenticated = CertificateServiceImpl::new(signer.clone());
+        assert_eq!(code(list(&unauthenticated, "admin-token").await), Code::Unauthenticated);
+        let private = CertificateServiceImpl::new(signer).with_trusted_callers();
+        assert!(list(&private, "").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_only_the_namespace_and_admins_revoke_and_look_up() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let admin = "system:serviceaccount:cacsi:cacsi-admin";
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("admin-token", admin, None)
+                .with_token("node-token", driver, Some("node-1"))
+                .with_token("app-token", "system:serviceaccount:team-a:web", None)
+                .with_token("other-app-token", "system:serviceaccount:team-b:web", None)
+                .with_pod("team-b", "db-0", "node-2"),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()])
+            .with_admins(vec![admin.to_string()]);
+        let service = CertificateServiceImpl::new(signer).with_authenticator(Arc::new(authenticator));
+        for (certificate_id, namespace, pod) in [("team-a-web-csi-abc", "team-a", "web-0"), ("team-b-db-csi-def", "team-b", "db-0")] {
+            service.issue(IssueCertificateRequest {
+                certificate_id: certificate_id.to_string(),
+                common_name: pod.to_string(),
+                metadata: HashMap::from([
+                    ("namespace".to_string(), namespace.to_string()),
+                    ("pod".to_string(), pod.to_string()),
+                ]),
+                ..Default::default()
+            }).await.unwrap();
+        }
+
+        let info = |token: &str| {
+            let mut request = Request::new(GetCertificateInfoRequest {
+                certificate_id: "team-a-web-csi-abc".to_string(),
+            });
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::get_certificate_info(&service, request)
+        };
+        let revoke = |token: &str, certificate_id: &str| {
+            let mut request = Request::new(RevokeCertificateRequest {
+                certificate_id: certificate_id.to_string(),
+                reason: RevocationReason::KeyCompromise as i32,
+            });
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::revoke_certificate(&service, request)
+        };
+        let reason = |status: Status| ErrorInfo::from_status(&status).and_then(|info| info.reason());
+
+        // Another namespace neither sees nor revokes the certificate
+        let status = info("other-app-token").await.unwrap_err();
+        assert_eq!((status.code(), reason(status)), (Code::PermissionDenied, Some(ErrorReason::CallerNotAllowed)));
+        let status = revoke("other-app-token", "team-a-web-csi-abc").await.unwrap_err();
+        assert_eq!((status.code(), reason(status)), (Code::PermissionDenied, Some(ErrorReason::CallerNotAllowed)));
+        assert_eq!(revoke("forged", "team-a-web-csi-abc").await.unwrap_err().code(), Code::PermissionDenied);
+        assert!(!info("app-token").await.unwrap().into_inner().revoked);
+
+        info("admin-token").await.unwrap();
+        revoke("admin-token", "team-a-web-csi-abc").await.unwrap();
+        assert!(info("app-token").await.unwrap().into_inner().revoked);
+        revoke("app-token", "team-a-web-csi-abc").await.unwrap();
+
+        // Node drivers revoke the certificates of deleted pods, but not those of pods on other nodes
+        assert_eq!(revoke("node-token", "team-b-db-csi-def").await.unwrap_err().code(), Code::PermissionDenied);
+        assert!(info("node-token").await.is_err());
+        service.revoke(&bearer_metadata("node-token"), RevokeCertificateRequest {
+            certificate_id: "team-a-web-csi-abc".to_string(),
+            reason: RevocationReason::CessationOfOperation as i32,
+        }).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tenants_follow_the_caller() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer: Arc<dyn Signer> = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let tenancy = Arc::new(Tenancy::in_memory(
+            "payments:\n  max_certificates: 2\n  dns_domains: [payments.example.com]\nsearch:\n  dns_domains: [search.example.com]\n",
+            &[("payments-prod", "payments"), ("search-prod", "search")],
+        ).unwrap());
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("search-token", "system:serviceaccount:search-prod:web", None)
+                .with_token("payments-token", "system:serviceaccount:payments-prod:web", None),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![]);
+        let service = Arc::new(
+            CertificateServiceImpl::new(signer.clone())
+                .with_tenancy(tenancy.clone(), HashMap::new())
+                .with_authenticator(Arc::new(authenticator)),
+        );
+
+        async fn issue(service: Arc<CertificateServiceImpl>, token: &str, id: &str, namespace: &str, san: &str) -> Result<IssueCertificateResponse, Status> {
+            let (dns_names, uris) = match san.contains("://") {
+                true => (vec![], vec![san.to_string()]),
+                false => (vec![san.to_string()], vec![]),
+            };
+            let mut request = Request::new(IssueCertificateRequest {
+                certificate_id: id.to_string(),
+                common_name: "web".to_string(),
+                dns_names,
+                uris,
+                metadata: HashMap::from([("namespace".to_string(), namespace.to_string())]),
+                ..Default::default()
+            });
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::issue_certificate(service.as_ref(), request).await.map(Response::into_inner)
+        }
+        let reason = |result: Result<IssueCertificateResponse, Status>| {
+            ErrorInfo::from_status(&result.unwrap_err()).and_then(|info| info.reason())
         };
 
-        Ok(Response::new(response))
+        // The namespace, and so the tenant, is the caller's own, not the one it claims
+        issue(service.clone(), "search-token", "a", "", "web.search.example.com").await.unwrap();
+        let record = service.store.get("a").await.unwrap().unwrap();
+        assert_eq!((record.namespace(), record.tenant()), (Some("search-prod"), Some("search")));
+        let claimed = issue(service.clone(), "search-token", "b", "payments-prod", "web.payments.example.com").await;
+        assert_eq!(reason(claimed), Some(ErrorReason::CallerNotAllowed));
+
+        // URI SANs belong to the tenant of their host
+        let uri = issue(service.clone(), "search-token", "c", "", "spiffe://payments.example.com/ns/x/sa/web").await;
+        assert_eq!(reason(uri), Some(ErrorReason::PolicyDenied));
+
+        // Concurrent issuances do not exceed the quota
+        let issues: Vec<_> = (0..4)
+            .map(|i| tokio::spawn(issue(service.clone(), "payments-token", ["p0", "p1", "p2", "p3"][i], "", "web.payments.example.com")))
+            .collect();
+        let mut exhausted = 0;
+        for issued in issues {
+            if let Err(status) = issued.await.unwrap() {
+                assert_eq!(status.code(), Code::ResourceExhausted);
+                exhausted += 1;
+            }
+        }
+        assert_eq!(exhausted, 2);
+
+        // Without an authenticator, a request without a namespace gets no tenant and nothing else
+        let unauthenticated = Arc::new(CertificateServiceImpl::new(signer).with_tenancy(tenancy, HashMap::new()));
+        let anonymous = issue(unauthenticated, "", "d", "", "web.search.example.com").await;
+        assert_eq!(reason(anonymous), Some(ErrorReason::TenantUnresolved));
+    }
+
+    #[tokio::test]
+    async fn test_only_node_drivers_replace_revoked_certificates() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(super::super::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let driver = "system:serviceaccount:cacsi:cacsi-driver";
+        let api = Arc::new(
+            MockClusterApi::new()
+                .with_token("node-token", driver, Some("node-1"))
+                .with_token("other-node-token", driver, Some("node-2"))
+                .with_token("app-token", "system:serviceaccount:team-a:web", None)
+                .with_pod("team-a", "web-0", "node-1"),
+        );
+        let authenticator = Authenticator::new(api, DEFAULT_TOKEN_AUDIENCE.to_string(), vec![driver.to_string()]);
+        let service = CertificateServiceImpl::new(signer).with_authenticator(Arc::new(authenticator));
+        service.issue(IssueCertificateRequest {
+            certificate_id: "team-a-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.team-a.svc".to_string()],
+            metadata: HashMap::from([
+                ("namespace".to_string(), "team-a".to_string()),
+                ("pod".to_string(), "web-0".to_string()),
+            ]),
+            ..Default::default()
+        }).await.unwrap();
+        service.revoke(&bearer_metadata("node-token"), RevokeCertificateRequest {
+            certificate_id: "team-a-web-csi-abc".to_string(),
+            reason: RevocationReason::KeyCompromise as i32,
+        }).await.unwrap();
+
+        let renew = |token: &str, replace_revoked: bool| {
+            let mut request = Request::new(RenewCertificateRequest {
+                certificate_id: "team-a-web-csi-abc".to_string(),
+                replace_revoked,
+                ..Default::default()
+            });
+            *request.metadata_mut() = bearer_metadata(token);
+            CertificateService::renew_certificate(&service, request)
+        };
+        let reason = |status: Status| ErrorInfo::from_status(&status).and_then(|info| info.reason());
+
+        // The workload, which may hold the compromised key, cannot get the identity back
+        assert_eq!(reason(renew("app-token", false).await.unwrap_err()), Some(ErrorReason::CertificateRevoked));
+        let status = renew("app-token", true).await.unwrap_err();
+        assert_eq!((status.code(), reason(status)), (Code::PermissionDenied, Some(ErrorReason::CallerNotAllowed)));
+        // Nor can the driver of another node
+        assert_eq!(renew("other-node-token", true).await.unwrap_err().code(), Code::PermissionDenied);
+
+        renew("node-token", true).await.unwrap();
+        // The replacement is a new certificate, so it renews like any other
+        renew("app-token", false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_key_encoding_checked_before_signing() {
+        let service = CertificateServiceImpl::new(Arc::new(NoSigner));
+        let issue = |key_encoding: &str| service.issue(IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            key_encoding: key_encoding.to_string(),
+            ..Default::default()
+        });
+        let reason = |status: Status| ErrorInfo::from_status(&status).and_then(|info| info.reason());
+
+        for key_encoding in ["pkcs1", "der"] {
+            let status = issue(key_encoding).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(reason(status), Some(ErrorReason::InvalidKeyEncoding));
+        }
+        // P-256 keys have both, so these get as far as the signer
+        for key_encoding in ["", "pkcs8", "sec1"] {
+            assert_eq!(reason(issue(key_encoding).await.unwrap_err()), Some(ErrorReason::SigningFailed));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_policy() {
+        let service = CertificateServiceImpl::new(Arc::new(NoSigner))
+            .with_namespace_policy(NamespacePolicy::parse("", "", "", "ingress").unwrap());
+        let request = |namespace: &str, dns_name: &str| PolicyRequest {
+            certificate_id: format!("{}-web-csi-abc", namespace),
+            common_name: "web".to_string(),
+            dns_names: vec![dns_name.to_string()],
+            ip_addresses: vec![],
+            uris: vec![],
+            organizational_units: vec![],
+            key_usages: vec![],
+            extended_key_usages: vec![],
+            extension_oids: vec![],
+            namespace: namespace.to_string(),
+            validity_days: 1,
+            validity_seconds: 86400,
+            metadata: HashMap::new(),
+            profile: String::new(),
+            renewal: false,
+        };
+
+        service.authorize(&request("default", "web.default.svc"), None, None).await.unwrap();
+        service.authorize(&request("ingress", "*.example.com"), None, None).await.unwrap();
+        let status = service.authorize(&request("default", "*.default.svc"), None, None).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(ErrorInfo::from_status(&status).unwrap().metadata["policy"], "wildcard");
+
+        // The profile decides when it says so
+        let allowing: CertificateProfile = serde_yaml::from_str("allow_wildcards: true").unwrap();
+        let denying: CertificateProfile = serde_yaml::from_str("allow_wildcards: false").unwrap();
+        service.authorize(&request("default", "*.default.svc"), Some(&allowing), None).await.unwrap();
+        assert!(service.authorize(&request("ingress", "*.example.com"), Some(&denying), None).await.is_err());
+    }
+
+    #[test]
+    fn test_effective_usages() {
+        let names = |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+
+        let (key_usages, extended_key_usages) = CertificateServiceImpl::effective_usages(&[], &[], None).unwrap();
+        assert_eq!(key_usages, default_key_usages());
+        assert_eq!(extended_key_usages, default_extended_key_usages());
+
+        let (key_usages, extended_key_usages) = CertificateServiceImpl::effective_usages(
+            &names(&["digital_signature"]),
+            &names(&["client_auth"]),
+            None,
+        ).unwrap();
+        assert_eq!(key_usages, [KeyUsage::DigitalSignature]);
+        assert_eq!(extended_key_usages, [ExtendedKeyUsage::ClientAuth]);
+
+        let status = CertificateServiceImpl::effective_usages(&names(&["cert_sign"]), &[], None).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let profile: CertificateProfile = serde_yaml::from_str("extended_key_usages: [server_auth]").unwrap();
+        let (_, extended_key_usages) = CertificateServiceImpl::effective_usages(&[], &[], Some(&profile)).unwrap();
+        assert_eq!(extended_key_usages, [ExtendedKeyUsage::ServerAuth]);
+        let status = CertificateServiceImpl::effective_usages(&[], &names(&["client_auth"]), Some(&profile)).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(ErrorInfo::from_status(&status).unwrap().reason(), Some(ErrorReason::PolicyDenied));
     }
 }
@@ -0,0 +1,334 @@
+use anyhow::{Result, Context};
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Args};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::audit::AuditLog;
+use super::issuer_urls::IssuerUrls;
+use super::namespace_policy::{NamespacePolicy, DEFAULT_DENIED_NAMESPACES};
+use super::node_intermediates::{NodeIntermediates, DEFAULT_NODE_INTERMEDIATE_VALIDITY_SECONDS};
+use super::policy::PolicyEngine;
+use super::profiles::ProfileStore;
+use super::service::{CertificateServiceImpl, IssuanceSettings, DEFAULT_NOT_BEFORE_BACKDATE_SECONDS};
+use super::signer::Signer;
+use super::validity::ValidityMode;
+use crate::tenancy::{Tenancy, DEFAULT_TENANT_LABEL};
+
+/// How often policy rules, certificate profiles and tenants are re-read from their ConfigMaps
+const POLICY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Issuance settings of the certificate service, independent of the signing backend
+///
+/// Part of the configuration of the certificate service and of the CSI driver in
+/// all-in-one mode (see [`crate::config`]).
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct ServiceSettings {
+    /// Comma-separated namespaces allowed to request certificates; empty allows all
+    #[arg(long, env = "ALLOWED_NAMESPACES", default_value = "")]
+    pub allowed_namespaces: String,
+    #[arg(long, env = "DENIED_NAMESPACES", default_value = DEFAULT_DENIED_NAMESPACES)]
+    pub denied_namespaces: String,
+    #[arg(long, env = "NAMESPACE_NAME_SUFFIXES", default_value = "")]
+    pub namespace_name_suffixes: String,
+    /// Comma-separated namespaces that may request wildcard DNS names; empty denies them everywhere
+    #[arg(long, env = "WILDCARD_NAMESPACES", default_value = "")]
+    pub wildcard_namespaces: String,
+    #[arg(long, env = "MAX_VALIDITY_DAYS")]
+    pub max_validity_days: Option<i64>,
+    #[arg(long, env = "VALIDITY_MODE", default_value = "clamp")]
+    pub validity_mode: ValidityMode,
+    #[arg(long, env = "POLICY_CONFIGMAP")]
+    pub policy_configmap: Option<String>,
+    /// Defaults to the CA secret namespace
+    #[arg(long, env = "POLICY_CONFIGMAP_NAMESPACE")]
+    pub policy_configmap_namespace: Option<String>,
+    #[arg(long, env = "PROFILES_CONFIGMAP")]
+    pub profiles_configmap: Option<String>,
+    /// Defaults to the CA secret namespace
+    #[arg(long, env = "PROFILES_CONFIGMAP_NAMESPACE")]
+    pub profiles_configmap_namespace: Option<String>,
+    #[arg(long, env = "DEFAULT_PROFILE")]
+    pub default_profile: Option<String>,
+    /// ConfigMap defining tenants, each with its own CA, serials, quota and policy
+    #[arg(long, env = "TENANTS_CONFIGMAP")]
+    pub tenants_configmap: Option<String>,
+    /// Defaults to the CA secret namespace
+    #[arg(long, env = "TENANTS_CONFIGMAP_NAMESPACE")]
+    pub tenants_configmap_namespace: Option<String>,
+    /// Namespace label naming the tenant a namespace belongs to
+    #[arg(long, env = "TENANT_LABEL", default_value = DEFAULT_TENANT_LABEL)]
+    pub tenant_label: String,
+    #[arg(long, env = "AUDIT_LOG_FILE")]
+    pub audit_log_file: Option<String>,
+    #[arg(long, env = "AUDIT_WEBHOOK_URL")]
+    pub audit_webhook_url: Option<String>,
+    #[arg(long, env = "NOT_BEFORE_BACKDATE_SECONDS", default_value_t = DEFAULT_NOT_BEFORE_BACKDATE_SECONDS)]
+    pub not_before_backdate_seconds: i64,
+    #[arg(long, env = "NODE_INTERMEDIATES", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub node_intermediates: bool,
+    #[arg(long, env = "NODE_INTERMEDIATE_VALIDITY_SECONDS", default_value_t = DEFAULT_NODE_INTERMEDIATE_VALIDITY_SECONDS)]
+    pub node_intermediate_validity_seconds: i64,
+    #[arg(long, env = "NODE_INTERMEDIATE_PERMITTED_DNS", default_value = "")]
+    pub node_intermediate_permitted_dns: String,
+    /// Comma-separated CRL distribution points embedded in issued certificates
+    #[arg(long, env = "CRL_URLS", default_value = "")]
+    pub crl_urls: String,
+    #[arg(long, env = "OCSP_URLS", default_value = "")]
+    pub ocsp_urls: String,
+    #[arg(long, env = "CA_ISSUERS_URLS", default_value = "")]
+    pub ca_issuers_urls: String,
+    /// PostgreSQL connection URL of a certificate store shared between replicas
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+}
+
+impl ServiceSettings {
+    /// Look for the policy, profiles and tenants ConfigMaps in `namespace` unless set otherwise
+    pub fn default_configmap_namespace(&mut self, namespace: &str) {
+        for configmap_namespace in [
+            &mut self.policy_configmap_namespace,
+            &mut self.profiles_configmap_namespace,
+            &mut self.tenants_configmap_namespace,
+        ] {
+            if configmap_namespace.as_deref().is_none_or(str::is_empty) {
+                *configmap_namespace = Some(namespace.to_string());
+            }
+        }
+        for value in [&mut self.audit_log_file, &mut self.audit_webhook_url, &mut self.database_url] {
+            if value.as_deref() == Some("") {
+                *value = None;
+            }
+        }
+    }
+
+    pub fn issuer_urls(&self) -> Result<IssuerUrls> {
+        IssuerUrls::parse(&self.crl_urls, &self.ocsp_urls, &self.ca_issuers_urls)
+            .context("Invalid CRL_URLS, OCSP_URLS or CA_ISSUERS_URLS")
+    }
+
+    /// The settings applied to every request, which can change while the service runs
+    pub fn issuance_settings(&self) -> Result<IssuanceSettings> {
+        Ok(IssuanceSettings {
+            namespace_policy: Some(NamespacePolicy::parse(
+                &self.allowed_namespaces,
+                &self.denied_namespaces,
+                &self.namespace_name_suffixes,
+                &self.wildcard_namespaces,
+            )?),
+            max_validity_days: self.max_validity_days,
+            validity_mode: self.validity_mode,
+            issuer_urls: self.issuer_urls()?,
+            not_before_backdate: chrono::Duration::seconds(self.not_before_backdate_seconds),
+        })
+    }
+
+    /// Whether settings other than the [`issuance_settings`](Self::issuance_settings)
+    /// differ from `previous`, so the change only takes effect after a restart
+    pub fn needs_restart(&self, previous: &Self) -> bool {
+        let mut reloaded = previous.clone();
+        reloaded.allowed_namespaces.clone_from(&self.allowed_namespaces);
+        reloaded.denied_namespaces.clone_from(&self.denied_namespaces);
+        reloaded.namespace_name_suffixes.clone_from(&self.namespace_name_suffixes);
+        reloaded.wildcard_namespaces.clone_from(&self.wildcard_namespaces);
+        reloaded.max_validity_days = self.max_validity_days;
+        reloaded.validity_mode = self.validity_mode;
+        reloaded.not_before_backdate_seconds = self.not_before_backdate_seconds;
+        reloaded.crl_urls.clone_from(&self.crl_urls);
+        reloaded.ocsp_urls.clone_from(&self.ocsp_urls);
+        reloaded.ca_issuers_urls.clone_from(&self.ca_issuers_urls);
+        reloaded != *self
+    }
+
+    /// Check the settings without loading anything
+    pub fn validate(&self) -> Result<()> {
+        if let Some(max_days) = self.max_validity_days {
+            if max_days <= 0 {
+                anyhow::bail!("MAX_VALIDITY_DAYS must be positive, got {}", max_days);
+            }
+        }
+        if self.not_before_backdate_seconds < 0 {
+            anyhow::bail!("NOT_BEFORE_BACKDATE_SECONDS must not be negative, got {}", self.not_before_backdate_seconds);
+        }
+        if self.node_intermediates {
+            self.node_intermediates()?;
+        }
+        if self.default_profile.is_some() && self.profiles_configmap.is_none() {
+            anyhow::bail!("DEFAULT_PROFILE requires PROFILES_CONFIGMAP");
+        }
+        if self.tenants_configmap.is_some() && self.tenant_label.is_empty() {
+            anyhow::bail!("TENANT_LABEL must not be empty");
+        }
+        #[cfg(not(feature = "postgres"))]
+        if self.database_url.is_some() {
+            anyhow::bail!("DATABASE_URL requires a build with the postgres feature");
+        }
+        self.issuance_settings()?;
+        Ok(())
+    }
+
+    fn node_intermediates(&self) -> Result<NodeIntermediates> {
+        NodeIntermediates::parse(self.node_intermediate_validity_seconds, &self.node_intermediate_permitted_dns)
+            .context("Invalid NODE_INTERMEDIATE_VALIDITY_SECONDS or NODE_INTERMEDIATE_PERMITTED_DNS")
+    }
+
+    /// Log the settings as part of the startup configuration
+    pub fn log(&self) {
+        info!("  Allowed Namespaces: {}", if self.allowed_namespaces.is_empty() { "*" } else { &self.allowed_namespaces });
+        info!("  Denied Namespaces: {}", self.denied_namespaces);
+        if !self.namespace_name_suffixes.is_empty() {
+            info!("  Namespace Name Suffixes: {}", self.namespace_name_suffixes);
+        }
+        if let Some(days) = self.max_validity_days {
+            info!("  Max Validity: {} days ({:?})", days, self.validity_mode);
+        }
+        info!("  NotBefore Backdate: {}s", self.not_before_backdate_seconds);
+        if let Some(name) = &self.policy_configmap {
+            info!("  Policy ConfigMap: {}/{}", self.policy_configmap_namespace.as_deref().unwrap_or_default(), name);
+        }
+        if let Some(name) = &self.profiles_configmap {
+            info!("  Profiles ConfigMap: {}/{}", self.profiles_configmap_namespace.as_deref().unwrap_or_default(), name);
+            if let Some(profile) = &self.default_profile {
+                info!("  Default Profile: {}", profile);
+            }
+        }
+        if let Some(name) = &self.tenants_configmap {
+            info!(
+                "  Tenants ConfigMap: {}/{} (label {})",
+                self.tenants_configmap_namespace.as_deref().unwrap_or_default(), name, self.tenant_label
+            );
+        }
+        if let Some(path) = &self.audit_log_file {
+            info!("  Audit Log File: {}", path);
+        }
+        if let Some(url) = &self.audit_webhook_url {
+            info!("  Audit Webhook: {}", url);
+        }
+        if self.node_intermediates {
+            info!(
+                "  Node Intermediates: {}s validity, permitted DNS {}",
+                self.node_intermediate_validity_seconds, self.node_intermediate_permitted_dns
+            );
+        }
+        if !self.crl_urls.is_empty() {
+            info!("  CRL URLs: {}", self.crl_urls);
+        }
+        if !self.ocsp_urls.is_empty() {
+            info!("  OCSP URLs: {}", self.ocsp_urls);
+        }
+        if !self.ca_issuers_urls.is_empty() {
+            info!("  CA Issuers URLs: {}", self.ca_issuers_urls);
+        }
+        // The URL may carry a password
+        info!("  Certificate Store: {}", if self.database_url.is_some() { "postgres" } else { "memory" });
+    }
+
+    /// Load the tenants, if a tenants ConfigMap is set, and keep them in sync with it
+    ///
+    /// Shared by the certificate service and the CSI driver, which both need the tenant CAs.
+    pub async fn tenancy(&self) -> Result<Option<Arc<Tenancy>>> {
+        let Some(name) = &self.tenants_configmap else {
+            return Ok(None);
+        };
+
+        let namespace = self.tenants_configmap_namespace.clone().unwrap_or_default();
+        let tenancy = Arc::new(Tenancy::new(self.tenant_label.clone(), name.clone(), namespace).await?);
+
+        let refreshed = tenancy.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POLICY_REFRESH_INTERVAL).await;
+                if let Err(e) = refreshed.reload().await {
+                    warn!("Failed to refresh tenants, keeping previous tenants: {}", e);
+                }
+            }
+        });
+
+        Ok(Some(tenancy))
+    }
+
+    /// Create the certificate service signing with `signer`, or the namespace's or tenant's own signer
+    ///
+    /// Loads profiles, policy rules and the audit log, and keeps the ConfigMaps in sync
+    /// in the background. `tenant_signers` are the signers of the tenant CAs by CA secret.
+    pub async fn build(
+        self,
+        signer: Arc<dyn Signer>,
+        namespace_signers: HashMap<String, Arc<dyn Signer>>,
+        tenancy: Option<Arc<Tenancy>>,
+        tenant_signers: HashMap<String, Arc<dyn Signer>>,
+    ) -> Result<CertificateServiceImpl> {
+        self.validate()?;
+        let mut cert_service = CertificateServiceImpl::new(signer.clone())
+            .with_issuance_settings(self.issuance_settings()?)
+            .with_namespace_signers(namespace_signers.clone());
+        if let Some(tenancy) = tenancy {
+            cert_service = cert_service.with_tenancy(tenancy, tenant_signers.clone());
+        }
+
+        // Tell watching nodes when a signing CA changes
+        let signers = std::iter::once(signer).chain(namespace_signers.into_values()).chain(tenant_signers.into_values());
+        for signer in signers {
+            let events = cert_service.events();
+            tokio::spawn(async move { events.watch_ca(signer).await });
+        }
+
+        if self.node_intermediates {
+            cert_service = cert_service.with_node_intermediates(self.node_intermediates()?);
+        }
+
+        // Load certificate profiles and keep them in sync with the ConfigMap
+        if let Some(name) = self.profiles_configmap {
+            let namespace = self.profiles_configmap_namespace.unwrap_or_default();
+            let profiles = Arc::new(ProfileStore::new(name, namespace).await?);
+            if let Some(profile) = &self.default_profile {
+                if profiles.get(profile).await.is_none() {
+                    anyhow::bail!("DEFAULT_PROFILE '{}' is not defined in the profiles ConfigMap", profile);
+                }
+            }
+            cert_service = cert_service.with_profiles(profiles.clone(), self.default_profile);
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(POLICY_REFRESH_INTERVAL).await;
+                    if let Err(e) = profiles.reload().await {
+                        warn!("Failed to refresh certificate profiles, keeping previous profiles: {}", e);
+                    }
+                }
+            });
+        }
+
+        // Load issuance policy and keep it in sync with the ConfigMap
+        if let Some(name) = self.policy_configmap {
+            let namespace = self.policy_configmap_namespace.unwrap_or_default();
+            let policy = Arc::new(PolicyEngine::new(name, namespace).await?);
+            cert_service = cert_service.with_policy(policy.clone());
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(POLICY_REFRESH_INTERVAL).await;
+                    if let Err(e) = policy.reload().await {
+                        warn!("Failed to refresh policy rules, keeping previous rules: {}", e);
+                    }
+                }
+            });
+        }
+
+        // Share issued certificates and revocations between replicas
+        #[cfg(feature = "postgres")]
+        if let Some(url) = self.database_url {
+            let store = super::store::PostgresStore::connect(&url).await?;
+            cert_service = cert_service.with_store(Arc::new(store));
+        }
+
+        // Record signing operations for compliance evidence
+        if self.audit_log_file.is_some() || self.audit_webhook_url.is_some() {
+            let audit_log = AuditLog::new(self.audit_log_file, self.audit_webhook_url).await?;
+            cert_service = cert_service.with_audit_log(Arc::new(audit_log));
+        }
+
+        Ok(cert_service)
+    }
+}
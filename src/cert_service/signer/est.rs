@@ -0,0 +1,382 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rcgen::{CertificateParams, KeyPair, PublicKeyData};
+use std::env;
+use std::time::Duration;
+use tracing::{info, debug};
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::extensions::ParsedExtension;
+use x509_parser::prelude::{FromDer, X509Certificate, X509Name};
+use yasna::Tag;
+
+use super::{certification_request, CurrentCertificate, SignPurpose, Signer, SubjectName};
+
+/// Connection settings for an EST (RFC 7030) server
+#[derive(Clone, Debug)]
+pub struct EstConfig {
+    /// Base URL of the EST server, e.g. https://est.example.com
+    pub server_url: String,
+    /// Optional CA label appended to /.well-known/est/
+    pub label: Option<String>,
+    /// HTTP basic auth credentials
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Path to a PEM bundle with the client certificate and key for TLS client auth
+    pub client_identity_path: Option<String>,
+    /// Path to the explicit trust anchor used to verify the EST server
+    pub ca_cert_path: Option<String>,
+}
+
+impl EstConfig {
+    /// Read the EST configuration from `EST_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        let server_url = env::var("EST_SERVER_URL")
+            .context("EST_SERVER_URL must be set for the est signer")?;
+
+        Ok(Self {
+            server_url: server_url.trim_end_matches('/').to_string(),
+            label: env::var("EST_LABEL").ok(),
+            username: env::var("EST_USERNAME").ok(),
+            password: env::var("EST_PASSWORD").ok(),
+            client_identity_path: env::var("EST_CLIENT_IDENTITY").ok(),
+            ca_cert_path: env::var("EST_CA_CERT").ok(),
+        })
+    }
+
+    fn operation_url(&self, operation: &str) -> String {
+        match &self.label {
+            Some(label) => format!("{}/.well-known/est/{}/{}", self.server_url, label, operation),
+            None => format!("{}/.well-known/est/{}", self.server_url, operation),
+        }
+    }
+}
+
+/// Enrolls certificates against an EST server using simpleenroll/simplereenroll
+///
+/// First-time enrollment authenticates as a registration authority (basic auth
+/// and/or a TLS client certificate). Re-enrollment authenticates with the
+/// certificate being renewed, as RFC 7030 requires; the CA keys stay with the EST server.
+pub struct EstSigner {
+    config: EstConfig,
+    http: reqwest::Client,
+    trust_anchor: Option<reqwest::Certificate>,
+    ca_cert_pem: Option<String>,
+}
+
+impl EstSigner {
+    pub fn new(config: EstConfig) -> Result<Self> {
+        let (trust_anchor, ca_cert_pem) = match &config.ca_cert_path {
+            Some(path) => {
+                let ca_pem = std::fs::read_to_string(path)
+                    .context(format!("Failed to read EST trust anchor {}", path))?;
+                let ca = reqwest::Certificate::from_pem(ca_pem.as_bytes())
+                    .context("Invalid EST trust anchor")?;
+                (Some(ca), Some(ca_pem))
+            }
+            None => (None, None),
+        };
+
+        let mut builder = client_builder(trust_anchor.as_ref());
+
+        if let Some(path) = &config.client_identity_path {
+            let identity_pem = std::fs::read(path)
+                .context(format!("Failed to read EST client identity {}", path))?;
+            let identity = reqwest::Identity::from_pem(&identity_pem)
+                .context("Invalid EST client identity (expected PEM certificate and key)")?;
+            builder = builder.identity(identity);
+        }
+
+        let http = builder.build().context("Failed to build HTTP client")?;
+
+        info!("Using EST signer at {}", config.server_url);
+
+        Ok(Self {
+            config,
+            http,
+            trust_anchor,
+            ca_cert_pem,
+        })
+    }
+
+    /// An HTTP client presenting the certificate being renewed as its TLS client identity
+    fn reenrollment_client(&self, current: &CurrentCertificate) -> Result<reqwest::Client> {
+        let identity_pem = format!("{}\n{}", current.cert_pem.trim_end(), current.key_pem.as_str());
+        let identity = reqwest::Identity::from_pem(identity_pem.as_bytes())
+            .context("Invalid certificate or key of the certificate being renewed")?;
+
+        client_builder(self.trust_anchor.as_ref())
+            .identity(identity)
+            .build()
+            .context("Failed to build HTTP client")
+    }
+}
+
+fn client_builder(trust_anchor: Option<&reqwest::Certificate>) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(5));
+
+    match trust_anchor {
+        Some(ca) => builder.add_root_certificate(ca.clone()),
+        None => builder,
+    }
+}
+
+#[async_trait]
+impl Signer for EstSigner {
+    fn name(&self) -> &'static str {
+        "est"
+    }
+
+    fn needs_current_certificate(&self) -> bool {
+        true
+    }
+
+    async fn sign(
+        &self,
+        params: CertificateParams,
+        subject: &SubjectName,
+        key_pair: &KeyPair,
+        purpose: SignPurpose<'_>,
+    ) -> Result<String> {
+        let csr = certification_request(&params, subject, key_pair)?;
+
+        let (operation, http) = match purpose {
+            SignPurpose::Issue => ("simpleenroll", self.http.clone()),
+            SignPurpose::Renew(current) => {
+                let current = current.ok_or_else(|| anyhow::anyhow!(
+                    "EST simplereenroll needs the certificate being renewed and its key, which the renewal did not carry"
+                ))?;
+                check_same_identity(&current.cert_pem, &csr)?;
+                ("simplereenroll", self.reenrollment_client(current)?)
+            }
+        };
+        let url = self.config.operation_url(operation);
+        debug!("Submitting CSR to {}", url);
+
+        let mut request = http
+            .post(&url)
+            .header("Content-Type", "application/pkcs10")
+            .header("Content-Transfer-Encoding", "base64")
+            .body(STANDARD.encode(&csr));
+
+        // Re-enrollment is authorized by the certificate being renewed, not the registration authority
+        if let (SignPurpose::Issue, Some(username)) = (purpose, &self.config.username) {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+
+        let response = request
+            .send()
+            .await
+            .context(format!("Failed to reach EST server at {}", url))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("EST server rejected {} ({}): {}", operation, status, detail));
+        }
+
+        let body = response
+            .text()
+            .await
+            .context("Failed to read EST response")?;
+
+        // certs-only responses are base64 encoded, possibly wrapped across lines
+        let compact: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+        let pkcs7 = STANDARD
+            .decode(compact)
+            .context("EST response is not valid base64")?;
+
+        let leaf = find_leaf_certificate(&pkcs7, key_pair)?;
+
+        Ok(pem::encode(&pem::Pem::new("CERTIFICATE", leaf)))
+    }
+
+    async fn ca_certificate(&self) -> Option<String> {
+        self.ca_cert_pem.clone()
+    }
+}
+
+/// Check that a re-enrollment CSR has the subject and SANs of the certificate being renewed
+///
+/// RFC 7030 section 4.2.2 requires them to be identical; attribute values are
+/// compared as text, since the CA may have re-encoded the string types.
+fn check_same_identity(current_pem: &str, csr: &[u8]) -> Result<()> {
+    let (_, current_pem) = x509_parser::pem::parse_x509_pem(current_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Invalid certificate being renewed: {}", e))?;
+    let current = current_pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Invalid certificate being renewed: {}", e))?;
+    let (_, request) = X509CertificationRequest::from_der(csr)
+        .map_err(|e| anyhow::anyhow!("Invalid CSR: {}", e))?;
+
+    if name_attributes(current.subject()) != name_attributes(&request.certification_request_info.subject) {
+        return Err(anyhow::anyhow!(
+            "Subject {} of the renewal does not match subject {} of the certificate being renewed",
+            request.certification_request_info.subject,
+            current.subject()
+        ));
+    }
+
+    let current_sans = current
+        .subject_alternative_name()
+        .map_err(|e| anyhow::anyhow!("Invalid SAN extension of the certificate being renewed: {}", e))?
+        .map(|san| san.value.general_names.clone())
+        .unwrap_or_default();
+    let requested_sans = request
+        .requested_extensions()
+        .and_then(|mut extensions| extensions.find_map(|extension| match extension {
+            ParsedExtension::SubjectAlternativeName(san) => Some(san.general_names.clone()),
+            _ => None,
+        }))
+        .unwrap_or_default();
+    if current_sans.len() != requested_sans.len() || !current_sans.iter().all(|name| requested_sans.contains(name)) {
+        return Err(anyhow::anyhow!("SANs of the renewal do not match those of the certificate being renewed"));
+    }
+
+    Ok(())
+}
+
+fn name_attributes(name: &X509Name) -> Vec<(String, String)> {
+    name.iter_attributes()
+        .map(|attribute| (
+            attribute.attr_type().to_id_string(),
+            attribute.as_str().map(str::to_string).unwrap_or_else(|_| STANDARD.encode(attribute.attr_value().data)),
+        ))
+        .collect()
+}
+
+/// Pick the certificate issued for `key_pair` out of a PKCS#7 certs-only response
+fn find_leaf_certificate(pkcs7: &[u8], key_pair: &KeyPair) -> Result<Vec<u8>> {
+    let spki = key_pair.subject_public_key_info();
+
+    pkcs7_certificates(pkcs7)?
+        .into_iter()
+        .find(|der| {
+            X509Certificate::from_der(der)
+                .map(|(_, cert)| cert.public_key().raw == spki.as_slice())
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| anyhow::anyhow!("EST response does not contain a certificate for the requested key"))
+}
+
+/// Extract the certificates from a degenerate PKCS#7 SignedData (RFC 2315 / RFC 5652)
+fn pkcs7_certificates(der: &[u8]) -> Result<Vec<Vec<u8>>> {
+    yasna::parse_ber(der, |reader| {
+        reader.read_sequence(|reader| {
+            let _content_type = reader.next().read_oid()?;
+            reader.next().read_tagged(Tag::context(0), |reader| {
+                reader.read_sequence(|reader| {
+                    let _version = reader.next().read_i64()?;
+                    let _digest_algorithms = reader.next().read_der()?;
+                    let _encap_content_info = reader.next().read_der()?;
+
+                    let certificates = reader.read_optional(|reader| {
+                        reader.read_tagged_implicit(Tag::context(0), |reader| {
+                            let mut certs = Vec::new();
+                            reader.read_set_of(|reader| {
+                                certs.push(reader.read_der()?);
+                                Ok(())
+                            })?;
+                            Ok(certs)
+                        })
+                    })?;
+
+                    // Skip the (empty) crls and signerInfos
+                    while reader.read_optional(|reader| reader.read_der())?.is_some() {}
+
+                    Ok(certificates.unwrap_or_default())
+                })
+            })
+        })
+    })
+    .map_err(|e| anyhow::anyhow!("Invalid PKCS#7 certs-only response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yasna::models::ObjectIdentifier;
+
+    fn certs_only(certs: &[Vec<u8>]) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_oid(&ObjectIdentifier::from_slice(&[1, 2, 840, 113549, 1, 7, 2]));
+                writer.next().write_tagged(Tag::context(0), |writer| {
+                    writer.write_sequence(|writer| {
+                        writer.next().write_i64(1);
+                        writer.next().write_set(|_| {});
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(&ObjectIdentifier::from_slice(&[1, 2, 840, 113549, 1, 7, 1]));
+                        });
+                        writer.next().write_tagged_implicit(Tag::context(0), |writer| {
+                            writer.write_set(|writer| {
+                                for cert in certs {
+                                    writer.next().write_der(cert);
+                                }
+                            });
+                        });
+                        writer.next().write_set(|_| {});
+                    });
+                });
+            });
+        })
+    }
+
+    fn self_signed(key_pair: &KeyPair, cn: &str) -> Vec<u8> {
+        let params = CertificateParams::new(vec![cn.to_string()]).unwrap();
+        params.self_signed(key_pair).unwrap().der().to_vec()
+    }
+
+    #[test]
+    fn test_find_leaf_certificate_matches_public_key() {
+        let leaf_key = KeyPair::generate().unwrap();
+        let other_key = KeyPair::generate().unwrap();
+        let leaf = self_signed(&leaf_key, "leaf.example.com");
+        let other = self_signed(&other_key, "ca.example.com");
+
+        let response = certs_only(&[other, leaf.clone()]);
+
+        assert_eq!(pkcs7_certificates(&response).unwrap().len(), 2);
+        assert_eq!(find_leaf_certificate(&response, &leaf_key).unwrap(), leaf);
+    }
+
+    #[tokio::test]
+    async fn test_reenrollment_requires_matching_identity() {
+        let current_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["web.default.svc".to_string()]).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, "web");
+        let current = CurrentCertificate {
+            cert_pem: params.self_signed(&current_key).unwrap().pem(),
+            key_pem: zeroize::Zeroizing::new(current_key.serialize_pem()),
+        };
+
+        let new_key = KeyPair::generate().unwrap();
+        let mut subject = SubjectName::new();
+        subject.push(rcgen::DnType::CommonName, "web");
+        let csr = certification_request(&params, &subject, &new_key).unwrap();
+        check_same_identity(&current.cert_pem, &csr).unwrap();
+
+        let mut other_cn = params.clone();
+        other_cn.distinguished_name.push(rcgen::DnType::CommonName, "admin");
+        let csr = certification_request(&other_cn, &subject, &new_key).unwrap();
+        assert!(check_same_identity(&current.cert_pem, &csr).unwrap_err().to_string().contains("Subject"));
+
+        let mut other_sans = params.clone();
+        other_sans.subject_alt_names.push(rcgen::SanType::DnsName("admin.default.svc".try_into().unwrap()));
+        let csr = certification_request(&other_sans, &subject, &new_key).unwrap();
+        assert!(check_same_identity(&current.cert_pem, &csr).unwrap_err().to_string().contains("SANs"));
+
+        // Without the certificate being renewed the signer has nothing to authenticate with
+        let signer = EstSigner::new(EstConfig {
+            server_url: "https://est.invalid".to_string(),
+            label: None,
+            username: Some("ra".to_string()),
+            password: Some("secret".to_string()),
+            client_identity_path: None,
+            ca_cert_path: None,
+        }).unwrap();
+        let error = signer.sign(params, &subject, &new_key, SignPurpose::Renew(None)).await.unwrap_err();
+        assert!(error.to_string().contains("did not carry"));
+    }
+}
@@ -0,0 +1,493 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use aws_sdk_kms::error::DisplayErrorContext;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::{MessageType, SigningAlgorithmSpec};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use rcgen::{CertificateParams, KeyPair, PublicKeyData, SignatureAlgorithm, SigningKey};
+use rustls_pki_types::CertificateDer;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, debug};
+use x509_parser::oid_registry::{OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION, OID_NIST_EC_P384};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::subject::{assemble_signed, to_be_signed, SignedKind};
+use super::{SignPurpose, Signer, SubjectName};
+
+/// GCE metadata endpoint handing out access tokens of the workload's service account
+const GCP_TOKEN_URL: &str = "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Azure Key Vault REST API version
+const AZURE_KEY_VAULT_API_VERSION: &str = "7.4";
+
+/// Access tokens are refreshed this long before they expire
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// Cloud key management service holding the CA key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KmsProvider {
+    /// AWS KMS, authenticated through the AWS SDK credential chain (environment, IRSA web
+    /// identity, EKS Pod Identity, instance profile)
+    Aws,
+    /// Google Cloud KMS, authenticated as the workload's service account via the metadata server
+    Gcp,
+    /// Azure Key Vault, authenticated through Azure AD workload identity
+    Azure,
+}
+
+/// Settings of the KMS signer
+#[derive(Clone, Debug)]
+pub struct KmsConfig {
+    pub provider: KmsProvider,
+    /// AWS key ID or ARN, GCP key version resource name, or Azure key identifier URL (with version)
+    pub key_id: String,
+    /// Path to the CA certificate whose key is held by the KMS
+    pub ca_cert_path: String,
+}
+
+impl KmsConfig {
+    /// Read the KMS configuration from `KMS_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        let provider = match env::var("KMS_PROVIDER").unwrap_or_default().as_str() {
+            "aws" => KmsProvider::Aws,
+            "gcp" => KmsProvider::Gcp,
+            "azure" => KmsProvider::Azure,
+            other => return Err(anyhow::anyhow!("Invalid KMS_PROVIDER '{}' (expected aws, gcp or azure)", other)),
+        };
+
+        Ok(Self {
+            provider,
+            key_id: env::var("KMS_KEY_ID").context("KMS_KEY_ID must be set for the KMS signer")?,
+            ca_cert_path: env::var("KMS_CA_CERT").context("KMS_CA_CERT must be set for the KMS signer")?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct GcpSignResponse {
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct AzureSignResponse {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// The CA key as seen by rcgen: the public half from the CA certificate and an empty
+/// signature, which [`KmsSigner::sign`] replaces with the one from the KMS
+struct UnsignedKey<'a> {
+    signer: &'a KmsSigner,
+}
+
+impl PublicKeyData for UnsignedKey<'_> {
+    fn der_bytes(&self) -> &[u8] {
+        &self.signer.public_key
+    }
+
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        self.signer.algorithm
+    }
+}
+
+impl SigningKey for UnsignedKey<'_> {
+    fn sign(&self, _msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> {
+        Ok(Vec::new())
+    }
+}
+
+/// Signs certificates with a CA key that never leaves a cloud KMS or HSM
+///
+/// Certificates are assembled locally and only the digest of the to-be-signed data is
+/// sent to the provider's sign API, so the CA private key never exists in the cluster.
+pub struct KmsSigner {
+    config: KmsConfig,
+    http: reqwest::Client,
+    /// Client of the `aws` provider
+    aws: Option<aws_sdk_kms::Client>,
+    ca_cert_pem: String,
+    ca_cert_der: Vec<u8>,
+    /// subjectPublicKey of the CA certificate
+    public_key: Vec<u8>,
+    algorithm: &'static SignatureAlgorithm,
+    access_token: Mutex<Option<(String, Instant)>>,
+}
+
+impl KmsSigner {
+    pub async fn new(config: KmsConfig) -> Result<Self> {
+        let aws = match config.provider {
+            KmsProvider::Aws => {
+                let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+                if sdk_config.region().is_none() {
+                    return Err(anyhow::anyhow!("AWS_REGION must be set for the AWS KMS signer"));
+                }
+                Some(aws_sdk_kms::Client::new(&sdk_config))
+            }
+            KmsProvider::Gcp | KmsProvider::Azure => None,
+        };
+        Self::with_aws_client(config, aws)
+    }
+
+    /// A signer making its AWS KMS calls with `aws`
+    fn with_aws_client(config: KmsConfig, aws: Option<aws_sdk_kms::Client>) -> Result<Self> {
+        let ca_cert_pem = std::fs::read_to_string(&config.ca_cert_path)
+            .context(format!("Failed to read KMS CA certificate {}", config.ca_cert_path))?;
+        let ca_cert_der = pem::parse(ca_cert_pem.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to parse KMS CA certificate PEM: {}", e))?
+            .into_contents();
+        let (public_key, algorithm) = ca_public_key(&ca_cert_der)?;
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .context("Failed to build HTTP client")?;
+
+        info!("Using {:?} KMS signer with key {}", config.provider, config.key_id);
+
+        Ok(Self {
+            config,
+            http,
+            aws,
+            ca_cert_pem,
+            ca_cert_der,
+            public_key,
+            algorithm,
+            access_token: Mutex::new(None),
+        })
+    }
+
+    /// Have the KMS sign `message` with the CA key, returning the signature as X.509 expects it
+    async fn sign_message(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let (digest, hash) = if self.algorithm == &rcgen::PKCS_ECDSA_P384_SHA384 {
+            (Sha384::digest(message).to_vec(), "SHA_384")
+        } else {
+            (Sha256::digest(message).to_vec(), "SHA_256")
+        };
+
+        match &self.config.provider {
+            KmsProvider::Aws => {
+                let algorithm = match self.algorithm {
+                    alg if alg == &rcgen::PKCS_RSA_SHA256 => SigningAlgorithmSpec::RsassaPkcs1V15Sha256,
+                    alg if alg == &rcgen::PKCS_ECDSA_P384_SHA384 => SigningAlgorithmSpec::EcdsaSha384,
+                    _ => SigningAlgorithmSpec::EcdsaSha256,
+                };
+                let client = self.aws.as_ref().context("AWS KMS client is not configured")?;
+                let response = client
+                    .sign()
+                    .key_id(&self.config.key_id)
+                    .message(Blob::new(digest))
+                    .message_type(MessageType::Digest)
+                    .signing_algorithm(algorithm)
+                    .send()
+                    .await
+                    .map_err(|e| anyhow::anyhow!("AWS KMS rejected sign request: {}", DisplayErrorContext(&e)))?;
+                response.signature.map(Blob::into_inner).context("AWS KMS returned no signature")
+            }
+            KmsProvider::Gcp => {
+                let field = if hash == "SHA_384" { "sha384" } else { "sha256" };
+                let request = self.http
+                    .post(format!("https://cloudkms.googleapis.com/v1/{}:asymmetricSign", self.config.key_id))
+                    .bearer_auth(self.access_token().await?)
+                    .json(&serde_json::json!({ "digest": { field: STANDARD.encode(&digest) } }));
+
+                let response: GcpSignResponse = Self::send(request, "Cloud KMS").await?;
+                STANDARD.decode(response.signature).context("Invalid signature from Cloud KMS")
+            }
+            KmsProvider::Azure => {
+                let algorithm = match self.algorithm {
+                    alg if alg == &rcgen::PKCS_RSA_SHA256 => "RS256",
+                    alg if alg == &rcgen::PKCS_ECDSA_P384_SHA384 => "ES384",
+                    _ => "ES256",
+                };
+                let request = self.http
+                    .post(format!(
+                        "{}/sign?api-version={}",
+                        self.config.key_id.trim_end_matches('/'), AZURE_KEY_VAULT_API_VERSION
+                    ))
+                    .bearer_auth(self.access_token().await?)
+                    .json(&serde_json::json!({ "alg": algorithm, "value": URL_SAFE_NO_PAD.encode(&digest) }));
+
+                let response: AzureSignResponse = Self::send(request, "Azure Key Vault").await?;
+                let signature = URL_SAFE_NO_PAD.decode(response.value).context("Invalid signature from Azure Key Vault")?;
+                // Key Vault returns ECDSA signatures as r || s (JWS); X.509 wants the DER encoding
+                if algorithm.starts_with("ES") { Ok(ecdsa_jose_to_der(&signature)?) } else { Ok(signature) }
+            }
+        }
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder, provider: &str) -> Result<T> {
+        let response = request
+            .send()
+            .await
+            .context(format!("Failed to reach {}", provider))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("{} rejected sign request ({}): {}", provider, status, detail));
+        }
+
+        response.json().await.context(format!("Invalid sign response from {}", provider))
+    }
+
+    /// OAuth access token for GCP or Azure, cached until shortly before it expires
+    async fn access_token(&self) -> Result<String> {
+        let mut cached = self.access_token.lock().await;
+        if let Some((token, expires)) = cached.as_ref() {
+            if Instant::now() + TOKEN_REFRESH_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let response: TokenResponse = match &self.config.provider {
+            KmsProvider::Gcp => {
+                let request = self.http.get(GCP_TOKEN_URL).header("Metadata-Flavor", "Google");
+                Self::send(request, "GCP metadata server").await?
+            }
+            KmsProvider::Azure => {
+                let tenant = env::var("AZURE_TENANT_ID").context("AZURE_TENANT_ID must be set for Azure Key Vault")?;
+                let client_id = env::var("AZURE_CLIENT_ID").context("AZURE_CLIENT_ID must be set for Azure Key Vault")?;
+                let token_file = env::var("AZURE_FEDERATED_TOKEN_FILE")
+                    .context("AZURE_FEDERATED_TOKEN_FILE must be set for Azure Key Vault")?;
+                let authority = env::var("AZURE_AUTHORITY_HOST")
+                    .unwrap_or_else(|_| "https://login.microsoftonline.com/".to_string());
+                let assertion = tokio::fs::read_to_string(&token_file)
+                    .await
+                    .context(format!("Failed to read federated token {}", token_file))?;
+
+                let request = self.http
+                    .post(format!("{}/{}/oauth2/v2.0/token", authority.trim_end_matches('/'), tenant))
+                    .form(&[
+                        ("grant_type", "client_credentials"),
+                        ("client_id", client_id.as_str()),
+                        ("scope", "https://vault.azure.net/.default"),
+                        ("client_assertion_type", "urn:ietf:params:oauth:client-assertion-type:jwt-bearer"),
+                        ("client_assertion", assertion.trim()),
+                    ]);
+                Self::send(request, "Azure AD").await?
+            }
+            KmsProvider::Aws => return Err(anyhow::anyhow!("AWS KMS calls are made by the AWS SDK")),
+        };
+
+        debug!("Obtained KMS access token, valid for {}s", response.expires_in);
+        *cached = Some((response.access_token.clone(), Instant::now() + Duration::from_secs(response.expires_in)));
+        Ok(response.access_token)
+    }
+}
+
+#[async_trait]
+impl Signer for KmsSigner {
+    fn name(&self) -> &'static str {
+        "kms"
+    }
+
+    async fn sign(
+        &self,
+        params: CertificateParams,
+        subject: &SubjectName,
+        key_pair: &KeyPair,
+        _purpose: SignPurpose<'_>,
+    ) -> Result<String> {
+        // rcgen signs synchronously, so it builds the certificate with an empty signature and
+        // the KMS signs the to-be-signed data afterwards, on whatever runtime this runs on
+        let ca_key = UnsignedKey { signer: self };
+        let ca_cert_der = CertificateDer::from(self.ca_cert_der.as_slice());
+        let ca_issuer = rcgen::Issuer::from_ca_cert_der(&ca_cert_der, &ca_key)
+            .map_err(|e| anyhow::anyhow!("Failed to create issuer from CA cert: {}", e))?;
+        let unsigned = params.signed_by(key_pair, &ca_issuer)
+            .map_err(|e| anyhow::anyhow!("Failed to build certificate: {}", e))?;
+
+        let repeated = subject.has_repeated_attributes().then_some(subject);
+        let (info, algorithm) = to_be_signed(unsigned.der(), SignedKind::Certificate, repeated)?;
+        let signature = self.sign_message(&info).await?;
+        let cert_der = assemble_signed(&info, &algorithm, &signature);
+
+        // A key that does not belong to the CA certificate yields certificates nobody can verify
+        let (_, cert) = X509Certificate::from_der(&cert_der)
+            .map_err(|e| anyhow::anyhow!("Failed to parse signed certificate: {}", e))?;
+        let (_, ca) = X509Certificate::from_der(&self.ca_cert_der)
+            .map_err(|e| anyhow::anyhow!("Failed to parse KMS CA certificate: {}", e))?;
+        cert.verify_signature(Some(ca.public_key()))
+            .map_err(|_| anyhow::anyhow!("KMS key {} does not match the CA certificate", self.config.key_id))?;
+
+        Ok(pem::encode(&pem::Pem::new("CERTIFICATE", cert_der)))
+    }
+
+    async fn ca_certificate(&self) -> Option<String> {
+        Some(self.ca_cert_pem.clone())
+    }
+}
+
+/// Public key and signature algorithm of a CA certificate (EC P-256, EC P-384 or RSA)
+fn ca_public_key(ca_cert_der: &[u8]) -> Result<(Vec<u8>, &'static SignatureAlgorithm)> {
+    let (_, cert) = X509Certificate::from_der(ca_cert_der)
+        .map_err(|e| anyhow::anyhow!("Failed to parse KMS CA certificate: {}", e))?;
+    let spki = cert.public_key();
+
+    let algorithm = if spki.algorithm.algorithm == OID_PKCS1_RSAENCRYPTION {
+        &rcgen::PKCS_RSA_SHA256
+    } else if spki.algorithm.algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = spki.algorithm.parameters.as_ref().and_then(|p| p.as_oid().ok());
+        match curve {
+            Some(oid) if oid == OID_EC_P256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            Some(oid) if oid == OID_NIST_EC_P384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            other => return Err(anyhow::anyhow!("Unsupported EC curve {:?} in KMS CA certificate", other)),
+        }
+    } else {
+        return Err(anyhow::anyhow!(
+            "Unsupported key type {} in KMS CA certificate (expected EC P-256, EC P-384 or RSA)",
+            spki.algorithm.algorithm
+        ));
+    };
+
+    Ok((spki.subject_public_key.data.to_vec(), algorithm))
+}
+
+/// Convert a JOSE ECDSA signature (r || s) into the DER `Ecdsa-Sig-Value` used by X.509
+fn ecdsa_jose_to_der(signature: &[u8]) -> Result<Vec<u8>> {
+    if signature.is_empty() || signature.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("Invalid ECDSA signature length {}", signature.len()));
+    }
+    let (r, s) = signature.split_at(signature.len() / 2);
+    Ok(yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_bigint_bytes(r, true);
+            writer.next().write_bigint_bytes(s, true);
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+    use p256::pkcs8::DecodePrivateKey;
+    use rcgen::DnType;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// An AWS KMS endpoint answering Sign requests with `key`; returns its URL and the number of requests
+    async fn mock_aws_kms(key: p256::ecdsa::SigningKey) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let (key, counter) = (key.clone(), counter.clone());
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                    let (key, counter) = (key.clone(), counter.clone());
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+                        assert_eq!(header("x-amz-target"), "TrentService.Sign");
+                        // Signed by the SDK with the credentials it was given
+                        assert!(header("authorization").starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                        assert_eq!((body["KeyId"].as_str(), body["MessageType"].as_str()), (Some("alias/cacsi"), Some("DIGEST")));
+                        assert_eq!(body["SigningAlgorithm"], "ECDSA_SHA_256");
+                        let digest = STANDARD.decode(body["Message"].as_str().unwrap()).unwrap();
+                        let signature: p256::ecdsa::Signature = key.sign_prehash(&digest).unwrap();
+
+                        let response = serde_json::json!({
+                            "KeyId": "alias/cacsi",
+                            "Signature": STANDARD.encode(signature.to_der().as_bytes()),
+                            "SigningAlgorithm": "ECDSA_SHA_256",
+                        });
+                        Ok::<_, hyper::Error>(Response::new(Body::from(response.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, requests)
+    }
+
+    /// A KMS signer for the CA certificate in `dir`, calling AWS KMS at `endpoint`
+    fn aws_signer(dir: &std::path::Path, ca_cert_pem: &str, endpoint: &str) -> KmsSigner {
+        let ca_cert_path = dir.join("ca.crt");
+        std::fs::write(&ca_cert_path, ca_cert_pem).unwrap();
+        let config = KmsConfig {
+            provider: KmsProvider::Aws,
+            key_id: "alias/cacsi".to_string(),
+            ca_cert_path: ca_cert_path.to_str().unwrap().to_string(),
+        };
+        let aws = aws_sdk_kms::Config::builder()
+            .behavior_version(aws_sdk_kms::config::BehaviorVersion::latest())
+            .region(aws_sdk_kms::config::Region::new("eu-west-1"))
+            .credentials_provider(aws_sdk_kms::config::Credentials::new("AKIDEXAMPLE", "secret", None, None, "test"))
+            .endpoint_url(endpoint)
+            .build();
+        KmsSigner::with_aws_client(config, Some(aws_sdk_kms::Client::from_conf(aws))).unwrap()
+    }
+
+    // A current-thread runtime, where waiting for the KMS inside rcgen's signing would panic
+    #[tokio::test]
+    async fn test_sign_with_aws_kms() {
+        let dir = TempDir::new("kms").unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::default();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "KMS CA");
+        let ca_cert_pem = ca_params.self_signed(&ca_key).unwrap().pem();
+        let kms_key = p256::ecdsa::SigningKey::from_pkcs8_der(&ca_key.serialize_der()).unwrap();
+
+        let mut subject = SubjectName::new();
+        subject.push(DnType::CommonName, "web");
+        subject.push(DnType::OrganizationalUnitName, "payments");
+        subject.push(DnType::OrganizationalUnitName, "eu");
+        let mut params = CertificateParams::new(vec!["web.default.svc".to_string()]).unwrap();
+        params.distinguished_name = subject.to_distinguished_name();
+        let key_pair = KeyPair::generate().unwrap();
+
+        let (endpoint, requests) = mock_aws_kms(kms_key).await;
+        let signer = aws_signer(&dir, &ca_cert_pem, &endpoint);
+        let cert_pem = signer.sign(params.clone(), &subject, &key_pair, SignPurpose::Issue).await.unwrap();
+        // One KMS call, with the repeated OUs already in place
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let der = pem::parse(&cert_pem).unwrap().into_contents();
+        let (_, cert) = X509Certificate::from_der(&der).unwrap();
+        assert_eq!(cert.subject().iter_organizational_unit().count(), 2);
+        assert_eq!(cert.issuer().to_string(), "CN=KMS CA");
+
+        // A KMS key that is not the CA's fails instead of producing an unverifiable certificate
+        let other_key = p256::ecdsa::SigningKey::from_pkcs8_der(&KeyPair::generate().unwrap().serialize_der()).unwrap();
+        let (endpoint, _) = mock_aws_kms(other_key).await;
+        let signer = aws_signer(&dir, &ca_cert_pem, &endpoint);
+        let error = signer.sign(params, &subject, &key_pair, SignPurpose::Issue).await.unwrap_err();
+        assert!(error.to_string().contains("does not match the CA certificate"), "{}", error);
+    }
+
+    #[test]
+    fn test_ca_key_and_jose_signature() {
+        let key = KeyPair::generate_for(&rcgen::PKCS_ECDSA_P384_SHA384).unwrap();
+        let ca = CertificateParams::default().self_signed(&key).unwrap();
+        let (public_key, algorithm) = ca_public_key(ca.der()).unwrap();
+        assert_eq!(public_key, key.der_bytes());
+        assert_eq!(algorithm, &rcgen::PKCS_ECDSA_P384_SHA384);
+
+        let mut jose = vec![0x80; 48];
+        jose.extend(vec![0x01; 48]);
+        let der = ecdsa_jose_to_der(&jose).unwrap();
+        let (r, s) = yasna::parse_der(&der, |reader| {
+            reader.read_sequence(|reader| Ok((reader.next().read_bigint_bytes()?, reader.next().read_bigint_bytes()?)))
+        }).unwrap();
+        assert!(r.1 && s.1);
+        assert_eq!(r.0.len(), 49);
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::Api;
+use k8s_openapi::api::core::v1::Secret;
+use rcgen::{CertificateParams, Issuer, KeyPair, PublicKeyData, SignatureAlgorithm, SigningKey};
+use rustls_pki_types::CertificateDer;
+use std::sync::Arc;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use crate::ca_manager::CaManager;
+
+use super::ca_key::{load_passphrase, parse_ca_key, parse_ca_secret};
+use super::subject::{replace_subject, SignedKind};
+use super::{SignPurpose, Signer, SubjectName};
+
+/// Signs certificates with a CA certificate and key loaded from a Kubernetes secret
+pub struct LocalSigner {
+    ca_secret_name: String,
+    ca_secret_namespace: String,
+    /// Secret (in the CA secret's namespace) with the passphrase of an encrypted CA key
+    passphrase_secret: Option<String>,
+    ca: Arc<tokio::sync::RwLock<Option<LoadedCa>>>,
+}
+
+/// The CA certificate with an issuer built from it, parsed once per (re)load instead of per certificate
+struct LoadedCa {
+    cert_pem: String,
+    issuer: Issuer<'static, CaKey>,
+}
+
+impl LoadedCa {
+    fn new(cert_pem: String, key: Zeroizing<KeyPair>) -> Result<Self> {
+        let ca_pems = pem::parse_many(cert_pem.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to parse CA cert PEM: {}", e))?;
+        let ca_cert_pem = ca_pems.into_iter().next()
+            .ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+        let ca_cert_der = CertificateDer::from(ca_cert_pem.contents().to_vec());
+
+        let issuer = Issuer::from_ca_cert_der(&ca_cert_der, CaKey(key))
+            .map_err(|e| anyhow::anyhow!("Failed to create issuer from CA cert: {}", e))?;
+
+        Ok(Self { cert_pem, issuer })
+    }
+
+    fn key(&self) -> &KeyPair {
+        &self.issuer.key().0
+    }
+}
+
+/// CA key pair owned by the issuer, wiped when the CA is replaced
+struct CaKey(Zeroizing<KeyPair>);
+
+impl PublicKeyData for CaKey {
+    fn der_bytes(&self) -> &[u8] {
+        self.0.der_bytes()
+    }
+
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        self.0.algorithm()
+    }
+}
+
+impl SigningKey for CaKey {
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> {
+        self.0.sign(msg)
+    }
+}
+
+impl LocalSigner {
+    pub async fn new(
+        ca_secret_name: String,
+        ca_secret_namespace: String,
+        passphrase_secret: Option<String>,
+    ) -> Result<Self> {
+        let signer = Self {
+            ca_secret_name,
+            ca_secret_namespace,
+            passphrase_secret,
+            ca: Arc::new(tokio::sync::RwLock::new(None)),
+        };
+
+        signer.load_ca().await?;
+
+        Ok(signer)
+    }
+
+    /// Sign with a fixed CA held in memory, without a CA secret (dev mode)
+    ///
+    /// The dev CA's key is generated unencrypted, so there is no passphrase to apply.
+    pub fn in_memory(ca_cert_pem: String, ca_key_pem: &str) -> Result<Self> {
+        Ok(Self::loaded(LoadedCa::new(ca_cert_pem, parse_ca_key(ca_key_pem, None)?)?))
+    }
+
+    /// Sign with the CA held by `ca_manager` (all-in-one mode)
+    ///
+    /// The CA manager must hold the CA key, so a trust-only CA cannot sign.
+    pub async fn from_ca_manager(ca_manager: &CaManager) -> Result<Self> {
+        let ca_key = ca_manager.get_ca_key().await?;
+        Ok(Self::loaded(LoadedCa::new(ca_manager.get_ca_cert().await?, ca_key)?))
+    }
+
+    fn loaded(ca: LoadedCa) -> Self {
+        Self {
+            ca_secret_name: String::new(),
+            ca_secret_namespace: String::new(),
+            passphrase_secret: None,
+            ca: Arc::new(tokio::sync::RwLock::new(Some(ca))),
+        }
+    }
+
+    /// Keep the CA in sync with `ca_manager`, so a rotated CA is used without a restart
+    ///
+    /// Runs until the process exits. Keys that fail to parse are logged and ignored;
+    /// the previous CA keeps signing.
+    pub async fn follow_ca_manager(&self, ca_manager: &CaManager) {
+        let mut generation = ca_manager.subscribe();
+        while generation.changed().await.is_ok() {
+            let ca = async {
+                let ca_key = ca_manager.get_ca_key().await?;
+                LoadedCa::new(ca_manager.get_ca_cert().await?, ca_key)
+            };
+            match ca.await {
+                Ok(ca) => {
+                    *self.ca.write().await = Some(ca);
+                    info!("CA rotated, certificate service signer reloaded");
+                }
+                Err(e) => warn!("Ignoring CA update, keeping current CA: {:#}", e),
+            }
+        }
+    }
+
+    async fn load_ca(&self) -> Result<()> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let secrets: Api<Secret> = Api::namespaced(client, &self.ca_secret_namespace);
+
+        let secret = crate::k8s_client::with_retries(|| secrets.get(&self.ca_secret_name))
+            .await
+            .context("Failed to get CA secret")?;
+
+        let passphrase = self.load_passphrase(&secrets).await?;
+        let (ca_cert_str, ca_key_pem) = parse_ca_secret(secret)?;
+        let ca_keypair = parse_ca_key(&ca_key_pem, passphrase.as_deref().map(String::as_str))?;
+
+        *self.ca.write().await = Some(LoadedCa::new(ca_cert_str, ca_keypair)?);
+
+        info!("CA loaded successfully from secret");
+
+        Ok(())
+    }
+
+    /// Keep the CA in sync with the secret, so a rotated CA is used without a restart
+    ///
+    /// Runs until the process exits. Invalid updates are logged and ignored; the
+    /// previous CA keeps signing.
+    pub async fn watch_secret(&self) -> Result<()> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let secrets: Api<Secret> = Api::namespaced(client, &self.ca_secret_namespace);
+        let config = watcher::Config::default().fields(&format!("metadata.name={}", self.ca_secret_name));
+
+        let mut updates = watcher(secrets.clone(), config)
+            .default_backoff()
+            .applied_objects()
+            .boxed();
+
+        loop {
+            match updates.try_next().await {
+                Ok(Some(secret)) => {
+                    // Re-read the passphrase, which is usually rotated together with the key
+                    let passphrase = match self.load_passphrase(&secrets).await {
+                        Ok(passphrase) => passphrase,
+                        Err(e) => {
+                            warn!("Ignoring CA secret update, keeping current CA: {:#}", e);
+                            continue;
+                        }
+                    };
+                    let ca = parse_ca_secret(secret).and_then(|(ca_cert_str, ca_key_pem)| {
+                        let ca_keypair = parse_ca_key(&ca_key_pem, passphrase.as_deref().map(String::as_str))?;
+                        LoadedCa::new(ca_cert_str, ca_keypair)
+                    });
+                    let ca = match ca {
+                        Ok(ca) => ca,
+                        Err(e) => {
+                            warn!("Ignoring invalid CA secret update, keeping current CA: {}", e);
+                            continue;
+                        }
+                    };
+
+                    // The watch replays the current secret on (re)connect
+                    let unchanged = self.ca.read().await.as_ref().is_some_and(|current| {
+                        current.cert_pem == ca.cert_pem && current.key().public_key_pem() == ca.key().public_key_pem()
+                    });
+                    if unchanged {
+                        continue;
+                    }
+
+                    *self.ca.write().await = Some(ca);
+
+                    info!("CA secret {}/{} changed, CA reloaded", self.ca_secret_namespace, self.ca_secret_name);
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => warn!("Error watching CA secret {}/{}: {}", self.ca_secret_namespace, self.ca_secret_name, e),
+            }
+        }
+    }
+
+    /// Read the CA key passphrase from the passphrase secret, if one is configured
+    async fn load_passphrase(&self, secrets: &Api<Secret>) -> Result<Option<Zeroizing<String>>> {
+        match &self.passphrase_secret {
+            Some(name) => load_passphrase(secrets, &self.ca_secret_namespace, name).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn sign(
+        &self,
+        params: CertificateParams,
+        subject: &SubjectName,
+        key_pair: &KeyPair,
+        _purpose: SignPurpose<'_>,
+    ) -> Result<String> {
+        let ca_lock = self.ca.read().await;
+        let ca = ca_lock
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("CA not loaded"))?;
+
+        // Sign the server certificate with the CA
+        let cert_signed = params.signed_by(key_pair, &ca.issuer)
+            .map_err(|e| anyhow::anyhow!("Failed to sign certificate with CA: {}", e))?;
+
+        let cert_der = if subject.has_repeated_attributes() {
+            replace_subject(cert_signed.der(), SignedKind::Certificate, subject, ca.key())?
+        } else {
+            cert_signed.der().to_vec()
+        };
+
+        Ok(pem::encode(&pem::Pem::new("CERTIFICATE", cert_der)))
+    }
+
+    async fn ca_certificate(&self) -> Option<String> {
+        self.ca.read().await.as_ref().map(|ca| ca.cert_pem.clone())
+    }
+}
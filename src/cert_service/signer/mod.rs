@@ -0,0 +1,115 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::RngCore;
+use rcgen::{CertificateParams, KeyPair};
+use zeroize::Zeroizing;
+
+mod ca_key;
+mod est;
+mod kms;
+mod local;
+mod step_ca;
+mod subject;
+
+pub use ca_key::{load_passphrase, parse_ca_key, parse_ca_secret};
+pub use est::{EstConfig, EstSigner};
+pub use kms::{KmsConfig, KmsSigner};
+pub use local::LocalSigner;
+pub use step_ca::{StepCaConfig, StepCaSigner};
+pub use subject::SubjectName;
+
+/// A random 159-bit certificate serial
+///
+/// RFC 5280 allows at most 20 octets and requires serials to be positive; the
+/// second-highest bit is set so the serial always takes all 20.
+pub fn random_serial() -> [u8; 20] {
+    let mut serial = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut serial);
+    serial[0] &= 0x7f;
+    serial[0] |= 0x40;
+    serial
+}
+
+/// Why a certificate is being signed
+///
+/// Some enrollment protocols (EST) use different endpoints for first-time
+/// enrollment and re-enrollment of an existing identity.
+#[derive(Clone, Copy, Debug)]
+pub enum SignPurpose<'a> {
+    Issue,
+    /// Renewal of a certificate, with the certificate and key the node holds if it sent them
+    Renew(Option<&'a CurrentCertificate>),
+}
+
+/// The certificate being renewed and its key, as held by the node
+///
+/// EST re-enrollment authenticates with this identity instead of the registration authority's.
+#[derive(Clone, Debug)]
+pub struct CurrentCertificate {
+    pub cert_pem: String,
+    pub key_pem: Zeroizing<String>,
+}
+
+/// A backend capable of turning certificate parameters into a signed certificate
+///
+/// The certificate service always generates the leaf key pair itself; signers only
+/// ever see the public half (either directly or through a CSR), so the private key
+/// returned to the node never leaves the service.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Short backend name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Sign a certificate for the public key of `key_pair`
+    ///
+    /// `subject` is the full subject; `params.distinguished_name` only holds what
+    /// rcgen can represent of it (see [`SubjectName`]).
+    ///
+    /// Returns the signed leaf certificate in PEM format. Remote backends may
+    /// override the requested validity, so callers should read the actual
+    /// validity period from the returned certificate.
+    async fn sign(
+        &self,
+        params: CertificateParams,
+        subject: &SubjectName,
+        key_pair: &KeyPair,
+        purpose: SignPurpose<'_>,
+    ) -> Result<String>;
+
+    /// Whether renewals must carry the certificate being renewed and its key
+    ///
+    /// The service asks the node for them only then, so other backends never see the key.
+    fn needs_current_certificate(&self) -> bool {
+        false
+    }
+
+    /// The issuing CA certificate (PEM) if it is known locally
+    ///
+    /// Used to inherit subject attributes such as C and O from the CA.
+    async fn ca_certificate(&self) -> Option<String>;
+}
+
+/// Build a CSR for `params`, carrying the full `subject`
+fn certification_request(params: &CertificateParams, subject: &SubjectName, key_pair: &KeyPair) -> Result<Vec<u8>> {
+    let csr = params
+        .serialize_request(key_pair)
+        .map_err(|e| anyhow::anyhow!("Failed to build CSR: {}", e))?;
+
+    if !subject.has_repeated_attributes() {
+        return Ok(csr.der().to_vec());
+    }
+
+    subject::replace_subject(csr.der(), subject::SignedKind::CertificationRequest, subject, key_pair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_serial() {
+        let serial = random_serial();
+        assert_eq!(serial[0] & 0xc0, 0x40);
+        assert_ne!(random_serial(), serial);
+    }
+}
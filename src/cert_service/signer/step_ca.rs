@@ -0,0 +1,328 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rcgen::{CertificateParams, DnType, DnValue, KeyPair, PublicKeyData, SanType, SigningKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::Duration;
+use tracing::{info, debug};
+
+use super::{certification_request, SignPurpose, Signer, SubjectName};
+
+/// Lifetime of the one-time tokens we mint for the JWK provisioner
+const TOKEN_LIFETIME_SECS: i64 = 300;
+
+/// Connection settings for a smallstep step-ca instance
+#[derive(Clone, Debug)]
+pub struct StepCaConfig {
+    /// Base URL of the CA, e.g. https://step-ca.step.svc:9000
+    pub ca_url: String,
+    /// Name of the provisioner used to authorize sign requests
+    pub provisioner: String,
+    /// Path to the JWK provisioner private key (unencrypted PKCS#8 PEM, P-256)
+    pub provisioner_key_path: Option<String>,
+    /// Key ID of the JWK provisioner; derived from the key thumbprint when unset
+    pub provisioner_kid: Option<String>,
+    /// Path to an OIDC ID token used with an OIDC provisioner (re-read on every request)
+    pub token_file: Option<String>,
+    /// Path to the step-ca root certificate used to verify the CA's TLS endpoint
+    pub root_cert_path: Option<String>,
+}
+
+impl StepCaConfig {
+    /// Read the step-ca configuration from `STEP_CA_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        let ca_url = env::var("STEP_CA_URL")
+            .context("STEP_CA_URL must be set for the step-ca signer")?;
+        let provisioner = env::var("STEP_CA_PROVISIONER")
+            .context("STEP_CA_PROVISIONER must be set for the step-ca signer")?;
+
+        Ok(Self {
+            ca_url: ca_url.trim_end_matches('/').to_string(),
+            provisioner,
+            provisioner_key_path: env::var("STEP_CA_PROVISIONER_KEY").ok(),
+            provisioner_kid: env::var("STEP_CA_PROVISIONER_KID").ok(),
+            token_file: env::var("STEP_CA_TOKEN_FILE").ok(),
+            root_cert_path: env::var("STEP_CA_ROOT_CERT").ok(),
+        })
+    }
+}
+
+/// How sign requests are authorized against the provisioner
+enum ProvisionerAuth {
+    /// Tokens are minted and signed locally with the JWK provisioner key
+    Jwk { key: KeyPair, kid: String },
+    /// A pre-issued OIDC ID token is read from disk for every request
+    Oidc { token_file: String },
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    crt: String,
+}
+
+/// Signs certificates through the step-ca `/1.0/sign` API
+///
+/// The CA keys stay inside step-ca; the service only submits a CSR together
+/// with a provisioner one-time token.
+pub struct StepCaSigner {
+    config: StepCaConfig,
+    auth: ProvisionerAuth,
+    http: reqwest::Client,
+    root_cert_pem: Option<String>,
+}
+
+impl StepCaSigner {
+    pub fn new(config: StepCaConfig) -> Result<Self> {
+        let auth = match (&config.provisioner_key_path, &config.token_file) {
+            (Some(key_path), _) => {
+                let key_pem = std::fs::read_to_string(key_path)
+                    .context(format!("Failed to read provisioner key {}", key_path))?;
+                let key = KeyPair::from_pem(&key_pem)
+                    .map_err(|e| anyhow::anyhow!("Failed to parse provisioner key: {}", e))?;
+                if key.algorithm() != &rcgen::PKCS_ECDSA_P256_SHA256 {
+                    return Err(anyhow::anyhow!("JWK provisioner key must be an ECDSA P-256 key"));
+                }
+                let kid = match &config.provisioner_kid {
+                    Some(kid) => kid.clone(),
+                    None => jwk_thumbprint(&key)?,
+                };
+                ProvisionerAuth::Jwk { key, kid }
+            }
+            (None, Some(token_file)) => ProvisionerAuth::Oidc { token_file: token_file.clone() },
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "step-ca signer requires STEP_CA_PROVISIONER_KEY (JWK) or STEP_CA_TOKEN_FILE (OIDC)"
+                ));
+            }
+        };
+
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(5));
+
+        let root_cert_pem = match &config.root_cert_path {
+            Some(path) => {
+                let root_pem = std::fs::read_to_string(path)
+                    .context(format!("Failed to read step-ca root certificate {}", path))?;
+                let root = reqwest::Certificate::from_pem(root_pem.as_bytes())
+                    .context("Invalid step-ca root certificate")?;
+                builder = builder.add_root_certificate(root);
+                Some(root_pem)
+            }
+            None => None,
+        };
+
+        let http = builder.build().context("Failed to build HTTP client")?;
+
+        info!("Using step-ca signer at {} (provisioner: {})", config.ca_url, config.provisioner);
+
+        Ok(Self {
+            config,
+            auth,
+            http,
+            root_cert_pem,
+        })
+    }
+
+    /// Produce the one-time token authorizing a sign request for `subject` and `sans`
+    async fn token(&self, subject: &str, sans: &[String]) -> Result<String> {
+        match &self.auth {
+            ProvisionerAuth::Jwk { key, kid } => {
+                let now = Utc::now().timestamp();
+                let header = serde_json::json!({
+                    "alg": "ES256",
+                    "typ": "JWT",
+                    "kid": kid,
+                });
+                let claims = serde_json::json!({
+                    "iss": self.config.provisioner,
+                    "aud": format!("{}/1.0/sign", self.config.ca_url),
+                    "sub": subject,
+                    "sans": sans,
+                    "iat": now,
+                    "nbf": now,
+                    "exp": now + TOKEN_LIFETIME_SECS,
+                    "jti": uuid::Uuid::new_v4().to_string(),
+                });
+
+                let signing_input = format!(
+                    "{}.{}",
+                    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+                    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?),
+                );
+                let der_signature = key
+                    .sign(signing_input.as_bytes())
+                    .map_err(|e| anyhow::anyhow!("Failed to sign provisioner token: {}", e))?;
+                let signature = ecdsa_der_to_jose(&der_signature)?;
+
+                Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature)))
+            }
+            ProvisionerAuth::Oidc { token_file } => {
+                let token = tokio::fs::read_to_string(token_file)
+                    .await
+                    .context(format!("Failed to read OIDC token {}", token_file))?;
+                Ok(token.trim().to_string())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for StepCaSigner {
+    fn name(&self) -> &'static str {
+        "step-ca"
+    }
+
+    async fn sign(
+        &self,
+        params: CertificateParams,
+        subject_name: &SubjectName,
+        key_pair: &KeyPair,
+        _purpose: SignPurpose<'_>,
+    ) -> Result<String> {
+        let subject = match params.distinguished_name.get(&DnType::CommonName) {
+            Some(DnValue::Utf8String(cn)) => cn.clone(),
+            _ => return Err(anyhow::anyhow!("step-ca requires a common name")),
+        };
+        let sans = subject_alt_names(&params);
+        let not_before = to_rfc3339(params.not_before.unix_timestamp());
+        let not_after = to_rfc3339(params.not_after.unix_timestamp());
+
+        let csr = pem::encode(&pem::Pem::new(
+            "CERTIFICATE REQUEST",
+            certification_request(&params, subject_name, key_pair)?,
+        ));
+
+        let token = self.token(&subject, &sans).await?;
+
+        let body = serde_json::json!({
+            "csr": csr,
+            "ott": token,
+            "notBefore": not_before,
+            "notAfter": not_after,
+        });
+
+        let url = format!("{}/1.0/sign", self.config.ca_url);
+        debug!("Submitting CSR for {} to {}", subject, url);
+
+        let response = self.http
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .context(format!("Failed to reach step-ca at {}", url))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("step-ca rejected sign request ({}): {}", status, detail));
+        }
+
+        let signed: SignResponse = response
+            .json()
+            .await
+            .context("Invalid sign response from step-ca")?;
+
+        Ok(signed.crt)
+    }
+
+    async fn ca_certificate(&self) -> Option<String> {
+        self.root_cert_pem.clone()
+    }
+}
+
+fn to_rfc3339(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339()
+}
+
+/// Collect DNS names, IPs and URIs from the certificate parameters as plain strings
+fn subject_alt_names(params: &CertificateParams) -> Vec<String> {
+    params
+        .subject_alt_names
+        .iter()
+        .filter_map(|san| match san {
+            SanType::DnsName(name) => Some(name.as_str().to_string()),
+            SanType::IpAddress(ip) => Some(ip.to_string()),
+            SanType::URI(uri) => Some(uri.as_str().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// RFC 7638 thumbprint of an EC P-256 public key, as used by step for JWK key IDs
+fn jwk_thumbprint(key: &KeyPair) -> Result<String> {
+    let point = key.der_bytes();
+    if point.len() != 65 || point[0] != 0x04 {
+        return Err(anyhow::anyhow!("Unexpected P-256 public key encoding"));
+    }
+
+    // Members must be in lexicographic order without whitespace
+    let jwk = format!(
+        r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+        URL_SAFE_NO_PAD.encode(&point[1..33]),
+        URL_SAFE_NO_PAD.encode(&point[33..65]),
+    );
+
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes())))
+}
+
+/// Convert an ASN.1 DER ECDSA signature into the fixed-size r || s form JWS expects
+fn ecdsa_der_to_jose(der: &[u8]) -> Result<Vec<u8>> {
+    let (r, s) = yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let (r, _) = reader.next().read_bigint_bytes()?;
+            let (s, _) = reader.next().read_bigint_bytes()?;
+            Ok((r, s))
+        })
+    })
+    .map_err(|e| anyhow::anyhow!("Invalid ECDSA signature: {}", e))?;
+
+    let mut raw = vec![0u8; 64];
+    for (component, offset) in [(r, 0usize), (s, 32usize)] {
+        let trimmed: Vec<u8> = component.into_iter().skip_while(|b| *b == 0).collect();
+        if trimmed.len() > 32 {
+            return Err(anyhow::anyhow!("ECDSA signature component too long"));
+        }
+        raw[offset + 32 - trimmed.len()..offset + 32].copy_from_slice(&trimmed);
+    }
+
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ecdsa_der_to_jose_pads_components() {
+        // r has a leading sign byte, s is shorter than 32 bytes
+        let mut r = vec![0x00];
+        r.extend(vec![0xff; 32]);
+        let s = vec![0x01; 31];
+        let der = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_bigint_bytes(&r, true);
+                writer.next().write_bigint_bytes(&s, true);
+            })
+        });
+
+        let raw = ecdsa_der_to_jose(&der).unwrap();
+        assert_eq!(raw.len(), 64);
+        assert_eq!(&raw[..32], &[0xff; 32]);
+        assert_eq!(raw[32], 0x00);
+        assert_eq!(&raw[33..], &[0x01; 31]);
+    }
+
+    #[test]
+    fn test_jwk_thumbprint_is_base64url_sha256() {
+        let key = KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        let kid = jwk_thumbprint(&key).unwrap();
+        assert_eq!(kid.len(), 43);
+        assert!(!kid.contains('=') && !kid.contains('+') && !kid.contains('/'));
+    }
+}
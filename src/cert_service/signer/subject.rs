@@ -0,0 +1,244 @@
+use anyhow::Result;
+use rcgen::{DistinguishedName, DnType, SigningKey};
+use yasna::models::ObjectIdentifier;
+
+/// Subject distinguished name of an issued certificate
+///
+/// Unlike rcgen's `DistinguishedName`, an attribute type may occur several times
+/// (e.g. one `OU` per organizational unit). Each attribute is encoded as its own
+/// single-valued RDN, in the order it was added.
+#[derive(Debug, Clone, Default)]
+pub struct SubjectName {
+    attributes: Vec<(DnType, String)>,
+}
+
+impl SubjectName {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an attribute
+    pub fn push(&mut self, ty: DnType, value: impl Into<String>) {
+        self.attributes.push((ty, value.into()));
+    }
+
+    /// Whether an attribute type occurs more than once, which rcgen cannot encode
+    pub fn has_repeated_attributes(&self) -> bool {
+        self.attributes
+            .iter()
+            .enumerate()
+            .any(|(i, (ty, _))| self.attributes[..i].iter().any(|(seen, _)| seen == ty))
+    }
+
+    /// The subject as far as rcgen can represent it: the first value of each attribute type
+    pub fn to_distinguished_name(&self) -> DistinguishedName {
+        let mut dn = DistinguishedName::new();
+        for (ty, value) in &self.attributes {
+            if dn.get(ty).is_none() {
+                dn.push(ty.clone(), value.as_str());
+            }
+        }
+        dn
+    }
+
+    /// DER encoding of the X.501 `Name`
+    pub fn to_der(&self) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                for (ty, value) in &self.attributes {
+                    writer.next().write_set(|writer| {
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(&ObjectIdentifier::from_slice(dn_type_oid(ty)));
+                            // RFC 5280 requires PrintableString for the country and serialNumber
+                            if matches!(dn_type_oid(ty), [2, 5, 4, 6] | [2, 5, 4, 5]) {
+                                writer.next().write_printable_string(value);
+                            } else {
+                                writer.next().write_utf8_string(value);
+                            }
+                        });
+                    });
+                }
+            });
+        })
+    }
+}
+
+fn dn_type_oid(ty: &DnType) -> &[u64] {
+    match ty {
+        DnType::CountryName => &[2, 5, 4, 6],
+        DnType::LocalityName => &[2, 5, 4, 7],
+        DnType::StateOrProvinceName => &[2, 5, 4, 8],
+        DnType::OrganizationName => &[2, 5, 4, 10],
+        DnType::OrganizationalUnitName => &[2, 5, 4, 11],
+        DnType::CommonName => &[2, 5, 4, 3],
+        DnType::CustomDnType(oid) => oid,
+        other => unreachable!("DN attribute type {:?} is not used in subjects", other),
+    }
+}
+
+/// A signed structure whose subject can be replaced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignedKind {
+    /// X.509 certificate; the subject is the sixth field of the TBSCertificate
+    Certificate,
+    /// PKCS#10 request; the subject is the second field of the CertificationRequestInfo
+    CertificationRequest,
+}
+
+impl SignedKind {
+    fn subject_index(&self) -> usize {
+        match self {
+            SignedKind::Certificate => 5,
+            SignedKind::CertificationRequest => 1,
+        }
+    }
+}
+
+/// Replace the subject of a DER certificate or CSR produced by rcgen and sign it again with `key`
+///
+/// rcgen signs with the subject it can represent; this swaps in the full subject
+/// (see [`SubjectName`]) and recomputes the signature over the new to-be-signed data.
+/// `key` must be the key that signed `der`, so the signature algorithm is unchanged.
+pub fn replace_subject(der: &[u8], kind: SignedKind, subject: &SubjectName, key: &impl SigningKey) -> Result<Vec<u8>> {
+    let (info, algorithm) = to_be_signed(der, kind, Some(subject))?;
+    let signature = key
+        .sign(&info)
+        .map_err(|e| anyhow::anyhow!("Failed to sign: {}", e))?;
+    Ok(assemble_signed(&info, &algorithm, &signature))
+}
+
+/// The to-be-signed data and signature algorithm of a DER certificate or CSR
+///
+/// With `subject`, its subject is replaced as in [`replace_subject`]. Signers that can only
+/// sign asynchronously have rcgen sign with a placeholder and sign these bytes afterwards.
+pub fn to_be_signed(der: &[u8], kind: SignedKind, subject: Option<&SubjectName>) -> Result<(Vec<u8>, Vec<u8>)> {
+    let outer = read_sequence(der)?;
+    let mut parts = split_tlvs(outer)?;
+    if parts.len() != 3 {
+        return Err(anyhow::anyhow!("Expected a signed structure with 3 fields, got {}", parts.len()));
+    }
+    let (info, algorithm) = (parts.remove(0), parts.remove(0));
+    let Some(subject) = subject else {
+        return Ok((info.to_vec(), algorithm.to_vec()));
+    };
+
+    let mut fields = split_tlvs(read_sequence(info)?)?;
+    let index = kind.subject_index();
+    if fields.len() <= index {
+        return Err(anyhow::anyhow!("Signed structure has no subject field"));
+    }
+    let subject_der = subject.to_der();
+    fields[index] = &subject_der;
+
+    let info = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            for field in &fields {
+                writer.next().write_der(field);
+            }
+        });
+    });
+    Ok((info, algorithm.to_vec()))
+}
+
+/// A DER certificate or CSR from its to-be-signed data, signature algorithm and signature
+pub fn assemble_signed(info: &[u8], algorithm: &[u8], signature: &[u8]) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_der(info);
+            writer.next().write_der(algorithm);
+            writer.next().write_bitvec_bytes(signature, signature.len() * 8);
+        });
+    })
+}
+
+/// Contents of a DER SEQUENCE that spans all of `der`
+fn read_sequence(der: &[u8]) -> Result<&[u8]> {
+    let (header, total) = read_tlv(der)?;
+    if der[0] != 0x30 || total != der.len() {
+        return Err(anyhow::anyhow!("Expected a DER SEQUENCE"));
+    }
+    Ok(&der[header..])
+}
+
+/// Split DER contents into its top-level TLVs
+fn split_tlvs(mut contents: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut tlvs = Vec::new();
+    while !contents.is_empty() {
+        let (_, total) = read_tlv(contents)?;
+        tlvs.push(&contents[..total]);
+        contents = &contents[total..];
+    }
+    Ok(tlvs)
+}
+
+/// Header length and total length of the DER TLV at the start of `der`
+fn read_tlv(der: &[u8]) -> Result<(usize, usize)> {
+    let truncated = || anyhow::anyhow!("Truncated DER");
+
+    let first_length = *der.get(1).ok_or_else(truncated)?;
+    let (header, length) = if first_length < 0x80 {
+        (2, first_length as usize)
+    } else {
+        let octets = (first_length & 0x7f) as usize;
+        if octets == 0 || octets > 4 {
+            return Err(anyhow::anyhow!("Unsupported DER length encoding"));
+        }
+        let bytes = der.get(2..2 + octets).ok_or_else(truncated)?;
+        (2 + octets, bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize))
+    };
+
+    if der.len() < header + length {
+        return Err(truncated());
+    }
+    Ok((header, header + length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair};
+    use x509_parser::prelude::{FromDer, X509Certificate, X509CertificationRequest};
+
+    fn subject() -> SubjectName {
+        let mut subject = SubjectName::new();
+        subject.push(DnType::CountryName, "DK");
+        subject.push(DnType::OrganizationalUnitName, "t:tenant");
+        subject.push(DnType::OrganizationalUnitName, "e:prod");
+        subject.push(DnType::CommonName, "web.default.svc");
+        subject
+    }
+
+    #[test]
+    fn test_certificate_with_repeated_ous() {
+        let subject = subject();
+        assert!(subject.has_repeated_attributes());
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.distinguished_name = subject.to_distinguished_name();
+        let cert = params.self_signed(&key).unwrap();
+
+        let der = replace_subject(cert.der(), SignedKind::Certificate, &subject, &key).unwrap();
+        let (_, parsed) = X509Certificate::from_der(&der).unwrap();
+
+        let ous: Vec<&str> = parsed.subject().iter_organizational_unit().map(|ou| ou.as_str().unwrap()).collect();
+        assert_eq!(ous, ["t:tenant", "e:prod"]);
+        assert_eq!(parsed.subject().to_string(), "C=DK, OU=t:tenant, OU=e:prod, CN=web.default.svc");
+        // Self-signed, so the issuer is still the rcgen subject; only the subject changes
+        assert_eq!(parsed.issuer().iter_organizational_unit().count(), 1);
+    }
+
+    #[test]
+    fn test_csr_with_repeated_ous() {
+        let subject = subject();
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.distinguished_name = subject.to_distinguished_name();
+        let csr = params.serialize_request(&key).unwrap();
+
+        let der = replace_subject(csr.der(), SignedKind::CertificationRequest, &subject, &key).unwrap();
+        let (_, parsed) = X509CertificationRequest::from_der(&der).unwrap();
+
+        assert_eq!(parsed.certification_request_info.subject.iter_organizational_unit().count(), 2);
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::proto::certservice::{Extension, RevocationFilter, RevocationReason, Subject};
+
+#[cfg(feature = "postgres")]
+mod postgres;
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+
+/// A certificate as issued, with what is needed to renew it
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(serde::Serialize, serde::Deserialize))]
+pub struct CertificateRecord {
+    pub certificate_id: String,
+    pub common_name: String,
+    pub dns_names: Vec<String>,
+    #[cfg_attr(feature = "postgres", serde(default))]
+    pub ip_addresses: Vec<String>,
+    #[cfg_attr(feature = "postgres", serde(default))]
+    pub uris: Vec<String>,
+    pub organizational_units: Vec<String>,
+    /// Requested subject attributes, reused on renewal
+    pub subject: Subject,
+    pub key_usages: Vec<String>,
+    pub extended_key_usages: Vec<String>,
+    pub extensions: Vec<Extension>,
+    /// Requested validity in seconds (0: the profile's or the default), reused on renewal
+    pub validity_seconds: i64,
+    pub not_before: i64,
+    pub not_after: i64,
+    pub metadata: HashMap<String, String>,
+    pub profile: String,
+    /// Serial number (lowercase hex) of the current certificate
+    pub serial: String,
+}
+
+impl CertificateRecord {
+    /// Namespace of the pod the certificate was issued for, if the node reported it
+    pub fn namespace(&self) -> Option<&str> {
+        self.metadata.get("namespace").map(String::as_str)
+    }
+
+    /// Tenant of the namespace when the certificate was issued, if it had one
+    pub fn tenant(&self) -> Option<&str> {
+        self.metadata.get("tenant").map(String::as_str)
+    }
+}
+
+/// Revocation of a single certificate serial
+#[derive(Clone, Debug)]
+pub struct Revocation {
+    pub certificate_id: String,
+    pub reason: RevocationReason,
+    pub revoked_at: i64,
+}
+
+/// Which certificates ListCertificates returns, before paging
+#[derive(Clone, Debug, Default)]
+pub struct ListFilter {
+    /// Only certificate IDs after this one
+    pub after: String,
+    pub namespace: Option<String>,
+    pub tenant: Option<String>,
+    /// Only certificates expiring at or before this time
+    pub expiring_before: Option<i64>,
+    /// Only certificates expiring after this time
+    pub expires_after: Option<i64>,
+    pub revocation: RevocationFilter,
+}
+
+/// Where the certificate service keeps issued certificates and revocations
+///
+/// The in-memory store is lost on restart and private to one replica; a shared
+/// store lets several replicas serve at once.
+#[async_trait]
+pub trait CertificateStore: Send + Sync {
+    /// Short backend name used in logs
+    fn name(&self) -> &'static str;
+
+    async fn get(&self, certificate_id: &str) -> Result<Option<CertificateRecord>>;
+
+    /// Store a certificate, replacing the record of an earlier one with the same ID
+    async fn put(&self, record: CertificateRecord) -> Result<()>;
+
+    /// Store a certificate of `tenant` unless the tenant holds `max_certificates` unexpired,
+    /// unrevoked certificates under other IDs at `now`; `false` when it does
+    ///
+    /// Counting and storing are one step, so concurrent issuances cannot exceed the quota.
+    async fn put_within_quota(&self, record: CertificateRecord, tenant: &str, max_certificates: usize, now: i64) -> Result<bool>;
+
+    /// Revocation of `serial`, if it was revoked
+    async fn revocation(&self, serial: &str) -> Result<Option<Revocation>>;
+
+    /// Revoke `serial`, returning the revocation in effect; revoking again keeps the first one
+    async fn revoke(&self, serial: &str, revocation: Revocation) -> Result<Revocation>;
+
+    /// Up to `limit` certificates matching `filter` with their revocations, ordered by certificate ID
+    async fn list(&self, filter: &ListFilter, limit: usize) -> Result<Vec<(CertificateRecord, Option<Revocation>)>>;
+}
+
+/// Certificates and revocations held in memory
+#[derive(Default)]
+pub struct MemoryStore {
+    certificates: DashMap<String, CertificateRecord>,
+    /// Revoked serials; kept after the certificate ID is re-issued so revocation lists stay complete
+    revocations: DashMap<String, Revocation>,
+    /// Held while counting and storing a certificate within a tenant's quota
+    quota_lock: Mutex<()>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CertificateStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
+        Ok(self.certificates.get(certificate_id).map(|record| record.clone()))
+    }
+
+    async fn put(&self, record: CertificateRecord) -> Result<()> {
+        self.certificates.insert(record.certificate_id.clone(), record);
+        Ok(())
+    }
+
+    async fn put_within_quota(&self, record: CertificateRecord, tenant: &str, max_certificates: usize, now: i64) -> Result<bool> {
+        let _quota = self.quota_lock.lock().unwrap();
+        let held = self
+            .certificates
+            .iter()
+            .filter(|entry| entry.key() != &record.certificate_id && entry.tenant() == Some(tenant))
+            .filter(|entry| entry.not_after > now && !self.revocations.contains_key(&entry.serial))
+            .count();
+        if held >= max_certificates {
+            return Ok(false);
+        }
+        self.certificates.insert(record.certificate_id.clone(), record);
+        Ok(true)
+    }
+
+    async fn revocation(&self, serial: &str) -> Result<Option<Revocation>> {
+        Ok(self.revocations.get(serial).map(|revocation| revocation.clone()))
+    }
+
+    async fn revoke(&self, serial: &str, revocation: Revocation) -> Result<Revocation> {
+        Ok(self.revocations.entry(serial.to_string()).or_insert(revocation).clone())
+    }
+
+    async fn list(&self, filter: &ListFilter, limit: usize) -> Result<Vec<(CertificateRecord, Option<Revocation>)>> {
+        let mut matching: Vec<(CertificateRecord, Option<Revocation>)> = self
+            .certificates
+            .iter()
+            .filter(|entry| entry.key().as_str() > filter.after.as_str())
+            .filter(|entry| filter.namespace.is_none() || entry.namespace() == filter.namespace.as_deref())
+            .filter(|entry| filter.tenant.is_none() || entry.tenant() == filter.tenant.as_deref())
+            .filter(|entry| filter.expiring_before.is_none_or(|before| entry.not_after <= before))
+            .filter(|entry| filter.expires_after.is_none_or(|after| entry.not_after > after))
+            .map(|entry| {
+                let revocation = self.revocations.get(&entry.serial).map(|r| r.clone());
+                (entry.value().clone(), revocation)
+            })
+            .filter(|(_, revocation)| match filter.revocation {
+                RevocationFilter::Any => true,
+                RevocationFilter::Revoked => revocation.is_some(),
+                RevocationFilter::NotRevoked => revocation.is_none(),
+            })
+            .collect();
+
+        matching.sort_by(|(a, _), (b, _)| a.certificate_id.cmp(&b.certificate_id));
+        matching.truncate(limit);
+        Ok(matching)
+    }
+}
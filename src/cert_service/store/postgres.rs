@@ -0,0 +1,225 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
+use sqlx::Row;
+use tracing::info;
+
+use super::{CertificateRecord, CertificateStore, ListFilter, Revocation};
+use super::super::proto::certservice::RevocationReason;
+
+/// Connections kept to the database per replica
+const MAX_CONNECTIONS: u32 = 10;
+
+/// Advisory lock held while creating the tables, since concurrent `CREATE TABLE IF NOT EXISTS`
+/// of replicas starting together can fail
+const SCHEMA_LOCK: i64 = 0x6361_6373_6900;
+
+/// Tables are created on startup; every statement is safe to run again
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS cacsi_certificates (
+        certificate_id TEXT PRIMARY KEY,
+        namespace TEXT,
+        serial TEXT NOT NULL,
+        not_after BIGINT NOT NULL,
+        record JSONB NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS cacsi_certificates_namespace ON cacsi_certificates (namespace, certificate_id)",
+    "CREATE INDEX IF NOT EXISTS cacsi_certificates_not_after ON cacsi_certificates (not_after)",
+    "CREATE TABLE IF NOT EXISTS cacsi_revocations (
+        serial TEXT PRIMARY KEY,
+        certificate_id TEXT NOT NULL,
+        reason INTEGER NOT NULL,
+        revoked_at BIGINT NOT NULL
+    )",
+];
+
+/// Insert or replace the record of a certificate
+const PUT_CERTIFICATE: &str = "INSERT INTO cacsi_certificates (certificate_id, namespace, serial, not_after, record)
+     VALUES ($1, $2, $3, $4, $5)
+     ON CONFLICT (certificate_id) DO UPDATE
+     SET namespace = EXCLUDED.namespace, serial = EXCLUDED.serial,
+         not_after = EXCLUDED.not_after, record = EXCLUDED.record";
+
+/// Certificates and revocations in PostgreSQL, shared by all certificate service replicas
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connect to the database at `url` and create the tables if needed
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .acquire_timeout(std::time::Duration::from_secs(5))
+            .connect(url)
+            .await
+            .context("Failed to connect to PostgreSQL")?;
+
+        let mut transaction = pool.begin().await.context("Failed to start schema transaction")?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(SCHEMA_LOCK)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to lock certificate store schema")?;
+        for statement in SCHEMA {
+            sqlx::query(statement)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to create certificate store tables")?;
+        }
+        transaction.commit().await.context("Failed to create certificate store tables")?;
+
+        info!("Certificate store connected to PostgreSQL");
+
+        Ok(Self { pool })
+    }
+}
+
+fn revocation_from_row(row: &PgRow) -> Result<Revocation> {
+    let reason: i32 = row.try_get("reason")?;
+    Ok(Revocation {
+        certificate_id: row.try_get("certificate_id")?,
+        reason: RevocationReason::try_from(reason).unwrap_or(RevocationReason::Unspecified),
+        revoked_at: row.try_get("revoked_at")?,
+    })
+}
+
+#[async_trait]
+impl CertificateStore for PostgresStore {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn get(&self, certificate_id: &str) -> Result<Option<CertificateRecord>> {
+        let record: Option<Json<CertificateRecord>> = sqlx::query_scalar(
+            "SELECT record FROM cacsi_certificates WHERE certificate_id = $1",
+        )
+        .bind(certificate_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read certificate record")?;
+
+        Ok(record.map(|Json(record)| record))
+    }
+
+    async fn put(&self, record: CertificateRecord) -> Result<()> {
+        sqlx::query(PUT_CERTIFICATE)
+            .bind(&record.certificate_id)
+            .bind(record.namespace())
+            .bind(&record.serial)
+            .bind(record.not_after)
+            .bind(Json(&record))
+            .execute(&self.pool)
+            .await
+            .context("Failed to store certificate record")?;
+
+        Ok(())
+    }
+
+    async fn put_within_quota(&self, record: CertificateRecord, tenant: &str, max_certificates: usize, now: i64) -> Result<bool> {
+        // Replicas issuing for the same tenant take turns until the transaction ends
+        let mut transaction = self.pool.begin().await.context("Failed to start quota transaction")?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(tenant)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to lock tenant quota")?;
+        let held: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM cacsi_certificates c
+             LEFT JOIN cacsi_revocations r ON r.serial = c.serial
+             WHERE c.record->'metadata'->>'tenant' = $1
+               AND c.not_after > $2
+               AND r.serial IS NULL
+               AND c.certificate_id <> $3",
+        )
+        .bind(tenant)
+        .bind(now)
+        .bind(&record.certificate_id)
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to count certificates of tenant")?;
+        if held as usize >= max_certificates {
+            return Ok(false);
+        }
+
+        sqlx::query(PUT_CERTIFICATE)
+            .bind(&record.certificate_id)
+            .bind(record.namespace())
+            .bind(&record.serial)
+            .bind(record.not_after)
+            .bind(Json(&record))
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to store certificate record")?;
+        transaction.commit().await.context("Failed to store certificate record")?;
+
+        Ok(true)
+    }
+
+    async fn revocation(&self, serial: &str) -> Result<Option<Revocation>> {
+        let row = sqlx::query("SELECT certificate_id, reason, revoked_at FROM cacsi_revocations WHERE serial = $1")
+            .bind(serial)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to read revocation")?;
+
+        row.as_ref().map(revocation_from_row).transpose()
+    }
+
+    async fn revoke(&self, serial: &str, revocation: Revocation) -> Result<Revocation> {
+        sqlx::query(
+            "INSERT INTO cacsi_revocations (serial, certificate_id, reason, revoked_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (serial) DO NOTHING",
+        )
+        .bind(serial)
+        .bind(&revocation.certificate_id)
+        .bind(revocation.reason as i32)
+        .bind(revocation.revoked_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store revocation")?;
+
+        // Another replica may have revoked it first
+        self.revocation(serial)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Revocation of {} disappeared", serial))
+    }
+
+    async fn list(&self, filter: &ListFilter, limit: usize) -> Result<Vec<(CertificateRecord, Option<Revocation>)>> {
+        let rows = sqlx::query(
+            "SELECT c.record, r.certificate_id, r.reason, r.revoked_at
+             FROM cacsi_certificates c
+             LEFT JOIN cacsi_revocations r ON r.serial = c.serial
+             WHERE c.certificate_id > $1
+               AND ($2::TEXT IS NULL OR c.namespace = $2)
+               AND ($3::BIGINT IS NULL OR c.not_after <= $3)
+               -- RevocationFilter: 0 any, 1 revoked, 2 not revoked
+               AND ($4 = 0 OR ($4 = 1 AND r.serial IS NOT NULL) OR ($4 = 2 AND r.serial IS NULL))
+               AND ($6::TEXT IS NULL OR c.record->'metadata'->>'tenant' = $6)
+               AND ($7::BIGINT IS NULL OR c.not_after > $7)
+             ORDER BY c.certificate_id
+             LIMIT $5",
+        )
+        .bind(&filter.after)
+        .bind(filter.namespace.as_deref())
+        .bind(filter.expiring_before)
+        .bind(filter.revocation as i32)
+        .bind(limit as i64)
+        .bind(filter.tenant.as_deref())
+        .bind(filter.expires_after)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list certificate records")?;
+
+        rows.iter()
+            .map(|row| {
+                let Json(record): Json<CertificateRecord> = row.try_get("record")?;
+                let revoked: Option<String> = row.try_get("certificate_id")?;
+                let revocation = revoked.map(|_| revocation_from_row(row)).transpose()?;
+                Ok((record, revocation))
+            })
+            .collect()
+    }
+}
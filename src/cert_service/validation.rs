@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use tonic::{Code, Status};
+
+use super::proto::certservice::{Extension, IssueCertificateRequest, Subject};
+use super::validity::SECONDS_PER_DAY;
+use crate::csi::san::{check_dns_name, check_uri};
+use crate::error_details::{ErrorInfo, ErrorReason, FieldViolation};
+
+/// Longest certificate ID, the longest Kubernetes object name
+pub const MAX_CERTIFICATE_ID_LENGTH: usize = 253;
+
+/// Upper bound on the length of a common name (`ub-common-name` in RFC 5280)
+pub const MAX_COMMON_NAME_LENGTH: usize = 64;
+
+/// Upper bound on the length of an organizational unit (`ub-organizational-unit-name` in RFC 5280)
+pub const MAX_ORGANIZATIONAL_UNIT_LENGTH: usize = 64;
+
+/// Most organizational units a certificate may carry
+pub const MAX_ORGANIZATIONAL_UNITS: usize = 16;
+
+/// Most DNS names, IP addresses and URIs a certificate may carry together
+pub const MAX_SANS: usize = 100;
+
+/// Longest URI SAN
+pub const MAX_URI_LENGTH: usize = 2048;
+
+/// Most custom extensions a certificate may carry
+pub const MAX_EXTENSIONS: usize = 16;
+
+/// Largest custom extension value accepted, in bytes
+pub const MAX_EXTENSION_SIZE: usize = 4096;
+
+/// Shortest validity that may be requested
+pub const MIN_VALIDITY_SECONDS: i64 = 60;
+
+/// Longest validity that may be requested, before the configured maximums apply
+pub const MAX_VALIDITY_SECONDS: i64 = 100 * 365 * SECONDS_PER_DAY;
+
+/// Problems found in a request, each with the field it is in
+#[derive(Default)]
+struct Violations(Vec<(ErrorReason, FieldViolation)>);
+
+impl Violations {
+    fn add(&mut self, reason: ErrorReason, field: impl Into<String>, description: impl Into<String>) {
+        self.0.push((reason, FieldViolation { field: field.into(), description: description.into() }));
+    }
+
+    /// INVALID_ARGUMENT listing every violation, with the reason of the first one
+    fn into_result(self) -> Result<(), Status> {
+        let Some((reason, _)) = self.0.first() else {
+            return Ok(());
+        };
+        let message = self.0
+            .iter()
+            .map(|(_, violation)| format!("{}: {}", violation.field, violation.description))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(ErrorInfo::new(*reason).into_status_with_violations(
+            Code::InvalidArgument,
+            format!("Invalid certificate request: {}", message),
+            self.0.into_iter().map(|(_, violation)| violation).collect(),
+        ))
+    }
+}
+
+/// Check an IssueCertificateRequest before anything is looked up or signed for it
+///
+/// Every problem is reported, each with the field it is in, both in the message and as
+/// `google.rpc.BadRequest` field violations.
+pub fn validate_issue_request(req: &IssueCertificateRequest) -> Result<(), Status> {
+    let mut violations = Violations::default();
+
+    check_certificate_id(&mut violations, &req.certificate_id);
+    check_common_name(&mut violations, &req.common_name);
+    check_organizational_units(&mut violations, &req.organizational_units);
+    check_subject(&mut violations, &req.subject.clone().unwrap_or_default());
+    check_sans(&mut violations, &req.dns_names, &req.ip_addresses, &req.uris);
+    check_extensions(&mut violations, &req.extensions);
+    check_validity(&mut violations, req.validity_seconds, req.validity_days);
+
+    violations.into_result()
+}
+
+/// The requested validity in seconds, preferring `validity_seconds` over `validity_days`
+///
+/// 0 means the request did not ask for a validity.
+pub fn requested_validity_seconds(validity_seconds: i64, validity_days: i64) -> Result<i64, Status> {
+    let mut violations = Violations::default();
+    check_validity(&mut violations, validity_seconds, validity_days);
+    violations.into_result()?;
+
+    Ok(match validity_seconds {
+        0 => validity_days * SECONDS_PER_DAY,
+        seconds => seconds,
+    })
+}
+
+/// Parse a dotted OID such as `1.3.6.1.4.1.99999.1` into its arcs
+pub fn parse_oid(oid: &str) -> Option<Vec<u64>> {
+    let arcs: Vec<u64> = oid.split('.').map(|arc| arc.parse().ok()).collect::<Option<_>>()?;
+    // The first arc is 0, 1 or 2; below 2 the second arc is less than 40
+    match arcs.as_slice() {
+        [first, second, ..] if *first < 2 && *second < 40 => Some(arcs),
+        [2, _, ..] => Some(arcs),
+        _ => None,
+    }
+}
+
+/// Certificate IDs key the store and show up in logs, so they are printable ASCII without spaces
+fn check_certificate_id(violations: &mut Violations, certificate_id: &str) {
+    let field = "certificate_id";
+    if certificate_id.is_empty() {
+        violations.add(ErrorReason::InvalidCertificateId, field, "is required");
+    } else if certificate_id.len() > MAX_CERTIFICATE_ID_LENGTH {
+        violations.add(ErrorReason::InvalidCertificateId, field, format!(
+            "is {} characters long, at most {} are allowed", certificate_id.len(), MAX_CERTIFICATE_ID_LENGTH
+        ));
+    } else if let Some(c) = certificate_id.chars().find(|c| !c.is_ascii_graphic()) {
+        violations.add(ErrorReason::InvalidCertificateId, field, format!(
+            "contains {:?}, only printable ASCII characters without spaces are allowed", c
+        ));
+    }
+}
+
+fn check_common_name(violations: &mut Violations, common_name: &str) {
+    let field = "common_name";
+    if common_name.chars().count() > MAX_COMMON_NAME_LENGTH {
+        violations.add(ErrorReason::InvalidSubject, field, format!(
+            "'{}' is longer than {} characters", common_name, MAX_COMMON_NAME_LENGTH
+        ));
+    }
+    if let Some(c) = common_name.chars().find(|c| c.is_control()) {
+        violations.add(ErrorReason::InvalidSubject, field, format!("contains control character {:?}", c));
+    }
+}
+
+fn check_organizational_units(violations: &mut Violations, organizational_units: &[String]) {
+    if organizational_units.len() > MAX_ORGANIZATIONAL_UNITS {
+        violations.add(ErrorReason::InvalidSubject, "organizational_units", format!(
+            "at most {} may be requested, got {}", MAX_ORGANIZATIONAL_UNITS, organizational_units.len()
+        ));
+    }
+    for (index, unit) in organizational_units.iter().enumerate() {
+        let field = format!("organizational_units[{}]", index);
+        if unit.is_empty() {
+            violations.add(ErrorReason::InvalidSubject, field, "is empty");
+        } else if unit.chars().count() > MAX_ORGANIZATIONAL_UNIT_LENGTH {
+            violations.add(ErrorReason::InvalidSubject, field, format!(
+                "'{}' is longer than {} characters", unit, MAX_ORGANIZATIONAL_UNIT_LENGTH
+            ));
+        } else if let Some(c) = unit.chars().find(|c| c.is_control()) {
+            violations.add(ErrorReason::InvalidSubject, field, format!("contains control character {:?}", c));
+        }
+    }
+}
+
+/// Reject subject attributes that cannot be encoded as RFC 5280 requires
+fn check_subject(violations: &mut Violations, subject: &Subject) {
+    if !subject.country.is_empty()
+        && (subject.country.len() != 2 || !subject.country.chars().all(|c| c.is_ascii_alphabetic()))
+    {
+        violations.add(ErrorReason::InvalidSubject, "subject.country", format!(
+            "must be a two-letter country code, got '{}'", subject.country
+        ));
+    }
+
+    // serialNumber is a PrintableString
+    let printable = |c: char| c.is_ascii_alphanumeric() || " '()+,-./:=?".contains(c);
+    if !subject.serial_number.chars().all(printable) {
+        violations.add(ErrorReason::InvalidSubject, "subject.serial_number", format!(
+            "may only contain letters, digits, spaces and '()+,-./:=?, got '{}'", subject.serial_number
+        ));
+    }
+}
+
+/// Reject SANs that cannot be put in a certificate: DNS names that are not RFC 1123 host
+/// names in ASCII (punycode), unparsable IP addresses and URIs that are not RFC 3986 absolute URIs
+fn check_sans(violations: &mut Violations, dns_names: &[String], ip_addresses: &[String], uris: &[String]) {
+    let count = dns_names.len() + ip_addresses.len() + uris.len();
+    if count > MAX_SANS {
+        violations.add(ErrorReason::InvalidSan, "dns_names", format!(
+            "at most {} DNS names, IP addresses and URIs may be requested together, got {}", MAX_SANS, count
+        ));
+        return;
+    }
+
+    for (index, name) in dns_names.iter().enumerate() {
+        let field = format!("dns_names[{}]", index);
+        if !name.is_ascii() {
+            violations.add(ErrorReason::InvalidSan, field, format!("'{}' is not ASCII; use its punycode form", name));
+        } else if let Err(problem) = check_dns_name(name) {
+            violations.add(ErrorReason::InvalidSan, field, format!("'{}' {}", name, problem));
+        }
+    }
+    for (index, ip) in ip_addresses.iter().enumerate() {
+        if ip.parse::<IpAddr>().is_err() {
+            violations.add(ErrorReason::InvalidSan, format!("ip_addresses[{}]", index), format!(
+                "'{}' is not an IP address", ip
+            ));
+        }
+    }
+    for (index, uri) in uris.iter().enumerate() {
+        let field = format!("uris[{}]", index);
+        if uri.len() > MAX_URI_LENGTH {
+            violations.add(ErrorReason::InvalidSan, field, format!(
+                "is {} characters long, at most {} are allowed", uri.len(), MAX_URI_LENGTH
+            ));
+        } else if let Err(problem) = check_uri(uri) {
+            violations.add(ErrorReason::InvalidSan, field, format!("'{}' {}", uri, problem));
+        }
+    }
+}
+
+/// Reject custom extensions that are malformed or would clash with extensions the service sets
+fn check_extensions(violations: &mut Violations, extensions: &[Extension]) {
+    if extensions.len() > MAX_EXTENSIONS {
+        violations.add(ErrorReason::InvalidExtension, "extensions", format!(
+            "at most {} may be requested, got {}", MAX_EXTENSIONS, extensions.len()
+        ));
+        return;
+    }
+
+    let mut seen = HashSet::new();
+    for (index, extension) in extensions.iter().enumerate() {
+        let field = format!("extensions[{}]", index);
+        let Some(oid) = parse_oid(&extension.oid) else {
+            violations.add(ErrorReason::InvalidExtension, format!("{}.oid", field), format!(
+                "'{}' is not a valid OID", extension.oid
+            ));
+            continue;
+        };
+
+        // Certificate extensions (2.5.29) and PKIX extensions (1.3.6.1.5.5.7.1) are set by the service
+        if oid.starts_with(&[2, 5, 29]) || oid.starts_with(&[1, 3, 6, 1, 5, 5, 7, 1]) {
+            violations.add(ErrorReason::InvalidExtension, format!("{}.oid", field), format!(
+                "{} is reserved for the certificate service", extension.oid
+            ));
+        } else if !seen.insert(oid) {
+            violations.add(ErrorReason::InvalidExtension, format!("{}.oid", field), format!(
+                "{} is requested twice", extension.oid
+            ));
+        }
+
+        if extension.value.len() > MAX_EXTENSION_SIZE {
+            violations.add(ErrorReason::InvalidExtension, format!("{}.value", field), format!(
+                "is larger than {} bytes", MAX_EXTENSION_SIZE
+            ));
+        } else if yasna::parse_der(&extension.value, |reader| reader.read_der()).is_err() {
+            violations.add(ErrorReason::InvalidExtension, format!("{}.value", field), "is not a single DER value");
+        }
+    }
+}
+
+/// Check the requested validity against the absolute bounds; 0 leaves it to the profile or default
+fn check_validity(violations: &mut Violations, validity_seconds: i64, validity_days: i64) {
+    let bounds = |violations: &mut Violations, field: &str, seconds: i64, value: i64| {
+        if seconds < 0 {
+            violations.add(ErrorReason::InvalidValidity, field, format!("must be positive, got {}", value));
+        } else if seconds > 0 && seconds < MIN_VALIDITY_SECONDS {
+            violations.add(ErrorReason::InvalidValidity, field, format!(
+                "must be at least {} seconds, got {}", MIN_VALIDITY_SECONDS, value
+            ));
+        } else if seconds > MAX_VALIDITY_SECONDS {
+            violations.add(ErrorReason::InvalidValidity, field, format!(
+                "must be at most {} days, got {}", MAX_VALIDITY_SECONDS / SECONDS_PER_DAY, value
+            ));
+        }
+    };
+
+    bounds(violations, "validity_seconds", validity_seconds, validity_seconds);
+    // validity_seconds takes precedence, validity_days is ignored when it is set
+    if validity_seconds == 0 {
+        let seconds = validity_days.saturating_mul(SECONDS_PER_DAY);
+        bounds(violations, "validity_days", seconds, validity_days);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_details::field_violations;
+
+    #[test]
+    fn test_validate_issue_request() {
+        let valid = IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc".to_string(), "*.web.default.svc".to_string()],
+            ip_addresses: vec!["10.0.0.1".to_string()],
+            uris: vec!["spiffe://cluster.local/ns/default/sa/web".to_string()],
+            validity_days: 7,
+            ..Default::default()
+        };
+        assert!(validate_issue_request(&valid).is_ok());
+
+        let status = validate_issue_request(&IssueCertificateRequest {
+            certificate_id: String::new(),
+            common_name: "w".repeat(65),
+            dns_names: vec!["web.default.svc".to_string(), "web_1.default.svc".to_string()],
+            ip_addresses: vec!["10.0.0.300".to_string()],
+            validity_seconds: 5,
+            ..valid.clone()
+        }).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(ErrorInfo::from_status(&status).unwrap().reason(), Some(ErrorReason::InvalidCertificateId));
+        let fields: Vec<String> = field_violations(&status).into_iter().map(|v| v.field).collect();
+        assert_eq!(fields, ["certificate_id", "common_name", "dns_names[1]", "ip_addresses[0]", "validity_seconds"]);
+        assert!(status.message().contains("dns_names[1]: 'web_1.default.svc' contains '_'"), "{}", status.message());
+
+        let too_many = IssueCertificateRequest {
+            dns_names: (0..MAX_SANS).map(|i| format!("web-{}.default.svc", i)).collect(),
+            ..valid.clone()
+        };
+        assert!(validate_issue_request(&too_many).is_err());
+        assert_eq!(requested_validity_seconds(0, 2).unwrap(), 2 * SECONDS_PER_DAY);
+        assert!(requested_validity_seconds(0, i64::MAX).is_err());
+        assert!(requested_validity_seconds(-1, 0).is_err());
+    }
+
+    #[test]
+    fn test_check_subject() {
+        let check = |country: &str, serial_number: &str| {
+            let mut violations = Violations::default();
+            check_subject(&mut violations, &Subject {
+                country: country.to_string(),
+                serial_number: serial_number.to_string(),
+                ..Default::default()
+            });
+            violations.0.is_empty()
+        };
+
+        assert!(check("", ""));
+        assert!(check("DK", "DEV-0042"));
+        assert!(!check("Denmark", ""));
+        assert!(!check("", "dev_0042"));
+    }
+
+    #[test]
+    fn test_check_extensions() {
+        let check = |extensions: &[Extension]| {
+            let mut violations = Violations::default();
+            check_extensions(&mut violations, extensions);
+            violations.0.is_empty()
+        };
+        let extension = |oid: &str, value: &[u8]| Extension { oid: oid.to_string(), value: value.to_vec() };
+        // UTF8String "abc"
+        let utf8 = [0x0c, 0x03, b'a', b'b', b'c'];
+
+        assert!(check(&[extension("1.3.6.1.4.1.99999.1", &utf8)]));
+        assert!(!check(&[extension("1.3.6.1.4.1.99999.1", &utf8[..4])]));
+        assert!(!check(&[extension("2.5.29.17", &utf8)]));
+        assert!(!check(&[extension("1.3.6.1.5.5.7.1.1", &utf8)]));
+        assert!(!check(&[extension("1.45.1", &utf8)]));
+        assert!(!check(&[
+            extension("1.3.6.1.4.1.99999.1", &utf8),
+            extension("1.3.6.1.4.1.99999.01", &utf8),
+        ]));
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use std::str::FromStr;
+
+/// What to do with requests asking for more validity than allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidityMode {
+    /// Issue the certificate with the maximum allowed validity instead
+    Clamp,
+    /// Refuse the request
+    Reject,
+}
+
+impl FromStr for ValidityMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            "reject" => Ok(Self::Reject),
+            other => Err(anyhow::anyhow!("Invalid validity mode '{}' (expected clamp or reject)", other)),
+        }
+    }
+}
+
+/// Seconds in a day, the unit of the configured validity limits
+pub const SECONDS_PER_DAY: i64 = 86400;
+
+/// Upper bound on certificate validity enforced by the service
+#[derive(Debug, Clone, Copy)]
+pub struct ValidityLimit {
+    pub max_days: i64,
+    pub mode: ValidityMode,
+}
+
+impl ValidityLimit {
+    /// Validity in seconds to issue for a request asking for `requested_seconds`
+    pub fn apply(&self, requested_seconds: i64) -> Result<i64, String> {
+        let max_seconds = self.max_days.saturating_mul(SECONDS_PER_DAY);
+        if requested_seconds <= max_seconds {
+            return Ok(requested_seconds);
+        }
+
+        match self.mode {
+            ValidityMode::Clamp => Ok(max_seconds),
+            ValidityMode::Reject => Err(format!(
+                "requested validity of {} exceeds the maximum of {} days",
+                format_validity(requested_seconds), self.max_days
+            )),
+        }
+    }
+}
+
+/// Whole days covering `seconds`, for consumers that only understand days
+pub fn days_rounded_up(seconds: i64) -> i64 {
+    (seconds + SECONDS_PER_DAY - 1) / SECONDS_PER_DAY
+}
+
+/// A validity in seconds in the largest unit that represents it exactly, e.g. `7d` or `90m`
+pub fn format_validity(seconds: i64) -> String {
+    for (unit, length) in [("d", SECONDS_PER_DAY), ("h", 3600), ("m", 60)] {
+        if seconds != 0 && seconds % length == 0 {
+            return format!("{}{}", seconds / length, unit);
+        }
+    }
+    format!("{}s", seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_and_reject() {
+        let clamp = ValidityLimit { max_days: 30, mode: ValidityMode::Clamp };
+        assert_eq!(clamp.apply(7 * SECONDS_PER_DAY), Ok(7 * SECONDS_PER_DAY));
+        assert_eq!(clamp.apply(1800), Ok(1800));
+        assert_eq!(clamp.apply(90 * SECONDS_PER_DAY), Ok(30 * SECONDS_PER_DAY));
+
+        let reject = ValidityLimit { max_days: 30, mode: ValidityMode::Reject };
+        assert_eq!(reject.apply(30 * SECONDS_PER_DAY), Ok(30 * SECONDS_PER_DAY));
+        assert!(reject.apply(30 * SECONDS_PER_DAY + 1).is_err());
+    }
+
+    #[test]
+    fn test_format_validity() {
+        assert_eq!(format_validity(7 * SECONDS_PER_DAY), "7d");
+        assert_eq!(format_validity(36 * 3600), "36h");
+        assert_eq!(format_validity(1800), "30m");
+        assert_eq!(format_validity(90), "90s");
+        assert_eq!(days_rounded_up(1800), 1);
+        assert_eq!(days_rounded_up(2 * SECONDS_PER_DAY), 2);
+    }
+}
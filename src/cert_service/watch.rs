@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::Status;
+use tracing::{info, warn};
+use x509_parser::pem::parse_x509_pem;
+
+use super::signer::Signer;
+use super::proto::certservice::{CertificateEvent, CertificateEventType, RevocationReason};
+
+/// Events buffered per watcher; a watcher that falls further behind is disconnected
+const EVENT_BUFFER: usize = 1024;
+
+/// Most certificates a single watch may cover
+pub const MAX_WATCHED_CERTIFICATES: usize = 10000;
+
+/// How often the signer's CA certificate is compared with the last one announced
+pub const CA_ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Stream of events sent to a WatchCertificates caller
+pub type CertificateEventStream = Pin<Box<dyn Stream<Item = Result<CertificateEvent, Status>> + Send>>;
+
+/// Renewal, revocation and CA rotation events pushed to node drivers
+///
+/// Events only reach watchers connected to the replica that produced them; with a
+/// shared certificate store, nodes keep polling for revocations as a fallback.
+#[derive(Clone)]
+pub struct CertificateEvents {
+    sender: broadcast::Sender<CertificateEvent>,
+}
+
+impl Default for CertificateEvents {
+    fn default() -> Self {
+        Self { sender: broadcast::channel(EVENT_BUFFER).0 }
+    }
+}
+
+impl CertificateEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn renewed(&self, certificate_id: &str, serial: &str, not_after: i64) {
+        self.send(CertificateEvent {
+            r#type: CertificateEventType::Renewed as i32,
+            certificate_id: certificate_id.to_string(),
+            serial: serial.to_string(),
+            not_after,
+            revocation_reason: RevocationReason::Unspecified as i32,
+        });
+    }
+
+    pub fn revoked(&self, certificate_id: &str, serial: &str, not_after: i64, reason: RevocationReason) {
+        self.send(CertificateEvent {
+            r#type: CertificateEventType::Revoked as i32,
+            certificate_id: certificate_id.to_string(),
+            serial: serial.to_string(),
+            not_after,
+            revocation_reason: reason as i32,
+        });
+    }
+
+    /// Announce a new signing CA, identified by its serial and expiry
+    pub fn ca_rotated(&self, serial: &str, not_after: i64) {
+        self.send(CertificateEvent {
+            r#type: CertificateEventType::CaRotated as i32,
+            certificate_id: String::new(),
+            serial: serial.to_string(),
+            not_after,
+            revocation_reason: RevocationReason::Unspecified as i32,
+        });
+    }
+
+    fn send(&self, event: CertificateEvent) {
+        // Fails only when nobody is watching
+        let _ = self.sender.send(event);
+    }
+
+    /// Events about `certificate_ids` and CA rotations, from now on
+    ///
+    /// A watcher that cannot keep up gets `ABORTED` and has to watch again and
+    /// re-check its certificates, since it missed events.
+    pub fn subscribe(&self, certificate_ids: Vec<String>) -> CertificateEventStream {
+        let certificate_ids: HashSet<String> = certificate_ids.into_iter().collect();
+
+        let stream = BroadcastStream::new(self.sender.subscribe())
+            .filter_map(move |event| match event {
+                Ok(event) if event.certificate_id.is_empty() || certificate_ids.contains(&event.certificate_id) => {
+                    Some(Ok(event))
+                }
+                Ok(_) => None,
+                // tonic ends the response with the first error
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Err(Status::aborted(format!(
+                    "Missed {} certificate events; watch again", missed
+                )))),
+            });
+
+        Box::pin(stream)
+    }
+
+    /// Announce every change of the signer's CA certificate until the process exits
+    ///
+    /// Signers reload their CA on their own (e.g. from a watched secret), so the CA is
+    /// compared with the last one seen every [`CA_ROTATION_CHECK_INTERVAL`].
+    pub async fn watch_ca(&self, signer: Arc<dyn Signer>) {
+        let mut current = signer.ca_certificate().await;
+        loop {
+            tokio::time::sleep(CA_ROTATION_CHECK_INTERVAL).await;
+
+            let ca_cert_pem = signer.ca_certificate().await;
+            if ca_cert_pem == current {
+                continue;
+            }
+            current = ca_cert_pem;
+
+            let Some(ca_cert_pem) = &current else {
+                continue;
+            };
+            match ca_serial(ca_cert_pem) {
+                Ok((serial, not_after)) => {
+                    info!("Signing CA changed (serial {}), notifying watchers", serial);
+                    self.ca_rotated(&serial, not_after);
+                }
+                Err(e) => warn!("Not announcing unreadable CA certificate: {:#}", e),
+            }
+        }
+    }
+}
+
+/// Serial (lowercase hex) and expiry of a CA certificate
+fn ca_serial(ca_cert_pem: &str) -> anyhow::Result<(String, i64)> {
+    let (_, pem) = parse_x509_pem(ca_cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate PEM: {}", e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse CA certificate: {}", e))?;
+
+    let serial = cert.raw_serial().iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((serial, cert.validity().not_after.timestamp()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watchers_only_see_their_certificates() {
+        let events = CertificateEvents::new();
+        let mut watch = events.subscribe(vec!["web".to_string()]);
+
+        events.renewed("other", "01", 100);
+        events.revoked("web", "02", 200, RevocationReason::KeyCompromise);
+        events.ca_rotated("03", 300);
+
+        let revoked = watch.next().await.unwrap().unwrap();
+        assert_eq!(revoked.r#type(), CertificateEventType::Revoked);
+        assert_eq!(revoked.certificate_id, "web");
+        assert_eq!(revoked.revocation_reason(), RevocationReason::KeyCompromise);
+
+        let rotated = watch.next().await.unwrap().unwrap();
+        assert_eq!(rotated.r#type(), CertificateEventType::CaRotated);
+        assert_eq!(rotated.serial, "03");
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{Result, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use rcgen::{KeyPair, PublicKeyData};
+use rustls_pki_types::PrivatePkcs8KeyDer;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+use zeroize::Zeroizing;
+
+use crate::encoding::KeyEncoding;
+
+/// Longest chain of intermediates accepted between a leaf and the CA
+const MAX_CHAIN_LENGTH: usize = 5;
+
+/// Check a certificate and key returned by the certificate service before they reach a workload
+///
+/// The certificate must chain to one of the CA certificates in `ca_pem` (through any
+/// intermediates bundled in `cert_pem`), be currently valid, match `key_pem`, and carry
+/// every requested DNS name and IP address as a SAN.
+pub fn validate_issued_certificate(
+    cert_pem: &str,
+    key_pem: &str,
+    ca_pem: &str,
+    dns_names: &[String],
+    ip_addresses: &[String],
+) -> Result<()> {
+    let chain = parse_pem_certificates(cert_pem).context("Invalid certificate")?;
+    let ca_certs = parse_pem_certificates(ca_pem).context("Invalid CA certificate")?;
+    let (leaf_der, intermediates_der) = chain
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("No certificate in the response"))?;
+
+    let leaf = parse_certificate(leaf_der)?;
+    let intermediates = intermediates_der
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+    let roots = ca_certs
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+
+    verify_chain(&leaf, &intermediates, &roots)?;
+
+    // Allow some clock skew between the node and the signer for the start of validity
+    let now = Utc::now().timestamp();
+    let (not_before, not_after) = (leaf.validity().not_before.timestamp(), leaf.validity().not_after.timestamp());
+    if now < not_before - 300 || now >= not_after {
+        return Err(anyhow::anyhow!(
+            "Certificate is not currently valid (not before {}, not after {})", not_before, not_after
+        ));
+    }
+
+    let key_pair = rcgen::KeyPair::from_pem(key_pem)
+        .map_err(|e| anyhow::anyhow!("Invalid private key: {}", e))?;
+    if key_pair.subject_public_key_info() != leaf.public_key().raw {
+        return Err(anyhow::anyhow!("Private key does not match the certificate"));
+    }
+
+    let (san_dns, san_ips) = subject_alt_names(&leaf)?;
+    if let Some(missing) = dns_names.iter().find(|name| !san_dns.iter().any(|san| san.eq_ignore_ascii_case(name))) {
+        return Err(anyhow::anyhow!("Certificate is missing the requested DNS name '{}'", missing));
+    }
+    for ip in ip_addresses {
+        let addr: IpAddr = ip.parse().context(format!("Invalid requested IP address '{}'", ip))?;
+        if !san_ips.contains(&addr) {
+            return Err(anyhow::anyhow!("Certificate is missing the requested IP address {}", addr));
+        }
+    }
+
+    Ok(())
+}
+
+/// The DNS and IP address SANs of a PEM certificate
+pub fn certificate_sans(cert_pem: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let chain = parse_pem_certificates(cert_pem)?;
+    let leaf_der = chain.first().ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+    let (dns_names, ip_addresses) = subject_alt_names(&parse_certificate(leaf_der)?)?;
+    Ok((dns_names, ip_addresses.iter().map(IpAddr::to_string).collect()))
+}
+
+/// What a certificate found on a volume says about itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnDiskCertificate {
+    pub not_before: i64,
+    pub not_after: i64,
+    /// Whether it chains to the CA it was checked against
+    pub issued_by_ca: bool,
+}
+
+/// Parse a certificate read from a volume, checking whether it chains to `ca_pem`
+///
+/// Unlike [`validate_issued_certificate`], an expired certificate or one from another
+/// CA is not an error; only one that cannot be parsed is.
+pub fn inspect_certificate(cert_pem: &str, ca_pem: &str) -> Result<OnDiskCertificate> {
+    let chain = parse_pem_certificates(cert_pem)?;
+    let (leaf_der, intermediates_der) = chain
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+    let leaf = parse_certificate(leaf_der)?;
+    let intermediates = intermediates_der
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+
+    let ca_certs = parse_pem_certificates(ca_pem).context("Invalid CA certificate")?;
+    let roots = ca_certs
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(OnDiskCertificate {
+        not_before: leaf.validity().not_before.timestamp(),
+        not_after: leaf.validity().not_after.timestamp(),
+        issued_by_ca: verify_chain(&leaf, &intermediates, &roots).is_ok(),
+    })
+}
+
+/// Whether the unencrypted key `key_pem`, in any [`KeyEncoding`], belongs to the leaf certificate in `cert_pem`
+pub fn key_matches_certificate(cert_pem: &str, key_pem: &str) -> Result<bool> {
+    let chain = parse_pem_certificates(cert_pem)?;
+    let leaf_der = chain.first().ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+    let leaf = parse_certificate(leaf_der)?;
+
+    let block = pem::parse(key_pem).map_err(|e| anyhow::anyhow!("Failed to parse key PEM: {}", e))?;
+    let encoding = KeyEncoding::of_label(block.tag())
+        .ok_or_else(|| anyhow::anyhow!("Unsupported key type '{}'", block.tag()))?;
+    let pkcs8 = encoding.to_pkcs8(&Zeroizing::new(block.into_contents()))?;
+    let key_pair = KeyPair::try_from(&PrivatePkcs8KeyDer::from(pkcs8.as_slice()))
+        .map_err(|e| anyhow::anyhow!("Invalid private key: {}", e))?;
+
+    Ok(key_pair.subject_public_key_info() == leaf.public_key().raw)
+}
+
+/// Walk from the leaf through the intermediates until a certificate is signed by a CA certificate
+fn verify_chain(leaf: &X509Certificate, intermediates: &[X509Certificate], roots: &[X509Certificate]) -> Result<()> {
+    let mut current = leaf;
+    for _ in 0..=MAX_CHAIN_LENGTH {
+        if roots.iter().any(|root| current.verify_signature(Some(root.public_key())).is_ok()) {
+            return Ok(());
+        }
+        current = intermediates
+            .iter()
+            .find(|issuer| current.verify_signature(Some(issuer.public_key())).is_ok())
+            .ok_or_else(|| anyhow::anyhow!(
+                "Certificate issued by '{}' does not chain to the CA", current.issuer()
+            ))?;
+    }
+    Err(anyhow::anyhow!("Certificate chain is longer than {} intermediates", MAX_CHAIN_LENGTH))
+}
+
+fn subject_alt_names(cert: &X509Certificate) -> Result<(Vec<String>, Vec<IpAddr>)> {
+    let mut dns_names = Vec::new();
+    let mut ip_addresses = Vec::new();
+
+    let extension = cert
+        .subject_alternative_name()
+        .map_err(|e| anyhow::anyhow!("Invalid subject alternative names: {}", e))?;
+    for name in extension.iter().flat_map(|ext| ext.value.general_names.iter()) {
+        match name {
+            GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => ip_addresses.push(IpAddr::from(<[u8; 4]>::try_from(*bytes)?)),
+                16 => ip_addresses.push(IpAddr::from(<[u8; 16]>::try_from(*bytes)?)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    Ok((dns_names, ip_addresses))
+}
+
+/// Lowercase hex SHA-256 of the leaf certificate's DER, as `openssl x509 -fingerprint -sha256` without colons
+pub fn certificate_fingerprint(cert_pem: &str) -> Result<String> {
+    let chain = parse_pem_certificates(cert_pem)?;
+    let leaf_der = chain.first().ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+    Ok(Sha256::digest(leaf_der).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// SPKI pins of every certificate in `pem`: the base64 SHA-256 of its SubjectPublicKeyInfo
+pub fn spki_pins(pem: &str) -> Result<Vec<String>> {
+    parse_pem_certificates(pem)?
+        .iter()
+        .map(|der| Ok(STANDARD.encode(Sha256::digest(parse_certificate(der)?.public_key().raw))))
+        .collect()
+}
+
+/// DER contents of every CERTIFICATE block in a PEM bundle
+fn parse_pem_certificates(pem: &str) -> Result<Vec<Vec<u8>>> {
+    let blocks = pem::parse_many(pem.as_bytes()).map_err(|e| anyhow::anyhow!("Failed to parse PEM: {}", e))?;
+    Ok(blocks
+        .into_iter()
+        .filter(|block| block.tag() == "CERTIFICATE")
+        .map(|block| block.into_contents())
+        .collect())
+}
+
+fn parse_certificate(der: &[u8]) -> Result<X509Certificate<'_>> {
+    X509Certificate::from_der(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+
+    #[test]
+    fn test_validate_issued_certificate() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let issuer = Issuer::new(ca_params, &ca_key);
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["web.default.svc".to_string()]).unwrap();
+        params.subject_alt_names.push(rcgen::SanType::IpAddress("10.0.0.1".parse().unwrap()));
+        let cert = params.signed_by(&key, &issuer).unwrap();
+
+        let dns = vec!["web.default.svc".to_string()];
+        let ips = vec!["10.0.0.1".to_string()];
+        validate_issued_certificate(&cert.pem(), &key.serialize_pem(), &ca.pem(), &dns, &ips).unwrap();
+
+        // Wrong key
+        let other_key = KeyPair::generate().unwrap();
+        assert!(validate_issued_certificate(&cert.pem(), &other_key.serialize_pem(), &ca.pem(), &dns, &ips).is_err());
+
+        // Missing SAN
+        let missing = vec!["api.default.svc".to_string()];
+        assert!(validate_issued_certificate(&cert.pem(), &key.serialize_pem(), &ca.pem(), &missing, &[]).is_err());
+
+        // Signed by another CA
+        let self_signed = params.self_signed(&key).unwrap();
+        assert!(validate_issued_certificate(&self_signed.pem(), &key.serialize_pem(), &ca.pem(), &dns, &ips).is_err());
+
+        assert_eq!(certificate_sans(&cert.pem()).unwrap(), (dns, ips));
+
+        let on_disk = inspect_certificate(&cert.pem(), &ca.pem()).unwrap();
+        assert!(on_disk.issued_by_ca);
+        assert_eq!(on_disk.not_after, params.not_after.unix_timestamp());
+        assert!(!inspect_certificate(&self_signed.pem(), &ca.pem()).unwrap().issued_by_ca);
+        assert!(inspect_certificate("-----BEGIN CERTIFICATE-----\ngarbage\n-----END CERTIFICATE-----\n", &ca.pem()).is_err());
+
+        // An expired certificate is still inspected
+        let mut expired_params = params.clone();
+        expired_params.not_before = rcgen::date_time_ymd(2020, 1, 1);
+        expired_params.not_after = rcgen::date_time_ymd(2021, 1, 1);
+        let expired = inspect_certificate(&expired_params.signed_by(&key, &issuer).unwrap().pem(), &ca.pem()).unwrap();
+        assert!(expired.issued_by_ca);
+        assert_eq!(expired.not_after, expired_params.not_after.unix_timestamp());
+
+        assert!(key_matches_certificate(&cert.pem(), &key.serialize_pem()).unwrap());
+        assert!(!key_matches_certificate(&cert.pem(), &other_key.serialize_pem()).unwrap());
+        let sec1 = KeyEncoding::Sec1.encode(&key.serialize_pem()).unwrap();
+        assert!(key_matches_certificate(&cert.pem(), &sec1).unwrap());
+        assert!(key_matches_certificate(&cert.pem(), &cert.pem()).is_err());
+
+        let pin = STANDARD.encode(Sha256::digest(key.subject_public_key_info()));
+        assert_eq!(spki_pins(&cert.pem()).unwrap(), [pin]);
+        assert_eq!(spki_pins(&format!("{}{}", ca.pem(), ca.pem())).unwrap().len(), 2);
+        let fingerprint = certificate_fingerprint(&cert.pem()).unwrap();
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(fingerprint, Sha256::digest(cert.der()).iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    }
+}
@@ -0,0 +1,498 @@
+use anyhow::{Result, Context};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Uri};
+use tracing::{info, debug, warn};
+
+use crate::error_details::ErrorInfo;
+use crate::retry::{is_transient_status, CircuitBreaker, RetryPolicy};
+use crate::proto::certservice::{
+    certificate_service_client::CertificateServiceClient,
+    IssueCertificateRequest, IssueCertificateResponse,
+    RenewCertificateRequest, RenewCertificateResponse,
+    RevokeCertificateRequest, RevokeCertificateResponse,
+    GetCertificateInfoRequest, GetCertificateInfoResponse,
+    ListCertificatesRequest, ListCertificatesResponse,
+    IssueNodeIntermediateRequest, IssueNodeIntermediateResponse,
+    WatchCertificatesRequest, CertificateEvent,
+};
+
+/// Deadline for a single certificate service call; remote signers may take a while
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for the certificate service (`certservice.v1`)
+///
+/// Connects on first use and shares one connection between clones, reconnecting
+/// after the service became unreachable. Transient failures are retried with
+/// backoff; rejected requests fail immediately.
+#[derive(Clone)]
+pub struct CertServiceClient {
+    addr: String,
+    /// Shared connection to the certificate service, dialed on first use
+    client: Arc<RwLock<Option<CertificateServiceClient<Channel>>>>,
+    retry_policy: RetryPolicy,
+    /// Fails calls fast while the service is unreachable, if set
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Bearer token sent with every call, read anew each time since projected tokens rotate
+    token_file: Option<PathBuf>,
+    /// CA certificates verifying the service at an `https://` address
+    tls_ca_pem: Option<String>,
+}
+
+impl CertServiceClient {
+    /// Client for the service at `addr`, e.g. `cacsi-service.kube-system:50051` (http:// is assumed)
+    /// or `unix:///path/to/socket`
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            client: Arc::new(RwLock::new(None)),
+            retry_policy: RetryPolicy::default(),
+            circuit_breaker: None,
+            token_file: None,
+            tls_ca_pem: None,
+        }
+    }
+
+    /// Authenticate with the service account token in `token_file`
+    pub fn with_token_file(mut self, token_file: PathBuf) -> Self {
+        self.token_file = Some(token_file);
+        self
+    }
+
+    /// Verify the service's TLS certificate against `ca_pem`
+    pub fn with_tls_ca(mut self, ca_pem: String) -> Self {
+        self.tls_ca_pem = Some(ca_pem);
+        self
+    }
+
+    /// Retry calls that fail with transient errors according to `retry_policy`
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fail calls right away while `circuit_breaker` is open
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Whether calls are protected from eavesdroppers, over TLS or a Unix socket
+    pub fn is_confidential(&self) -> bool {
+        self.addr.starts_with("https://") || self.addr.starts_with("unix://")
+    }
+
+    /// Issue a certificate, with a new idempotency key unless the request has one
+    ///
+    /// Retries of the call carry the same key, so a retry after a lost response gets the
+    /// certificate the first attempt was issued.
+    pub async fn issue_certificate(&self, mut request: IssueCertificateRequest) -> Result<IssueCertificateResponse> {
+        if request.idempotency_key.is_empty() {
+            request.idempotency_key = new_idempotency_key();
+        }
+        self.call("issue certificate", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.issue_certificate(request).await }
+        })
+        .await
+    }
+
+    pub async fn renew_certificate(&self, request: RenewCertificateRequest) -> Result<RenewCertificateResponse> {
+        self.call("renew certificate", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.renew_certificate(request).await }
+        })
+        .await
+    }
+
+    pub async fn revoke_certificate(&self, request: RevokeCertificateRequest) -> Result<RevokeCertificateResponse> {
+        self.call("revoke certificate", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.revoke_certificate(request).await }
+        })
+        .await
+    }
+
+    pub async fn get_certificate_info(&self, request: GetCertificateInfoRequest) -> Result<GetCertificateInfoResponse> {
+        self.call("get certificate info", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.get_certificate_info(request).await }
+        })
+        .await
+    }
+
+    pub async fn list_certificates(&self, request: ListCertificatesRequest) -> Result<ListCertificatesResponse> {
+        self.call("list certificates", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.list_certificates(request).await }
+        })
+        .await
+    }
+
+    pub async fn issue_node_intermediate(
+        &self,
+        request: IssueNodeIntermediateRequest,
+    ) -> Result<IssueNodeIntermediateResponse> {
+        self.call("issue node intermediate", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.issue_node_intermediate(request).await }
+        })
+        .await
+    }
+
+    /// Subscribe to renewal and revocation events of `certificate_ids` and to CA rotations
+    ///
+    /// The stream ends or fails when the connection is lost; callers watch again.
+    pub async fn watch_certificates(&self, certificate_ids: Vec<String>) -> Result<tonic::Streaming<CertificateEvent>> {
+        let request = WatchCertificatesRequest { certificate_ids };
+        self.call("watch certificates", |mut client| {
+            let request = self.request(request.clone());
+            async move { client.watch_certificates(request).await }
+        })
+        .await
+    }
+
+    /// Call the certificate service, retrying transient failures with backoff
+    ///
+    /// Errors keep the `tonic::Status` of the last attempt as their source.
+    pub async fn call<T, F, Fut>(&self, operation: &str, call: F) -> Result<T>
+    where
+        F: Fn(CertificateServiceClient<Channel>) -> Fut,
+        Fut: std::future::Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    {
+        let mut attempt = 1;
+        loop {
+            if let Some(Err(retry_in)) = self.circuit_breaker.as_ref().map(CircuitBreaker::check) {
+                let status = tonic::Status::unavailable(format!(
+                    "certificate service unreachable, circuit open for another {}s",
+                    retry_in.as_secs().max(1)
+                ));
+                return Err(anyhow::Error::new(status).context(format!("Failed to {}", operation)));
+            }
+
+            let result = match self.client().await {
+                Ok(client) => call(client).await.map(tonic::Response::into_inner).map_err(|status| {
+                    let transient = is_transient_status(&status);
+                    (anyhow::Error::new(status), transient)
+                }),
+                // Failing to connect is always worth another try
+                Err(e) => Err((e, true)),
+            };
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                match &result {
+                    Err((error, _)) if is_connection_failure(error) => circuit_breaker.record_failure(),
+                    _ => circuit_breaker.record_success(),
+                }
+            }
+
+            let error = match result {
+                Ok(response) => return Ok(response),
+                Err((error, transient)) => {
+                    if let Some(status) = error.downcast_ref::<tonic::Status>() {
+                        self.check_connection(status).await;
+                    }
+                    if !transient || attempt >= self.retry_policy.max_attempts {
+                        return Err(error.context(format!("Failed to {}", operation)));
+                    }
+                    error
+                }
+            };
+
+            let backoff = self.retry_policy.backoff(attempt);
+            warn!(
+                "Attempt {}/{} to {} failed, retrying in {:?}: {:#}",
+                attempt, self.retry_policy.max_attempts, operation, backoff, error
+            );
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    /// `message` as a request carrying the bearer token, if one is configured
+    ///
+    /// A token that cannot be read is left out; the service then refuses calls that need it.
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        let Some(token_file) = &self.token_file else {
+            return request;
+        };
+        let header = std::fs::read_to_string(token_file)
+            .context(format!("Failed to read token file {}", token_file.display()))
+            .and_then(|token| format!("Bearer {}", token.trim()).parse().context("Invalid token"));
+        match header {
+            Ok(header) => {
+                request.metadata_mut().insert("authorization", header);
+            }
+            Err(e) => warn!("Calling the certificate service without a token: {:#}", e),
+        }
+        request
+    }
+
+    /// Get the shared certificate service client, connecting if there is none
+    ///
+    /// The channel multiplexes concurrent calls over one HTTP/2 connection, so
+    /// issuance does not pay for a new connection each time.
+    async fn client(&self) -> Result<CertificateServiceClient<Channel>> {
+        if let Some(client) = self.client.read().await.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let mut guard = self.client.write().await;
+        if let Some(client) = guard.as_ref() {
+            return Ok(client.clone());
+        }
+
+        // A Unix socket needs a placeholder URI; every connection dials the socket
+        let socket_path = self.addr.strip_prefix("unix://").map(str::to_string);
+        let addr = if socket_path.is_some() {
+            "http://[::]:50051".to_string()
+        } else if !self.addr.starts_with("http://") && !self.addr.starts_with("https://") {
+            format!("http://{}", self.addr)
+        } else {
+            self.addr.clone()
+        };
+
+        info!("Connecting to certificate service at: {}", self.addr);
+
+        let tls = addr.starts_with("https://");
+        let mut endpoint = Endpoint::from_shared(addr)
+            .context("Invalid endpoint URL")?
+            .timeout(RPC_TIMEOUT)
+            .connect_timeout(Duration::from_secs(5))
+            .http2_keep_alive_interval(Duration::from_secs(30))
+            .keep_alive_timeout(Duration::from_secs(10))
+            .keep_alive_while_idle(true);
+        if tls {
+            let mut tls_config = ClientTlsConfig::new();
+            if let Some(ca_pem) = &self.tls_ca_pem {
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(ca_pem));
+            }
+            endpoint = endpoint.tls_config(tls_config).context("Invalid TLS configuration")?;
+        }
+        let channel = match socket_path {
+            Some(path) => endpoint
+                .connect_with_connector(tower::service_fn(move |_: Uri| tokio::net::UnixStream::connect(path.clone())))
+                .await,
+            None => endpoint.connect().await,
+        }
+        .map_err(|source| ConnectError { addr: self.addr.clone(), source })?;
+
+        let client = CertificateServiceClient::new(channel);
+        *guard = Some(client.clone());
+
+        Ok(client)
+    }
+
+    /// Drop the shared connection after a call failed because the service was unreachable,
+    /// so the next call dials again instead of reusing a broken connection
+    async fn check_connection(&self, status: &tonic::Status) {
+        // Errors with details were answered by the service over a working connection
+        if matches!(status.code(), tonic::Code::Unavailable | tonic::Code::Unknown) && ErrorInfo::from_status(status).is_none() {
+            debug!("Resetting certificate service connection after {:?}: {}", status.code(), status.message());
+            *self.client.write().await = None;
+        }
+    }
+}
+
+/// Random idempotency key, 128 bits in hex
+fn new_idempotency_key() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Connecting to the certificate service failed, so it never saw the call
+#[derive(Debug)]
+pub struct ConnectError {
+    addr: String,
+    source: tonic::transport::Error,
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to connect to certificate service at {}", self.addr)
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Whether a call failed while connecting to the service, before any answer
+pub fn is_connect_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<ConnectError>())
+}
+
+/// Whether a call failed because the service could not be reached, rather than being answered
+///
+/// The service itself answers UNAVAILABLE with error details, e.g. when its store is down.
+fn is_connection_failure(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<tonic::Status>() {
+        Some(status) => status.code() == tonic::Code::Unavailable && ErrorInfo::from_status(status).is_none(),
+        None => is_connect_error(error),
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::proto::certservice::certificate_service_server::{CertificateService, CertificateServiceServer};
+    use crate::testing::MockCertService;
+
+    #[tokio::test]
+    async fn test_issue_and_rejected_call() {
+        let mock = MockCertService::new().unwrap();
+        let (addr, server) = mock.serve().await.unwrap();
+        let client = CertServiceClient::new(addr.to_string());
+
+        let issued = client
+            .issue_certificate(IssueCertificateRequest {
+                certificate_id: "web".to_string(),
+                common_name: "web".to_string(),
+                dns_names: vec!["web".to_string()],
+                validity_seconds: 3600,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(issued.certificate_id, "web");
+        assert_eq!(issued.not_after - issued.not_before, 3600 + 300);
+
+        // Rejected calls fail right away and keep their status
+        let error = client.revoke_certificate(RevokeCertificateRequest::default()).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<tonic::Status>().unwrap().code(), tonic::Code::Unimplemented);
+        assert!(error.to_string().contains("Failed to revoke certificate"), "{}", error);
+
+        server.abort();
+    }
+
+    /// Certificate service that issues but loses the response of the first issuance, as a deadline would
+    struct LosesFirstResponse {
+        service: crate::cert_service::service::CertificateServiceImpl,
+        /// Idempotency key of each issuance and the certificate issued to it
+        issued: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    }
+
+    #[tonic::async_trait]
+    impl CertificateService for LosesFirstResponse {
+        async fn issue_certificate(
+            &self,
+            request: tonic::Request<IssueCertificateRequest>,
+        ) -> Result<tonic::Response<IssueCertificateResponse>, tonic::Status> {
+            let key = request.get_ref().idempotency_key.clone();
+            let response = self.service.issue_certificate(request).await?;
+            let mut issued = self.issued.lock().unwrap();
+            issued.push((key, response.get_ref().certificate_pem.clone()));
+            if issued.len() == 1 {
+                return Err(tonic::Status::deadline_exceeded("Deadline expired before operation could complete"));
+            }
+            Ok(response)
+        }
+
+        async fn renew_certificate(&self, request: tonic::Request<RenewCertificateRequest>) -> Result<tonic::Response<RenewCertificateResponse>, tonic::Status> {
+            self.service.renew_certificate(request).await
+        }
+
+        async fn revoke_certificate(&self, request: tonic::Request<RevokeCertificateRequest>) -> Result<tonic::Response<RevokeCertificateResponse>, tonic::Status> {
+            self.service.revoke_certificate(request).await
+        }
+
+        async fn get_certificate_info(&self, request: tonic::Request<GetCertificateInfoRequest>) -> Result<tonic::Response<GetCertificateInfoResponse>, tonic::Status> {
+            self.service.get_certificate_info(request).await
+        }
+
+        async fn list_certificates(&self, request: tonic::Request<ListCertificatesRequest>) -> Result<tonic::Response<ListCertificatesResponse>, tonic::Status> {
+            self.service.list_certificates(request).await
+        }
+
+        async fn issue_node_intermediate(&self, request: tonic::Request<IssueNodeIntermediateRequest>) -> Result<tonic::Response<IssueNodeIntermediateResponse>, tonic::Status> {
+            self.service.issue_node_intermediate(request).await
+        }
+
+        type WatchCertificatesStream = <crate::cert_service::service::CertificateServiceImpl as CertificateService>::WatchCertificatesStream;
+
+        async fn watch_certificates(&self, request: tonic::Request<WatchCertificatesRequest>) -> Result<tonic::Response<Self::WatchCertificatesStream>, tonic::Status> {
+            self.service.watch_certificates(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_deadline_does_not_issue_twice() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let signer = Arc::new(crate::cert_service::signer::LocalSigner::in_memory(ca_cert, &ca_key).unwrap());
+        let issued = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let service = LosesFirstResponse {
+            service: crate::cert_service::service::CertificateServiceImpl::new(signer),
+            issued: issued.clone(),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(CertificateServiceServer::new(service))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let client = CertServiceClient::new(addr.to_string()).with_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        });
+        let request = IssueCertificateRequest {
+            certificate_id: "default-web-csi-abc".to_string(),
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            ..Default::default()
+        };
+
+        // The retry carries the key of the first attempt and gets the certificate issued to it
+        let response = client.issue_certificate(request.clone()).await.unwrap();
+        {
+            let issued = issued.lock().unwrap();
+            assert_eq!(issued.len(), 2);
+            assert_eq!(issued[0], issued[1]);
+            assert!(!issued[0].0.is_empty());
+            assert_eq!(response.certificate_pem, issued[0].1);
+        }
+
+        // A key the caller chose is sent as is, so its own retries are recognised too
+        let first = issued.lock().unwrap()[0].clone();
+        let replayed = client
+            .issue_certificate(IssueCertificateRequest { idempotency_key: first.0.clone(), ..request })
+            .await
+            .unwrap();
+        assert_eq!(replayed.certificate_pem, response.certificate_pem);
+        assert_eq!(issued.lock().unwrap()[2], first);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_address() {
+        let dir = TempDir::new("client").unwrap();
+        let socket_path = dir.join("cert-service.sock");
+
+        let mock = MockCertService::new().unwrap();
+        let server = mock.serve_unix(&socket_path).unwrap();
+
+        let client = CertServiceClient::new(format!("unix://{}", socket_path.display()));
+        let issued = client
+            .issue_certificate(IssueCertificateRequest {
+                certificate_id: "web".to_string(),
+                common_name: "web".to_string(),
+                validity_seconds: 3600,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(issued.certificate_id, "web");
+        assert_eq!(mock.issue_calls(), 1);
+        assert!(client.is_confidential());
+        assert!(!CertServiceClient::new("cacsi-service:50051").is_confidential());
+
+        server.abort();
+    }
+}
@@ -0,0 +1,1088 @@
+use anyhow::{Result, Context};
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Args, Command, Parser};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::sleep;
+use tracing::{info, warn};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
+
+use crate::cert_manager::DEFAULT_PREVIOUS_VERSIONS;
+use crate::cert_monitor::{MonitorSettings, RevocationAction};
+use crate::cert_service::auth::{service_account_usernames, DEFAULT_TOKEN_AUDIENCE};
+use crate::cert_service::leader_election::DEFAULT_LEASE_DURATION_SECONDS;
+use crate::cert_service::settings::ServiceSettings;
+use crate::ca_expiry::DEFAULT_CA_EXPIRY_WARNING_DAYS;
+use crate::k8s_client::ApiSettings;
+use crate::notifier::{
+    Notifier, NotifyFormat, DEFAULT_NOTIFY_COOLDOWN_SECONDS, DEFAULT_NOTIFY_EXPIRY_HOURS, DEFAULT_NOTIFY_FAILURE_THRESHOLD,
+};
+use crate::orphans::DEFAULT_ORPHAN_GC_INTERVAL;
+use crate::retry::{CircuitBreaker, RetryPolicy};
+
+/// Environment variable naming the config file when `--config` is not given
+pub const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+/// How often the config file is checked for changes
+pub const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Admin socket of the CSI driver, also the default of `cacsictl`
+pub const DEFAULT_ADMIN_SOCKET: &str = "/csi/admin.sock";
+
+/// Parse the configuration from the command line, the environment and the config file
+///
+/// A setting is taken from its flag, else its environment variable, else the config
+/// file, else its default. Invalid flags and values print usage and exit; problems
+/// with the config file are returned.
+pub fn load<T: Parser>() -> Result<T> {
+    load_from(std::env::args_os(), std::env::var_os(CONFIG_FILE_ENV))
+}
+
+/// [`load`] with explicit arguments and `CONFIG_FILE` value
+pub fn load_from<T, I>(args: I, config_file_env: Option<OsString>) -> Result<T>
+where
+    T: Parser,
+    I: IntoIterator<Item = OsString>,
+{
+    let args: Vec<OsString> = args.into_iter().collect();
+    let matches = command::<T>(&args, config_file_env)?.get_matches_from(args);
+    T::from_arg_matches(&matches).map_err(|e| e.exit())
+}
+
+/// [`load_from`] that returns every problem instead of exiting, for reloading a running process
+fn reload<T: Parser>(args: &[OsString], config_file_env: Option<OsString>) -> Result<T> {
+    let matches = command::<T>(args, config_file_env)?
+        .try_get_matches_from(args)
+        .map_err(|e| anyhow::anyhow!("{}", e.render().to_string().trim()))?;
+    Ok(T::from_arg_matches(&matches)?)
+}
+
+/// Command line parser of `T` with the config file settings as defaults
+fn command<T: Parser>(args: &[OsString], config_file_env: Option<OsString>) -> Result<Command> {
+    let mut command = T::command();
+
+    // Empty environment variables count as unset, as with `VAR=""` in a manifest
+    let unset: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| arg.get_env().and_then(std::env::var_os).is_some_and(|value| value.is_empty()))
+        .map(|arg| arg.get_id().clone())
+        .collect();
+    for id in unset {
+        command = command.mut_arg(id, |arg| arg.env(None));
+    }
+
+    // Config file values become the defaults, so flags and the environment override them
+    if let Some(path) = config_file_path(args).or(config_file_env.map(PathBuf::from)) {
+        for (name, values) in read_config_file(&path)? {
+            let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == name.as_str() && name != "config") else {
+                anyhow::bail!("Unknown setting '{}' in {}", name, path.display());
+            };
+            let values = match arg.get_value_delimiter() {
+                Some(delimiter) => values
+                    .iter()
+                    .flat_map(|value| value.split(delimiter))
+                    .map(|value| value.trim().to_string())
+                    .collect(),
+                None if values.len() == 1 => values,
+                None => anyhow::bail!("Setting '{}' in {} takes a single value, not a list", name, path.display()),
+            };
+            command = command.mut_arg(name, |arg| arg.default_values(values));
+        }
+    }
+
+    Ok(command)
+}
+
+/// Load the configuration again whenever the config file changes or on SIGHUP, until the process exits
+///
+/// Each new configuration is passed to `apply`. When loading or applying it fails, e.g.
+/// on an invalid setting, the error is logged and the running configuration stays.
+pub async fn watch<T: Parser>(apply: impl FnMut(T) -> Result<()>) {
+    watch_from(std::env::args_os().collect(), std::env::var_os(CONFIG_FILE_ENV), CONFIG_CHECK_INTERVAL, apply).await
+}
+
+/// [`watch`] with explicit arguments, `CONFIG_FILE` value and interval between checks of the file
+async fn watch_from<T: Parser>(
+    args: Vec<OsString>,
+    config_file_env: Option<OsString>,
+    check_interval: Duration,
+    mut apply: impl FnMut(T) -> Result<()>,
+) {
+    let path = config_file_path(&args).or(config_file_env.clone().map(PathBuf::from));
+    let read = || path.as_ref().and_then(|path| std::fs::read_to_string(path).ok());
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) if path.is_some() => {
+            warn!("Failed to handle SIGHUP, only reloading on config file changes: {}", e);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to handle SIGHUP, not reloading the configuration: {}", e);
+            return;
+        }
+    };
+
+    // Mounted ConfigMaps are replaced through a symlink, so the content is compared rather than the mtime
+    let mut contents = read();
+    loop {
+        tokio::select! {
+            Some(()) = async { hangup.as_mut()?.recv().await } => {
+                info!("Received SIGHUP, reloading the configuration");
+                contents = read();
+            }
+            _ = sleep(check_interval), if path.is_some() => {
+                let current = read();
+                if current == contents {
+                    continue;
+                }
+                contents = current;
+                info!("Config file changed, reloading the configuration");
+            }
+        }
+
+        if let Err(e) = reload::<T>(&args, config_file_env.clone()).and_then(&mut apply) {
+            warn!("Failed to reload the configuration, keeping the running one: {:#}", e);
+        }
+    }
+}
+
+/// Path given with `--config <path>` or `--config=<path>`
+fn config_file_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Settings of a YAML or TOML config file, as setting name and values
+///
+/// The file is a flat map from setting names, the environment variable names in
+/// lowercase, to a value or a list of values. TOML is recognized by the `.toml`
+/// extension; anything else is read as YAML, which includes JSON.
+fn read_config_file(path: &Path) -> Result<Vec<(String, Vec<String>)>> {
+    let content = std::fs::read_to_string(path)
+        .context(format!("Failed to read config file {}", path.display()))?;
+    let document: serde_json::Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content)
+            .context(format!("Failed to parse config file {}", path.display()))?,
+        _ => serde_yaml::from_str(&content)
+            .context(format!("Failed to parse config file {}", path.display()))?,
+    };
+
+    let settings = match document {
+        serde_json::Value::Object(settings) => settings,
+        serde_json::Value::Null => return Ok(Vec::new()),
+        _ => anyhow::bail!("Config file {} must be a map of setting names to values", path.display()),
+    };
+
+    let mut parsed = Vec::new();
+    for (name, value) in settings {
+        let name = name.to_lowercase().replace('-', "_");
+        let values = match value {
+            serde_json::Value::Null => continue,
+            serde_json::Value::Array(items) => items
+                .into_iter()
+                .map(|item| scalar(&item))
+                .collect::<Option<Vec<_>>>(),
+            value => scalar(&value).map(|value| vec![value]),
+        };
+        let Some(values) = values else {
+            anyhow::bail!("Setting '{}' in {} must be a string, number, boolean or a list of them", name, path.display());
+        };
+        parsed.push((name, values));
+    }
+    Ok(parsed)
+}
+
+fn scalar(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Empty values count as unset
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|s| !s.is_empty())
+}
+
+/// Scratch directory of dev mode for the socket and certificates
+fn dev_dir() -> PathBuf {
+    std::env::temp_dir().join("cacsi-dev")
+}
+
+/// Log filter of the running process, changeable without a restart
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// Start logging with `log_level`, or `RUST_LOG` when unset, defaulting to `info`
+    pub fn init(log_level: Option<&str>) -> Result<Self> {
+        let (filter, handle) = reload::Layer::new(env_filter(log_level)?);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_subscriber::fmt::layer())
+            .init();
+        Ok(Self { handle })
+    }
+
+    pub fn set(&self, log_level: Option<&str>) -> Result<()> {
+        self.handle.reload(env_filter(log_level)?).context("Failed to change the log level")
+    }
+}
+
+fn env_filter(log_level: Option<&str>) -> Result<EnvFilter> {
+    match log_level.filter(|level| !level.is_empty()) {
+        Some(level) => EnvFilter::try_new(level).context(format!("Invalid LOG_LEVEL '{}'", level)),
+        None => Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into())),
+    }
+}
+
+/// Timeout, rate limit and retries of Kubernetes API requests, for both binaries
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct KubeApiConfig {
+    /// Seconds the API server may take to answer a request; 0 waits indefinitely
+    #[arg(long, env = "KUBE_API_TIMEOUT_SECONDS", default_value_t = 10)]
+    pub kube_api_timeout_seconds: u64,
+
+    /// Average Kubernetes API requests per second; 0 disables the limit
+    #[arg(long, env = "KUBE_API_QPS", default_value_t = 20)]
+    pub kube_api_qps: u32,
+
+    /// Kubernetes API requests sent right away before the rate limit applies
+    #[arg(long, env = "KUBE_API_BURST", default_value_t = 40)]
+    pub kube_api_burst: u32,
+
+    /// Attempts of Kubernetes API reads that time out, are throttled or fail on the server
+    #[arg(long, env = "KUBE_API_MAX_ATTEMPTS", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub kube_api_max_attempts: u32,
+}
+
+impl KubeApiConfig {
+    pub fn api_settings(&self) -> ApiSettings {
+        let defaults = ApiSettings::default();
+        ApiSettings {
+            timeout: Duration::from_secs(self.kube_api_timeout_seconds),
+            qps: self.kube_api_qps,
+            burst: self.kube_api_burst,
+            retry: RetryPolicy { max_attempts: self.kube_api_max_attempts, ..defaults.retry },
+        }
+    }
+}
+
+/// Configuration of the `csi-driver` binary
+#[derive(Parser, Debug, Clone, PartialEq)]
+#[command(name = "csi-driver", about = "CSI driver mounting certificates into pods")]
+pub struct DriverConfig {
+    /// YAML or TOML file with settings; flags and environment variables override it
+    #[arg(long, env = CONFIG_FILE_ENV, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Log filter, e.g. `info` or `cacsi_driver=debug`; defaults to `RUST_LOG`, else `info`
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Run without Kubernetes, with a self-signed CA, e.g. for csi-sanity on a laptop
+    #[arg(long = "dev", env = "DEV_MODE")]
+    pub dev_mode: bool,
+
+    /// CSI socket, `unix://<path>` or `tcp://<addr>:<port>`
+    #[arg(long, env = "CSI_ENDPOINT")]
+    pub csi_endpoint: Option<String>,
+
+    /// `uid[:gid]` to chown the CSI socket to
+    #[arg(long, env = "CSI_SOCKET_OWNER")]
+    pub csi_socket_owner: Option<String>,
+
+    /// Octal permissions of the CSI socket
+    #[arg(long, env = "CSI_SOCKET_MODE")]
+    pub csi_socket_mode: Option<String>,
+
+    #[arg(long, env = "DRIVER_MODE", default_value = "node", value_parser = ["node", "controller", "all-in-one"])]
+    pub driver_mode: String,
+
+    /// Below the default terminationGracePeriodSeconds of 30
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECONDS", default_value_t = 25)]
+    pub shutdown_timeout_seconds: u64,
+
+    /// Defaults to the hostname
+    #[arg(long, env = "NODE_ID")]
+    pub node_id: Option<String>,
+
+    /// Path of the CSI socket on the host; registers with the kubelet without the node-driver-registrar sidecar
+    #[arg(long, env = "KUBELET_REGISTRATION_PATH")]
+    pub kubelet_registration_path: Option<String>,
+
+    #[arg(long, env = "PLUGIN_REGISTRATION_DIR", default_value = "/registration")]
+    pub plugin_registration_dir: String,
+
+    /// Socket of the admin API `cacsictl` talks to; defaults to `/csi/admin.sock`, empty disables it
+    #[arg(long, env = "ADMIN_SOCKET")]
+    pub admin_socket: Option<String>,
+
+    #[arg(long, env = "CERT_SERVICE_ADDR")]
+    pub cert_service_addr: Option<String>,
+
+    /// Service account token sent to the certificate service, re-read on every call
+    #[arg(long, env = "CERT_SERVICE_TOKEN_FILE")]
+    pub cert_service_token_file: Option<PathBuf>,
+
+    /// CA certificates verifying the certificate service's TLS certificate at an `https://` address
+    #[arg(long, env = "CERT_SERVICE_CA_FILE")]
+    pub cert_service_ca_file: Option<PathBuf>,
+
+    #[arg(long, env = "CA_SECRET_NAME", default_value = "csi-ca-secret")]
+    pub ca_secret_name: String,
+
+    #[arg(long, env = "CA_SECRET_NAMESPACE", default_value = "kube-system")]
+    pub ca_secret_namespace: String,
+
+    /// ConfigMap with the CA certificate only; the CA key never reaches the node
+    #[arg(long, env = "CA_TRUST_BUNDLE_CONFIGMAP")]
+    pub ca_trust_bundle_configmap: Option<String>,
+
+    /// Also unlocks the keys of the namespace and tenant CAs
+    #[arg(long, env = "CA_KEY_PASSPHRASE_SECRET")]
+    pub ca_key_passphrase_secret: Option<String>,
+
+    /// `<namespace>=<secret>` entries giving namespaces their own CA secret in the CA secret namespace
+    #[arg(long, env = "NAMESPACE_CA_SECRETS", value_delimiter = ',')]
+    pub namespace_ca_secrets: Vec<String>,
+
+    #[arg(long, env = "CA_EXPIRY_WARNING_DAYS", default_value_t = DEFAULT_CA_EXPIRY_WARNING_DAYS)]
+    pub ca_expiry_warning_days: i64,
+
+    #[arg(long, env = "CA_EXPIRY_PROBE_DAYS")]
+    pub ca_expiry_probe_days: Option<i64>,
+
+    /// Directory keeping the dev CA across restarts
+    #[arg(long, env = "DEV_CA_DIR")]
+    pub dev_ca_dir: Option<String>,
+
+    #[arg(long, env = "CERT_BASE_PATH")]
+    pub cert_base_path: Option<String>,
+
+    #[arg(long, env = "CLUSTER_DOMAIN", default_value = "cluster.local")]
+    pub cluster_domain: String,
+
+    #[arg(long, env = "KUBELET_PODS_DIR", default_value = "/var/lib/kubelet/pods")]
+    pub kubelet_pods_dir: String,
+
+    /// Seconds between passes cleaning up volumes of pods that are gone; 0 disables them
+    #[arg(long, env = "ORPHAN_GC_INTERVAL", default_value_t = DEFAULT_ORPHAN_GC_INTERVAL)]
+    pub orphan_gc_interval: u64,
+
+    /// Watch the pods on the node, cleaning up the volumes of deleted pods right away, even
+    /// without NodeUnpublishVolume, and caching pods for templates
+    #[arg(long, env = "POD_WATCH", action = ArgAction::Set, default_value_t = true, value_parser = BoolishValueParser::new())]
+    pub pod_watch: bool,
+
+    /// Revoke the certificates of deleted pods (needs `POD_WATCH`)
+    #[arg(long, env = "REVOKE_ON_POD_DELETE", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub revoke_on_pod_delete: bool,
+
+    #[arg(long, env = "CERT_SERVICE_MAX_ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub cert_service_max_attempts: Option<u32>,
+
+    #[arg(long, env = "CERT_SERVICE_RETRY_BACKOFF_MS")]
+    pub cert_service_retry_backoff_ms: Option<u64>,
+
+    #[arg(long, env = "CERT_SERVICE_RETRY_MAX_BACKOFF_MS")]
+    pub cert_service_retry_max_backoff_ms: Option<u64>,
+
+    /// Seconds NodePublishVolume keeps retrying while the certificate service is unavailable; 0 fails right away
+    #[arg(long, env = "PUBLISH_RETRY_DEADLINE_SECONDS", default_value_t = 0)]
+    pub publish_retry_deadline_seconds: u64,
+
+    /// Connection failures in a row after which calls fail fast; 0 disables the circuit breaker
+    #[arg(long, env = "CERT_SERVICE_CIRCUIT_THRESHOLD", default_value_t = 5)]
+    pub cert_service_circuit_threshold: u32,
+
+    /// Longest time calls fail fast before the service is tried again
+    #[arg(long, env = "CERT_SERVICE_CIRCUIT_MAX_OPEN_SECONDS", default_value_t = 60)]
+    pub cert_service_circuit_max_open_seconds: u64,
+
+    #[arg(long, env = "RENEWAL_CONCURRENCY", default_value_t = 8)]
+    pub renewal_concurrency: usize,
+
+    /// Seconds between certificate expiry checks
+    #[arg(long, env = "CERT_CHECK_INTERVAL", default_value_t = 300)]
+    pub cert_check_interval: u64,
+
+    /// Percentage of the certificate lifetime left when renewal is due
+    #[arg(long, env = "RENEWAL_THRESHOLD_PERCENT", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..=90))]
+    pub renewal_threshold_percent: u32,
+
+    #[arg(long, env = "RENEWAL_JITTER_PERCENT", default_value_t = 10)]
+    pub renewal_jitter_percent: u32,
+
+    #[arg(long, env = "KEY_ENCRYPTION_SECRET")]
+    pub key_encryption_secret: Option<String>,
+
+    /// Defaults to the CA secret namespace
+    #[arg(long, env = "KEY_ENCRYPTION_SECRET_NAMESPACE")]
+    pub key_encryption_secret_namespace: Option<String>,
+
+    #[arg(long, env = "ENCRYPT_KEYS", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub encrypt_keys: bool,
+
+    /// Previous certificates and keys kept in each volume on renewal, 0 for none
+    #[arg(long, env = "PREVIOUS_VERSIONS", default_value_t = DEFAULT_PREVIOUS_VERSIONS)]
+    pub previous_versions: usize,
+
+    #[arg(long, env = "REQUIRE_TMPFS", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub require_tmpfs: bool,
+
+    /// Empty disables the metrics endpoint
+    #[arg(long, env = "METRICS_ADDR", default_value = "0.0.0.0:9810")]
+    pub metrics_addr: String,
+
+    /// Seconds between revocation checks, 0 to disable
+    #[arg(long, env = "REVOCATION_CHECK_INTERVAL", default_value_t = 300)]
+    pub revocation_check_interval: u64,
+
+    #[arg(long, env = "REVOCATION_ACTION", default_value = "reissue")]
+    pub revocation_action: RevocationAction,
+
+    /// Revocations and CA rotations are pushed by the certificate service instead of polled
+    #[arg(long, env = "WATCH_CERTIFICATES", action = ArgAction::Set, default_value_t = true, value_parser = BoolishValueParser::new())]
+    pub watch_certificates: bool,
+
+    /// Restore certificate files deleted or truncated by something else on the node
+    #[arg(long, env = "WATCH_VOLUMES", action = ArgAction::Set, default_value_t = true, value_parser = BoolishValueParser::new())]
+    pub watch_volumes: bool,
+
+    #[arg(long, env = "ANNOTATE_PODS", action = ArgAction::Set, default_value_t = true, value_parser = BoolishValueParser::new())]
+    pub annotate_pods: bool,
+
+    /// Keep a CertificateBinding per published volume; needs the CertificateBinding CRD
+    #[arg(long, env = "CERTIFICATE_BINDINGS", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub certificate_bindings: bool,
+
+    /// Defaults to enabled in dev mode, so no certificate service is needed
+    #[arg(long, env = "LOCAL_SIGNING_FALLBACK", value_parser = BoolishValueParser::new())]
+    pub local_signing_fallback: Option<bool>,
+
+    #[arg(long, env = "LOCAL_SIGNING_MAX_VALIDITY_SECONDS", default_value_t = 3600)]
+    pub local_signing_max_validity_seconds: i64,
+
+    /// Namespaces that may get locally signed certificates, `*` for all; defaults to `*` in dev mode
+    #[arg(long, env = "LOCAL_SIGNING_NAMESPACES", value_delimiter = ',')]
+    pub local_signing_namespaces: Vec<String>,
+
+    #[arg(long, env = "DELEGATED_CA", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub delegated_ca: bool,
+
+    #[arg(long, env = "METADATA_LABELS", value_delimiter = ',')]
+    pub metadata_labels: Vec<String>,
+
+    /// Webhook receiving alerts about certificates that keep failing to renew or are about to expire
+    #[arg(long, env = "NOTIFY_WEBHOOK_URL")]
+    pub notify_webhook_url: Option<String>,
+
+    #[arg(long, env = "NOTIFY_FORMAT", default_value = "generic")]
+    pub notify_format: NotifyFormat,
+
+    /// Consecutive failed renewals before an alert
+    #[arg(long, env = "NOTIFY_FAILURE_THRESHOLD", default_value_t = DEFAULT_NOTIFY_FAILURE_THRESHOLD, value_parser = clap::value_parser!(u32).range(1..))]
+    pub notify_failure_threshold: u32,
+
+    /// Alert on certificates expiring within this many hours
+    #[arg(long, env = "NOTIFY_EXPIRY_HOURS", default_value_t = DEFAULT_NOTIFY_EXPIRY_HOURS)]
+    pub notify_expiry_hours: u64,
+
+    /// Minimum seconds between alerts of the same kind for one certificate
+    #[arg(long, env = "NOTIFY_COOLDOWN_SECONDS", default_value_t = DEFAULT_NOTIFY_COOLDOWN_SECONDS)]
+    pub notify_cooldown_seconds: u64,
+
+    #[command(flatten)]
+    pub kube_api: KubeApiConfig,
+
+    /// Certificate service settings, used in all-in-one mode
+    #[command(flatten)]
+    pub service: ServiceSettings,
+}
+
+impl DriverConfig {
+    pub fn csi_endpoint(&self) -> String {
+        match non_empty(&self.csi_endpoint) {
+            Some(endpoint) => endpoint.to_string(),
+            None if self.dev_mode => format!("unix://{}", dev_dir().join("csi.sock").display()),
+            None => "unix:///csi/csi.sock".to_string(),
+        }
+    }
+
+    pub fn node_id(&self) -> String {
+        match non_empty(&self.node_id) {
+            Some(node_id) => node_id.to_string(),
+            None => hostname::get().unwrap().to_string_lossy().to_string(),
+        }
+    }
+
+    pub fn cert_service_addr(&self) -> String {
+        match non_empty(&self.cert_service_addr) {
+            Some(addr) => addr.to_string(),
+            None if self.dev_mode => "http://127.0.0.1:50051".to_string(),
+            None => "http://cacsi-service:50051".to_string(),
+        }
+    }
+
+    pub fn admin_socket(&self) -> Option<PathBuf> {
+        match self.admin_socket.as_deref() {
+            Some("") => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None if self.dev_mode => Some(dev_dir().join("admin.sock")),
+            None => Some(PathBuf::from(DEFAULT_ADMIN_SOCKET)),
+        }
+    }
+
+    pub fn cert_base_path(&self) -> String {
+        match non_empty(&self.cert_base_path) {
+            Some(path) => path.to_string(),
+            None if self.dev_mode => dev_dir().join("certs").display().to_string(),
+            None => "/var/lib/csi-certs".to_string(),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: self.cert_service_max_attempts.unwrap_or(defaults.max_attempts),
+            initial_backoff: self.cert_service_retry_backoff_ms
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(defaults.initial_backoff),
+            max_backoff: self.cert_service_retry_max_backoff_ms
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(defaults.max_backoff),
+        }
+    }
+
+    /// Circuit breaker of certificate service calls, unless disabled
+    pub fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        (self.cert_service_circuit_threshold > 0).then(|| {
+            CircuitBreaker::new(
+                self.cert_service_circuit_threshold,
+                std::time::Duration::from_secs(self.cert_service_circuit_max_open_seconds),
+            )
+        })
+    }
+
+    pub fn namespace_ca_secrets(&self) -> Result<BTreeMap<String, String>> {
+        parse_namespace_ca_secrets(&self.namespace_ca_secrets)
+    }
+
+    pub fn key_encryption_secret_namespace(&self) -> String {
+        non_empty(&self.key_encryption_secret_namespace).unwrap_or(&self.ca_secret_namespace).to_string()
+    }
+
+    pub fn local_signing_fallback(&self) -> bool {
+        self.local_signing_fallback.unwrap_or(self.dev_mode)
+    }
+
+    pub fn monitor_settings(&self) -> MonitorSettings {
+        MonitorSettings {
+            check_interval: Duration::from_secs(self.cert_check_interval),
+            renewal_concurrency: self.renewal_concurrency,
+            renewal_threshold: f64::from(self.renewal_threshold_percent) / 100.0,
+            renewal_jitter: f64::from(self.renewal_jitter_percent) / 100.0,
+            revocation_check_interval: Some(Duration::from_secs(self.revocation_check_interval))
+                .filter(|interval| !interval.is_zero()),
+            revocation_action: self.revocation_action,
+        }
+    }
+
+    /// Alert webhook of the certificate monitor, if `NOTIFY_WEBHOOK_URL` is set
+    pub fn notifier(&self) -> Result<Option<Notifier>> {
+        let Some(url) = &self.notify_webhook_url else {
+            return Ok(None);
+        };
+        Notifier::new(
+            url.clone(),
+            self.node_id(),
+            self.notify_format,
+            self.notify_failure_threshold,
+            Duration::from_secs(self.notify_expiry_hours * 3600),
+            Duration::from_secs(self.notify_cooldown_seconds),
+        ).map(Some)
+    }
+
+    /// Whether settings that are only read at startup differ from `previous`
+    ///
+    /// The log level, the [`monitor_settings`](Self::monitor_settings) and, in
+    /// all-in-one mode, the issuance settings of the embedded service are applied to
+    /// the running driver.
+    pub fn needs_restart(&self, previous: &Self) -> bool {
+        let mut reloaded = previous.clone();
+        reloaded.config.clone_from(&self.config);
+        reloaded.log_level.clone_from(&self.log_level);
+        reloaded.cert_check_interval = self.cert_check_interval;
+        reloaded.renewal_concurrency = self.renewal_concurrency;
+        reloaded.renewal_threshold_percent = self.renewal_threshold_percent;
+        reloaded.renewal_jitter_percent = self.renewal_jitter_percent;
+        reloaded.revocation_check_interval = self.revocation_check_interval;
+        reloaded.revocation_action = self.revocation_action;
+        reloaded.service.clone_from(&self.service);
+        reloaded != *self || (self.driver_mode == "all-in-one" && self.service.needs_restart(&previous.service))
+    }
+
+    /// Treat empty values as unset and check settings that only make sense together
+    pub fn validate(&mut self) -> Result<()> {
+        for value in [
+            &mut self.csi_socket_owner,
+            &mut self.csi_socket_mode,
+            &mut self.kubelet_registration_path,
+            &mut self.ca_trust_bundle_configmap,
+            &mut self.ca_key_passphrase_secret,
+            &mut self.dev_ca_dir,
+            &mut self.key_encryption_secret,
+            &mut self.notify_webhook_url,
+        ] {
+            if value.as_deref() == Some("") {
+                *value = None;
+            }
+        }
+        for path in [&mut self.cert_service_token_file, &mut self.cert_service_ca_file] {
+            if path.as_ref().is_some_and(|path| path.as_os_str().is_empty()) {
+                *path = None;
+            }
+        }
+        env_filter(self.log_level.as_deref())?;
+        self.local_signing_namespaces.retain(|namespace| !namespace.trim().is_empty());
+        self.metadata_labels.retain(|label| !label.trim().is_empty());
+        self.namespace_ca_secrets.retain(|entry| !entry.trim().is_empty());
+        self.service.default_configmap_namespace(&self.ca_secret_namespace);
+        self.namespace_ca_secrets()?;
+
+        if self.dev_mode
+            && (self.ca_trust_bundle_configmap.is_some() || self.key_encryption_secret.is_some() || self.ca_key_passphrase_secret.is_some())
+        {
+            anyhow::bail!("DEV_MODE cannot be combined with CA_TRUST_BUNDLE_CONFIGMAP, KEY_ENCRYPTION_SECRET or CA_KEY_PASSPHRASE_SECRET, which need Kubernetes");
+        }
+        if self.dev_mode && (!self.namespace_ca_secrets.is_empty() || self.service.tenants_configmap.is_some()) {
+            anyhow::bail!("DEV_MODE cannot be combined with NAMESPACE_CA_SECRETS or TENANTS_CONFIGMAP, which need Kubernetes");
+        }
+        if self.encrypt_keys && self.key_encryption_secret.is_none() {
+            anyhow::bail!("ENCRYPT_KEYS requires KEY_ENCRYPTION_SECRET");
+        }
+        if !self.dev_mode && self.ca_trust_bundle_configmap.is_some() && self.local_signing_fallback() {
+            anyhow::bail!("LOCAL_SIGNING_FALLBACK requires the CA key and cannot be used with CA_TRUST_BUNDLE_CONFIGMAP");
+        }
+        if self.dev_mode && self.local_signing_namespaces.is_empty() {
+            self.local_signing_namespaces = vec!["*".to_string()];
+        }
+        if self.local_signing_fallback() && self.local_signing_namespaces.is_empty() {
+            anyhow::bail!("LOCAL_SIGNING_FALLBACK requires LOCAL_SIGNING_NAMESPACES, e.g. `*` for all namespaces");
+        }
+        if !self.metrics_addr.is_empty() {
+            self.metrics_addr.parse::<std::net::SocketAddr>()
+                .context(format!("Invalid METRICS_ADDR '{}'", self.metrics_addr))?;
+        }
+
+        if self.cert_service_addr().starts_with("https://") && self.cert_service_ca_file.is_none() {
+            anyhow::bail!("An https:// CERT_SERVICE_ADDR requires CERT_SERVICE_CA_FILE");
+        }
+        if self.delegated_ca && (self.dev_mode || self.driver_mode == "all-in-one" || self.cert_service_token_file.is_none()) {
+            anyhow::bail!("DELEGATED_CA requires CERT_SERVICE_TOKEN_FILE and cannot be combined with DEV_MODE or DRIVER_MODE=all-in-one");
+        }
+
+        if self.driver_mode == "all-in-one" {
+            if self.ca_trust_bundle_configmap.is_some() {
+                anyhow::bail!("DRIVER_MODE=all-in-one signs with the CA key and cannot be used with CA_TRUST_BUNDLE_CONFIGMAP");
+            }
+            if self.service.node_intermediates {
+                anyhow::bail!("NODE_INTERMEDIATES needs the standalone certificate service, which authenticates node drivers");
+            }
+            if self.dev_mode && (self.service.policy_configmap.is_some() || self.service.profiles_configmap.is_some()) {
+                anyhow::bail!("DEV_MODE cannot load POLICY_CONFIGMAP or PROFILES_CONFIGMAP, which need Kubernetes");
+            }
+            self.service.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of the `cacsi-service` binary
+///
+/// Signer backends other than `local` read their settings, mostly credentials, from
+/// the environment (see the README).
+#[derive(Parser, Debug, Clone, PartialEq)]
+#[command(name = "cacsi-service", about = "Certificate service signing certificates for CSI driver nodes")]
+pub struct ServiceConfig {
+    /// YAML or TOML file with settings; flags and environment variables override it
+    #[arg(long, env = CONFIG_FILE_ENV, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Log filter, e.g. `info` or `cacsi_driver=debug`; defaults to `RUST_LOG`, else `info`
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Run without Kubernetes, signing with a self-signed CA
+    #[arg(long = "dev", env = "DEV_MODE")]
+    pub dev_mode: bool,
+
+    /// Directory keeping the dev CA across restarts
+    #[arg(long, env = "DEV_CA_DIR")]
+    pub dev_ca_dir: Option<String>,
+
+    #[arg(long, env = "LISTEN_ADDR")]
+    pub listen_addr: Option<String>,
+
+    #[arg(long, env = "CA_SECRET_NAME", default_value = "csi-ca-secret")]
+    pub ca_secret_name: String,
+
+    #[arg(long, env = "CA_SECRET_NAMESPACE", default_value = "kube-system")]
+    pub ca_secret_namespace: String,
+
+    /// `<namespace>=<secret>` entries giving namespaces their own CA secret in the CA secret namespace
+    #[arg(long, env = "NAMESPACE_CA_SECRETS", value_delimiter = ',')]
+    pub namespace_ca_secrets: Vec<String>,
+
+    /// Also unlocks the keys of the namespace CAs
+    #[arg(long, env = "CA_KEY_PASSPHRASE_SECRET")]
+    pub ca_key_passphrase_secret: Option<String>,
+
+    #[arg(long, env = "SIGNER_BACKEND", default_value = "local", value_parser = ["local", "step-ca", "est", "kms"])]
+    pub signer_backend: String,
+
+    /// Lets several replicas run, with one serving at a time
+    #[arg(long, env = "LEADER_ELECTION", action = ArgAction::Set, default_value_t = false, value_parser = BoolishValueParser::new())]
+    pub leader_election: bool,
+
+    #[arg(long, env = "LEADER_ELECTION_LEASE_NAME", default_value = "cacsi-service")]
+    pub leader_election_lease_name: String,
+
+    /// Defaults to the CA secret namespace
+    #[arg(long, env = "LEADER_ELECTION_NAMESPACE")]
+    pub leader_election_namespace: Option<String>,
+
+    #[arg(
+        long,
+        env = "LEADER_ELECTION_LEASE_DURATION_SECONDS",
+        default_value_t = DEFAULT_LEASE_DURATION_SECONDS,
+        value_parser = clap::value_parser!(u64).range(3..),
+    )]
+    pub leader_election_lease_duration_seconds: u64,
+
+    /// Identity of this replica in the lease; defaults to the hostname
+    #[arg(long, env = "POD_NAME")]
+    pub pod_name: Option<String>,
+
+    /// Serves request counts and latencies on `/metrics`; unset disables the endpoint
+    #[arg(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<String>,
+
+    /// Serve gRPC over TLS with this certificate chain; requires `TLS_KEY_FILE`
+    #[arg(long, env = "TLS_CERT_FILE")]
+    pub tls_cert_file: Option<PathBuf>,
+
+    #[arg(long, env = "TLS_KEY_FILE")]
+    pub tls_key_file: Option<PathBuf>,
+
+    /// Audience of the service account tokens callers authenticate with
+    #[arg(long, env = "TOKEN_AUDIENCE", default_value = DEFAULT_TOKEN_AUDIENCE)]
+    pub token_audience: String,
+
+    /// `<namespace>/<name>` service accounts of the CSI driver; with any set, every caller must authenticate
+    #[arg(long, env = "NODE_SERVICE_ACCOUNTS", value_delimiter = ',')]
+    pub node_service_accounts: Vec<String>,
+
+    /// `<namespace>/<name>` service accounts allowed to list every certificate
+    #[arg(long, env = "ADMIN_SERVICE_ACCOUNTS", value_delimiter = ',')]
+    pub admin_service_accounts: Vec<String>,
+
+    #[command(flatten)]
+    pub kube_api: KubeApiConfig,
+
+    #[command(flatten)]
+    pub settings: ServiceSettings,
+}
+
+impl ServiceConfig {
+    pub fn listen_addr(&self) -> String {
+        match non_empty(&self.listen_addr) {
+            Some(addr) => addr.to_string(),
+            None if self.dev_mode => "127.0.0.1:50051".to_string(),
+            None => "0.0.0.0:50051".to_string(),
+        }
+    }
+
+    pub fn namespace_ca_secrets(&self) -> Result<BTreeMap<String, String>> {
+        parse_namespace_ca_secrets(&self.namespace_ca_secrets)
+    }
+
+    pub fn leader_election_namespace(&self) -> String {
+        non_empty(&self.leader_election_namespace).unwrap_or(&self.ca_secret_namespace).to_string()
+    }
+
+    /// User names of the `NODE_SERVICE_ACCOUNTS`
+    pub fn node_service_accounts(&self) -> Result<Vec<String>> {
+        service_account_usernames(&self.node_service_accounts)
+    }
+
+    /// User names of the `ADMIN_SERVICE_ACCOUNTS`
+    pub fn admin_service_accounts(&self) -> Result<Vec<String>> {
+        service_account_usernames(&self.admin_service_accounts)
+    }
+
+    /// Whether settings that are only read at startup differ from `previous`
+    ///
+    /// The log level and the issuance settings are applied to the running service.
+    pub fn needs_restart(&self, previous: &Self) -> bool {
+        let mut reloaded = previous.clone();
+        reloaded.config.clone_from(&self.config);
+        reloaded.log_level.clone_from(&self.log_level);
+        reloaded.settings.clone_from(&self.settings);
+        reloaded != *self || self.settings.needs_restart(&previous.settings)
+    }
+
+    pub fn pod_name(&self) -> String {
+        match non_empty(&self.pod_name) {
+            Some(name) => name.to_string(),
+            None => hostname::get()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Treat empty values as unset and check settings that only make sense together
+    pub fn validate(&mut self) -> Result<()> {
+        for value in [&mut self.dev_ca_dir, &mut self.ca_key_passphrase_secret, &mut self.metrics_addr] {
+            if value.as_deref() == Some("") {
+                *value = None;
+            }
+        }
+        for path in [&mut self.tls_cert_file, &mut self.tls_key_file] {
+            if path.as_ref().is_some_and(|path| path.as_os_str().is_empty()) {
+                *path = None;
+            }
+        }
+        self.namespace_ca_secrets.retain(|entry| !entry.trim().is_empty());
+        self.node_service_accounts.retain(|entry| !entry.trim().is_empty());
+        self.node_service_accounts()?;
+        self.admin_service_accounts.retain(|entry| !entry.trim().is_empty());
+        self.admin_service_accounts()?;
+        self.settings.default_configmap_namespace(&self.ca_secret_namespace);
+        env_filter(self.log_level.as_deref())?;
+        self.namespace_ca_secrets()?;
+
+        self.listen_addr().parse::<std::net::SocketAddr>()
+            .context(format!("Invalid LISTEN_ADDR '{}'", self.listen_addr()))?;
+        if let Some(addr) = &self.metrics_addr {
+            addr.parse::<std::net::SocketAddr>().context(format!("Invalid METRICS_ADDR '{}'", addr))?;
+        }
+
+        if self.tls_cert_file.is_some() != self.tls_key_file.is_some() {
+            anyhow::bail!("TLS_CERT_FILE and TLS_KEY_FILE must be set together");
+        }
+        // Intermediate CAs need control of the CA key; remote CAs only sign leaf certificates
+        if self.settings.node_intermediates && !matches!(self.signer_backend.as_str(), "local" | "kms") {
+            anyhow::bail!("NODE_INTERMEDIATES requires SIGNER_BACKEND=local or kms");
+        }
+        // An intermediate CA key only goes to an authenticated node driver, over TLS
+        if self.settings.node_intermediates
+            && (self.dev_mode || self.node_service_accounts.is_empty() || self.tls_cert_file.is_none())
+        {
+            anyhow::bail!("NODE_INTERMEDIATES requires NODE_SERVICE_ACCOUNTS and TLS_CERT_FILE, and cannot be combined with DEV_MODE");
+        }
+        if self.dev_mode
+            && (self.signer_backend != "local" || self.settings.policy_configmap.is_some() || self.settings.profiles_configmap.is_some())
+        {
+            anyhow::bail!("DEV_MODE requires SIGNER_BACKEND=local and cannot load POLICY_CONFIGMAP or PROFILES_CONFIGMAP");
+        }
+        if !self.namespace_ca_secrets.is_empty() && (self.dev_mode || self.signer_backend != "local") {
+            anyhow::bail!("NAMESPACE_CA_SECRETS requires SIGNER_BACKEND=local and cannot be combined with DEV_MODE");
+        }
+        if self.dev_mode && (self.leader_election || self.settings.tenants_configmap.is_some()) {
+            anyhow::bail!("DEV_MODE cannot be combined with LEADER_ELECTION or TENANTS_CONFIGMAP, which need Kubernetes");
+        }
+        // The tenant of a request follows the namespace its authenticated caller may call for
+        if self.settings.tenants_configmap.is_some() && self.node_service_accounts.is_empty() {
+            anyhow::bail!("TENANTS_CONFIGMAP requires NODE_SERVICE_ACCOUNTS, so callers are authenticated");
+        }
+        // Administrators are told apart by the same token reviews as node drivers
+        if !self.admin_service_accounts.is_empty() && self.node_service_accounts.is_empty() {
+            anyhow::bail!("ADMIN_SERVICE_ACCOUNTS requires NODE_SERVICE_ACCOUNTS, so callers are authenticated");
+        }
+        // A leader keeping the inventory in memory could not renew what the previous one issued
+        if self.leader_election && self.settings.database_url.is_none() {
+            anyhow::bail!("LEADER_ELECTION requires DATABASE_URL, so a new leader finds the certificates the previous one issued");
+        }
+        if self.leader_election && self.pod_name().is_empty() {
+            anyhow::bail!("LEADER_ELECTION requires POD_NAME to identify this replica");
+        }
+
+        self.settings.validate()
+    }
+}
+
+/// Parse `<namespace>=<secret>` entries of NAMESPACE_CA_SECRETS
+fn parse_namespace_ca_secrets(entries: &[String]) -> Result<BTreeMap<String, String>> {
+    let mut secrets = BTreeMap::new();
+    for entry in entries {
+        let Some((namespace, secret)) = entry
+            .split_once('=')
+            .map(|(namespace, secret)| (namespace.trim(), secret.trim()))
+            .filter(|(namespace, secret)| !namespace.is_empty() && !secret.is_empty())
+        else {
+            anyhow::bail!("Invalid NAMESPACE_CA_SECRETS entry '{}', expected <namespace>=<secret>", entry);
+        };
+        if secrets.insert(namespace.to_string(), secret.to_string()).is_some() {
+            anyhow::bail!("NAMESPACE_CA_SECRETS gives namespace {} more than one CA", namespace);
+        }
+    }
+    Ok(secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_config_file_is_overridden_by_flags() {
+        let dir = TempDir::new("config").unwrap();
+        let args = |extra: &[&str]| {
+            let mut args = vec![OsString::from("csi-driver")];
+            args.extend(extra.iter().map(OsString::from));
+            args
+        };
+
+        let yaml = dir.join("driver.yaml");
+        std::fs::write(
+            &yaml,
+            "driver_mode: all-in-one\ncert-check-interval: 60\nmetadata_labels: [app, team]\nencrypt_keys: false\nnamespace_ca_secrets: [tenant-a=tenant-a-ca]\n",
+        ).unwrap();
+        let config: DriverConfig = load_from(
+            args(&["--cert-check-interval", "30", "--denied-namespaces=kube-system,kube-public"]),
+            Some(yaml.clone().into()),
+        ).unwrap();
+        assert_eq!(config.driver_mode, "all-in-one");
+        assert_eq!(config.cert_check_interval, 30);
+        assert_eq!(config.metadata_labels, ["app", "team"]);
+        assert_eq!(config.service.denied_namespaces, "kube-system,kube-public");
+        assert_eq!(config.renewal_concurrency, 8);
+        assert_eq!(config.namespace_ca_secrets().unwrap(), BTreeMap::from([("tenant-a".to_string(), "tenant-a-ca".to_string())]));
+        assert!(parse_namespace_ca_secrets(&["tenant-a".to_string()]).is_err());
+
+
+        let toml = dir.join("service.toml");
+        std::fs::write(&toml, "signer_backend = \"kms\"\nnode_intermediates = true\nmax_validity_days = 30\n").unwrap();
+        let config: ServiceConfig = load_from(args(&["--config", toml.to_str().unwrap()]), None).unwrap();
+        assert_eq!(config.signer_backend, "kms");
+        assert!(config.settings.node_intermediates);
+        assert_eq!(config.settings.max_validity_days, Some(30));
+
+        std::fs::write(&yaml, "cert_chek_interval: 60\n").unwrap();
+        let error = load_from::<DriverConfig, _>(args(&[]), Some(yaml.clone().into())).unwrap_err();
+        assert!(error.to_string().contains("Unknown setting 'cert_chek_interval'"), "{}", error);
+    }
+
+    #[test]
+    fn test_driver_config_needs_restart() {
+        let config: DriverConfig = load_from([OsString::from("csi-driver")], None).unwrap();
+        let changed = |change: &dyn Fn(&mut DriverConfig)| {
+            let mut reloaded = config.clone();
+            change(&mut reloaded);
+            reloaded.needs_restart(&config)
+        };
+
+        assert!(!changed(&|_| {}));
+        // Applied to the running driver
+        assert!(!changed(&|c| c.log_level = Some("debug".to_string())));
+        assert!(!changed(&|c| c.cert_check_interval = 10));
+        assert!(!changed(&|c| c.renewal_concurrency = 1));
+        assert!(!changed(&|c| c.renewal_threshold_percent = 50));
+        assert!(!changed(&|c| c.renewal_jitter_percent = 0));
+        assert!(!changed(&|c| c.revocation_check_interval = 60));
+        assert!(!changed(&|c| c.revocation_action = RevocationAction::Remove));
+        assert!(!changed(&|c| c.service.max_validity_days = Some(7)));
+        assert!(!changed(&|c| c.service.node_intermediates = true));
+        // Read once at startup
+        assert!(changed(&|c| c.cluster_domain = "example.com".to_string()));
+        assert!(changed(&|c| c.cert_base_path = Some("/tmp/certs".to_string())));
+        assert!(changed(&|c| c.ca_secret_name = "other-ca".to_string()));
+        assert!(changed(&|c| c.encrypt_keys = true));
+        assert!(changed(&|c| c.metadata_labels = vec!["app".to_string()]));
+        assert!(changed(&|c| c.kube_api.kube_api_qps += 1));
+
+        // The embedded service only reloads what a running service can
+        let all_in_one = DriverConfig { driver_mode: "all-in-one".to_string(), ..config.clone() };
+        let embedded = |change: &dyn Fn(&mut ServiceSettings)| {
+            let mut reloaded = all_in_one.clone();
+            change(&mut reloaded.service);
+            reloaded.needs_restart(&all_in_one)
+        };
+        assert!(!embedded(&|s| s.max_validity_days = Some(7)));
+        assert!(!embedded(&|s| s.denied_namespaces = "kube-system".to_string()));
+        assert!(embedded(&|s| s.node_intermediates = true));
+    }
+
+    #[test]
+    fn test_service_config_needs_restart() {
+        let config: ServiceConfig = load_from([OsString::from("cacsi-service")], None).unwrap();
+        let changed = |change: &dyn Fn(&mut ServiceConfig)| {
+            let mut reloaded = config.clone();
+            change(&mut reloaded);
+            reloaded.needs_restart(&config)
+        };
+
+        assert!(!changed(&|c| c.log_level = Some("debug".to_string())));
+        assert!(!changed(&|c| c.settings.max_validity_days = Some(7)));
+        assert!(!changed(&|c| c.settings.crl_urls = "http://crl.example.com/ca.crl".to_string()));
+        assert!(changed(&|c| c.settings.node_intermediates = true));
+        assert!(changed(&|c| c.signer_backend = "kms".to_string()));
+        assert!(changed(&|c| c.listen_addr = Some("127.0.0.1:50052".to_string())));
+    }
+
+    #[test]
+    fn test_leader_election_requires_a_shared_store() {
+        let mut config: ServiceConfig = load_from([OsString::from("cacsi-service")], None).unwrap();
+        config.leader_election = true;
+        config.pod_name = Some("cacsi-service-0".to_string());
+
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("DATABASE_URL"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_watch_reloads_a_changed_file() {
+        let dir = TempDir::new("config-watch").unwrap();
+        let path = dir.join("driver.yaml");
+        std::fs::write(&path, "cert_check_interval: 60\n").unwrap();
+
+        let (reloaded, mut reloads) = tokio::sync::mpsc::unbounded_channel();
+        let args = vec![OsString::from("csi-driver"), OsString::from("--config"), path.clone().into()];
+        let watcher = tokio::spawn(watch_from(args, None, Duration::from_millis(20), move |config: DriverConfig| {
+            reloaded.send(config.cert_check_interval).unwrap();
+            Ok(())
+        }));
+
+        // An unchanged file is not reloaded, an invalid one is skipped
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(reloads.try_recv().is_err());
+        std::fs::write(&path, "cert_check_interval: soon\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(reloads.try_recv().is_err());
+
+        std::fs::write(&path, "cert_check_interval: 30\n").unwrap();
+        let interval = tokio::time::timeout(Duration::from_secs(5), reloads.recv()).await.unwrap();
+        assert_eq!(interval, Some(30));
+
+        watcher.abort();
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// Prefix used by cert-manager's csi-driver for its volume attributes
+const CERT_MANAGER_PREFIX: &str = "csi.cert-manager.io/";
+
+/// cert-manager csi-driver attribute names and the native attribute they map to
+const CERT_MANAGER_ALIASES: &[(&str, &str)] = &[
+    ("common-name", "cn_template"),
+    ("dns-names", "dns_names"),
+    ("uri-sans", "uris"),
+    ("duration", "validity"),
+    ("key-usages", "key_usages"),
+    ("fs-group", "fs_group"),
+];
+
+/// cert-manager csi-driver template variables and their TemplateParser equivalents
+const CERT_MANAGER_VARIABLES: &[(&str, &str)] = &[
+    ("POD_NAME", "{metadata.name}"),
+    ("POD_NAMESPACE", "{metadata.namespace}"),
+    ("POD_UID", "{metadata.uid}"),
+    ("SERVICE_ACCOUNT_NAME", "{spec.serviceAccountName}"),
+];
+
+/// cert-manager key usage names that are extended key usages, and their native names
+const CERT_MANAGER_EXTENDED_KEY_USAGES: &[(&str, &str)] = &[
+    ("server auth", "server_auth"),
+    ("client auth", "client_auth"),
+    ("code signing", "code_signing"),
+    ("email protection", "email_protection"),
+];
+
+/// Translate `csi.cert-manager.io/*` volume attributes into this driver's native names
+///
+/// Values are converted where the formats differ (template variables, Go durations),
+/// so pod specs written for cert-manager's csi-driver work after only changing the
+/// driver name. Setting both an alias and its native attribute is rejected.
+pub fn normalize_volume_context(
+    volume_context: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, String> {
+    let mut normalized = HashMap::new();
+
+    // Native attributes and kubelet-provided keys pass through unchanged
+    for (key, value) in volume_context {
+        if !key.starts_with(CERT_MANAGER_PREFIX) {
+            normalized.insert(key.clone(), value.clone());
+        }
+    }
+
+    for (key, value) in volume_context {
+        let Some(name) = key.strip_prefix(CERT_MANAGER_PREFIX) else {
+            continue;
+        };
+
+        let Some((_, native)) = CERT_MANAGER_ALIASES.iter().find(|(alias, _)| *alias == name) else {
+            warn!("Ignoring unsupported cert-manager attribute: {}", key);
+            continue;
+        };
+
+        let translated = match *native {
+            "cn_template" | "dns_names" | "uris" => translate_variables(value),
+            "validity" => {
+                parse_go_duration(value)
+                    .ok_or_else(|| format!("{} must be a duration like 2160h, got '{}'", key, value))?;
+                value.trim().to_string()
+            }
+            "key_usages" => {
+                // cert-manager lists key usages and EKUs together; split off the EKUs
+                let (key_usages, extended_key_usages) = translate_key_usages(value);
+                if !extended_key_usages.is_empty() {
+                    if normalized.contains_key("extended_key_usages") {
+                        return Err(format!("{} conflicts with extended_key_usages; set only one of them", key));
+                    }
+                    normalized.insert("extended_key_usages".to_string(), extended_key_usages);
+                }
+                key_usages
+            }
+            _ => value.clone(),
+        };
+
+        if normalized.contains_key(*native) {
+            return Err(format!("{} conflicts with {}; set only one of them", key, native));
+        }
+
+        debug!("Mapped {}={} to {}={}", key, value, native, translated);
+        normalized.insert(native.to_string(), translated);
+    }
+
+    Ok(normalized)
+}
+
+/// Rewrite `${POD_NAME}`-style variables into `{metadata.name}`-style placeholders
+fn translate_variables(value: &str) -> String {
+    let mut result = value.to_string();
+    for (variable, placeholder) in CERT_MANAGER_VARIABLES {
+        result = result.replace(&format!("${{{}}}", variable), placeholder);
+        result = result.replace(&format!("${}", variable), placeholder);
+    }
+    result
+}
+
+/// Split cert-manager key usages (e.g. `digital signature, server auth`) into native
+/// `key_usages` and `extended_key_usages` values
+///
+/// Names the driver does not support are passed through and rejected by the certificate service.
+fn translate_key_usages(value: &str) -> (String, String) {
+    let mut key_usages = Vec::new();
+    let mut extended_key_usages = Vec::new();
+
+    for usage in value.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+        let usage = usage.to_lowercase();
+        if let Some((_, native)) = CERT_MANAGER_EXTENDED_KEY_USAGES.iter().find(|(name, _)| *name == usage) {
+            extended_key_usages.push(native.to_string());
+        } else if usage == "signing" {
+            key_usages.push("digital_signature".to_string());
+        } else {
+            key_usages.push(usage.replace(' ', "_"));
+        }
+    }
+
+    (key_usages.join(","), extended_key_usages.join(","))
+}
+
+/// Parse the `validity` volume attribute into seconds
+///
+/// Accepts a number of days (`7d`) or a Go duration (`30m`, `12h`, `1h30m`).
+pub fn parse_validity(value: &str) -> Option<i64> {
+    match value.trim().strip_suffix('d') {
+        Some(days) => days.parse::<i64>().ok().filter(|d| *d > 0)?.checked_mul(86400),
+        None => parse_go_duration(value),
+    }
+}
+
+/// Parse a Go `time.Duration` string (e.g. `2160h`, `1h30m`, `1.5h`, `500ms`) into seconds
+///
+/// Accepts the units of `time.ParseDuration` (`ns`, `us`, `µs`, `ms`, `s`, `m`, `h`) with
+/// decimal fractions. Sub-second remainders round up to a whole second.
+fn parse_go_duration(value: &str) -> Option<i64> {
+    const UNITS: [(&str, i128); 8] = [
+        ("ns", 1),
+        ("us", 1_000),
+        ("µs", 1_000),
+        ("μs", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+        ("m", 60_000_000_000),
+        ("h", 3_600_000_000_000),
+    ];
+
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut nanos = 0i128;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let unit_len = tail.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        // A number without a unit is not a valid Go duration
+        let (_, scale) = UNITS.iter().find(|(name, _)| *name == unit)?;
+
+        let whole: i128 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        let mut amount = whole.checked_mul(*scale)?;
+        if !fraction.is_empty() {
+            let digits: i128 = fraction.parse().ok()?;
+            let divisor = 10i128.checked_pow(u32::try_from(fraction.len()).ok()?)?;
+            amount = amount.checked_add(digits.checked_mul(*scale)? / divisor)?;
+        }
+        nanos = nanos.checked_add(amount)?;
+        rest = tail;
+    }
+
+    if nanos <= 0 {
+        return None;
+    }
+
+    i64::try_from((nanos + 999_999_999) / 1_000_000_000).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_cert_manager_aliases_are_translated() {
+        let normalized = normalize_volume_context(&context(&[
+            ("csi.cert-manager.io/common-name", "${SERVICE_ACCOUNT_NAME}.${POD_NAMESPACE}"),
+            ("csi.cert-manager.io/dns-names", "${POD_NAME}.${POD_NAMESPACE}.svc.cluster.local"),
+            ("csi.cert-manager.io/duration", "36h"),
+            ("csi.cert-manager.io/fs-group", "2000"),
+            ("csi.cert-manager.io/key-usages", "signing, key encipherment, client auth"),
+            ("csi.storage.k8s.io/pod.name", "web"),
+        ])).unwrap();
+
+        assert_eq!(normalized["cn_template"], "{spec.serviceAccountName}.{metadata.namespace}");
+        assert_eq!(normalized["dns_names"], "{metadata.name}.{metadata.namespace}.svc.cluster.local");
+        assert_eq!(normalized["validity"], "36h");
+        assert_eq!(normalized["fs_group"], "2000");
+        assert_eq!(normalized["key_usages"], "digital_signature,key_encipherment");
+        assert_eq!(normalized["extended_key_usages"], "client_auth");
+        assert_eq!(normalized["csi.storage.k8s.io/pod.name"], "web");
+    }
+
+    #[test]
+    fn test_alias_conflicting_with_native_attribute() {
+        let result = normalize_volume_context(&context(&[
+            ("csi.cert-manager.io/common-name", "a"),
+            ("cn_template", "b"),
+        ]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_go_duration() {
+        assert_eq!(parse_go_duration("2160h"), Some(2160 * 3600));
+        assert_eq!(parse_go_duration("1h30m"), Some(5400));
+        assert_eq!(parse_go_duration("90s"), Some(90));
+        assert_eq!(parse_go_duration("24"), None);
+        assert_eq!(parse_go_duration("1d"), None);
+        assert_eq!(parse_go_duration("1.5h"), Some(5400));
+        assert_eq!(parse_go_duration(".5m"), Some(30));
+        assert_eq!(parse_go_duration("1h0.5s"), Some(3601));
+        assert_eq!(parse_go_duration("1500ms"), Some(2));
+        assert_eq!(parse_go_duration("1us"), Some(1));
+        assert_eq!(parse_go_duration("1µs"), Some(1));
+        assert_eq!(parse_go_duration("100ns"), Some(1));
+        assert_eq!(parse_go_duration("0s"), None);
+        assert_eq!(parse_go_duration("."), None);
+        assert_eq!(parse_go_duration("1.5"), None);
+        assert_eq!(parse_go_duration("h"), None);
+
+        assert_eq!(parse_validity("7d"), Some(7 * 86400));
+        assert_eq!(parse_validity("30m"), Some(1800));
+        assert_eq!(parse_validity("0d"), None);
+    }
+}
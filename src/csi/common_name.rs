@@ -0,0 +1,99 @@
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+use crate::cert_service::validation::MAX_COMMON_NAME_LENGTH;
+
+/// What to do with a common name longer than [`MAX_COMMON_NAME_LENGTH`] characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongCnStrategy {
+    /// Fail the mount
+    #[default]
+    Reject,
+    /// Cut the name to the maximum length
+    Truncate,
+    /// Cut the name and append a hash of the full name, so truncated names stay distinct
+    Hash,
+    /// Move the full name to a DNS SAN and use its first label as the common name
+    San,
+}
+
+impl FromStr for LongCnStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "truncate" => Ok(Self::Truncate),
+            "hash" => Ok(Self::Hash),
+            "san" => Ok(Self::San),
+            other => Err(format!(
+                "Invalid long_cn_strategy '{}' (expected reject, truncate, hash or san)", other
+            )),
+        }
+    }
+}
+
+impl LongCnStrategy {
+    /// Shorten `common_name` if it is too long, adding it to `dns_names` for [`LongCnStrategy::San`]
+    pub fn apply(&self, common_name: String, dns_names: &mut Vec<String>) -> Result<String, String> {
+        let length = common_name.chars().count();
+        if length <= MAX_COMMON_NAME_LENGTH {
+            return Ok(common_name);
+        }
+
+        match self {
+            LongCnStrategy::Reject => Err(format!(
+                "Common name '{}' is {} characters long, X.509 allows at most {}; \
+                 set long_cn_strategy to truncate, hash or san, or use a shorter cn_template",
+                common_name, length, MAX_COMMON_NAME_LENGTH
+            )),
+            LongCnStrategy::Truncate => Ok(truncate(&common_name, MAX_COMMON_NAME_LENGTH)),
+            LongCnStrategy::Hash => {
+                let digest = Sha256::digest(common_name.as_bytes());
+                let suffix: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+                let prefix = truncate(&common_name, MAX_COMMON_NAME_LENGTH - suffix.len() - 1);
+                Ok(format!("{}-{}", prefix, suffix))
+            }
+            LongCnStrategy::San => {
+                let first_label = common_name.split('.').next().unwrap_or_default();
+                let short_name = truncate(first_label, MAX_COMMON_NAME_LENGTH);
+                if !dns_names.contains(&common_name) {
+                    dns_names.push(common_name);
+                }
+                Ok(short_name)
+            }
+        }
+    }
+}
+
+/// The first `max` characters of `value`, without a trailing dot or hyphen
+fn truncate(value: &str, max: usize) -> String {
+    let truncated: String = value.chars().take(max).collect();
+    truncated.trim_end_matches(['.', '-']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_common_names() {
+        let long = format!("{}.payments-production.svc.cluster.local", "checkout-api-7d9f8b6c5d-x2x4q");
+        assert!(long.len() > MAX_COMMON_NAME_LENGTH);
+
+        let mut dns_names = vec![];
+        assert!(LongCnStrategy::Reject.apply(long.clone(), &mut dns_names).is_err());
+        assert_eq!(LongCnStrategy::Reject.apply("web".to_string(), &mut dns_names).unwrap(), "web");
+
+        let truncated = LongCnStrategy::Truncate.apply(long.clone(), &mut dns_names).unwrap();
+        assert!(long.starts_with(&truncated) && truncated.len() <= MAX_COMMON_NAME_LENGTH);
+
+        let hashed = LongCnStrategy::Hash.apply(long.clone(), &mut dns_names).unwrap();
+        assert!(hashed.len() <= MAX_COMMON_NAME_LENGTH);
+        assert_ne!(hashed, LongCnStrategy::Hash.apply(format!("{}x", long), &mut dns_names).unwrap());
+
+        let short = LongCnStrategy::San.apply(long.clone(), &mut dns_names).unwrap();
+        assert_eq!(short, "checkout-api-7d9f8b6c5d-x2x4q");
+        assert_eq!(dns_names, [long]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+use tracing::{info, debug};
+
+use crate::proto::csi::{
+    controller_server::Controller,
+    CreateVolumeRequest, CreateVolumeResponse,
+    DeleteVolumeRequest, DeleteVolumeResponse,
+    ValidateVolumeCapabilitiesRequest, ValidateVolumeCapabilitiesResponse,
+    ControllerGetCapabilitiesRequest, ControllerGetCapabilitiesResponse,
+    ControllerServiceCapability, controller_service_capability::{self, rpc},
+    validate_volume_capabilities_response::Confirmed,
+    Volume, VolumeCapability,
+};
+use super::attributes::{normalize_volume_context, parse_validity};
+use super::node::validate_volume_capability;
+
+/// Controller service for certificate volumes provisioned from a StorageClass
+///
+/// Nothing is allocated up front: the StorageClass parameters become the volume
+/// context, and each pod mounting the volume gets its own certificate when the
+/// node driver publishes it, exactly as for inline ephemeral volumes.
+pub struct ControllerService {}
+
+impl ControllerService {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+/// Check the StorageClass parameters the same way the node driver will read them
+fn validate_parameters(parameters: &HashMap<String, String>) -> Result<(), Status> {
+    let normalized = normalize_volume_context(parameters).map_err(Status::invalid_argument)?;
+
+    if let Some(validity) = normalized.get("validity") {
+        if normalized.contains_key("validity_days") {
+            return Err(Status::invalid_argument("Set only one of validity and validity_days"));
+        }
+        if parse_validity(validity).is_none() {
+            return Err(Status::invalid_argument(format!("validity must be a duration like 30m, 12h or 7d, got '{}'", validity)));
+        }
+    }
+
+    if let Some(days) = normalized.get("validity_days") {
+        match days.parse::<i64>() {
+            Ok(d) if d > 0 => {}
+            _ => return Err(Status::invalid_argument(format!("validity_days must be a positive integer, got '{}'", days))),
+        }
+    }
+
+    if let Some(gid) = normalized.get("fs_group") {
+        gid.parse::<u32>().map_err(|_| {
+            Status::invalid_argument(format!("fs_group must be a numeric group id, got '{}'", gid))
+        })?;
+    }
+
+    if let Some(strategy) = normalized.get("reload_strategy") {
+        strategy.parse::<crate::reload::ReloadStrategy>().map_err(Status::invalid_argument)?;
+    }
+
+    if let Some(layout) = normalized.get("layout") {
+        layout.parse::<crate::layout::Layout>().map_err(Status::invalid_argument)?;
+    }
+
+    if let Some(encoding) = normalized.get("encoding") {
+        let encoding = encoding.parse::<crate::encoding::Encoding>().map_err(Status::invalid_argument)?;
+        if encoding == crate::encoding::Encoding::Der && normalized.get("encrypt_key").is_some_and(|value| value == "true") {
+            return Err(Status::invalid_argument("encrypt_key cannot be combined with encoding der"));
+        }
+    }
+
+    if let Some(key_encoding) = normalized.get("key_encoding") {
+        let key_encoding = key_encoding.parse::<crate::encoding::KeyEncoding>().map_err(Status::invalid_argument)?;
+        if key_encoding != crate::encoding::KeyEncoding::Pkcs8 && normalized.get("encrypt_key").is_some_and(|value| value == "true") {
+            return Err(Status::invalid_argument("encrypt_key requires key_encoding pkcs8"));
+        }
+    }
+
+    if let Some(mode) = normalized.get("mode") {
+        let mode = mode.parse::<crate::csi::mode::VolumeMode>().map_err(Status::invalid_argument)?;
+        if mode == crate::csi::mode::VolumeMode::Dual && normalized.contains_key("extended_key_usages") {
+            return Err(Status::invalid_argument("extended_key_usages cannot be combined with mode dual"));
+        }
+    }
+
+    if let Some(uid) = normalized.get("layout_owner") {
+        uid.parse::<u32>().map_err(|_| {
+            Status::invalid_argument(format!("layout_owner must be a numeric user id, got '{}'", uid))
+        })?;
+    }
+
+    for flag in ["encrypt_key", "require_tmpfs", "pins", "truststore"] {
+        if let Some(value) = normalized.get(flag) {
+            value.parse::<bool>().map_err(|_| {
+                Status::invalid_argument(format!("{} must be true or false, got '{}'", flag, value))
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_capabilities(capabilities: &[VolumeCapability]) -> Result<(), Status> {
+    if capabilities.is_empty() {
+        return Err(Status::invalid_argument("Missing volume capabilities"));
+    }
+    for capability in capabilities {
+        validate_volume_capability(Some(capability))?;
+    }
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl Controller for ControllerService {
+    async fn create_volume(
+        &self,
+        request: Request<CreateVolumeRequest>,
+    ) -> Result<Response<CreateVolumeResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("CreateVolume called for: {}", req.name);
+        debug!("Parameters: {:?}", req.parameters);
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("Missing volume name"));
+        }
+        validate_capabilities(&req.volume_capabilities)?;
+        validate_parameters(&req.parameters)?;
+
+        // The volume holds a few small files; report the requested size so the PV matches the claim
+        let capacity_bytes = req.capacity_range
+            .map(|range| range.required_bytes)
+            .unwrap_or(0);
+
+        // The name is stable across retries, so using it as the ID keeps CreateVolume idempotent
+        let volume = Volume {
+            capacity_bytes,
+            volume_id: req.name,
+            volume_context: req.parameters,
+        };
+
+        info!("Volume created: {}", volume.volume_id);
+
+        Ok(Response::new(CreateVolumeResponse { volume: Some(volume) }))
+    }
+
+    async fn delete_volume(
+        &self,
+        request: Request<DeleteVolumeRequest>,
+    ) -> Result<Response<DeleteVolumeResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("Missing volume ID"));
+        }
+
+        // Certificates live on the nodes and are removed when the volume is unpublished
+        info!("Volume deleted: {}", req.volume_id);
+
+        Ok(Response::new(DeleteVolumeResponse {}))
+    }
+
+    async fn validate_volume_capabilities(
+        &self,
+        request: Request<ValidateVolumeCapabilitiesRequest>,
+    ) -> Result<Response<ValidateVolumeCapabilitiesResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("Missing volume ID"));
+        }
+
+        let response = match validate_capabilities(&req.volume_capabilities) {
+            Ok(()) => ValidateVolumeCapabilitiesResponse {
+                confirmed: Some(Confirmed {
+                    volume_context: req.volume_context,
+                    volume_capabilities: req.volume_capabilities,
+                    parameters: req.parameters,
+                }),
+                message: String::new(),
+            },
+            Err(status) if status.code() == tonic::Code::InvalidArgument && !req.volume_capabilities.is_empty() => {
+                ValidateVolumeCapabilitiesResponse {
+                    confirmed: None,
+                    message: status.message().to_string(),
+                }
+            }
+            Err(status) => return Err(status),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn controller_get_capabilities(
+        &self,
+        _request: Request<ControllerGetCapabilitiesRequest>,
+    ) -> Result<Response<ControllerGetCapabilitiesResponse>, Status> {
+        debug!("ControllerGetCapabilities called");
+
+        let capabilities = vec![ControllerServiceCapability {
+            r#type: Some(controller_service_capability::Type::Rpc(controller_service_capability::Rpc {
+                r#type: rpc::Type::CreateDeleteVolume as i32,
+            })),
+        }];
+
+        Ok(Response::new(ControllerGetCapabilitiesResponse { capabilities }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_parameters() {
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        assert!(validate_parameters(&params(&[("validity_days", "30"), ("reload_strategy", "sentinel")])).is_ok());
+        assert!(validate_parameters(&params(&[("validity_days", "0")])).is_err());
+        assert!(validate_parameters(&params(&[("layout", "postgres"), ("layout_owner", "70")])).is_ok());
+        assert!(validate_parameters(&params(&[("layout", "nginx")])).is_err());
+        assert!(validate_parameters(&params(&[("encoding", "der")])).is_ok());
+        assert!(validate_parameters(&params(&[("encoding", "der"), ("encrypt_key", "true")])).is_err());
+        assert!(validate_parameters(&params(&[("key_encoding", "sec1")])).is_ok());
+        assert!(validate_parameters(&params(&[("mode", "trust-only"), ("truststore", "true")])).is_ok());
+        assert!(validate_parameters(&params(&[("mode", "trust")])).is_err());
+        assert!(validate_parameters(&params(&[("mode", "dual"), ("extended_key_usages", "client_auth")])).is_err());
+        assert!(validate_parameters(&params(&[("key_encoding", "pkcs1"), ("encrypt_key", "true")])).is_err());
+        assert!(validate_parameters(&params(&[("validity", "12h")])).is_ok());
+        assert!(validate_parameters(&params(&[("validity", "12")])).is_err());
+        assert!(validate_parameters(&params(&[("validity", "12h"), ("validity_days", "1")])).is_err());
+        assert!(validate_parameters(&params(&[("fs_group", "staff")])).is_err());
+        assert!(validate_parameters(&params(&[("csi.cert-manager.io/duration", "1d")])).is_err());
+        assert!(validate_parameters(&params(&[("require_tmpfs", "yes")])).is_err());
+    }
+}
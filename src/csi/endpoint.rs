@@ -0,0 +1,154 @@
+use anyhow::{Result, Context};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio_stream::wrappers::UnixListenerStream;
+use tracing::info;
+
+/// Default mode of the CSI socket: kubelet and the sidecars run as root
+pub const DEFAULT_SOCKET_MODE: u32 = 0o660;
+
+/// Ownership and mode applied to the CSI socket, so only kubelet can talk to the driver
+#[derive(Clone, Copy, Debug)]
+pub struct SocketPermissions {
+    pub mode: u32,
+    /// Owner and group, unchanged if not set
+    pub owner: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl Default for SocketPermissions {
+    fn default() -> Self {
+        Self { mode: DEFAULT_SOCKET_MODE, owner: None }
+    }
+}
+
+impl SocketPermissions {
+    /// Parse an octal mode (`0660`) and an optional `<uid>:<gid>` owner, either side of which may be empty
+    pub fn parse(mode: Option<&str>, owner: Option<&str>) -> Result<Self> {
+        let mode = match mode {
+            Some(mode) => u32::from_str_radix(mode.trim().trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .ok_or_else(|| anyhow::anyhow!("Invalid socket mode '{}' (expected octal like 0660)", mode))?,
+            None => DEFAULT_SOCKET_MODE,
+        };
+
+        let owner = match owner {
+            Some(owner) => {
+                let (uid, gid) = owner.split_once(':').unwrap_or((owner, ""));
+                let id = |value: &str| -> Result<Option<u32>> {
+                    match value.trim() {
+                        "" => Ok(None),
+                        value => value.parse().map(Some).map_err(|_| {
+                            anyhow::anyhow!("Invalid socket owner '{}' (expected numeric <uid>:<gid>)", owner)
+                        }),
+                    }
+                };
+                Some((id(uid)?, id(gid)?))
+            }
+            None => None,
+        };
+
+        Ok(Self { mode, owner })
+    }
+}
+
+/// Bind the CSI socket (or the embedded certificate service's), replacing a socket left over from a previous run
+///
+/// A socket another driver instance still answers on is never replaced, and
+/// neither is anything that is not a socket. The socket is bound under a temporary
+/// name and renamed into place once its permissions are set, so it never accepts
+/// connections with the default permissions.
+pub fn bind_socket(endpoint: &str, permissions: &SocketPermissions) -> Result<UnixListenerStream> {
+    let socket_path = Path::new(endpoint.strip_prefix("unix://").unwrap_or(endpoint));
+
+    remove_stale_socket(socket_path)?;
+
+    // Create socket directory if it doesn't exist
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent).context(format!("Failed to create {}", parent.display()))?;
+    }
+
+    let tmp_path = socket_path.with_extension(format!("tmp-{}", std::process::id()));
+    let _ = std::fs::remove_file(&tmp_path);
+    let uds = tokio::net::UnixListener::bind(&tmp_path)
+        .context(format!("Failed to bind {}", tmp_path.display()))?;
+
+    let placed = (|| -> Result<()> {
+        if let Some((uid, gid)) = permissions.owner {
+            std::os::unix::fs::chown(&tmp_path, uid, gid)
+                .context(format!("Failed to change owner of {}", tmp_path.display()))?;
+        }
+        std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(permissions.mode))
+            .context(format!("Failed to set mode of {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, socket_path)
+            .context(format!("Failed to move socket to {}", socket_path.display()))
+    })();
+    if let Err(e) = placed {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    info!("Listening on {} (mode {:o})", socket_path.display(), permissions.mode);
+
+    Ok(UnixListenerStream::new(uds))
+}
+
+/// Remove the socket at `path` unless another process is still serving on it
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(format!("Failed to inspect {}", path.display())),
+    };
+
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!("{} exists and is not a socket; refusing to replace it", path.display());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        anyhow::bail!(
+            "Another driver instance is serving on {}; refusing to replace its socket",
+            path.display()
+        );
+    }
+
+    info!("Removing stale socket {}", path.display());
+    std::fs::remove_file(path).context(format!("Failed to remove stale socket {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_bind_socket_replaces_only_stale_sockets() {
+        let dir = TempDir::new("socket").unwrap();
+        let socket_path = dir.join("csi.sock");
+        let endpoint = format!("unix://{}", socket_path.display());
+        let permissions = SocketPermissions::parse(Some("0600"), None).unwrap();
+
+        let live = bind_socket(&endpoint, &permissions).unwrap();
+        let mode = std::fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let error = bind_socket(&endpoint, &permissions).unwrap_err().to_string();
+        assert!(error.contains("Another driver instance"), "{}", error);
+
+        // Once the listener is gone the socket is stale
+        drop(live);
+        let _rebound = bind_socket(&endpoint, &permissions).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&socket_path).unwrap();
+        assert!(bind_socket(&endpoint, &permissions).is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_permissions() {
+        let permissions = SocketPermissions::parse(Some("0640"), Some("0:1000")).unwrap();
+        assert_eq!(permissions.mode, 0o640);
+        assert_eq!(permissions.owner, Some((Some(0), Some(1000))));
+        assert_eq!(SocketPermissions::parse(None, Some(":1000")).unwrap().owner, Some((None, Some(1000))));
+        assert!(SocketPermissions::parse(Some("rw-rw----"), None).is_err());
+        assert!(SocketPermissions::parse(None, Some("kubelet")).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::proto::certservice::Extension;
+
+/// Shorthand extension values and the pod field they embed
+const SHORTHANDS: &[(&str, &str)] = &[
+    ("pod_uid", "{metadata.uid}"),
+    ("pod_name", "{metadata.name}"),
+    ("pod_namespace", "{metadata.namespace}"),
+    ("service_account", "{spec.serviceAccountName}"),
+];
+
+/// Parse the `extensions` volume attribute into custom extensions for the certificate service
+///
+/// Entries are comma-separated `<oid>=<value>` pairs, where the value is one of:
+/// - `base64:<DER>`: the extension value as given
+/// - `utf8:<text>`: the text as a DER UTF8String; templates are resolved with `resolve`
+/// - `pod_uid`, `pod_name`, `pod_namespace` or `service_account`: that pod field as a UTF8String
+///
+/// OIDs and DER values are validated by the certificate service.
+pub fn parse_extensions(
+    value: &str,
+    resolve: impl Fn(&str) -> Result<String, String>,
+) -> Result<Vec<Extension>, String> {
+    let mut extensions = Vec::new();
+
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (oid, value) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Extension '{}' must be <oid>=<value>", entry))?;
+        let (oid, value) = (oid.trim(), value.trim());
+
+        let der = if let Some(encoded) = value.strip_prefix("base64:") {
+            STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("Invalid base64 value for extension {}: {}", oid, e))?
+        } else {
+            let template = match value.strip_prefix("utf8:") {
+                Some(text) => text,
+                None => SHORTHANDS
+                    .iter()
+                    .find(|(name, _)| *name == value)
+                    .map(|(_, template)| *template)
+                    .ok_or_else(|| format!(
+                        "Unknown value '{}' for extension {} (expected base64:, utf8:, pod_uid, pod_name, pod_namespace or service_account)",
+                        value, oid
+                    ))?,
+            };
+            let text = resolve(template)?;
+            yasna::construct_der(|writer| writer.write_utf8_string(&text))
+        };
+
+        extensions.push(Extension { oid: oid.to_string(), value: der });
+    }
+
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_extensions() {
+        let resolve = |template: &str| Ok(template.replace("{metadata.uid}", "1234"));
+
+        let extensions = parse_extensions(
+            "1.3.6.1.4.1.99999.1=pod_uid, 1.3.6.1.4.1.99999.2=base64:DAJoaQ==, 1.3.6.1.4.1.99999.3=utf8:x-{metadata.uid}",
+            resolve,
+        ).unwrap();
+
+        assert_eq!(extensions.len(), 3);
+        assert_eq!(extensions[0].oid, "1.3.6.1.4.1.99999.1");
+        assert_eq!(extensions[0].value, [0x0c, 0x04, b'1', b'2', b'3', b'4']);
+        assert_eq!(extensions[1].value, [0x0c, 0x02, b'h', b'i']);
+        assert_eq!(extensions[2].value, [0x0c, 0x06, b'x', b'-', b'1', b'2', b'3', b'4']);
+
+        assert!(parse_extensions("1.2.3", resolve).is_err());
+        assert!(parse_extensions("1.2.3=pod_ip", resolve).is_err());
+    }
+}
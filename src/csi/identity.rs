@@ -1,19 +1,56 @@
 use tonic::{Request, Response, Status};
+use tracing::warn;
 use crate::proto::csi::{
     identity_server::Identity,
     GetPluginInfoRequest, GetPluginInfoResponse,
     GetPluginCapabilitiesRequest, GetPluginCapabilitiesResponse,
     ProbeRequest, ProbeResponse,
+    PluginCapability, plugin_capability,
 };
+use crate::ca_expiry::CaExpiryMonitor;
+use crate::cert_monitor::MonitorHeartbeat;
+use crate::retry::CircuitBreaker;
 
-const PLUGIN_NAME: &str = "csi.k8s.cacsi-driver";
+pub const PLUGIN_NAME: &str = "csi.k8s.cacsi-driver";
 const PLUGIN_VERSION: &str = "0.1.0";
 
-pub struct IdentityService {}
+pub struct IdentityService {
+    controller: bool,
+    ca_expiry: Option<CaExpiryMonitor>,
+    monitor: Option<MonitorHeartbeat>,
+    circuit_breaker: Option<CircuitBreaker>,
+}
 
 impl IdentityService {
     pub fn new() -> Self {
-        Self {}
+        Self { controller: false, ca_expiry: None, monitor: None, circuit_breaker: None }
+    }
+
+    /// Advertise the controller service, for instances serving CreateVolume/DeleteVolume
+    pub fn with_controller(mut self) -> Self {
+        self.controller = true;
+        self
+    }
+
+    /// Report not ready while the CA is about to expire
+    pub fn with_ca_expiry(mut self, ca_expiry: CaExpiryMonitor) -> Self {
+        self.ca_expiry = Some(ca_expiry);
+        self
+    }
+
+    /// Report not ready while the certificate monitor is stalled
+    pub fn with_monitor_heartbeat(mut self, heartbeat: MonitorHeartbeat) -> Self {
+        self.monitor = Some(heartbeat);
+        self
+    }
+
+    /// Warn while calls to the certificate service fail fast
+    ///
+    /// The driver stays ready: restarting it would not bring the service back, and
+    /// mounted certificates keep being served and retried.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
     }
 }
 
@@ -40,8 +77,15 @@ impl Identity for IdentityService {
     ) -> Result<Response<GetPluginCapabilitiesResponse>, Status> {
         tracing::debug!("GetPluginCapabilities called");
 
-        // This driver only supports node service (ephemeral volumes)
-        let capabilities = vec![];
+        // Ephemeral volumes only need the node service; persistent volumes also need the controller
+        let mut capabilities = vec![];
+        if self.controller {
+            capabilities.push(PluginCapability {
+                r#type: Some(plugin_capability::Type::Service(plugin_capability::Service {
+                    r#type: plugin_capability::service::Type::ControllerService as i32,
+                })),
+            });
+        }
 
         let response = GetPluginCapabilitiesResponse {
             capabilities,
@@ -56,8 +100,20 @@ impl Identity for IdentityService {
     ) -> Result<Response<ProbeResponse>, Status> {
         tracing::debug!("Probe called");
 
+        let degraded = self.ca_expiry.as_ref().map(CaExpiryMonitor::is_degraded).unwrap_or(false);
+        if degraded {
+            warn!("Probe: CA certificate is about to expire, reporting not ready");
+        }
+        let stalled = self.monitor.as_ref().map(MonitorHeartbeat::is_stalled).unwrap_or(false);
+        if stalled {
+            warn!("Probe: certificate monitor has stalled, reporting not ready");
+        }
+        if self.circuit_breaker.as_ref().is_some_and(CircuitBreaker::is_open) {
+            warn!("Probe: certificate service unreachable, calls are failing fast");
+        }
+
         let response = ProbeResponse {
-            ready: true,
+            ready: !degraded && !stalled,
         };
 
         Ok(Response::new(response))
@@ -1,2 +1,10 @@
+pub mod attributes;
+pub mod common_name;
+pub mod controller;
+pub mod endpoint;
+pub mod extensions;
 pub mod identity;
+pub mod mode;
 pub mod node;
+pub mod registration;
+pub mod san;
@@ -0,0 +1,51 @@
+use std::path::Path;
+use std::str::FromStr;
+
+/// Subdirectories of a `mode: dual` volume, with the extended key usage of the certificate in each
+pub const DUAL_CERTIFICATES: [(&str, &str); 2] = [("server", "server_auth"), ("client", "client_auth")];
+
+/// What a volume holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VolumeMode {
+    /// A certificate and key issued for the pod
+    #[default]
+    Certificate,
+    /// Only the CA certificates, for pods that verify peers but need no identity
+    TrustOnly,
+    /// A server certificate in `server/` and a client certificate in `client/`, for mTLS
+    /// setups that listen and dial with separate identities
+    Dual,
+}
+
+impl FromStr for VolumeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "certificate" => Ok(Self::Certificate),
+            "trust-only" => Ok(Self::TrustOnly),
+            "dual" => Ok(Self::Dual),
+            other => Err(format!("Invalid mode '{}' (expected certificate, trust-only or dual)", other)),
+        }
+    }
+}
+
+/// Whether a volume was published with `mode: dual`
+pub fn is_dual(mount_path: &str) -> bool {
+    DUAL_CERTIFICATES
+        .iter()
+        .all(|(directory, _)| Path::new(mount_path).join(directory).is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_volume_mode() {
+        assert_eq!("trust-only".parse::<VolumeMode>().unwrap(), VolumeMode::TrustOnly);
+        assert_eq!("dual".parse::<VolumeMode>().unwrap(), VolumeMode::Dual);
+        assert_eq!("certificate".parse::<VolumeMode>().unwrap(), VolumeMode::default());
+        assert!("trust".parse::<VolumeMode>().is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tonic::{Request, Response, Status};
-use tracing::{info, error, debug};
+use tracing::{info, error, debug, warn};
 
 use crate::proto::csi::{
     node_server::Node,
@@ -12,11 +15,49 @@ use crate::proto::csi::{
     NodeExpandVolumeRequest, NodeExpandVolumeResponse,
     NodeGetCapabilitiesRequest, NodeGetCapabilitiesResponse,
     NodeGetInfoRequest, NodeGetInfoResponse,
+    VolumeCapability, volume_capability::AccessType,
+    VolumeCondition, VolumeUsage, volume_usage::Unit,
+    NodeServiceCapability, node_service_capability::{self, rpc},
 };
 
-use crate::cert_manager::CertificateManager;
+use crate::proto::certservice::Subject;
+use crate::csi::common_name::LongCnStrategy;
+use crate::csi::san::{InvalidNameStrategy, SanValidator};
+use crate::csi::extensions::parse_extensions;
+use crate::csi::mode::{self, VolumeMode, DUAL_CERTIFICATES};
+use crate::cert_manager::{certificate_validity, CertificateManager};
+use crate::delegated_ca::DelegatedCa;
+use crate::local_signing::{is_outage, sign_on_node, LocalSigner, LocalSigningRequest};
+use crate::cert_validation::validate_issued_certificate;
+use crate::cert_binding::BindingRecorder;
 use crate::ca_manager::CaManager;
+use crate::events::EventRecorder;
+use crate::error_details::ErrorInfo;
+use crate::k8s_client::{PodCache, PodRef};
+use crate::pod_annotations::PodAnnotator;
+use crate::cert_service::profiles::KeyType;
+use crate::encoding::{self, Encoding, KeyEncoding};
+use crate::layout::Layout;
+use crate::reload::ReloadStrategy;
 use crate::template_parser::TemplateParser;
+use crate::trust_bundle::{self, CA_FILE};
+use super::attributes::{normalize_volume_context, parse_validity};
+
+/// Volume attributes that set subject DN attributes besides CN and OU
+/// First delay between issuance attempts while the certificate service is unavailable
+const PUBLISH_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between issuance attempts while the certificate service is unavailable
+const PUBLISH_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+const SUBJECT_ATTRIBUTES: &[&str] = &["country", "organization", "locality", "province", "serial_number"];
+
+/// Volume attributes whose values may contain templates
+fn template_attributes() -> impl Iterator<Item = &'static str> {
+    ["cn_template", "organizational_units", "dns_names", "uris", "extensions"]
+        .into_iter()
+        .chain(SUBJECT_ATTRIBUTES.iter().copied())
+}
 
 pub struct NodeService {
     node_id: String,
@@ -24,6 +65,26 @@ pub struct NodeService {
     ca_manager: CaManager,
     cluster_domain: String,
     template_parser: TemplateParser,
+    events: EventRecorder,
+    annotator: PodAnnotator,
+    /// Keeps a CertificateBinding per published volume
+    bindings: BindingRecorder,
+    /// Mount a tmpfs at the target paths of volumes that do not set `require_tmpfs`
+    require_tmpfs: bool,
+    /// Pod labels recorded in the certificate metadata
+    metadata_labels: Vec<String>,
+    /// Signs eligible volumes on the node while the certificate service is down
+    local_signer: Option<LocalSigner>,
+    /// Signs eligible volumes with the node intermediate CA instead of the certificate service
+    delegated_ca: Option<DelegatedCa>,
+    /// Take pod information from the volume context instead of the Kubernetes API (dev mode)
+    literal_pod_info: bool,
+    /// Pods on this node from the pod watch, saving a GET per published volume
+    pod_cache: Option<PodCache>,
+    /// How long NodePublishVolume keeps retrying while the certificate service is unavailable
+    publish_retry_deadline: Duration,
+    /// Name and labels of this node for `{node.*}` templates, fetched on first use
+    node_info: tokio::sync::OnceCell<HashMap<String, String>>,
 }
 
 impl NodeService {
@@ -32,6 +93,8 @@ impl NodeService {
         cert_manager: CertificateManager,
         ca_manager: CaManager,
         cluster_domain: String,
+        events: EventRecorder,
+        annotator: PodAnnotator,
     ) -> Self {
         Self {
             node_id,
@@ -39,9 +102,110 @@ impl NodeService {
             ca_manager,
             cluster_domain,
             template_parser: TemplateParser::default(),
+            events,
+            annotator,
+            bindings: BindingRecorder::new(false, String::new()),
+            require_tmpfs: false,
+            metadata_labels: Vec::new(),
+            local_signer: None,
+            delegated_ca: None,
+            literal_pod_info: false,
+            pod_cache: None,
+            publish_retry_deadline: Duration::ZERO,
+            node_info: tokio::sync::OnceCell::new(),
         }
     }
 
+    /// Refuse to publish keys onto persistent storage unless a volume opts out
+    pub fn with_require_tmpfs(mut self, require_tmpfs: bool) -> Self {
+        self.require_tmpfs = require_tmpfs;
+        self
+    }
+
+    /// Record these pod labels with every certificate, so the service can tell which workload owns it
+    pub fn with_metadata_labels(mut self, metadata_labels: Vec<String>) -> Self {
+        self.metadata_labels = metadata_labels;
+        self
+    }
+
+    /// Sign certificates on the node when the certificate service is unreachable
+    pub fn with_local_signer(mut self, local_signer: LocalSigner) -> Self {
+        self.local_signer = Some(local_signer);
+        self
+    }
+
+    /// Sign eligible certificates with the node's intermediate CA when the certificate service is unreachable
+    pub fn with_delegated_ca(mut self, delegated_ca: DelegatedCa) -> Self {
+        self.delegated_ca = Some(delegated_ca);
+        self
+    }
+
+    /// Keep retrying issuance for up to `deadline` while the certificate service is unavailable,
+    /// e.g. during its rollout, before failing the mount or signing on the node
+    pub fn with_publish_retry_deadline(mut self, deadline: Duration) -> Self {
+        self.publish_retry_deadline = deadline;
+        self
+    }
+
+    /// Create a CertificateBinding for each published volume, showing its certificate
+    pub fn with_bindings(mut self, bindings: BindingRecorder) -> Self {
+        self.bindings = bindings;
+        self
+    }
+
+    /// Read pod fields from `pod.metadata.*`/`pod.spec.*` volume attributes instead of the API server
+    pub fn with_literal_pod_info(mut self) -> Self {
+        self.literal_pod_info = true;
+        self
+    }
+
+    /// Read pod fields for templates from the pod watch's cache, falling back to the API server
+    pub fn with_pod_cache(mut self, pod_cache: PodCache) -> Self {
+        self.pod_cache = Some(pod_cache);
+        self
+    }
+
+    /// Pod fields for templates, from the cache when it has the pod
+    ///
+    /// A cached pod is only used when its UID matches the volume's, so a pod recreated
+    /// under the same name is never resolved with its predecessor's labels.
+    async fn pod_info(&self, pod: &PodRef) -> Result<(HashMap<String, String>, HashMap<String, String>), Status> {
+        let cached = self
+            .pod_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&pod.namespace, &pod.name))
+            .filter(|cached| pod.uid.is_none() || cached.metadata.uid == pod.uid);
+        if let Some(cached) = cached {
+            debug!("Using cached pod information for {}/{}", pod.namespace, pod.name);
+            return Ok(crate::k8s_client::pod_info(&cached));
+        }
+
+        let client = crate::k8s_client::get_client()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get Kubernetes client: {}", e)))?;
+
+        crate::k8s_client::get_pod_info(&client, &pod.namespace, &pod.name)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get pod info: {}", e)))
+    }
+
+    /// Name and labels of this node, fetched once from the Kubernetes API
+    ///
+    /// Node labels such as the zone rarely change while pods run, so they are kept for
+    /// the life of the driver.
+    async fn node_info(&self) -> Result<&HashMap<String, String>, Status> {
+        self.node_info
+            .get_or_try_init(|| async {
+                let client = crate::k8s_client::get_client()
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to get Kubernetes client: {}", e)))?;
+                crate::k8s_client::get_node_info(&client, &self.node_id)
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to get node info: {:#}", e)))
+            })
+            .await
+    }
+
     fn extract_pod_info(&self, volume_context: &HashMap<String, String>) -> Result<(String, String), Status> {
         let pod_namespace = volume_context
             .get("csi.storage.k8s.io/pod.namespace")
@@ -53,6 +217,135 @@ impl NodeService {
 
         Ok((pod_namespace.clone(), pod_name.clone()))
     }
+
+    /// Report whether the certificates at `volume_path` are usable
+    ///
+    /// The volume is abnormal when a mounted certificate has expired or its
+    /// renewal has been failing.
+    async fn volume_condition(&self, volume_path: &str) -> VolumeCondition {
+        if !mode::is_dual(volume_path) {
+            return self.certificate_condition(volume_path).await;
+        }
+
+        let mut messages = Vec::new();
+        for (directory, _) in DUAL_CERTIFICATES {
+            let condition = self.certificate_condition(&Path::new(volume_path).join(directory).to_string_lossy()).await;
+            let message = format!("{}: {}", directory, condition.message);
+            if condition.abnormal {
+                return VolumeCondition { abnormal: true, message };
+            }
+            messages.push(message);
+        }
+        VolumeCondition {
+            abnormal: false,
+            message: messages.join("; "),
+        }
+    }
+
+    /// Condition of the certificate, or the CA certificates of a trust-only volume, at `volume_path`
+    async fn certificate_condition(&self, volume_path: &str) -> VolumeCondition {
+        let abnormal = |message: String| VolumeCondition { abnormal: true, message };
+
+        // Trust-only volumes hold no certificate of their own, only the CA
+        let (certificate, cert_pem) = if trust_bundle::is_trust_only(volume_path) {
+            ("CA certificate", tokio::fs::read_to_string(Path::new(volume_path).join(CA_FILE)).await)
+        } else {
+            ("Certificate", encoding::read_certificate(volume_path).await)
+        };
+        let cert_pem = match cert_pem {
+            Ok(pem) => pem,
+            Err(e) => return abnormal(format!("{} is missing: {}", certificate, e)),
+        };
+
+        let not_after = match certificate_validity(&cert_pem) {
+            Ok((_, not_after)) => not_after,
+            Err(e) => return abnormal(format!("{} is unreadable: {}", certificate, e)),
+        };
+
+        let expiry = chrono::DateTime::from_timestamp(not_after, 0)
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        if chrono::Utc::now().timestamp() >= not_after {
+            return abnormal(format!("{} expired at {}", certificate, expiry));
+        }
+
+        if let Some(error) = self.cert_manager
+            .find_by_mount_path(volume_path)
+            .and_then(|info| info.last_renewal_error)
+        {
+            return abnormal(format!("Certificate renewal is failing (expires at {}): {}", expiry, error));
+        }
+
+        VolumeCondition {
+            abnormal: false,
+            message: format!("{} valid until {}", certificate, expiry),
+        }
+    }
+
+    /// Publish a `mode: trust-only` volume: the CA certificates of the pod's namespace,
+    /// and nothing issued
+    async fn publish_trust_bundle(
+        &self,
+        target_path: &str,
+        volume_context: &HashMap<String, String>,
+        pod: &PodRef,
+    ) -> Result<(), Status> {
+        // Extract truststore from volume attributes (default: false): a PKCS#12 truststore next to ca.crt
+        let truststore = match volume_context.get("truststore") {
+            Some(value) => value.parse::<bool>().map_err(|_| {
+                Status::invalid_argument(format!("truststore must be true or false, got '{}'", value))
+            })?,
+            None => false,
+        };
+
+        let ca_pem = self.ca_manager.for_namespace(&pod.namespace)
+            .await
+            .map_err(|e| Status::failed_precondition(format!("Failed to get the CA of namespace {}: {:#}", pod.namespace, e)))?
+            .get_ca_cert()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get CA certificate: {}", e)))?;
+
+        tokio::fs::create_dir_all(target_path)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create target path: {}", e)))?;
+        trust_bundle::write(target_path, &ca_pem, truststore)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to write CA certificates: {:#}", e)))?;
+
+        info!("CA certificates written to {}", target_path);
+        Ok(())
+    }
+
+    /// Whether `target_path` already holds a valid certificate published for `cert_id`
+    ///
+    /// Kubelet retries NodePublishVolume, and CSI requires repeated calls to succeed
+    /// without side effects, so a retry must not issue a new certificate and key.
+    async fn is_already_published(&self, cert_id: &str, target_path: &str) -> bool {
+        let Some(info) = self.cert_manager.get_certificate(cert_id) else {
+            return false;
+        };
+        if info.mount_path != target_path {
+            return false;
+        }
+
+        let target = Path::new(target_path);
+        if !target.join("tls.key").exists() {
+            return false;
+        }
+
+        let Ok(cert_pem) = encoding::read_certificate(target_path).await else {
+            return false;
+        };
+
+        match certificate_validity(&cert_pem) {
+            Ok((_, not_after)) => chrono::Utc::now().timestamp() < not_after,
+            Err(e) => {
+                debug!("Existing certificate at {} is unreadable: {}", target_path, e);
+                false
+            }
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -83,37 +376,145 @@ impl Node for NodeService {
         debug!("Target path: {}", req.target_path);
         debug!("Volume context: {:?}", req.volume_context);
 
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("Missing volume ID"));
+        }
+        if req.target_path.is_empty() {
+            return Err(Status::invalid_argument("Missing target path"));
+        }
+        validate_volume_capability(req.volume_capability.as_ref())?;
+
+        // Accept cert-manager csi-driver attribute names alongside our own
+        let volume_context = normalize_volume_context(&req.volume_context)
+            .map_err(Status::invalid_argument)?;
+
         // Extract pod information from volume context
-        let (pod_namespace, pod_name) = self.extract_pod_info(&req.volume_context)?;
+        let (pod_namespace, pod_name) = self.extract_pod_info(&volume_context)?;
         
         info!("Publishing volume for pod: {}/{}", pod_namespace, pod_name);
 
+        let pod = PodRef {
+            namespace: pod_namespace.clone(),
+            name: pod_name.clone(),
+            uid: volume_context.get("csi.storage.k8s.io/pod.uid").cloned(),
+        };
+
+        // Extract mode from volume attributes (default: certificate)
+        let mode = match volume_context.get("mode") {
+            Some(mode) => mode.parse::<VolumeMode>().map_err(Status::invalid_argument)?,
+            None => VolumeMode::Certificate,
+        };
+        if mode == VolumeMode::TrustOnly {
+            self.publish_trust_bundle(&req.target_path, &volume_context, &pod).await?;
+            return Ok(Response::new(NodePublishVolumeResponse {}));
+        }
+        if mode == VolumeMode::Dual {
+            if volume_context.contains_key("extended_key_usages") {
+                return Err(Status::invalid_argument("extended_key_usages cannot be combined with mode dual"));
+            }
+            // Each certificate is published like a volume of its own, into its subdirectory
+            for (directory, extended_key_usage) in DUAL_CERTIFICATES {
+                let mut volume_context = volume_context.clone();
+                volume_context.remove("mode");
+                volume_context.insert("extended_key_usages".to_string(), extended_key_usage.to_string());
+                let request = NodePublishVolumeRequest {
+                    volume_id: format!("{}-{}", req.volume_id, directory),
+                    target_path: Path::new(&req.target_path).join(directory).to_string_lossy().to_string(),
+                    volume_context,
+                    ..req.clone()
+                };
+                self.node_publish_volume(Request::new(request)).await?;
+            }
+            return Ok(Response::new(NodePublishVolumeResponse {}));
+        }
+
         // Generate certificate ID from pod info and volume ID
-        let cert_id = format!("{}-{}-{}", pod_namespace, pod_name, req.volume_id);
+        let cert_id = pod.certificate_id(&req.volume_id);
+
+        // A retry of a call still in flight waits for it and then finds the volume published
+        let _locked = self.cert_manager.lock_certificate(&cert_id).await;
+        if self.is_already_published(&cert_id, &req.target_path).await {
+            info!("Volume {} already published at {}, keeping existing certificate", req.volume_id, req.target_path);
+            return Ok(Response::new(NodePublishVolumeResponse {}));
+        }
 
         // Fetch pod details from Kubernetes API once for all template resolution
-        let needs_pod_info = req.volume_context.get("cn_template").map(|t| self.template_parser.has_templates(t)).unwrap_or(false)
-            || req.volume_context.get("organizational_units").map(|ou| self.template_parser.has_templates(ou)).unwrap_or(false);
+        let needs_pod_info = ["cn_template", "organizational_units", "dns_names", "uris"]
+            .iter()
+            .chain(SUBJECT_ATTRIBUTES.iter())
+            .any(|attr| volume_context.get(*attr).map(|t| self.template_parser.has_templates(t)).unwrap_or(false))
+            // Extension shorthands embed pod fields even without explicit templates
+            || volume_context.contains_key("extensions")
+            || !self.metadata_labels.is_empty();
         
-        let (pod_metadata, pod_spec) = if needs_pod_info {
+        let (pod_metadata, pod_spec) = if self.literal_pod_info {
+            crate::k8s_client::pod_info_from_volume_context(&volume_context)
+        } else if needs_pod_info {
+            self.pod_info(&pod).await?
+        } else {
+            (HashMap::new(), HashMap::new())
+        };
+
+        let references = |section: &str| {
+            template_attributes().any(|attr| {
+                volume_context.get(attr).is_some_and(|t| self.template_parser.references_section(t, section))
+            })
+        };
+
+        let node_info = if self.literal_pod_info {
+            crate::k8s_client::node_info_from_volume_context(&self.node_id, &volume_context)
+        } else if references("node") {
+            self.node_info().await?.clone()
+        } else {
+            HashMap::new()
+        };
+
+        // Only fetched for templates that embed ServiceAccount annotations
+        let service_account_info = if self.literal_pod_info {
+            crate::k8s_client::service_account_info_from_volume_context(&volume_context)
+        } else if references("serviceaccount") {
+            let service_account = volume_context
+                .get("csi.storage.k8s.io/serviceAccount.name")
+                .or_else(|| pod_spec.get("serviceAccountName"))
+                .map(String::as_str)
+                .unwrap_or("default");
             let client = crate::k8s_client::get_client()
                 .await
                 .map_err(|e| Status::internal(format!("Failed to get Kubernetes client: {}", e)))?;
-            
-            crate::k8s_client::get_pod_info(&client, &pod_namespace, &pod_name)
+
+            crate::k8s_client::get_service_account_info(&client, &pod_namespace, service_account)
                 .await
-                .map_err(|e| Status::internal(format!("Failed to get pod info: {}", e)))?
+                .map_err(|e| Status::internal(format!("Failed to get service account info: {:#}", e)))?
         } else {
-            (HashMap::new(), HashMap::new())
+            HashMap::new()
         };
+        let template_sections = [
+            ("metadata", &pod_metadata),
+            ("spec", &pod_spec),
+            ("node", &node_info),
+            ("serviceaccount", &service_account_info),
+        ];
+
+        // Report every template problem at once rather than the first one resolved
+        let template_problems: Vec<String> = template_attributes()
+            .filter_map(|attr| {
+                let template = volume_context.get(attr)?;
+                let problems = self.template_parser.validate(template, &template_sections).err()?;
+                Some(format!("{}: {}", attr, problems))
+            })
+            .collect();
+        if !template_problems.is_empty() {
+            error!("Invalid templates for volume {}: {}", req.volume_id, template_problems.join("; "));
+            return Err(Status::invalid_argument(format!("Invalid templates: {}", template_problems.join("; "))));
+        }
 
         // Determine the common name (CN) to use
-        let common_name = if let Some(cn_template) = req.volume_context.get("cn_template") {
+        let common_name = if let Some(cn_template) = volume_context.get("cn_template") {
             // CN template is provided - resolve it using pod information
             info!("Using CN template: {}", cn_template);
             
             // Resolve template
-            self.template_parser.resolve(cn_template, &pod_metadata, &pod_spec)
+            self.template_parser.resolve_with(cn_template, &template_sections)
                 .map_err(|e| Status::invalid_argument(format!("Failed to resolve CN template: {}", e)))?
         } else {
             // Default CN format: pod-name.namespace.svc.cluster-domain
@@ -127,11 +528,42 @@ impl Node for NodeService {
             .await
             .map_err(|e| Status::internal(format!("Failed to create target path: {}", e)))?;
 
-        // Extract validity_days from volume attributes (default: 7 days)
-        let validity_days = match req.volume_context.get("validity_days") {
-            Some(v_str) => {
+        // Extract require_tmpfs from volume attributes (default: REQUIRE_TMPFS)
+        let require_tmpfs = match volume_context.get("require_tmpfs") {
+            Some(value) => value.parse::<bool>().map_err(|_| {
+                Status::invalid_argument(format!("require_tmpfs must be true or false, got '{}'", value))
+            })?,
+            None => self.require_tmpfs,
+        };
+        if require_tmpfs {
+            let target = Path::new(&req.target_path);
+            mount_tmpfs(target)
+                .map_err(|e| Status::internal(format!("Failed to mount tmpfs at {}: {}", req.target_path, e)))?;
+            let memory_backed = is_memory_backed(target)
+                .map_err(|e| Status::internal(format!("Failed to check filesystem of {}: {}", req.target_path, e)))?;
+            if !memory_backed {
+                return Err(Status::failed_precondition(format!(
+                    "require_tmpfs is set but target path {} is not on tmpfs; refusing to write the private key to persistent storage",
+                    req.target_path
+                )));
+            }
+        }
+
+        // Extract profile from volume attributes (optional): named settings managed on the service
+        let profile = volume_context.get("profile").cloned();
+
+        // Extract validity or validity_days from volume attributes (default: 7 days, or the profile's validity)
+        if volume_context.contains_key("validity") && volume_context.contains_key("validity_days") {
+            return Err(Status::invalid_argument("Set only one of validity and validity_days"));
+        }
+        let validity_seconds = match (volume_context.get("validity"), volume_context.get("validity_days")) {
+            (Some(v_str), _) => parse_validity(v_str).ok_or_else(|| {
+                error!("Failed to parse validity '{}'", v_str);
+                Status::invalid_argument(format!("validity must be a duration like 30m, 12h or 7d, got '{}'", v_str))
+            })?,
+            (None, Some(v_str)) => {
                 match v_str.parse::<i64>() {
-                    Ok(days) if days > 0 => days,
+                    Ok(days) if days > 0 => days.saturating_mul(86400),
                     Ok(days) => {
                         error!("Invalid validity_days value (must be positive): {}", days);
                         return Err(Status::invalid_argument(format!("validity_days must be a positive integer, got {}", days)));
@@ -142,7 +574,9 @@ impl Node for NodeService {
                     }
                 }
             }
-            None => 7,
+            // 0 lets the service pick the validity configured for the profile
+            (None, None) if profile.is_some() => 0,
+            (None, None) => 7 * 86400,
         };
 
         // Extract organizational_units from volume attributes (optional, comma-separated)
@@ -150,7 +584,7 @@ impl Node for NodeService {
         // - Simple values: "IT, Engineering, Security"
         // - Key-value pairs: "t:tenantid, e:environment, n:{metadata.namespace}"
         // Template placeholders will be resolved
-        let organizational_units = match req.volume_context.get("organizational_units") {
+        let organizational_units = match volume_context.get("organizational_units") {
             Some(ou_str) => {
                 // Parse each OU entry
                 let mut parsed_ous = Vec::new();
@@ -162,7 +596,7 @@ impl Node for NodeService {
                     
                     // Resolve templates in the entire OU entry (preserving colon delimiters)
                     let ou_value = if self.template_parser.has_templates(trimmed) {
-                        match self.template_parser.resolve(trimmed, &pod_metadata, &pod_spec) {
+                        match self.template_parser.resolve_with(trimmed, &template_sections) {
                             Ok(resolved) => resolved,
                             Err(e) => {
                                 error!("Failed to resolve OU template '{}': {}", trimmed, e);
@@ -185,45 +619,337 @@ impl Node for NodeService {
             info!("Organizational units: {:?}", organizational_units);
         }
 
-        // Request certificate from certificate service
-        match self.cert_manager.issue_certificate(
-            &cert_id,
-            &common_name,
-            vec![pod_name.clone()],
-            vec![],
-            organizational_units,
-            validity_days,
-        ).await {
+        // Extract dns_names from volume attributes (optional, comma-separated, templates allowed)
+        // Defaults to the pod name when not set
+        let mut dns_names = match volume_context.get("dns_names") {
+            Some(names_str) => {
+                let mut names = Vec::new();
+                for name in names_str.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    let resolved = self.template_parser.resolve_with(name, &template_sections)
+                        .map_err(|e| Status::invalid_argument(format!("Failed to resolve DNS name template '{}': {}", name, e)))?;
+                    names.push(resolved);
+                }
+                names
+            }
+            None => vec![pod_name.clone()],
+        };
+
+        // Extract long_cn_strategy from volume attributes (default: reject)
+        // Templated CNs easily exceed the 64 character limit of X.509
+        let long_cn_strategy = match volume_context.get("long_cn_strategy") {
+            Some(strategy) => strategy.parse::<LongCnStrategy>().map_err(Status::invalid_argument)?,
+            None => LongCnStrategy::default(),
+        };
+        let requested_cn_length = common_name.chars().count();
+        let common_name = long_cn_strategy.apply(common_name, &mut dns_names).map_err(Status::invalid_argument)?;
+        if common_name.chars().count() != requested_cn_length {
+            info!("Shortened CN to {} ({:?})", common_name, long_cn_strategy);
+        }
+
+        // Extract invalid_name_strategy and punycode from volume attributes (default: reject, false)
+        // Resolved names are checked against RFC 1123 (DNS names) and RFC 3986 (URIs)
+        let san_validator = SanValidator {
+            strategy: match volume_context.get("invalid_name_strategy") {
+                Some(strategy) => strategy.parse::<InvalidNameStrategy>().map_err(Status::invalid_argument)?,
+                None => InvalidNameStrategy::default(),
+            },
+            punycode: match volume_context.get("punycode") {
+                Some(value) => value.parse::<bool>().map_err(|_| {
+                    Status::invalid_argument(format!("punycode must be true or false, got '{}'", value))
+                })?,
+                None => false,
+            },
+        };
+
+        // dns_names entries that are IP literals become IP SANs
+        let (dns_names, ip_addresses) = san_validator.dns_names_and_ips(dns_names).map_err(Status::invalid_argument)?;
+
+        // Extract uris from volume attributes (optional, comma-separated, templates allowed)
+        let uris = match volume_context.get("uris") {
+            Some(uris_str) => {
+                let mut uris = Vec::new();
+                for uri in uris_str.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+                    let resolved = self.template_parser.resolve_with(uri, &template_sections)
+                        .map_err(|e| Status::invalid_argument(format!("Failed to resolve URI template '{}': {}", uri, e)))?;
+                    uris.push(resolved);
+                }
+                san_validator.uris(uris).map_err(Status::invalid_argument)?
+            }
+            None => vec![],
+        };
+
+        // Extract subject attributes from volume attributes (optional, templates allowed)
+        let mut subject_values: HashMap<&str, String> = HashMap::new();
+        for &attr in SUBJECT_ATTRIBUTES {
+            if let Some(value) = volume_context.get(attr).map(|v| v.trim()).filter(|v| !v.is_empty()) {
+                let resolved = self.template_parser.resolve_with(value, &template_sections)
+                    .map_err(|e| Status::invalid_argument(format!("Failed to resolve {} template '{}': {}", attr, value, e)))?;
+                subject_values.insert(attr, resolved);
+            }
+        }
+        let mut subject_value = |attr: &str| subject_values.remove(attr).unwrap_or_default();
+        let subject = Subject {
+            country: subject_value("country"),
+            organization: subject_value("organization"),
+            locality: subject_value("locality"),
+            province: subject_value("province"),
+            serial_number: subject_value("serial_number"),
+        };
+
+        // Extract key_usages and extended_key_usages from volume attributes (optional, comma-separated)
+        // The certificate service validates the names and applies its defaults when they are empty
+        let usage_list = |attr: &str| -> Vec<String> {
+            volume_context
+                .get(attr)
+                .map(|value| value.split(',').map(str::trim).filter(|u| !u.is_empty()).map(String::from).collect())
+                .unwrap_or_default()
+        };
+        let key_usages = usage_list("key_usages");
+        let extended_key_usages = usage_list("extended_key_usages");
+
+        // Extract extensions from volume attributes (optional, comma-separated <oid>=<value>)
+        let extensions = match volume_context.get("extensions") {
+            Some(value) => parse_extensions(value, |template| {
+                self.template_parser.resolve_with(template, &template_sections)
+                    .map_err(|e| format!("Failed to resolve extension template '{}': {}", template, e))
+            }).map_err(Status::invalid_argument)?,
+            None => vec![],
+        };
+
+        // Extract fs_group from volume attributes (optional): group that may read the key
+        let fs_group = match volume_context.get("fs_group") {
+            Some(gid_str) => Some(gid_str.parse::<u32>().map_err(|_| {
+                Status::invalid_argument(format!("fs_group must be a numeric group id, got '{}'", gid_str))
+            })?),
+            None => None,
+        };
+
+        // Extract reload_strategy from volume attributes (default: none)
+        let reload_strategy = match volume_context.get("reload_strategy") {
+            Some(strategy) => strategy.parse::<ReloadStrategy>().map_err(Status::invalid_argument)?,
+            None => ReloadStrategy::None,
+        };
+
+        // Extract encrypt_key from volume attributes (default: ENCRYPT_KEYS)
+        let encrypt_key = match volume_context.get("encrypt_key") {
+            Some(value) => value.parse::<bool>().map_err(|_| {
+                Status::invalid_argument(format!("encrypt_key must be true or false, got '{}'", value))
+            })?,
+            None => self.cert_manager.encrypt_keys_by_default(),
+        };
+        if encrypt_key && !self.cert_manager.can_encrypt_keys() {
+            return Err(Status::failed_precondition(
+                "encrypt_key is set but the driver has no KEY_ENCRYPTION_SECRET configured",
+            ));
+        }
+
+        // Extract encoding from volume attributes (default: pem)
+        let encoding = match volume_context.get("encoding") {
+            Some(encoding) => encoding.parse::<Encoding>().map_err(Status::invalid_argument)?,
+            None => Encoding::Pem,
+        };
+        if encoding == Encoding::Der && volume_context.get("encrypt_key").is_some_and(|value| value == "true") {
+            return Err(Status::invalid_argument("encrypt_key cannot be combined with encoding der"));
+        }
+
+        // Extract key_encoding from volume attributes (default: pkcs8)
+        let key_encoding = match volume_context.get("key_encoding") {
+            Some(key_encoding) => key_encoding.parse::<KeyEncoding>().map_err(Status::invalid_argument)?,
+            None => KeyEncoding::Pkcs8,
+        };
+        if key_encoding != KeyEncoding::Pkcs8 && volume_context.get("encrypt_key").is_some_and(|value| value == "true") {
+            return Err(Status::invalid_argument("encrypt_key requires key_encoding pkcs8"));
+        }
+        // Keys issued without a profile are P-256; the service checks the key type of profiles
+        if profile.is_none() && !KeyType::default().has_encoding(key_encoding) {
+            return Err(Status::invalid_argument(format!(
+                "key_encoding {} does not exist for {:?} keys", key_encoding.as_str(), KeyType::default()
+            )));
+        }
+        let encrypt_key = encrypt_key && encoding == Encoding::Pem && key_encoding == KeyEncoding::Pkcs8;
+
+        // Extract layout from volume attributes (default: tls): extra files for specific servers
+        let layout = match volume_context.get("layout") {
+            Some(layout) => layout.parse::<Layout>().map_err(Status::invalid_argument)?,
+            None => Layout::Tls,
+        };
+        let layout_owner = match volume_context.get("layout_owner") {
+            Some(uid) => Some(uid.parse::<u32>().map_err(|_| {
+                Status::invalid_argument(format!("layout_owner must be a numeric user id, got '{}'", uid))
+            })?),
+            None => layout.default_owner(),
+        };
+
+        // Extract pins from volume attributes (default: false): fingerprint and SPKI pin files
+        let pins = match volume_context.get("pins") {
+            Some(value) => value.parse::<bool>().map_err(|_| {
+                Status::invalid_argument(format!("pins must be true or false, got '{}'", value))
+            })?,
+            None => false,
+        };
+
+        // Volumes that need nothing but a CN and DNS names may be signed on the node during an
+        // outage, with the node intermediate CA or the CA itself
+        let local_request = (profile.is_none()
+            && organizational_units.is_empty()
+            && subject == Subject::default()
+            && key_usages.is_empty()
+            && extended_key_usages.is_empty()
+            && extensions.is_empty()
+            && ip_addresses.is_empty()
+            && uris.is_empty())
+        .then(|| LocalSigningRequest {
+            common_name: common_name.clone(),
+            dns_names: dns_names.clone(),
+            validity_seconds,
+        });
+
+        // Request certificate from certificate service, retrying within the publish
+        // retry deadline while it is unavailable
+        let metadata = request_metadata(&pod, &volume_context, &pod_metadata, &pod_spec, &self.metadata_labels);
+        let started = Instant::now();
+        let mut backoff = PUBLISH_RETRY_BACKOFF;
+        let issued = loop {
+            let issued = self.cert_manager.issue_certificate(
+                &cert_id,
+                &common_name,
+                dns_names.clone(),
+                ip_addresses.clone(),
+                uris.clone(),
+                organizational_units.clone(),
+                subject.clone(),
+                key_usages.clone(),
+                extended_key_usages.clone(),
+                extensions.clone(),
+                validity_seconds,
+                metadata.clone(),
+                profile.clone(),
+                key_encoding,
+            ).await;
+            match &issued {
+                Err(e) if is_outage(e) && started.elapsed() + backoff <= self.publish_retry_deadline => {
+                    warn!("Certificate service unavailable while issuing {}, retrying in {:?}: {:#}", cert_id, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(PUBLISH_RETRY_MAX_BACKOFF);
+                }
+                _ => break issued,
+            }
+        };
+
+        let (issued, local_request, fallback) = match (issued, local_request) {
+            (Err(e), Some(request)) if is_outage(&e) => {
+                let signed = sign_on_node(
+                    self.delegated_ca.as_ref(),
+                    self.local_signer.as_ref(),
+                    &cert_id,
+                    &pod_namespace,
+                    &request,
+                    "issue",
+                ).await;
+                match signed {
+                    Some(Ok(issued)) => (Ok(issued), Some(request), true),
+                    _ => (Err(e), None, false),
+                }
+            }
+            (issued, _) => (issued, None, false),
+        };
+
+        match issued {
             Ok((cert_pem, key_pem, not_before, not_after)) => {
                 info!("Certificate issued for {}", cert_id);
-                
+
+                // Never hand the workload a certificate it cannot use
+                let ca_manager = self.ca_manager.for_namespace(&pod_namespace)
+                    .await
+                    .map_err(|e| Status::failed_precondition(format!("Failed to get the CA of namespace {}: {:#}", pod_namespace, e)))?;
+                let ca_pem = ca_manager.get_ca_cert()
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to get CA certificate: {}", e)))?;
+                validate_issued_certificate(&cert_pem, &key_pem, &ca_pem, &dns_names, &ip_addresses).map_err(|e| {
+                    error!("Certificate issued for {} failed validation: {:#}", cert_id, e);
+                    Status::internal(format!("Certificate service returned an unusable certificate: {:#}", e))
+                })?;
+
                 // Write certificate and key to target path
                 let cert_path = std::path::Path::new(&req.target_path).join("tls.crt");
                 let key_path = std::path::Path::new(&req.target_path).join("tls.key");
 
-                tokio::fs::write(&cert_path, cert_pem)
+                // Convert the key before writing anything, since its type may lack the encoding
+                let key_pem = key_encoding.encode(&key_pem)
+                    .map_err(|e| Status::invalid_argument(format!("Failed to encode key: {:#}", e)))?;
+
+                let cert_file = encoding.encode_certificate(&cert_pem)
+                    .map_err(|e| Status::internal(format!("Failed to encode certificate: {:#}", e)))?;
+                tokio::fs::write(&cert_path, cert_file)
                     .await
                     .map_err(|e| Status::internal(format!("Failed to write certificate: {}", e)))?;
 
-                tokio::fs::write(&key_path, key_pem)
+                let key_pem = self.cert_manager.encode_private_key(&key_pem, encrypt_key)
+                    .map_err(|e| Status::internal(format!("Failed to encrypt key: {:#}", e)))?;
+                let key_file = encoding.encode_key(&key_pem)
+                    .map_err(|e| Status::internal(format!("Failed to encode key: {:#}", e)))?;
+
+                tokio::fs::write(&key_path, key_file.as_slice())
                     .await
                     .map_err(|e| Status::internal(format!("Failed to write key: {}", e)))?;
 
+                if let Some(gid) = fs_group {
+                    apply_fs_group(&[&cert_path, &key_path], gid)
+                        .map_err(|e| Status::internal(format!("Failed to apply fs_group {}: {}", gid, e)))?;
+                }
+
+                if req.readonly {
+                    make_read_only(&[&cert_path, &key_path])
+                        .map_err(|e| Status::internal(format!("Failed to make files read-only: {}", e)))?;
+                }
+                self.cert_manager.remember_certificate_files(&req.target_path).await;
+                self.cert_manager.write_metadata(&req.target_path, &cert_id, &cert_pem, false).await;
+                if pins {
+                    self.cert_manager.write_pin_files(&req.target_path, &cert_pem, &ca_pem)
+                        .await
+                        .map_err(|e| Status::internal(format!("Failed to write pin files: {:#}", e)))?;
+                }
+                layout.write(&req.target_path, &cert_pem, &key_pem, layout_owner)
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to write the {:?} layout: {:#}", layout, e)))?;
+
+                reload_strategy.on_issue(&req.target_path)
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))?;
+
                 // Store certificate metadata for monitoring
                 self.cert_manager.register_certificate(
                     cert_id.clone(),
                     req.target_path.clone(),
+                    pod.clone(),
+                    reload_strategy,
                     not_before,
                     not_after,
+                    local_request.clone(),
                 ).await;
 
                 info!("Certificate written to {}", req.target_path);
+                if fallback {
+                    self.events.issued_locally(&pod, &cert_id, not_after).await;
+                } else {
+                    self.events.issued(&pod, &cert_id, not_after).await;
+                }
+                self.annotator.record_certificate(&pod, &cert_pem).await;
+                self.bindings.record_certificate(&pod, &cert_id, &cert_pem, false).await;
                 
                 Ok(Response::new(NodePublishVolumeResponse {}))
             }
             Err(e) => {
                 error!("Failed to issue certificate: {}", e);
-                Err(Status::internal(format!("Failed to issue certificate: {}", e)))
+                self.events.issue_failed(&pod, &cert_id, &format!("{:#}", e)).await;
+                // Keep the code and error details of a refusal, e.g. POLICY_DENIED, for callers
+                let message = format!("Failed to issue certificate: {}", e);
+                Err(match e.downcast_ref::<Status>() {
+                    Some(status) if ErrorInfo::from_status(status).is_some() => {
+                        Status::with_details(status.code(), message, status.details().to_vec().into())
+                    }
+                    _ => Status::internal(message),
+                })
             }
         }
     }
@@ -237,9 +963,14 @@ impl Node for NodeService {
         info!("NodeUnpublishVolume called for volume: {}", req.volume_id);
 
         // Unregister certificate from monitoring
-        self.cert_manager.unregister_certificate(&req.volume_id).await;
+        for cert_info in self.cert_manager.unregister_certificate(&req.target_path).await {
+            self.bindings.remove(&cert_info.pod, &cert_info.cert_id).await;
+        }
 
-        // Remove target directory
+        // Remove target directory, after the tmpfs of require_tmpfs volumes
+        if let Err(e) = unmount_tmpfs(Path::new(&req.target_path)) {
+            error!("Failed to unmount tmpfs at {}: {}", req.target_path, e);
+        }
         if let Err(e) = tokio::fs::remove_dir_all(&req.target_path).await {
             error!("Failed to remove target path: {}", e);
             // Don't fail the operation if cleanup fails
@@ -252,10 +983,52 @@ impl Node for NodeService {
 
     async fn node_get_volume_stats(
         &self,
-        _request: Request<NodeGetVolumeStatsRequest>,
+        request: Request<NodeGetVolumeStatsRequest>,
     ) -> Result<Response<NodeGetVolumeStatsResponse>, Status> {
-        // Not implemented for ephemeral volumes
-        Err(Status::unimplemented("Volume stats not supported"))
+        let req = request.into_inner();
+
+        debug!("NodeGetVolumeStats called for volume: {}", req.volume_id);
+
+        if req.volume_id.is_empty() {
+            return Err(Status::invalid_argument("Missing volume ID"));
+        }
+        if req.volume_path.is_empty() {
+            return Err(Status::invalid_argument("Missing volume path"));
+        }
+
+        let volume_path = Path::new(&req.volume_path);
+        if !volume_path.exists() {
+            return Err(Status::not_found(format!("Volume path {} does not exist", req.volume_path)));
+        }
+
+        let stats = nix::sys::statvfs::statvfs(volume_path)
+            .map_err(|e| Status::internal(format!("Failed to stat {}: {}", req.volume_path, e)))?;
+
+        let block_size = stats.fragment_size() as i64;
+        let usage = vec![
+            VolumeUsage {
+                available: stats.blocks_available() as i64 * block_size,
+                total: stats.blocks() as i64 * block_size,
+                used: (stats.blocks() - stats.blocks_free()) as i64 * block_size,
+                unit: Unit::Bytes as i32,
+            },
+            VolumeUsage {
+                available: stats.files_available() as i64,
+                total: stats.files() as i64,
+                used: (stats.files() - stats.files_free()) as i64,
+                unit: Unit::Inodes as i32,
+            },
+        ];
+
+        let volume_condition = self.volume_condition(&req.volume_path).await;
+        if volume_condition.abnormal {
+            debug!("Volume {} is abnormal: {}", req.volume_id, volume_condition.message);
+        }
+
+        Ok(Response::new(NodeGetVolumeStatsResponse {
+            usage,
+            volume_condition: Some(volume_condition),
+        }))
     }
 
     async fn node_expand_volume(
@@ -272,9 +1045,14 @@ impl Node for NodeService {
     ) -> Result<Response<NodeGetCapabilitiesResponse>, Status> {
         debug!("NodeGetCapabilities called");
 
-        let capabilities = vec![
-            // No special capabilities needed for ephemeral volumes
-        ];
+        let capabilities = [rpc::Type::GetVolumeStats, rpc::Type::VolumeCondition]
+            .into_iter()
+            .map(|rpc_type| NodeServiceCapability {
+                r#type: Some(node_service_capability::Type::Rpc(node_service_capability::Rpc {
+                    r#type: rpc_type as i32,
+                })),
+            })
+            .collect();
 
         Ok(Response::new(NodeGetCapabilitiesResponse { capabilities }))
     }
@@ -294,3 +1072,172 @@ impl Node for NodeService {
         Ok(Response::new(response))
     }
 }
+
+/// Reject volume capabilities this driver cannot serve
+///
+/// Certificates are published as files, so only the mount access type is supported.
+pub fn validate_volume_capability(capability: Option<&VolumeCapability>) -> Result<(), Status> {
+    let capability = capability
+        .ok_or_else(|| Status::invalid_argument("Missing volume capability"))?;
+
+    match &capability.access_type {
+        Some(AccessType::Mount(_)) => Ok(()),
+        Some(AccessType::Block(_)) => Err(Status::invalid_argument(
+            "Block access type is not supported, certificate volumes must use mount access",
+        )),
+        None => Err(Status::invalid_argument("Missing volume access type")),
+    }
+}
+
+/// Whether `path` is on a memory-backed (tmpfs) filesystem
+fn is_memory_backed(path: &Path) -> nix::Result<bool> {
+    let stats = nix::sys::statfs::statfs(path)?;
+    Ok(stats.filesystem_type() == nix::sys::statfs::TMPFS_MAGIC)
+}
+
+/// Mount a tmpfs at `path` for the key files, unless it is memory-backed already
+fn mount_tmpfs(path: &Path) -> nix::Result<()> {
+    if is_memory_backed(path)? {
+        return Ok(());
+    }
+    use nix::mount::MsFlags;
+    nix::mount::mount(
+        Some("tmpfs"),
+        path,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some("mode=0755,size=4m"),
+    )
+}
+
+/// Unmount a tmpfs mounted by [`mount_tmpfs`]; nothing to do when `path` is gone or not a mount point
+fn unmount_tmpfs(path: &Path) -> nix::Result<()> {
+    match is_memory_backed(path) {
+        Ok(true) => {}
+        Ok(false) | Err(nix::errno::Errno::ENOENT) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    match nix::mount::umount2(path, nix::mount::MntFlags::MNT_DETACH) {
+        Err(nix::errno::Errno::EINVAL) => Ok(()),
+        result => result,
+    }
+}
+
+/// Clear the write bits of the published files for read-only volumes
+///
+/// The driver runs as root, so renewals can still replace the contents.
+fn make_read_only(paths: &[&Path]) -> std::io::Result<()> {
+    for path in paths {
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & !0o222))?;
+    }
+    Ok(())
+}
+
+/// Hand group ownership of the published files to `gid` and make them group-readable
+fn apply_fs_group(paths: &[&Path], gid: u32) -> std::io::Result<()> {
+    for path in paths {
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o640))?;
+    }
+    Ok(())
+}
+
+/// Metadata sent with the issuance request, identifying the workload that owns the certificate
+///
+/// The pod UID and service account come from kubelet (podInfoOnMount) when available,
+/// otherwise from the pod fetched for templates; labels only from the fetched pod.
+fn request_metadata(
+    pod: &PodRef,
+    volume_context: &HashMap<String, String>,
+    pod_metadata: &HashMap<String, String>,
+    pod_spec: &HashMap<String, String>,
+    labels: &[String],
+) -> HashMap<String, String> {
+    let mut metadata = HashMap::from([
+        ("namespace".to_string(), pod.namespace.clone()),
+        ("pod".to_string(), pod.name.clone()),
+    ]);
+
+    if let Some(uid) = pod.uid.as_ref().or_else(|| pod_metadata.get("uid")) {
+        metadata.insert("pod_uid".to_string(), uid.clone());
+    }
+    if let Some(service_account) = volume_context
+        .get("csi.storage.k8s.io/serviceAccount.name")
+        .or_else(|| pod_spec.get("serviceAccountName"))
+    {
+        metadata.insert("service_account".to_string(), service_account.clone());
+    }
+    for label in labels {
+        if let Some(value) = pod_metadata.get(&format!("labels.{}", label)) {
+            metadata.insert(format!("label.{}", label), value.clone());
+        }
+    }
+
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::proto::csi::volume_capability::{BlockVolume, MountVolume};
+
+    #[test]
+    fn test_validate_volume_capability() {
+        let mount = VolumeCapability {
+            access_type: Some(AccessType::Mount(MountVolume::default())),
+            access_mode: None,
+        };
+        let block = VolumeCapability {
+            access_type: Some(AccessType::Block(BlockVolume::default())),
+            access_mode: None,
+        };
+
+        assert!(validate_volume_capability(Some(&mount)).is_ok());
+        assert_eq!(validate_volume_capability(Some(&block)).unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert!(validate_volume_capability(None).is_err());
+    }
+
+    #[test]
+    fn test_tmpfs_target_path() {
+        let dir = TempDir::new("tmpfs").unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Mounting needs CAP_SYS_ADMIN, which the driver has but a test run may lack
+        match mount_tmpfs(&dir) {
+            Ok(()) => {
+                assert!(is_memory_backed(&dir).unwrap());
+                unmount_tmpfs(&dir).unwrap();
+                assert_eq!(is_memory_backed(&dir).unwrap(), is_memory_backed(&std::env::temp_dir()).unwrap());
+            }
+            Err(e) => assert_eq!(e, nix::errno::Errno::EPERM),
+        }
+        // Nothing to unmount for a plain directory or one that is gone
+        unmount_tmpfs(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        unmount_tmpfs(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_request_metadata() {
+        let pod = PodRef { namespace: "prod".to_string(), name: "web-0".to_string(), uid: None };
+        let volume_context = HashMap::from([
+            ("csi.storage.k8s.io/serviceAccount.name".to_string(), "web".to_string()),
+        ]);
+        let pod_metadata = HashMap::from([
+            ("uid".to_string(), "1234".to_string()),
+            ("labels.app".to_string(), "web".to_string()),
+            ("labels.team".to_string(), "payments".to_string()),
+        ]);
+
+        let metadata = request_metadata(&pod, &volume_context, &pod_metadata, &HashMap::new(), &["app".to_string(), "tier".to_string()]);
+
+        assert_eq!(metadata["namespace"], "prod");
+        assert_eq!(metadata["pod"], "web-0");
+        assert_eq!(metadata["pod_uid"], "1234");
+        assert_eq!(metadata["service_account"], "web");
+        assert_eq!(metadata["label.app"], "web");
+        assert!(!metadata.contains_key("label.team") && !metadata.contains_key("label.tier"));
+    }
+}
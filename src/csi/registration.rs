@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+use tonic::{Request, Response, Status};
+use tracing::{info, error};
+use crate::proto::pluginregistration::{
+    registration_server::Registration,
+    InfoRequest, PluginInfo,
+    RegistrationStatus, RegistrationStatusResponse,
+};
+use super::identity::PLUGIN_NAME;
+
+/// Plugin type of CSI drivers in the kubelet's plugin registration API
+const CSI_PLUGIN_TYPE: &str = "CSIPlugin";
+/// CSI versions the driver implements
+const SUPPORTED_VERSIONS: &[&str] = &["1.0.0"];
+
+/// Registers the driver with the kubelet, replacing the node-driver-registrar sidecar
+///
+/// The kubelet watches its plugins_registry directory for sockets, calls GetInfo on
+/// each to find the CSI socket, and reports back through NotifyRegistrationStatus.
+pub struct RegistrationService {
+    /// Path of the CSI socket as seen by the kubelet, on the host
+    kubelet_registration_path: String,
+}
+
+impl RegistrationService {
+    pub fn new(kubelet_registration_path: String) -> Self {
+        Self { kubelet_registration_path }
+    }
+
+    /// Path of the registration socket in the kubelet's plugins_registry directory
+    pub fn socket_path(registration_dir: &Path) -> PathBuf {
+        registration_dir.join(format!("{}-reg.sock", PLUGIN_NAME))
+    }
+}
+
+#[tonic::async_trait]
+impl Registration for RegistrationService {
+    async fn get_info(
+        &self,
+        _request: Request<InfoRequest>,
+    ) -> Result<Response<PluginInfo>, Status> {
+        tracing::debug!("GetInfo called");
+
+        let response = PluginInfo {
+            r#type: CSI_PLUGIN_TYPE.to_string(),
+            name: PLUGIN_NAME.to_string(),
+            endpoint: self.kubelet_registration_path.clone(),
+            supported_versions: SUPPORTED_VERSIONS.iter().map(|v| v.to_string()).collect(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn notify_registration_status(
+        &self,
+        request: Request<RegistrationStatus>,
+    ) -> Result<Response<RegistrationStatusResponse>, Status> {
+        let status = request.into_inner();
+
+        if status.plugin_registered {
+            info!("Registered with the kubelet as {}", PLUGIN_NAME);
+        } else {
+            error!("Kubelet rejected the plugin registration: {}", status.error);
+        }
+
+        Ok(Response::new(RegistrationStatusResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_info() {
+        let service = RegistrationService::new("/var/lib/kubelet/plugins/csi.k8s.cacsi-driver/csi.sock".to_string());
+
+        let info = service.get_info(Request::new(InfoRequest {})).await.unwrap().into_inner();
+        assert_eq!(info.r#type, "CSIPlugin");
+        assert_eq!(info.name, PLUGIN_NAME);
+        assert_eq!(info.endpoint, "/var/lib/kubelet/plugins/csi.k8s.cacsi-driver/csi.sock");
+        assert_eq!(info.supported_versions, vec!["1.0.0"]);
+        assert_eq!(
+            RegistrationService::socket_path(Path::new("/registration")),
+            Path::new("/registration/csi.k8s.cacsi-driver-reg.sock")
+        );
+    }
+}
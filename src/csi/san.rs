@@ -0,0 +1,242 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::template_parser::dns_safe;
+
+/// Longest DNS name in presentation format, without a trailing dot (RFC 1035)
+const MAX_DNS_NAME_LENGTH: usize = 253;
+/// Longest DNS label (RFC 1035)
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// What to do with a resolved DNS name or URI that is not valid in a certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidNameStrategy {
+    /// Fail the mount
+    #[default]
+    Reject,
+    /// Replace characters that are not allowed: `-` in DNS names, percent-encoding in URIs
+    Sanitize,
+}
+
+impl FromStr for InvalidNameStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "sanitize" => Ok(Self::Sanitize),
+            other => Err(format!("Invalid invalid_name_strategy '{}' (expected reject or sanitize)", other)),
+        }
+    }
+}
+
+/// Checks resolved SANs: DNS names against RFC 1123, IP literals, and URIs against RFC 3986
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SanValidator {
+    pub strategy: InvalidNameStrategy,
+    /// Convert internationalized domain names to their ASCII (punycode) form
+    pub punycode: bool,
+}
+
+impl SanValidator {
+    /// Split the entries of `dns_names` into DNS names and IP addresses, checking each
+    ///
+    /// Every invalid entry is reported in one error.
+    pub fn dns_names_and_ips(&self, entries: Vec<String>) -> Result<(Vec<String>, Vec<String>), String> {
+        let mut dns_names = Vec::new();
+        let mut ip_addresses = Vec::new();
+        let mut problems = Vec::new();
+
+        for entry in entries {
+            if let Ok(ip) = entry.parse::<IpAddr>() {
+                ip_addresses.push(ip.to_string());
+                continue;
+            }
+            match self.dns_name(&entry) {
+                Ok(name) if !dns_names.contains(&name) => dns_names.push(name),
+                Ok(_) => {}
+                Err(e) => problems.push(e),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(problems.join("; "));
+        }
+        Ok((dns_names, ip_addresses))
+    }
+
+    /// `name` as it goes into a DNS SAN, or why it cannot
+    pub fn dns_name(&self, name: &str) -> Result<String, String> {
+        let mut candidate = name.to_string();
+        if !candidate.is_ascii() && self.punycode {
+            candidate = idna::domain_to_ascii(&candidate)
+                .map_err(|_| format!("DNS name '{}' is not a valid internationalized domain name", name))?;
+        }
+
+        let problem = match check_dns_name(&candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(problem) => problem,
+        };
+        if self.strategy == InvalidNameStrategy::Sanitize {
+            let sanitized = match candidate.strip_prefix("*.") {
+                Some(rest) => format!("*.{}", dns_safe(rest)),
+                None => dns_safe(&candidate),
+            };
+            if check_dns_name(&sanitized).is_ok() {
+                return Ok(sanitized);
+            }
+        }
+
+        let hint = if !name.is_ascii() && !self.punycode {
+            "; set punycode to true to convert internationalized names"
+        } else if self.strategy == InvalidNameStrategy::Reject {
+            "; set invalid_name_strategy to sanitize to replace invalid characters"
+        } else {
+            ""
+        };
+        Err(format!("DNS name '{}' {}{}", name, problem, hint))
+    }
+
+    /// Check the entries of `uris`, reporting every invalid one in one error
+    pub fn uris(&self, entries: Vec<String>) -> Result<Vec<String>, String> {
+        let mut uris = Vec::new();
+        let mut problems = Vec::new();
+        for entry in entries {
+            match self.uri(&entry) {
+                Ok(uri) => uris.push(uri),
+                Err(e) => problems.push(e),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(problems.join("; "));
+        }
+        Ok(uris)
+    }
+
+    /// `uri` as it goes into a URI SAN, or why it cannot
+    pub fn uri(&self, uri: &str) -> Result<String, String> {
+        let uri = match self.strategy {
+            InvalidNameStrategy::Reject => uri.to_string(),
+            InvalidNameStrategy::Sanitize => percent_encode_invalid(uri),
+        };
+        check_uri(&uri).map_err(|problem| format!("URI '{}' {}", uri, problem))?;
+        Ok(uri)
+    }
+}
+
+/// Check a DNS name against RFC 1123, allowing a leading `*` label for wildcards
+pub fn check_dns_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("is empty".to_string());
+    }
+    if name.len() > MAX_DNS_NAME_LENGTH {
+        return Err(format!("is {} characters long, at most {} are allowed", name.len(), MAX_DNS_NAME_LENGTH));
+    }
+
+    let labels: Vec<&str> = name.split('.').collect();
+    for (index, label) in labels.iter().enumerate() {
+        if index == 0 && *label == "*" && labels.len() > 1 {
+            continue;
+        }
+        if label.is_empty() {
+            return Err("has an empty label".to_string());
+        }
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(format!("has label '{}' longer than {} characters", label, MAX_LABEL_LENGTH));
+        }
+        if let Some(c) = label.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-') {
+            return Err(format!("contains '{}', only letters, digits, hyphens and dots are allowed", c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("has label '{}' starting or ending with a hyphen", label));
+        }
+    }
+
+    // All-numeric names are mistyped IP addresses rather than host names
+    if labels.last().is_some_and(|label| label.chars().all(|c| c.is_ascii_digit())) {
+        return Err("is neither a valid IP address nor a DNS name".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `c` may appear literally in a URI (RFC 3986 unreserved and reserved characters)
+fn is_uri_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=".contains(c)
+}
+
+/// Check that `uri` is an absolute URI (RFC 3986), as RFC 5280 requires of URI SANs
+pub fn check_uri(uri: &str) -> Result<(), String> {
+    let Some((scheme, rest)) = uri.split_once(':') else {
+        return Err("has no scheme, e.g. spiffe://".to_string());
+    };
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if !valid_scheme {
+        return Err(format!("has invalid scheme '{}'", scheme));
+    }
+    if rest.is_empty() {
+        return Err("is empty after the scheme".to_string());
+    }
+
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let escaped: String = chars.by_ref().take(2).collect();
+            if escaped.len() != 2 || !escaped.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("has an invalid percent-encoding".to_string());
+            }
+        } else if !is_uri_char(c) {
+            return Err(format!("contains {:?}, which must be percent-encoded", c));
+        }
+    }
+    Ok(())
+}
+
+/// Percent-encode the characters of `uri` that may not appear literally, as UTF-8
+fn percent_encode_invalid(uri: &str) -> String {
+    let mut encoded = String::new();
+    for c in uri.chars() {
+        if is_uri_char(c) || c == '%' {
+            encoded.push(c);
+        } else {
+            let mut buffer = [0u8; 4];
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_san_validation() {
+        let reject = SanValidator::default();
+        let entries = ["web.default.svc", "*.web.example.com", "10.0.0.1", "::1"].map(String::from).to_vec();
+        let (dns_names, ip_addresses) = reject.dns_names_and_ips(entries).unwrap();
+        assert_eq!(dns_names, ["web.default.svc", "*.web.example.com"]);
+        assert_eq!(ip_addresses, ["10.0.0.1", "::1"]);
+
+        let error = reject
+            .dns_names_and_ips(["my_app.default", "web server", "10.0.0.256", "-web"].map(String::from).to_vec())
+            .unwrap_err();
+        assert_eq!(error.matches("DNS name '").count(), 4, "{}", error);
+        assert!(reject.dns_name("bücher.example").unwrap_err().contains("punycode"));
+
+        let sanitize = SanValidator { strategy: InvalidNameStrategy::Sanitize, punycode: false };
+        assert_eq!(sanitize.dns_name("My_App.Default").unwrap(), "my-app.default");
+        assert_eq!(sanitize.dns_name("*.web server.example").unwrap(), "*.web-server.example");
+
+        let punycode = SanValidator { strategy: InvalidNameStrategy::Reject, punycode: true };
+        assert_eq!(punycode.dns_name("bücher.example").unwrap(), "xn--bcher-kva.example");
+
+        assert_eq!(reject.uri("spiffe://cluster.local/ns/default/sa/web").unwrap(), "spiffe://cluster.local/ns/default/sa/web");
+        assert!(reject.uri("cluster.local/ns/default").is_err());
+        assert!(reject.uri("https://example.com/a b").is_err());
+        assert_eq!(sanitize.uri("https://example.com/a b").unwrap(), "https://example.com/a%20b");
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Result, Context};
+use chrono::{Duration, Utc};
+use rcgen::KeyPair;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, debug, warn};
+use zeroize::Zeroizing;
+
+use crate::ca_manager::CaManager;
+use crate::cert_manager::CertificateManager;
+use crate::cert_service::signer::parse_ca_key;
+use crate::cert_service::name_constraints::NameConstraints;
+use crate::cert_validation::validate_issued_certificate;
+use crate::local_signing::{sign_leaf, LocalPolicy, LocalSigningRequest};
+use crate::metrics::{self, Metrics};
+
+/// How long to wait before asking for an intermediate again after a failure
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Intermediates this close to expiring are no longer used for signing
+const MIN_REMAINING_VALIDITY: Duration = Duration::seconds(60);
+
+/// An intermediate CA issued to this node by the certificate service
+struct Intermediate {
+    cert_pem: String,
+    key: Zeroizing<KeyPair>,
+    not_after: i64,
+    constraints: NameConstraints,
+}
+
+impl Intermediate {
+    /// Refuses intermediates without permitted DNS subtrees, which could sign for any name
+    fn new(cert_pem: String, key_pem: Zeroizing<String>, not_after: i64) -> Result<Self> {
+        let constraints = NameConstraints::from_ca_pem(&cert_pem)?
+            .filter(|constraints| !constraints.permitted_dns().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Node intermediate CA is not constrained to DNS domains"))?;
+        // Generated by the certificate service and sent unencrypted over TLS
+        let key = parse_ca_key(&key_pem, None).context("Invalid node intermediate CA key")?;
+        Ok(Self {
+            cert_pem,
+            key,
+            not_after,
+            constraints,
+        })
+    }
+
+    /// Sign `request`, returning the certificate followed by the intermediate
+    fn sign(&self, request: &LocalSigningRequest) -> Result<(String, Zeroizing<String>, i64, i64)> {
+        let remaining = Duration::seconds(self.not_after - Utc::now().timestamp());
+        if remaining < MIN_REMAINING_VALIDITY {
+            return Err(anyhow::anyhow!("Node intermediate CA expires at {}", self.not_after));
+        }
+
+        self.constraints.check(&request.common_name, &request.dns_names, &[]).map_err(|reason| {
+            anyhow::anyhow!("Outside the name constraints of the node intermediate CA: {}", reason)
+        })?;
+
+        let (cert_pem, key_pem, not_before, not_after) = sign_leaf(&self.cert_pem, &self.key, request, remaining)?;
+        let chain = format!("{}\n{}\n", cert_pem.trim_end(), self.cert_pem.trim_end());
+        Ok((chain, key_pem, not_before, not_after))
+    }
+}
+
+/// Signs workload certificates on the node with a short-lived intermediate CA from the certificate service
+///
+/// The intermediate is name-constrained and issued for this node only, so a compromised
+/// node can only sign names within the constraints until its intermediate expires. It is
+/// only used while the certificate service is unavailable, for volumes eligible for local
+/// signing (see [`LocalSigningRequest`]) that pass the [`LocalPolicy`].
+#[derive(Clone)]
+pub struct DelegatedCa {
+    cert_manager: CertificateManager,
+    ca_manager: CaManager,
+    node_id: String,
+    intermediate: Arc<RwLock<Option<Intermediate>>>,
+    policy: LocalPolicy,
+    metrics: Metrics,
+}
+
+impl DelegatedCa {
+    pub fn new(cert_manager: CertificateManager, ca_manager: CaManager, node_id: String, metrics: Metrics) -> Self {
+        Self {
+            cert_manager,
+            ca_manager,
+            node_id,
+            intermediate: Arc::new(RwLock::new(None)),
+            policy: LocalPolicy::default(),
+            metrics,
+        }
+    }
+
+    /// Apply the certificate service's namespace and tenant checks before signing
+    pub fn with_policy(mut self, policy: LocalPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Keep an intermediate, replacing it after two thirds of its lifetime
+    ///
+    /// Runs until the process exits.
+    pub async fn run(&self) {
+        loop {
+            let wait = match self.refresh().await {
+                Ok(wait) => wait,
+                Err(e) => {
+                    warn!("Failed to obtain a node intermediate CA, retrying in {:?}: {:#}", RETRY_INTERVAL, e);
+                    RETRY_INTERVAL
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Request a new intermediate, returning how long to wait before replacing it
+    async fn refresh(&self) -> Result<std::time::Duration> {
+        let (cert_pem, key_pem, not_before, not_after) = self.cert_manager.issue_node_intermediate(&self.node_id).await?;
+
+        let ca_pem = self.ca_manager.get_ca_cert().await?;
+        validate_issued_certificate(&cert_pem, &key_pem, &ca_pem, &[], &[])
+            .context("Certificate service returned an unusable intermediate CA")?;
+        let intermediate = Intermediate::new(cert_pem, key_pem, not_after)?;
+
+        info!(
+            "Obtained node intermediate CA, expires {}, permitted DNS names: {}",
+            not_after,
+            intermediate.constraints.permitted_dns().join(", ")
+        );
+        *self.intermediate.write().await = Some(intermediate);
+        self.metrics.set(&metrics::NODE_INTERMEDIATE_EXPIRY, &[], not_after as f64);
+
+        let refresh_at = not_before + (not_after - not_before) * 2 / 3;
+        let wait = (refresh_at - Utc::now().timestamp()).max(RETRY_INTERVAL.as_secs() as i64);
+        Ok(std::time::Duration::from_secs(wait as u64))
+    }
+
+    /// Sign the certificate `cert_id` of a pod in `namespace` with the node intermediate CA
+    ///
+    /// Fails when there is no usable intermediate, the policy denies the request, a name is
+    /// outside the constraints or the namespace has its own CA, which the intermediate does
+    /// not chain to. `operation` (`issue` or `renew`) labels the metric.
+    pub async fn sign(
+        &self,
+        cert_id: &str,
+        namespace: &str,
+        request: &LocalSigningRequest,
+        operation: &str,
+    ) -> Result<(String, Zeroizing<String>, i64, i64)> {
+        if self.ca_manager.has_namespace_ca(namespace).await? {
+            return Err(anyhow::anyhow!("Namespace {} has its own or its tenant's CA", namespace));
+        }
+
+        self.policy.check(namespace, request).await?;
+
+        let result = match self.intermediate.read().await.as_ref() {
+            Some(intermediate) => intermediate.sign(request),
+            None => Err(anyhow::anyhow!("No node intermediate CA yet")),
+        };
+
+        match &result {
+            Ok((_, _, _, not_after)) => {
+                debug!("Signed certificate {} with the node intermediate CA ({}), expires {}", cert_id, operation, not_after);
+                self.metrics.inc(&metrics::DELEGATED_SIGNED, &[("operation", operation), ("result", "success")]);
+            }
+            Err(_) => self.metrics.inc(&metrics::DELEGATED_SIGNED, &[("operation", operation), ("result", "failure")]),
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, GeneralSubtree, IsCa, Issuer, KeyPair, NameConstraints};
+
+    #[test]
+    fn test_intermediate_signs_within_constraints() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::default();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let issuer = Issuer::new(ca_params, &ca_key);
+
+        let intermediate_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.name_constraints = Some(NameConstraints {
+            permitted_subtrees: vec![GeneralSubtree::DnsName("svc.cluster.local".to_string())],
+            excluded_subtrees: vec![],
+        });
+        let cert = params.signed_by(&intermediate_key, &issuer).unwrap();
+        let not_after = (Utc::now() + Duration::hours(1)).timestamp();
+        let intermediate =
+            Intermediate::new(cert.pem(), Zeroizing::new(intermediate_key.serialize_pem()), not_after).unwrap();
+
+        let request = LocalSigningRequest {
+            common_name: "web".to_string(),
+            dns_names: vec!["web.default.svc.cluster.local".to_string()],
+            validity_seconds: 7 * 86400,
+        };
+        let (chain, key, _, leaf_not_after) = intermediate.sign(&request).unwrap();
+        assert!(leaf_not_after <= not_after);
+        validate_issued_certificate(&chain, &key, &ca.pem(), &request.dns_names, &[]).unwrap();
+
+        let outside = LocalSigningRequest {
+            dns_names: vec!["web.example.com".to_string()],
+            ..request.clone()
+        };
+        assert!(intermediate.sign(&outside).is_err());
+        let unqualified = LocalSigningRequest {
+            dns_names: vec!["web".to_string()],
+            ..request
+        };
+        assert!(intermediate.sign(&unqualified).is_err());
+
+        // An intermediate that could sign for any name is not used
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        let unconstrained = params.signed_by(&intermediate_key, &issuer).unwrap();
+        assert!(Intermediate::new(unconstrained.pem(), Zeroizing::new(intermediate_key.serialize_pem()), not_after).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Result, Context};
+use chrono::{Duration, Utc};
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+/// Validity of a generated dev CA
+const DEV_CA_VALIDITY: Duration = Duration::days(365);
+
+/// Self-signed CA for dev mode, so the driver and service run without a CA secret
+///
+/// With `dir`, the CA in `dir/ca.crt` and `dir/ca.key` is reused, or generated and
+/// written there; pointing both binaries at the same directory lets the driver
+/// validate what the service issues. Without it the CA only lives in memory.
+pub fn dev_ca(dir: Option<&Path>) -> Result<(String, Zeroizing<String>)> {
+    if let Some(dir) = dir {
+        let (cert_path, key_path) = (dir.join("ca.crt"), dir.join("ca.key"));
+        if cert_path.exists() && key_path.exists() {
+            let cert = std::fs::read_to_string(&cert_path)
+                .context(format!("Failed to read dev CA certificate {}", cert_path.display()))?;
+            let key = Zeroizing::new(std::fs::read_to_string(&key_path)
+                .context(format!("Failed to read dev CA key {}", key_path.display()))?);
+            info!("Dev mode: using CA from {}", dir.display());
+            return Ok((cert, key));
+        }
+    }
+
+    let key_pair = KeyPair::generate().map_err(|e| anyhow::anyhow!("Failed to generate dev CA key: {}", e))?;
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, "cacsi dev CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let now = Utc::now();
+    params.not_before = time::OffsetDateTime::from(SystemTime::from(now - Duration::minutes(5)));
+    params.not_after = time::OffsetDateTime::from(SystemTime::from(now + DEV_CA_VALIDITY));
+    let cert = params
+        .self_signed(&key_pair)
+        .map_err(|e| anyhow::anyhow!("Failed to self-sign dev CA: {}", e))?
+        .pem();
+    let key = Zeroizing::new(key_pair.serialize_pem());
+
+    if let Some(dir) = dir {
+        std::fs::create_dir_all(dir).context(format!("Failed to create dev CA directory {}", dir.display()))?;
+        std::fs::write(dir.join("ca.crt"), &cert).context("Failed to write dev CA certificate")?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(dir.join("ca.key"))
+            .and_then(|mut file| file.write_all(key.as_bytes()))
+            .context("Failed to write dev CA key")?;
+    }
+
+    warn!("Dev mode: generated a self-signed CA; never use dev mode in a cluster");
+    Ok((cert, key))
+}
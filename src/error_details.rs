@@ -0,0 +1,288 @@
+use prost::Message;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tonic::{Code, Status};
+
+/// Domain of the `ErrorInfo` details the certificate service attaches to its errors
+pub const ERROR_DOMAIN: &str = "cacsi.k8s.io";
+
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// Why the certificate service failed a call, for callers that need more than the status code
+///
+/// Sent as the `reason` of a `google.rpc.ErrorInfo` in the status details, so clients in
+/// any language can read it with their gRPC library's rich error support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorReason {
+    /// The certificate ID is missing or cannot be used as one
+    InvalidCertificateId,
+    /// The namespace policy, a profile, the CA's name constraints, a CEL rule or tenant rule denied the request
+    PolicyDenied,
+    /// The CA that would sign the certificate is not loaded
+    CaNotLoaded,
+    /// A DNS name, IP address or URI cannot be put in a certificate
+    InvalidSan,
+    /// The common name or another subject attribute cannot be encoded
+    InvalidSubject,
+    /// A custom extension is malformed or reserved
+    InvalidExtension,
+    /// The requested validity is invalid or longer than allowed
+    InvalidValidity,
+    /// A key usage or extended key usage is unknown
+    InvalidKeyUsage,
+    /// The key encoding is unknown or does not exist for the profile's key type
+    InvalidKeyEncoding,
+    /// The requested profile does not exist, or no profiles are configured
+    UnknownProfile,
+    /// The caller sends requests faster than the service accepts them; retry later
+    RateLimited,
+    /// The tenant holds its maximum number of certificates; retrying does not help
+    QuotaExceeded,
+    /// The tenant of the namespace could not be determined
+    TenantUnresolved,
+    /// The certificate ID has a valid certificate that the request did not ask to replace
+    AlreadyIssued,
+    /// The certificate was revoked and the renewal did not ask to replace it
+    CertificateRevoked,
+    /// The certificate store cannot be reached
+    StoreUnavailable,
+    /// Generating the key or signing the certificate failed
+    SigningFailed,
+    /// The caller did not authenticate, or its identity may not make the call
+    CallerNotAllowed,
+    /// The signer re-enrolls with the certificate being renewed, which the renewal did not carry with its key
+    CurrentKeyRequired,
+}
+
+impl ErrorReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorReason::InvalidCertificateId => "INVALID_CERTIFICATE_ID",
+            ErrorReason::PolicyDenied => "POLICY_DENIED",
+            ErrorReason::CaNotLoaded => "CA_NOT_LOADED",
+            ErrorReason::InvalidSan => "INVALID_SAN",
+            ErrorReason::InvalidSubject => "INVALID_SUBJECT",
+            ErrorReason::InvalidExtension => "INVALID_EXTENSION",
+            ErrorReason::InvalidValidity => "INVALID_VALIDITY",
+            ErrorReason::InvalidKeyUsage => "INVALID_KEY_USAGE",
+            ErrorReason::InvalidKeyEncoding => "INVALID_KEY_ENCODING",
+            ErrorReason::UnknownProfile => "UNKNOWN_PROFILE",
+            ErrorReason::RateLimited => "RATE_LIMITED",
+            ErrorReason::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorReason::TenantUnresolved => "TENANT_UNRESOLVED",
+            ErrorReason::AlreadyIssued => "ALREADY_ISSUED",
+            ErrorReason::CertificateRevoked => "CERTIFICATE_REVOKED",
+            ErrorReason::StoreUnavailable => "STORE_UNAVAILABLE",
+            ErrorReason::SigningFailed => "SIGNING_FAILED",
+            ErrorReason::CallerNotAllowed => "CALLER_NOT_ALLOWED",
+            ErrorReason::CurrentKeyRequired => "CURRENT_KEY_REQUIRED",
+        }
+    }
+
+    /// A status with `code` and `message` carrying this reason
+    pub fn status(self, code: Code, message: impl Into<String>) -> Status {
+        ErrorInfo::new(self).into_status(code, message)
+    }
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let reasons = [
+            ErrorReason::InvalidCertificateId,
+            ErrorReason::PolicyDenied,
+            ErrorReason::CaNotLoaded,
+            ErrorReason::InvalidSan,
+            ErrorReason::InvalidSubject,
+            ErrorReason::InvalidExtension,
+            ErrorReason::InvalidValidity,
+            ErrorReason::InvalidKeyUsage,
+            ErrorReason::InvalidKeyEncoding,
+            ErrorReason::UnknownProfile,
+            ErrorReason::RateLimited,
+            ErrorReason::QuotaExceeded,
+            ErrorReason::TenantUnresolved,
+            ErrorReason::AlreadyIssued,
+            ErrorReason::CertificateRevoked,
+            ErrorReason::StoreUnavailable,
+            ErrorReason::SigningFailed,
+            ErrorReason::CallerNotAllowed,
+            ErrorReason::CurrentKeyRequired,
+        ];
+        reasons
+            .into_iter()
+            .find(|reason| reason.as_str() == s)
+            .ok_or_else(|| format!("Unknown error reason '{}'", s))
+    }
+}
+
+/// `google.rpc.ErrorInfo`
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// `google.rpc.BadRequest.FieldViolation`
+#[derive(Clone, PartialEq, Message)]
+pub struct FieldViolation {
+    /// Path of the field, e.g. `dns_names[2]`
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// `google.rpc.BadRequest`
+#[derive(Clone, PartialEq, Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<FieldViolation>,
+}
+
+/// `google.rpc.Status`, the encoding of the `grpc-status-details-bin` trailer
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+/// `google.protobuf.Any`
+#[derive(Clone, PartialEq, Message)]
+struct Any {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+impl ErrorInfo {
+    pub fn new(reason: ErrorReason) -> Self {
+        Self {
+            reason: reason.as_str().to_string(),
+            domain: ERROR_DOMAIN.to_string(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Add a metadata entry; keys are lowerCamelCase by `google.rpc.ErrorInfo` convention
+    pub fn with_metadata(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// The reason, if it is one of this service's
+    pub fn reason(&self) -> Option<ErrorReason> {
+        if self.domain != ERROR_DOMAIN {
+            return None;
+        }
+        self.reason.parse().ok()
+    }
+
+    /// A status with `code` and `message` carrying these details
+    pub fn into_status(self, code: Code, message: impl Into<String>) -> Status {
+        self.into_status_with_violations(code, message, Vec::new())
+    }
+
+    /// A status with `code` and `message` carrying these details and, if there are any,
+    /// a `google.rpc.BadRequest` with `violations`
+    pub fn into_status_with_violations(
+        self,
+        code: Code,
+        message: impl Into<String>,
+        violations: Vec<FieldViolation>,
+    ) -> Status {
+        let message = message.into();
+        let mut details = vec![Any {
+            type_url: ERROR_INFO_TYPE_URL.to_string(),
+            value: self.encode_to_vec(),
+        }];
+        if !violations.is_empty() {
+            details.push(Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: BadRequest { field_violations: violations }.encode_to_vec(),
+            });
+        }
+        let status = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details,
+        };
+        Status::with_details(code, message, status.encode_to_vec().into())
+    }
+
+    /// The `ErrorInfo` in the details of `status`, if it has one
+    pub fn from_status(status: &Status) -> Option<Self> {
+        detail(status, ERROR_INFO_TYPE_URL)
+    }
+}
+
+/// The field violations in the details of `status`, empty if it has none
+pub fn field_violations(status: &Status) -> Vec<FieldViolation> {
+    detail::<BadRequest>(status, BAD_REQUEST_TYPE_URL)
+        .map(|bad_request| bad_request.field_violations)
+        .unwrap_or_default()
+}
+
+/// The detail of type `type_url` in the details of `status`
+fn detail<T: Message + Default>(status: &Status, type_url: &str) -> Option<T> {
+    let details = RpcStatus::decode(status.details()).ok()?;
+    details
+        .details
+        .iter()
+        .find(|any| any.type_url == type_url)
+        .and_then(|any| T::decode(any.value.as_slice()).ok())
+}
+
+/// Reason of a failed certificate service call, if the service gave one
+pub fn error_reason(error: &anyhow::Error) -> Option<ErrorReason> {
+    error_info(error).and_then(|info| info.reason())
+}
+
+/// `ErrorInfo` of a failed certificate service call, if the service sent one
+pub fn error_info(error: &anyhow::Error) -> Option<ErrorInfo> {
+    error.downcast_ref::<Status>().and_then(ErrorInfo::from_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_details_round_trip() {
+        let status = ErrorInfo::new(ErrorReason::AlreadyIssued)
+            .with_metadata("serial", "0abc")
+            .into_status(Code::AlreadyExists, "Certificate web already has a valid certificate");
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        let error = anyhow::Error::new(status).context("Failed to issue certificate");
+        assert_eq!(error_reason(&error), Some(ErrorReason::AlreadyIssued));
+        let info = error_info(&error).unwrap();
+        assert_eq!(info.domain, ERROR_DOMAIN);
+        assert_eq!(info.metadata.get("serial").map(String::as_str), Some("0abc"));
+
+        // Statuses of older services and other domains have no reason
+        assert_eq!(error_reason(&anyhow::Error::new(Status::permission_denied("denied"))), None);
+        let foreign = ErrorInfo { domain: "example.com".to_string(), ..ErrorInfo::new(ErrorReason::PolicyDenied) };
+        assert_eq!(foreign.reason(), None);
+        assert_eq!("RATE_LIMITED".parse::<ErrorReason>(), Ok(ErrorReason::RateLimited));
+    }
+}
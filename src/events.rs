@@ -0,0 +1,169 @@
+use anyhow::{Result, Context};
+use chrono::Utc;
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use kube::api::{Api, PostParams};
+use tracing::{debug, info, warn};
+
+use crate::k8s_client::PodRef;
+
+/// Component name reported as the source of events
+const REPORTING_COMPONENT: &str = "cacsi-driver";
+
+/// Kubernetes rejects event messages longer than this
+const MAX_MESSAGE_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum EventType {
+    Normal,
+    Warning,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Normal => "Normal",
+            EventType::Warning => "Warning",
+        }
+    }
+}
+
+/// Posts certificate lifecycle events on the pod that owns the volume
+///
+/// Events are best effort: failures to post them are logged and never fail the
+/// operation they describe.
+#[derive(Clone)]
+pub struct EventRecorder {
+    node_id: String,
+    /// Log events instead of posting them, for running without Kubernetes
+    log_only: bool,
+}
+
+impl EventRecorder {
+    pub fn new(node_id: String) -> Self {
+        Self { node_id, log_only: false }
+    }
+
+    /// Log events instead of posting them to the API server (dev mode)
+    pub fn log_only(mut self) -> Self {
+        self.log_only = true;
+        self
+    }
+
+    /// A certificate was issued and written to the volume
+    pub async fn issued(&self, pod: &PodRef, cert_id: &str, not_after: i64) {
+        let message = format!("Issued certificate {} valid until {}", cert_id, format_timestamp(not_after));
+        self.publish(pod, EventType::Normal, "Issued", message).await;
+    }
+
+    /// The certificate service was down and the certificate was signed on the node instead
+    pub async fn issued_locally(&self, pod: &PodRef, cert_id: &str, not_after: i64) {
+        let message = format!(
+            "Certificate service unavailable, signed certificate {} on the node, valid until {}",
+            cert_id, format_timestamp(not_after)
+        );
+        self.publish(pod, EventType::Warning, "IssuedLocally", message).await;
+    }
+
+    /// Issuing the certificate for a new volume failed
+    pub async fn issue_failed(&self, pod: &PodRef, cert_id: &str, error: &str) {
+        let message = format!("Failed to issue certificate {}: {}", cert_id, error);
+        self.publish(pod, EventType::Warning, "IssueFailed", message).await;
+    }
+
+    /// A certificate was renewed and the volume updated
+    pub async fn renewed(&self, pod: &PodRef, cert_id: &str, not_after: i64) {
+        let message = format!("Renewed certificate {} valid until {}", cert_id, format_timestamp(not_after));
+        self.publish(pod, EventType::Normal, "Renewed", message).await;
+    }
+
+    /// Renewing a certificate failed; the current certificate stays in place
+    pub async fn renewal_failed(&self, pod: &PodRef, cert_id: &str, error: &str) {
+        let message = format!("Failed to renew certificate {}: {}", cert_id, error);
+        self.publish(pod, EventType::Warning, "RenewalFailed", message).await;
+    }
+
+    /// The certificate was revoked by the CA; `action` says what the driver did about it
+    pub async fn revoked(&self, pod: &PodRef, cert_id: &str, reason: &str, action: &str) {
+        let message = format!("Certificate {} was revoked ({}), {}", cert_id, reason, action);
+        self.publish(pod, EventType::Warning, "Revoked", message).await;
+    }
+
+    /// Certificate files of the volume were deleted or truncated; `action` says what the driver did about it
+    pub async fn tampered(&self, pod: &PodRef, cert_id: &str, files: &str, action: &str) {
+        let message = format!("Certificate files {} of {} were deleted or truncated, {}", files, cert_id, action);
+        self.publish(pod, EventType::Warning, "Tampered", message).await;
+    }
+
+    async fn publish(&self, pod: &PodRef, event_type: EventType, reason: &str, message: String) {
+        if self.log_only {
+            info!("{} event for pod {}/{}: {}", reason, pod.namespace, pod.name, message);
+            return;
+        }
+
+        if let Err(e) = self.try_publish(pod, event_type, reason, message).await {
+            warn!("Failed to post {} event for pod {}/{}: {}", reason, pod.namespace, pod.name, e);
+        }
+    }
+
+    async fn try_publish(&self, pod: &PodRef, event_type: EventType, reason: &str, mut message: String) -> Result<()> {
+        if message.len() > MAX_MESSAGE_LEN {
+            let mut end = MAX_MESSAGE_LEN - 3;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+            message.push_str("...");
+        }
+
+        let now = Time(Utc::now());
+        let event = Event {
+            metadata: ObjectMeta {
+                generate_name: Some(format!("{}.", pod.name)),
+                namespace: Some(pod.namespace.clone()),
+                ..Default::default()
+            },
+            involved_object: ObjectReference {
+                api_version: Some("v1".to_string()),
+                kind: Some("Pod".to_string()),
+                name: Some(pod.name.clone()),
+                namespace: Some(pod.namespace.clone()),
+                uid: pod.uid.clone(),
+                ..Default::default()
+            },
+            reason: Some(reason.to_string()),
+            message: Some(message),
+            type_: Some(event_type.as_str().to_string()),
+            count: Some(1),
+            first_timestamp: Some(now.clone()),
+            last_timestamp: Some(now),
+            source: Some(EventSource {
+                component: Some(REPORTING_COMPONENT.to_string()),
+                host: Some(self.node_id.clone()),
+            }),
+            reporting_component: Some(REPORTING_COMPONENT.to_string()),
+            reporting_instance: Some(self.node_id.clone()),
+            ..Default::default()
+        };
+
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let events: Api<Event> = Api::namespaced(client, &pod.namespace);
+        events
+            .create(&PostParams::default(), &event)
+            .await
+            .context("Failed to create event")?;
+
+        debug!("Posted {} event for pod {}/{}", reason, pod.namespace, pod.name);
+
+        Ok(())
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_else(|| "unknown".to_string())
+}
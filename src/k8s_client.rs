@@ -1,11 +1,124 @@
 use anyhow::{Result, Context};
+use kube::client::ClientBuilder;
 use kube::{Client, Api};
-use k8s_openapi::api::core::v1::Pod;
+use kube::runtime::reflector::{ObjectRef, Store};
+use k8s_openapi::api::core::v1::{Node, Pod, ServiceAccount};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tracing::debug;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tower::limit::RateLimitLayer;
+use tower::timeout::TimeoutLayer;
+use tower::util::option_layer;
+use tracing::{debug, warn};
 
+use crate::retry::RetryPolicy;
+
+/// Client shared by everything in the process, so its connections and credentials are reused
+static CLIENT: OnceCell<Client> = OnceCell::const_new();
+
+/// Settings the shared client is created with
+static SETTINGS: OnceLock<ApiSettings> = OnceLock::new();
+
+/// Timeout, rate limit and retries of Kubernetes API requests
+#[derive(Clone, Copy, Debug)]
+pub struct ApiSettings {
+    /// How long the API server may take to answer a request; zero waits indefinitely
+    ///
+    /// Only covers the response headers, so watches keep streaming past it.
+    pub timeout: Duration,
+    /// Requests per second on average; 0 disables the rate limit
+    pub qps: u32,
+    /// Requests sent right away before the rate limit holds them back
+    pub burst: u32,
+    /// Retries of reads that timed out, were throttled or failed on the server
+    pub retry: RetryPolicy,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            qps: 20,
+            burst: 40,
+            retry: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(200),
+                max_backoff: Duration::from_secs(2),
+            },
+        }
+    }
+}
+
+/// Create the shared client with `settings` instead of the defaults
+///
+/// Must be called before the client is first used; later calls are ignored.
+pub fn configure(settings: ApiSettings) {
+    if SETTINGS.set(settings).is_err() || CLIENT.initialized() {
+        warn!("Kubernetes API settings changed after the client was created, ignoring them");
+    }
+}
+
+fn settings() -> ApiSettings {
+    SETTINGS.get().copied().unwrap_or_default()
+}
+
+/// The shared Kubernetes client, created on first use
+///
+/// Creation is retried on the next call if it fails.
 pub async fn get_client() -> Result<Client, kube::Error> {
-    Client::try_default().await
+    CLIENT.get_or_try_init(create_client).await.cloned()
+}
+
+async fn create_client() -> Result<Client, kube::Error> {
+    let settings = settings();
+    let config = kube::Config::infer().await.map_err(kube::Error::InferConfig)?;
+
+    let rate_limit = (settings.qps > 0).then(|| {
+        let burst = settings.burst.max(1);
+        RateLimitLayer::new(u64::from(burst), Duration::from_secs_f64(f64::from(burst) / f64::from(settings.qps)))
+    });
+    let timeout = (!settings.timeout.is_zero()).then(|| TimeoutLayer::new(settings.timeout));
+
+    Ok(ClientBuilder::try_from(config)?
+        .with_layer(&option_layer(rate_limit))
+        .with_layer(&option_layer(timeout))
+        .build())
+}
+
+/// Send a read request, retrying it while the API server times out, throttles or fails
+///
+/// Only for requests that are safe to repeat; writes are left to their callers.
+pub async fn with_retries<T, F, Fut>(mut request: F) -> Result<T, kube::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    let retry = settings().retry;
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if attempt < retry.max_attempts && is_transient(&e) => {
+                let backoff = retry.backoff(attempt);
+                debug!("Kubernetes API request failed, retrying in {:?}: {}", backoff, e);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether a request may succeed when repeated: timeouts, connection errors, throttling
+/// and server errors
+pub fn is_transient(error: &kube::Error) -> bool {
+    match error {
+        kube::Error::Api(response) => response.code == 429 || response.code >= 500,
+        kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+        _ => false,
+    }
 }
 
 /// Fetch pod information from Kubernetes API
@@ -16,12 +129,17 @@ pub async fn get_pod_info(
 ) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
     let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
     
-    let pod = pods.get(pod_name)
+    let pod = with_retries(|| pods.get(pod_name))
         .await
         .context(format!("Failed to get pod {}/{}", namespace, pod_name))?;
     
     debug!("Retrieved pod information for {}/{}", namespace, pod_name);
-    
+
+    Ok(pod_info(&pod))
+}
+
+/// Pod metadata and spec fields for templates, e.g. `labels.app` and `serviceAccountName`
+pub fn pod_info(pod: &Pod) -> (HashMap<String, String>, HashMap<String, String>) {
     // Extract metadata
     let mut metadata_map = HashMap::new();
     let metadata = &pod.metadata;
@@ -74,5 +192,253 @@ pub async fn get_pod_info(
     
     debug!("Extracted {} metadata fields and {} spec fields", metadata_map.len(), spec_map.len());
     
-    Ok((metadata_map, spec_map))
+    (metadata_map, spec_map)
+}
+
+/// Pods on this node as last seen by the pod watch
+///
+/// Lets NodePublishVolume read pod fields for templates without a GET per volume. A pod
+/// that was just scheduled may not have reached the cache yet; callers fall back to the
+/// API server then.
+#[derive(Clone)]
+pub struct PodCache {
+    store: Store<Pod>,
+}
+
+impl PodCache {
+    pub fn new(store: Store<Pod>) -> Self {
+        Self { store }
+    }
+
+    /// The cached pod, if the watch has seen it
+    pub fn get(&self, namespace: &str, name: &str) -> Option<Arc<Pod>> {
+        self.store.get(&ObjectRef::new(name).within(namespace))
+    }
+}
+
+/// Get the name and labels of a node, as `name` and `labels.<key>` fields for templates
+pub async fn get_node_info(client: &Client, node_name: &str) -> Result<HashMap<String, String>> {
+    let nodes: Api<Node> = Api::all(client.clone());
+
+    let node = with_retries(|| nodes.get(node_name))
+        .await
+        .context(format!("Failed to get node {}", node_name))?;
+
+    let mut node_map = HashMap::from([("name".to_string(), node_name.to_string())]);
+    for (key, value) in node.metadata.labels.unwrap_or_default() {
+        node_map.insert(format!("labels.{}", key), value);
+    }
+
+    debug!("Retrieved {} labels of node {}", node_map.len() - 1, node_name);
+
+    Ok(node_map)
+}
+
+/// Get the name and annotations of a ServiceAccount, as `name` and `annotations.<key>` fields for templates
+pub async fn get_service_account_info(
+    client: &Client,
+    namespace: &str,
+    name: &str,
+) -> Result<HashMap<String, String>> {
+    let service_accounts: Api<ServiceAccount> = Api::namespaced(client.clone(), namespace);
+
+    let service_account = with_retries(|| service_accounts.get(name))
+        .await
+        .context(format!("Failed to get service account {}/{}", namespace, name))?;
+
+    let mut service_account_map = HashMap::from([("name".to_string(), name.to_string())]);
+    for (key, value) in service_account.metadata.annotations.unwrap_or_default() {
+        service_account_map.insert(format!("annotations.{}", key), value);
+    }
+
+    debug!("Retrieved service account information for {}/{}", namespace, name);
+
+    Ok(service_account_map)
+}
+
+/// Pod information taken literally from the volume context instead of the Kubernetes API
+///
+/// Used in dev mode, where there is no API server. Besides the `csi.storage.k8s.io/pod.*`
+/// and `serviceAccount.name` attributes kubelet passes, attributes named
+/// `pod.metadata.<field>` and `pod.spec.<field>` supply what templates reference, e.g.
+/// `pod.metadata.labels.app: web`.
+pub fn pod_info_from_volume_context(
+    volume_context: &HashMap<String, String>,
+) -> (HashMap<String, String>, HashMap<String, String>) {
+    let mut metadata_map = HashMap::new();
+    let mut spec_map = HashMap::new();
+
+    for (attribute, field) in [
+        ("csi.storage.k8s.io/pod.name", "name"),
+        ("csi.storage.k8s.io/pod.namespace", "namespace"),
+        ("csi.storage.k8s.io/pod.uid", "uid"),
+    ] {
+        if let Some(value) = volume_context.get(attribute) {
+            metadata_map.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(service_account) = volume_context.get("csi.storage.k8s.io/serviceAccount.name") {
+        spec_map.insert("serviceAccountName".to_string(), service_account.clone());
+    }
+
+    for (key, value) in volume_context {
+        if let Some(field) = key.strip_prefix("pod.metadata.") {
+            metadata_map.insert(field.to_string(), value.clone());
+        } else if let Some(field) = key.strip_prefix("pod.spec.") {
+            spec_map.insert(field.to_string(), value.clone());
+        }
+    }
+
+    (metadata_map, spec_map)
+}
+
+/// Node information taken literally from the volume context in dev mode
+///
+/// `name` is the node ID; attributes named `node.<field>` add to it, e.g.
+/// `node.labels.topology.kubernetes.io/zone: local`.
+pub fn node_info_from_volume_context(node_name: &str, volume_context: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut node_map = HashMap::from([("name".to_string(), node_name.to_string())]);
+    for (key, value) in volume_context {
+        if let Some(field) = key.strip_prefix("node.") {
+            node_map.insert(field.to_string(), value.clone());
+        }
+    }
+    node_map
+}
+
+/// ServiceAccount information taken literally from the volume context in dev mode
+///
+/// `name` comes from the `csi.storage.k8s.io/serviceAccount.name` attribute; attributes
+/// named `serviceaccount.<field>` add to it, e.g. `serviceaccount.annotations.tenant: acme`.
+pub fn service_account_info_from_volume_context(volume_context: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut service_account_map = HashMap::new();
+    if let Some(name) = volume_context.get("csi.storage.k8s.io/serviceAccount.name") {
+        service_account_map.insert("name".to_string(), name.clone());
+    }
+    for (key, value) in volume_context {
+        if let Some(field) = key.strip_prefix("serviceaccount.") {
+            service_account_map.insert(field.to_string(), value.clone());
+        }
+    }
+    service_account_map
+}
+
+/// Reference to the pod that owns a certificate volume
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PodRef {
+    pub namespace: String,
+    pub name: String,
+    /// Only known when kubelet passes `csi.storage.k8s.io/pod.uid` (podInfoOnMount)
+    pub uid: Option<String>,
+}
+
+impl PodRef {
+    /// ID of the certificate for one of this pod's volumes
+    ///
+    /// Includes the pod UID when known, so a pod recreated under the same name gets
+    /// a new certificate ID instead of taking over the records of its predecessor.
+    pub fn certificate_id(&self, volume_id: &str) -> String {
+        match &self.uid {
+            Some(uid) => format!("{}-{}-{}-{}", self.namespace, self.name, uid, volume_id),
+            None => format!("{}-{}-{}", self.namespace, self.name, volume_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_id() {
+        let mut pod = PodRef { namespace: "default".to_string(), name: "web".to_string(), uid: None };
+        assert_eq!(pod.certificate_id("csi-abc"), "default-web-csi-abc");
+
+        pod.uid = Some("6f1c2d4e-0b7a-4c39-9d2e-5a8f3b1c7e90".to_string());
+        assert_eq!(pod.certificate_id("csi-abc"), "default-web-6f1c2d4e-0b7a-4c39-9d2e-5a8f3b1c7e90-csi-abc");
+    }
+
+    #[tokio::test]
+    async fn test_with_retries() {
+        let api_error = |code| kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: String::new(),
+            reason: String::new(),
+            code,
+        });
+        assert!(is_transient(&api_error(503)));
+        assert!(is_transient(&api_error(429)));
+        assert!(!is_transient(&api_error(404)));
+
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let result = with_retries(|| async {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err(api_error(503)),
+                _ => Ok("pod"),
+            }
+        }).await;
+        assert_eq!(result.unwrap(), "pod");
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        attempts.store(0, std::sync::atomic::Ordering::SeqCst);
+        let result: Result<(), _> = with_retries(|| async {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(api_error(404))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_pod_cache() {
+        let mut pod = Pod::default();
+        pod.metadata.name = Some("web".to_string());
+        pod.metadata.namespace = Some("default".to_string());
+        pod.metadata.labels = Some([("app".to_string(), "frontend".to_string())].into());
+        pod.spec = Some(k8s_openapi::api::core::v1::PodSpec {
+            service_account_name: Some("web-sa".to_string()),
+            ..Default::default()
+        });
+
+        let (store, mut writer) = kube::runtime::reflector::store();
+        writer.apply_watcher_event(&kube::runtime::watcher::Event::Apply(pod));
+        let cache = PodCache::new(store);
+        assert!(cache.get("other", "web").is_none());
+
+        let (metadata, spec) = pod_info(&cache.get("default", "web").unwrap());
+        assert_eq!(metadata.get("labels.app").map(String::as_str), Some("frontend"));
+        assert_eq!(spec.get("serviceAccountName").map(String::as_str), Some("web-sa"));
+    }
+
+    #[test]
+    fn test_pod_info_from_volume_context() {
+        let volume_context = HashMap::from([
+            ("csi.storage.k8s.io/pod.name".to_string(), "web".to_string()),
+            ("csi.storage.k8s.io/serviceAccount.name".to_string(), "web-sa".to_string()),
+            ("pod.metadata.labels.app".to_string(), "frontend".to_string()),
+            ("pod.spec.nodeName".to_string(), "laptop".to_string()),
+            ("cn_template".to_string(), "{metadata.labels.app}".to_string()),
+        ]);
+
+        let (metadata, spec) = pod_info_from_volume_context(&volume_context);
+        assert_eq!(metadata.get("name").map(String::as_str), Some("web"));
+        assert_eq!(metadata.get("labels.app").map(String::as_str), Some("frontend"));
+        assert_eq!(spec.get("serviceAccountName").map(String::as_str), Some("web-sa"));
+        assert_eq!(spec.get("nodeName").map(String::as_str), Some("laptop"));
+        assert_eq!(metadata.len() + spec.len(), 4);
+    }
+
+    #[test]
+    fn test_service_account_info_from_volume_context() {
+        let volume_context = HashMap::from([
+            ("csi.storage.k8s.io/serviceAccount.name".to_string(), "web-sa".to_string()),
+            ("serviceaccount.annotations.example.com/tenant-id".to_string(), "acme".to_string()),
+            ("pod.metadata.labels.app".to_string(), "frontend".to_string()),
+        ]);
+
+        let service_account = service_account_info_from_volume_context(&volume_context);
+        assert_eq!(service_account.get("name").map(String::as_str), Some("web-sa"));
+        assert_eq!(service_account.get("annotations.example.com/tenant-id").map(String::as_str), Some("acme"));
+        assert_eq!(service_account.len(), 2);
+    }
 }
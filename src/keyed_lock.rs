@@ -0,0 +1,117 @@
+use dashmap::DashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Async mutexes created per key on first use and dropped once nobody holds or waits for them
+///
+/// Each lock guards a `T`, which lives as long as the lock does; holders can leave a
+/// result in it for callers waiting on the same key.
+pub struct KeyedLocks<T = ()> {
+    locks: Arc<DashMap<String, Arc<Mutex<T>>>>,
+}
+
+impl<T> Clone for KeyedLocks<T> {
+    fn clone(&self) -> Self {
+        Self { locks: self.locks.clone() }
+    }
+}
+
+impl<T: Default> Default for KeyedLocks<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Default> KeyedLocks<T> {
+    pub fn new() -> Self {
+        Self { locks: Arc::new(DashMap::new()) }
+    }
+
+    /// Wait until the lock of `key` is free and take it
+    pub async fn lock(&self, key: &str) -> KeyedLockGuard<T> {
+        let lock = self.locks.entry(key.to_string()).or_default().clone();
+        KeyedLockGuard {
+            locks: self.locks.clone(),
+            key: key.to_string(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    /// Number of keys currently locked or waited for
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.is_empty()
+    }
+}
+
+/// Holds the lock of one key until dropped
+pub struct KeyedLockGuard<T> {
+    locks: Arc<DashMap<String, Arc<Mutex<T>>>>,
+    key: String,
+    guard: Option<OwnedMutexGuard<T>>,
+}
+
+impl<T> KeyedLockGuard<T> {
+    /// Whether other callers are waiting for this lock
+    pub fn has_waiters(&self) -> bool {
+        // One reference is the map's, one the guard's
+        self.locks.get(&self.key).is_some_and(|lock| Arc::strong_count(&lock) > 2)
+    }
+}
+
+impl<T> Deref for KeyedLockGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().expect("guard is only taken on drop")
+    }
+}
+
+impl<T> DerefMut for KeyedLockGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().expect("guard is only taken on drop")
+    }
+}
+
+impl<T> Drop for KeyedLockGuard<T> {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Waiters hold a reference too, so the lock is only removed when none are left
+        self.locks.remove_if(&self.key, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_keyed_locks() {
+        let locks: KeyedLocks<Option<u32>> = KeyedLocks::new();
+
+        let mut first = locks.lock("default-web-csi-abc").await;
+        let other = locks.lock("default-api-csi-abc").await;
+        assert!(!first.has_waiters());
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { *locks.lock("default-web-csi-abc").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(first.has_waiters());
+        assert!(!waiter.is_finished());
+
+        *first = Some(7);
+        drop(first);
+        assert_eq!(waiter.await.unwrap(), Some(7));
+
+        drop(other);
+        assert!(locks.is_empty());
+        assert_eq!(*locks.lock("default-web-csi-abc").await, None);
+    }
+}
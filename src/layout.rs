@@ -0,0 +1,158 @@
+use anyhow::{Result, Context};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Key, certificate and chain in one file, for `layout: haproxy`
+pub const HAPROXY_FILE: &str = "haproxy.pem";
+
+/// Certificate and key in one file, for `layout: mongodb` (`net.tls.certificateKeyFile`)
+pub const MONGODB_FILE: &str = "mongodb.pem";
+
+/// Certificate for `layout: postgres` (`ssl_cert_file`)
+pub const POSTGRES_CERT_FILE: &str = "server.crt";
+
+/// Key for `layout: postgres` (`ssl_key_file`)
+pub const POSTGRES_KEY_FILE: &str = "server.key";
+
+/// Owner of the postgres layout files unless `layout_owner` is set: the postgres user of
+/// the official Debian-based image
+pub const DEFAULT_POSTGRES_UID: u32 = 999;
+
+/// Files of every layout, removed together with the certificate
+pub const LAYOUT_FILES: [&str; 4] = [HAPROXY_FILE, MONGODB_FILE, POSTGRES_CERT_FILE, POSTGRES_KEY_FILE];
+
+/// Extra files for servers with rigid expectations of where their certificate is
+///
+/// `tls.crt` and `tls.key` are always written; a layout adds copies in the form the
+/// server reads, kept up to date on renewal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Only `tls.crt` and `tls.key`
+    #[default]
+    Tls,
+    /// `haproxy.pem` with the key followed by the certificate and chain
+    Haproxy,
+    /// `mongodb.pem` with the certificate and chain followed by the key
+    Mongodb,
+    /// `server.crt` and `server.key` (mode `0600`), owned by the postgres user
+    Postgres,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" => Ok(Self::Tls),
+            "haproxy" => Ok(Self::Haproxy),
+            "mongodb" => Ok(Self::Mongodb),
+            "postgres" => Ok(Self::Postgres),
+            other => Err(format!("layout must be tls, haproxy, mongodb or postgres, got '{}'", other)),
+        }
+    }
+}
+
+impl Layout {
+    /// Layout a volume was published with, from its files, and the owner of those files
+    ///
+    /// Renewals write the layout again with the same owner.
+    pub async fn detect(mount_path: &str) -> (Self, Option<u32>) {
+        for (layout, file) in [
+            (Layout::Haproxy, HAPROXY_FILE),
+            (Layout::Mongodb, MONGODB_FILE),
+            (Layout::Postgres, POSTGRES_KEY_FILE),
+        ] {
+            if let Ok(metadata) = tokio::fs::metadata(Path::new(mount_path).join(file)).await {
+                return (layout, Some(metadata.uid()));
+            }
+        }
+        (Layout::Tls, None)
+    }
+
+    /// Owner of the layout files when the volume sets none
+    pub fn default_owner(&self) -> Option<u32> {
+        match self {
+            Layout::Postgres => Some(DEFAULT_POSTGRES_UID),
+            _ => None,
+        }
+    }
+
+    /// Write the layout's files next to `tls.crt` and `tls.key`, which must be written first
+    ///
+    /// `key_pem` is the key as written to `tls.key`. Files holding the key get the mode
+    /// and group of `tls.key` (at most `0600` for postgres), the others those of `tls.crt`.
+    pub async fn write(&self, mount_path: &str, cert_pem: &str, key_pem: &str, owner: Option<u32>) -> Result<()> {
+        let cert_pem = with_newline(cert_pem);
+        let key_pem = with_newline(key_pem);
+        let files = match self {
+            Layout::Tls => return Ok(()),
+            Layout::Haproxy => vec![(HAPROXY_FILE, format!("{}{}", key_pem, cert_pem), "tls.key")],
+            Layout::Mongodb => vec![(MONGODB_FILE, format!("{}{}", cert_pem, key_pem), "tls.key")],
+            Layout::Postgres => vec![
+                (POSTGRES_CERT_FILE, cert_pem.to_string(), "tls.crt"),
+                (POSTGRES_KEY_FILE, key_pem.to_string(), "tls.key"),
+            ],
+        };
+
+        for (file, contents, like) in files {
+            let path = Path::new(mount_path).join(file);
+            let like = tokio::fs::metadata(Path::new(mount_path).join(like))
+                .await
+                .context(format!("Failed to read the mode of {}", like))?;
+            let mode = match (self, file) {
+                (Layout::Postgres, POSTGRES_KEY_FILE) => like.permissions().mode() & 0o600,
+                _ => like.permissions().mode() & 0o777,
+            };
+
+            tokio::fs::write(&path, contents)
+                .await
+                .context(format!("Failed to write {}", path.display()))?;
+            std::os::unix::fs::chown(&path, owner, Some(like.gid()))
+                .context(format!("Failed to set the owner of {}", path.display()))?;
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                .await
+                .context(format!("Failed to set the mode of {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn with_newline(pem: &str) -> std::borrow::Cow<'_, str> {
+    match pem.ends_with('\n') {
+        true => pem.into(),
+        false => format!("{}\n", pem).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_layouts() {
+        let dir = TempDir::new("layout").unwrap();
+        let mount_path = dir.to_string_lossy().to_string();
+        std::fs::write(dir.join("tls.crt"), "CERT\n").unwrap();
+        std::fs::write(dir.join("tls.key"), "KEY\n").unwrap();
+        std::fs::set_permissions(dir.join("tls.key"), std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        assert_eq!("mongodb".parse::<Layout>().unwrap(), Layout::Mongodb);
+        assert!("nginx".parse::<Layout>().is_err());
+        assert_eq!(Layout::detect(&mount_path).await, (Layout::Tls, None));
+
+        Layout::Haproxy.write(&mount_path, "CERT", "KEY", None).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join(HAPROXY_FILE)).unwrap(), "KEY\nCERT\n");
+        assert_eq!(std::fs::metadata(dir.join(HAPROXY_FILE)).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(Layout::detect(&mount_path).await.0, Layout::Haproxy);
+        std::fs::remove_file(dir.join(HAPROXY_FILE)).unwrap();
+
+        let owner = std::fs::metadata(&dir).unwrap().uid();
+        Layout::Postgres.write(&mount_path, "CERT\n", "KEY\n", Some(owner)).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join(POSTGRES_KEY_FILE)).unwrap(), "KEY\n");
+        assert_eq!(std::fs::metadata(dir.join(POSTGRES_KEY_FILE)).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(Layout::detect(&mount_path).await, (Layout::Postgres, Some(owner)));
+    }
+}
@@ -0,0 +1,115 @@
+//! Certificate issuance for Kubernetes workloads
+//!
+//! The `csi-driver` and `cacsi-service` binaries are thin entry points over this
+//! library. Embed [`cert_manager::CertificateManager`], [`ca_manager::CaManager`]
+//! and [`template_parser::TemplateParser`] to issue certificates from another
+//! controller, or serve [`csi::node::NodeService`] and
+//! [`cert_service::service::CertificateServiceImpl`] behind a custom frontend.
+//!
+//! With `default-features = false, features = ["client"]` only
+//! [`client::CertServiceClient`] and the `certservice.v1` types are built, without
+//! the Kubernetes and signing dependencies.
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod error_details;
+#[cfg(feature = "client")]
+pub mod retry;
+#[cfg(feature = "server")]
+pub mod csi;
+#[cfg(feature = "server")]
+pub mod admin;
+#[cfg(feature = "server")]
+pub mod cert_binding;
+#[cfg(feature = "server")]
+pub mod cert_manager;
+#[cfg(feature = "server")]
+pub mod cert_service;
+#[cfg(feature = "server")]
+pub mod ca_expiry;
+#[cfg(feature = "server")]
+pub mod ca_manager;
+#[cfg(feature = "server")]
+pub mod cert_monitor;
+#[cfg(feature = "server")]
+pub mod cert_validation;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod delegated_ca;
+#[cfg(feature = "server")]
+pub mod dev_ca;
+#[cfg(feature = "server")]
+pub mod encoding;
+#[cfg(feature = "server")]
+pub mod events;
+#[cfg(feature = "server")]
+pub mod k8s_client;
+#[cfg(feature = "server")]
+pub mod key_encryption;
+#[cfg(feature = "server")]
+pub mod keyed_lock;
+#[cfg(feature = "server")]
+pub mod layout;
+#[cfg(feature = "server")]
+pub mod local_signing;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod notifier;
+#[cfg(feature = "server")]
+pub mod orphans;
+#[cfg(feature = "server")]
+pub mod pod_annotations;
+#[cfg(feature = "server")]
+pub mod pod_watch;
+#[cfg(feature = "server")]
+pub mod recovery;
+#[cfg(feature = "server")]
+pub mod reload;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "server")]
+pub mod tamper;
+#[cfg(feature = "server")]
+pub mod template_parser;
+#[cfg(feature = "server")]
+pub mod tenancy;
+#[cfg(feature = "server")]
+pub mod trust_bundle;
+#[cfg(feature = "server")]
+pub mod volume_metadata;
+#[cfg(all(feature = "server", any(test, feature = "test-utils")))]
+pub mod testing;
+
+// Include generated protobuf code
+pub mod proto {
+    #[cfg(feature = "server")]
+    pub mod csi {
+        tonic::include_proto!("csi.v1");
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("csi_descriptor");
+    }
+    #[cfg(feature = "server")]
+    pub mod pluginregistration {
+        tonic::include_proto!("pluginregistration");
+    }
+    #[cfg(feature = "server")]
+    pub mod admin {
+        tonic::include_proto!("admin.v1");
+    }
+    #[cfg(feature = "client")]
+    pub mod certservice {
+        tonic::include_proto!("certservice.v1");
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("cert_service_descriptor");
+
+        impl RevocationReason {
+            /// RFC 5280 style name, e.g. `key_compromise`
+            pub fn short_name(&self) -> String {
+                self.as_str_name().trim_start_matches("REVOCATION_REASON_").to_lowercase()
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use rcgen::{
+    CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use rustls_pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tonic::Code;
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+
+use crate::ca_manager::CaManager;
+use crate::client::is_connect_error;
+use crate::cert_service::namespace_policy::NamespacePolicy;
+use crate::cert_service::signer::random_serial;
+use crate::delegated_ca::DelegatedCa;
+use crate::metrics::{self, Metrics};
+use crate::tenancy::Tenancy;
+
+/// How far notBefore is set in the past, matching the certificate service default
+const NOT_BEFORE_BACKDATE: Duration = Duration::seconds(300);
+
+/// What a locally signed certificate was issued for, so the certificate service can re-issue it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalSigningRequest {
+    pub common_name: String,
+    pub dns_names: Vec<String>,
+    /// Requested validity in seconds, capped by the local signing maximum
+    pub validity_seconds: i64,
+}
+
+/// The certificate service's namespace, wildcard and tenant checks, applied before signing on the node
+///
+/// Built from the `service` settings of the driver config, which must match those of the
+/// certificate service. Wildcard names are never signed on the node, and neither are
+/// certificates of tenants with policy rules or a quota, which only the service enforces.
+#[derive(Clone, Default)]
+pub struct LocalPolicy {
+    namespace_policy: Option<NamespacePolicy>,
+    tenancy: Option<Arc<Tenancy>>,
+}
+
+impl LocalPolicy {
+    pub fn new(namespace_policy: Option<NamespacePolicy>, tenancy: Option<Arc<Tenancy>>) -> Self {
+        Self { namespace_policy, tenancy }
+    }
+
+    /// Check whether a pod in `namespace` may get a certificate for `request` signed on the node
+    pub async fn check(&self, namespace: &str, request: &LocalSigningRequest) -> Result<()> {
+        let names: Vec<&str> = std::iter::once(request.common_name.as_str())
+            .chain(request.dns_names.iter().map(String::as_str))
+            .collect();
+
+        if let Some(wildcard) = names.iter().find(|name| name.contains('*')) {
+            return Err(anyhow::anyhow!("Wildcard name '{}' is only issued by the certificate service", wildcard));
+        }
+
+        if let Some(namespace_policy) = &self.namespace_policy {
+            namespace_policy.check(namespace, &names).map_err(|reason| anyhow::anyhow!("Denied: {}", reason))?;
+        }
+
+        if let Some(tenancy) = &self.tenancy {
+            let tenant = tenancy.tenant_of(namespace).await?;
+            if let Some(tenant) = &tenant {
+                if !tenant.rules.is_empty() || tenant.max_certificates.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "Tenant {} has policy rules or a quota, which only the certificate service enforces", tenant.name
+                    ));
+                }
+            }
+            for other in tenancy.tenants().await {
+                if tenant.as_ref().is_some_and(|tenant| tenant.name == other.name) {
+                    continue;
+                }
+                if let Some(name) = names.iter().find(|name| other.owns_name(name)) {
+                    return Err(anyhow::anyhow!("Denied: {} is in a DNS domain of tenant {}", name, other.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Signs certificates on the node with the CA from the CA secret while the certificate service is down
+///
+/// Only volumes that need nothing from the service (no profile, subject attributes, OUs,
+/// key usages or extensions) of the allowed namespaces are eligible, the [`LocalPolicy`]
+/// applies, validity is capped, and every certificate is logged, counted and reported as
+/// an event on the pod. Locally signed certificates are re-issued by the certificate
+/// service at their first renewal once it is reachable again.
+#[derive(Clone)]
+pub struct LocalSigner {
+    ca_manager: CaManager,
+    max_validity: Duration,
+    /// Namespaces that may fall back to local signing; `*` allows all, empty none
+    namespaces: Vec<String>,
+    policy: LocalPolicy,
+    metrics: Metrics,
+}
+
+impl LocalSigner {
+    pub fn new(ca_manager: CaManager, max_validity: Duration, namespaces: Vec<String>, metrics: Metrics) -> Self {
+        Self {
+            ca_manager,
+            max_validity,
+            namespaces,
+            policy: LocalPolicy::default(),
+            metrics,
+        }
+    }
+
+    /// Apply the certificate service's namespace, wildcard and tenant checks before signing
+    pub fn with_policy(mut self, policy: LocalPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Whether pods in `namespace` may get locally signed certificates
+    pub fn allows(&self, namespace: &str) -> bool {
+        self.namespaces.iter().any(|n| n == "*" || n == namespace)
+    }
+
+    /// Sign the certificate `cert_id` with the node's copy of the CA of `namespace`
+    ///
+    /// `operation` (`issue` or `renew`) labels the log line and metric.
+    pub async fn sign(
+        &self,
+        cert_id: &str,
+        namespace: &str,
+        request: &LocalSigningRequest,
+        operation: &str,
+    ) -> Result<(String, Zeroizing<String>, i64, i64)> {
+        let result = self.sign_with_ca(namespace, request).await;
+
+        match &result {
+            Ok((_, _, _, not_after)) => warn!(
+                "Certificate service unavailable, signed certificate {} locally ({}), CN={}, DNS names={:?}, expires {}",
+                cert_id, operation, request.common_name, request.dns_names, not_after
+            ),
+            Err(e) => warn!("Certificate service unavailable and local signing of {} failed: {:#}", cert_id, e),
+        }
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        self.metrics.inc(&metrics::LOCALLY_SIGNED, &[("operation", operation), ("result", outcome)]);
+
+        result
+    }
+
+    async fn sign_with_ca(&self, namespace: &str, request: &LocalSigningRequest) -> Result<(String, Zeroizing<String>, i64, i64)> {
+        if !self.allows(namespace) {
+            return Err(anyhow::anyhow!("Namespace {} is not in LOCAL_SIGNING_NAMESPACES", namespace));
+        }
+        self.policy.check(namespace, request).await?;
+
+        let ca_manager = self.ca_manager.for_namespace(namespace).await?;
+        let ca_cert_pem = ca_manager.get_ca_cert().await?;
+        let ca_key = ca_manager.get_ca_key().await?;
+        sign_leaf(&ca_cert_pem, &ca_key, request, self.max_validity)
+    }
+}
+
+/// Sign a certificate for `request` with a CA certificate and key, valid for at most `max_validity`
+///
+/// Returns the certificate, its new private key and the validity period as unix timestamps.
+pub fn sign_leaf(
+    ca_cert_pem: &str,
+    ca_key: &KeyPair,
+    request: &LocalSigningRequest,
+    max_validity: Duration,
+) -> Result<(String, Zeroizing<String>, i64, i64)> {
+    let ca_cert = pem::parse_many(ca_cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse CA cert PEM: {}", e))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No certificate in PEM"))?;
+    let ca_cert_der = CertificateDer::from(ca_cert.contents().to_vec());
+    let issuer = Issuer::from_ca_cert_der(&ca_cert_der, ca_key)
+        .map_err(|e| anyhow::anyhow!("Failed to create issuer from CA cert: {}", e))?;
+
+    let key_pair = Zeroizing::new(
+        KeyPair::generate().map_err(|e| anyhow::anyhow!("Failed to generate key pair: {}", e))?,
+    );
+
+    let mut params = CertificateParams::new(request.dns_names.clone())
+        .map_err(|e| anyhow::anyhow!("Invalid DNS names: {}", e))?;
+    params.distinguished_name.push(DnType::CommonName, request.common_name.as_str());
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+        KeyUsagePurpose::KeyAgreement,
+    ];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth, ExtendedKeyUsagePurpose::ClientAuth];
+    params.is_ca = IsCa::ExplicitNoCa;
+    params.use_authority_key_identifier_extension = true;
+
+    params.serial_number = Some(SerialNumber::from_slice(&random_serial()));
+
+    let validity = match request.validity_seconds {
+        0 => max_validity,
+        seconds => Duration::seconds(seconds).min(max_validity),
+    };
+    let now = Utc::now();
+    let (not_before, not_after) = (now - NOT_BEFORE_BACKDATE, now + validity);
+    params.not_before = time::OffsetDateTime::from(SystemTime::from(not_before));
+    params.not_after = time::OffsetDateTime::from(SystemTime::from(not_after));
+
+    let cert = params
+        .signed_by(&*key_pair, &issuer)
+        .map_err(|e| anyhow::anyhow!("Failed to sign certificate with CA: {}", e))?;
+
+    Ok((
+        cert.pem(),
+        Zeroizing::new(key_pair.serialize_pem()),
+        not_before.timestamp(),
+        not_after.timestamp(),
+    ))
+}
+
+/// Sign `request` on the node after issuing it through the certificate service failed with an outage
+///
+/// Uses the node intermediate CA when there is a usable one, else the CA itself when local
+/// signing is enabled for the namespace; `None` when neither applies.
+pub async fn sign_on_node(
+    delegated_ca: Option<&DelegatedCa>,
+    local_signer: Option<&LocalSigner>,
+    cert_id: &str,
+    namespace: &str,
+    request: &LocalSigningRequest,
+    operation: &str,
+) -> Option<Result<(String, Zeroizing<String>, i64, i64)>> {
+    if let Some(delegated_ca) = delegated_ca {
+        match delegated_ca.sign(cert_id, namespace, request, operation).await {
+            Ok(signed) => return Some(Ok(signed)),
+            Err(e) => debug!("Not signing {} with the node intermediate CA: {:#}", cert_id, e),
+        }
+    }
+
+    match local_signer {
+        Some(signer) if signer.allows(namespace) => Some(signer.sign(cert_id, namespace, request, operation).await),
+        _ => None,
+    }
+}
+
+/// Whether a failed certificate service call means the service is down, rather than that it refused the request
+///
+/// Only UNAVAILABLE, DEADLINE_EXCEEDED and failures to connect count; anything else, e.g.
+/// an invalid request or a misconfigured client, is no reason to sign without the service.
+pub fn is_outage(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<tonic::Status>() {
+        Some(status) => matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+        None => is_connect_error(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::RetryPolicy;
+
+    #[tokio::test]
+    async fn test_is_outage() {
+        let unavailable = anyhow::Error::new(tonic::Status::unavailable("connection refused"))
+            .context("Failed to issue certificate");
+        assert!(is_outage(&unavailable));
+
+        let denied = anyhow::Error::new(tonic::Status::permission_denied("namespace not allowed"))
+            .context("Failed to issue certificate");
+        assert!(!is_outage(&denied));
+
+        // Errors without a status only count when the service could not be reached at all
+        assert!(!is_outage(&anyhow::anyhow!("Invalid TLS configuration")));
+        let client = crate::client::CertServiceClient::new("http://127.0.0.1:1").with_retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        });
+        let unreachable = client.get_certificate_info(Default::default()).await.unwrap_err();
+        assert!(is_outage(&unreachable));
+    }
+
+    #[tokio::test]
+    async fn test_local_signer_namespaces() {
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let ca_manager = CaManager::in_memory(ca_cert, ca_key);
+        let namespace_policy = NamespacePolicy::parse("", "kube-system", "", "").unwrap();
+        let request = LocalSigningRequest {
+            common_name: "web.default.svc".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            validity_seconds: 0,
+        };
+        let signer = |namespaces: &[&str]| {
+            LocalSigner::new(
+                ca_manager.clone(),
+                Duration::seconds(600),
+                namespaces.iter().map(|n| n.to_string()).collect(),
+                Metrics::new(),
+            )
+            .with_policy(LocalPolicy::new(Some(namespace_policy.clone()), None))
+        };
+
+        assert!(!signer(&[]).allows("default"));
+        assert!(signer(&[]).sign("web", "default", &request, "issue").await.is_err());
+        assert!(signer(&["prod"]).sign("web", "default", &request, "issue").await.is_err());
+        assert!(signer(&["default"]).sign("web", "default", &request, "issue").await.is_ok());
+        assert!(signer(&["*"]).sign("web", "default", &request, "issue").await.is_ok());
+        assert!(signer(&["*"]).sign("web", "kube-system", &request, "issue").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sign_with_sec1_ca_key() {
+        // CA secrets may hold any key format the certificate service accepts
+        let (ca_cert, ca_key) = crate::dev_ca::dev_ca(None).unwrap();
+        let sec1 = crate::encoding::KeyEncoding::Sec1.encode(&ca_key).unwrap();
+        let signer = LocalSigner::new(CaManager::in_memory(ca_cert.clone(), sec1), Duration::seconds(600), vec!["*".to_string()], Metrics::new());
+        let request = LocalSigningRequest {
+            common_name: "web.default.svc".to_string(),
+            dns_names: vec!["web.default.svc".to_string()],
+            validity_seconds: 0,
+        };
+
+        let (cert_pem, key_pem, _, _) = signer.sign("web", "default", &request, "issue").await.unwrap();
+        crate::cert_validation::validate_issued_certificate(&cert_pem, &key_pem, &ca_cert, &request.dns_names, &[]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_policy() {
+        let request = |names: &[&str]| LocalSigningRequest {
+            common_name: names.first().copied().unwrap_or("web").to_string(),
+            dns_names: names.iter().map(|name| name.to_string()).collect(),
+            validity_seconds: 0,
+        };
+        let namespace_policy = NamespacePolicy::parse("", "kube-system", "team-a=.team-a.svc.cluster.local", "").unwrap();
+        let tenancy = Tenancy::in_memory(
+            "payments:\n  dns_domains: [pay.example.com]\nshop:\n  dns_domains: [shop.example.com]\nsearch:\n  max_certificates: 10\n",
+            &[("team-a", "payments"), ("team-b", "search"), ("team-c", "shop")],
+        ).unwrap();
+        let policy = LocalPolicy::new(Some(namespace_policy), Some(Arc::new(tenancy)));
+
+        assert!(policy.check("team-a", &request(&["web.team-a.svc.cluster.local"])).await.is_ok());
+        assert!(policy.check("team-a", &request(&["web.team-b.svc.cluster.local"])).await.is_err());
+        assert!(policy.check("team-a", &request(&["*.team-a.svc.cluster.local"])).await.is_err());
+        assert!(policy.check("kube-system", &request(&[])).await.is_err());
+        assert!(policy.check("team-c", &request(&["api.shop.example.com"])).await.is_ok());
+        assert!(policy.check("team-c", &request(&["api.pay.example.com"])).await.is_err());
+        assert!(policy.check("team-b", &request(&[])).await.is_err());
+    }
+}
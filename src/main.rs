@@ -1,137 +1,729 @@
-use anyhow::Result;
-use std::env;
+use anyhow::{Result, Context};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use tokio::signal;
-use tonic::transport::Server;
-use tracing::{info, error};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tonic::transport::{server::Router, Server};
+use tracing::{info, error, warn};
 
-mod csi;
-mod cert_manager;
-mod ca_manager;
-mod cert_monitor;
-mod k8s_client;
-mod template_parser;
+use cacsi_driver::{
+    ca_expiry, ca_manager, cert_binding, cert_manager, config, delegated_ca, dev_ca, events, key_encryption,
+    k8s_client, local_signing, metrics, orphans, pod_annotations, pod_watch, proto, recovery, trust_bundle,
+};
+use cacsi_driver::admin::AdminService;
+use cacsi_driver::cert_service::request_log;
+use cacsi_driver::cert_service::signer::{LocalSigner as ServiceSigner, Signer};
+use cacsi_driver::config::{DriverConfig, LogFilter};
+use cacsi_driver::csi::{controller::ControllerService, identity::IdentityService, node::NodeService};
+use cacsi_driver::csi::endpoint::{bind_socket, SocketPermissions};
+use cacsi_driver::csi::registration::RegistrationService;
+use cacsi_driver::cert_monitor::CertificateMonitor;
+use cacsi_driver::shutdown::shutdown_signal;
+use cacsi_driver::tamper::TamperWatch;
 
-use csi::{identity::IdentityService, node::NodeService};
-use cert_monitor::CertificateMonitor;
-
-// Include generated protobuf code
-pub mod proto {
-    pub mod csi {
-        tonic::include_proto!("csi.v1");
-    }
-    pub mod certservice {
-        tonic::include_proto!("certservice.v1");
-    }
-}
+/// Delay before the certificate monitor is started again after it failed or panicked
+const MONITOR_RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // Flags override environment variables, which override the config file
+    let mut config: DriverConfig = config::load()?;
+    config.validate()?;
+    let log_filter = LogFilter::init(config.log_level.as_deref())?;
 
     info!("Starting CSI Certificate Driver");
 
-    // Get configuration from environment variables
-    let socket_path = env::var("CSI_ENDPOINT")
-        .unwrap_or_else(|_| "unix:///csi/csi.sock".to_string());
-    let node_id = env::var("NODE_ID")
-        .unwrap_or_else(|_| hostname::get()
-            .unwrap()
-            .to_string_lossy()
-            .to_string());
-    let cert_service_addr = env::var("CERT_SERVICE_ADDR")
-        .unwrap_or_else(|_| "http://cacsi-service:50051".to_string());
-    let ca_secret_name = env::var("CA_SECRET_NAME")
-        .unwrap_or_else(|_| "csi-ca-secret".to_string());
-    let ca_secret_namespace = env::var("CA_SECRET_NAMESPACE")
-        .unwrap_or_else(|_| "kube-system".to_string());
-    let cert_base_path = env::var("CERT_BASE_PATH")
-        .unwrap_or_else(|_| "/var/lib/csi-certs".to_string());
-    let cluster_domain = env::var("CLUSTER_DOMAIN")
-        .unwrap_or_else(|_| "cluster.local".to_string());
+    let dev_mode = config.dev_mode;
+    let csi_endpoint = config.csi_endpoint();
+    let csi_socket_owner = config.csi_socket_owner.clone();
+    let socket_permissions = SocketPermissions::parse(config.csi_socket_mode.as_deref(), csi_socket_owner.as_deref())?;
+    let driver_mode = config.driver_mode.clone();
+    let shutdown_timeout = std::time::Duration::from_secs(config.shutdown_timeout_seconds);
+    let kube_api = config.kube_api.api_settings();
+    k8s_client::configure(kube_api);
+
+    // Stop on SIGTERM or Ctrl-C, draining requests and renewals in flight
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // The controller only provisions persistent volumes and needs none of the node state
+    if driver_mode == "controller" {
+        info!("Configuration:");
+        info!("  Endpoint: {}", csi_endpoint);
+        info!("  Socket Mode: {:o} (owner: {})", socket_permissions.mode, csi_socket_owner.as_deref().unwrap_or("unchanged"));
+        info!("  Driver Mode: {}", driver_mode);
+        info!("  Shutdown Timeout: {:?}", shutdown_timeout);
+
+        let router = Server::builder()
+            .add_service(reflection_service()?)
+            .add_service(proto::csi::identity_server::IdentityServer::new(IdentityService::new().with_controller()))
+            .add_service(proto::csi::controller_server::ControllerServer::new(ControllerService::new()));
+        let served = serve(router, &csi_endpoint, &socket_permissions, shutdown.clone());
+        drain(served, &shutdown, shutdown_timeout).await?;
+
+        info!("CSI controller shutdown complete");
+        return Ok(());
+    }
+
+    let node_id = config.node_id();
+    let cert_service_addr = config.cert_service_addr();
+    let cert_service_token_file = config.cert_service_token_file.clone();
+    let cert_service_ca_file = config.cert_service_ca_file.clone();
+    let cert_base_path = config.cert_base_path();
+    let retry_policy = config.retry_policy();
+    let circuit_breaker = config.circuit_breaker();
+    let publish_retry_deadline = std::time::Duration::from_secs(config.publish_retry_deadline_seconds);
+    let key_encryption_secret_namespace = config.key_encryption_secret_namespace();
+    let local_signing_fallback = config.local_signing_fallback();
+    let monitor_settings = config.monitor_settings();
+    let namespace_ca_secrets = config.namespace_ca_secrets()?;
+    let notifier = config.notifier()?;
+    let admin_socket = config.admin_socket();
+    // Compared with reloaded configurations
+    let initial_config = config.clone();
+    let kubelet_registration_path = config.kubelet_registration_path;
+    let plugin_registration_dir = config.plugin_registration_dir;
+    let ca_secret_name = config.ca_secret_name;
+    let ca_secret_namespace = config.ca_secret_namespace;
+    let ca_trust_bundle = config.ca_trust_bundle_configmap;
+    let ca_key_passphrase_secret = config.ca_key_passphrase_secret;
+    let ca_expiry_warning_days = config.ca_expiry_warning_days;
+    let ca_expiry_probe_days = config.ca_expiry_probe_days;
+    let dev_ca_dir = config.dev_ca_dir;
+    let cluster_domain = config.cluster_domain;
+    let kubelet_pods_dir = config.kubelet_pods_dir;
+    let orphan_gc_interval = config.orphan_gc_interval;
+    let pod_watch = !dev_mode && config.pod_watch;
+    let revoke_on_pod_delete = config.revoke_on_pod_delete;
+    let renewal_concurrency = config.renewal_concurrency;
+    let cert_check_interval = config.cert_check_interval;
+    let renewal_threshold_percent = config.renewal_threshold_percent;
+    let renewal_jitter_percent = config.renewal_jitter_percent;
+    let key_encryption_secret = config.key_encryption_secret;
+    let encrypt_keys = config.encrypt_keys;
+    let previous_versions = config.previous_versions;
+    let require_tmpfs = config.require_tmpfs;
+    let metrics_addr = config.metrics_addr;
+    let revocation_check_interval = config.revocation_check_interval;
+    let revocation_action = config.revocation_action;
+    let watch_certificates = config.watch_certificates;
+    let watch_volumes = config.watch_volumes;
+    let annotate_pods = !dev_mode && config.annotate_pods;
+    let certificate_bindings = !dev_mode && config.certificate_bindings;
+    let local_signing_max_validity = config.local_signing_max_validity_seconds;
+    let local_signing_namespaces = config.local_signing_namespaces;
+    let delegated_ca_enabled = config.delegated_ca;
+    let metadata_labels = config.metadata_labels;
+
+    // All-in-one mode serves the certificate service in this process, signing with the
+    // node's CA, behind a socket only the driver can reach
+    let cert_service_socket = (driver_mode == "all-in-one")
+        .then(|| PathBuf::from(&cert_base_path).join("cert-service.sock"));
+    // Tenant CAs are needed by the node as well as by the embedded certificate service
+    let tenancy = config.service.tenancy().await?;
+    // Certificates signed on the node pass the service's namespace and tenant checks first
+    let local_policy = local_signing::LocalPolicy::new(config.service.issuance_settings()?.namespace_policy, tenancy.clone());
+    let service_settings = cert_service_socket.as_ref().map(|_| config.service);
+    let cert_service_addr = match &cert_service_socket {
+        Some(socket) => format!("unix://{}", socket.display()),
+        None => cert_service_addr,
+    };
 
     info!("Configuration:");
-    info!("  Socket: {}", socket_path);
+    info!("  Endpoint: {}", csi_endpoint);
+    info!("  Socket Mode: {:o} (owner: {})", socket_permissions.mode, csi_socket_owner.as_deref().unwrap_or("unchanged"));
+    info!("  Driver Mode: {}", driver_mode);
+    if dev_mode {
+        info!("  Dev Mode: enabled (no Kubernetes, pod info from volume attributes)");
+    }
     info!("  Node ID: {}", node_id);
-    info!("  Cert Service: {}", cert_service_addr);
-    info!("  CA Secret: {}/{}", ca_secret_namespace, ca_secret_name);
+    match &kubelet_registration_path {
+        Some(path) => info!("  Kubelet Registration: {} (via {})", path, plugin_registration_dir),
+        None => info!("  Kubelet Registration: node-driver-registrar sidecar"),
+    }
+    if let Some(socket) = &admin_socket {
+        info!("  Admin Socket: {}", socket.display());
+    }
+    match &service_settings {
+        Some(settings) => {
+            info!("  Cert Service: embedded ({})", cert_service_addr);
+            settings.log();
+        }
+        None => info!("  Cert Service: {}", cert_service_addr),
+    }
+    info!(
+        "  Cert Service Retries: {} attempts, backoff {:?} up to {:?}",
+        retry_policy.max_attempts, retry_policy.initial_backoff, retry_policy.max_backoff
+    );
+    if circuit_breaker.is_some() {
+        info!(
+            "  Cert Service Circuit Breaker: after {} connection failures, open up to {}s",
+            config.cert_service_circuit_threshold, config.cert_service_circuit_max_open_seconds
+        );
+    } else {
+        info!("  Cert Service Circuit Breaker: disabled");
+    }
+    if !publish_retry_deadline.is_zero() {
+        info!("  Publish Retry Deadline: {:?}", publish_retry_deadline);
+    }
+    match &ca_trust_bundle {
+        _ if dev_mode => info!("  CA: dev CA ({})", dev_ca_dir.as_deref().unwrap_or("in memory")),
+        Some(configmap) => info!("  CA Trust Bundle: {}/{} (CA key not loaded)", ca_secret_namespace, configmap),
+        None => info!("  CA Secret: {}/{}", ca_secret_namespace, ca_secret_name),
+    }
+    if let Some(name) = ca_key_passphrase_secret.as_ref().filter(|_| ca_trust_bundle.is_none()) {
+        info!("  CA Key Passphrase Secret: {}/{}", ca_secret_namespace, name);
+    }
+    for (namespace, secret) in &namespace_ca_secrets {
+        info!("  CA Secret of namespace {}: {}/{}", namespace, ca_secret_namespace, secret);
+    }
+    if let Some(tenancy) = &tenancy {
+        info!("  Tenants: {} (namespace label {})", tenancy.tenants().await.len(), tenancy.label());
+    }
+    match ca_expiry_probe_days {
+        Some(days) => info!("  CA Expiry: warn {} days before, Probe not ready {} days before", ca_expiry_warning_days, days),
+        None => info!("  CA Expiry: warn {} days before", ca_expiry_warning_days),
+    }
     info!("  Cert Base Path: {}", cert_base_path);
     info!("  Cluster Domain: {}", cluster_domain);
+    info!("  Kubelet Pods Dir: {}", kubelet_pods_dir);
+    if orphan_gc_interval > 0 && !dev_mode {
+        info!("  Orphan GC: every {}s", orphan_gc_interval);
+    } else {
+        info!("  Orphan GC: disabled");
+    }
+    info!("  Pod Watch: {} (revoke on delete: {})", pod_watch, revoke_on_pod_delete);
+    if !dev_mode {
+        info!(
+            "  Kubernetes API: timeout {:?}, {} QPS (burst {}), {} attempts",
+            kube_api.timeout, kube_api.qps, kube_api.burst, kube_api.retry.max_attempts
+        );
+    }
+    info!("  Renewal Concurrency: {}", renewal_concurrency);
+    info!("  Certificate Check Interval: {}s", cert_check_interval);
+    info!("  Shutdown Timeout: {:?}", shutdown_timeout);
+    info!("  Renewal Threshold: {}% of lifetime left", renewal_threshold_percent);
+    info!("  Renewal Jitter: {}%", renewal_jitter_percent);
+    if revocation_check_interval > 0 {
+        info!("  Revocation Checks: every {}s ({:?})", revocation_check_interval, revocation_action);
+    } else {
+        info!("  Revocation Checks: disabled");
+    }
+    info!("  Watch Certificates: {}", watch_certificates);
+    info!("  Watch Volumes: {}", watch_volumes);
+    info!("  Annotate Pods: {}", annotate_pods);
+    info!("  Certificate Bindings: {}", certificate_bindings);
+    if !metadata_labels.is_empty() {
+        info!("  Metadata Labels: {}", metadata_labels.join(", "));
+    }
+    if local_signing_fallback {
+        info!(
+            "  Local Signing Fallback: up to {}s validity, namespaces {}",
+            local_signing_max_validity,
+            local_signing_namespaces.join(", ")
+        );
+    } else {
+        info!("  Local Signing Fallback: disabled");
+    }
+    info!("  Delegated CA: {}", if delegated_ca_enabled { "node intermediate" } else { "disabled" });
+    info!("  Require tmpfs: {}", require_tmpfs);
+    match &key_encryption_secret {
+        Some(secret) => info!(
+            "  Key Encryption: {}/{} (default: {})",
+            key_encryption_secret_namespace, secret, if encrypt_keys { "encrypted" } else { "plaintext" }
+        ),
+        None => info!("  Key Encryption: disabled"),
+    }
+    info!("  Previous Versions: {}", previous_versions);
+    info!("  Metrics Address: {}", if metrics_addr.is_empty() { "disabled" } else { &metrics_addr });
+    // The webhook URL usually carries a token, so it is not logged
+    if initial_config.notify_webhook_url.is_some() {
+        info!(
+            "  Alerts: {:?} webhook after {} failed renewals or {}h before expiry, every {}s at most",
+            initial_config.notify_format, initial_config.notify_failure_threshold,
+            initial_config.notify_expiry_hours, initial_config.notify_cooldown_seconds
+        );
+    }
+
+    // Initialize CA manager; in trust-only mode the CA key never reaches the node
+    let ca_manager = match ca_trust_bundle {
+        _ if dev_mode => {
+            let (ca_cert, ca_key) = dev_ca::dev_ca(dev_ca_dir.as_deref().map(std::path::Path::new))?;
+            ca_manager::CaManager::in_memory(ca_cert, ca_key)
+        }
+        Some(configmap) => ca_manager::CaManager::trust_only(configmap, ca_secret_namespace).await?,
+        None => ca_manager::CaManager::new(
+            ca_secret_name,
+            ca_secret_namespace,
+            ca_key_passphrase_secret,
+        ).await?,
+    };
+    let ca_manager = ca_manager
+        .with_namespace_cas(&namespace_ca_secrets)
+        .await?
+        .with_tenancy(tenancy.clone())
+        .await?;
+
+    // Pick up CA rotations without a restart
+    tokio::spawn({
+        let ca_manager = ca_manager.clone();
+        async move {
+            if let Err(e) = ca_manager.watch_secret().await {
+                error!("CA watch error: {}", e);
+            }
+        }
+    });
+
+    let metrics = metrics::Metrics::new();
+
+    // Serve the embedded certificate service before anything asks it for certificates
+    let mut issuance_settings = None;
+    if let (Some(settings), Some(socket)) = (service_settings, &cert_service_socket) {
+        let signer = Arc::new(ServiceSigner::from_ca_manager(&ca_manager).await?);
+        tokio::spawn({
+            let signer = signer.clone();
+            let ca_manager = ca_manager.clone();
+            async move { signer.follow_ca_manager(&ca_manager).await }
+        });
+
+        let mut namespace_signers: HashMap<String, Arc<dyn Signer>> = HashMap::new();
+        for (namespace, namespace_ca) in ca_manager.namespace_cas() {
+            let signer = Arc::new(ServiceSigner::from_ca_manager(namespace_ca).await?);
+            tokio::spawn({
+                let signer = signer.clone();
+                let namespace_ca = namespace_ca.clone();
+                async move { signer.follow_ca_manager(&namespace_ca).await }
+            });
+            namespace_signers.insert(namespace.clone(), signer);
+        }
+
+        let mut tenant_signers: HashMap<String, Arc<dyn Signer>> = HashMap::new();
+        for (secret, tenant_ca) in ca_manager.tenant_cas() {
+            let signer = Arc::new(ServiceSigner::from_ca_manager(tenant_ca).await?);
+            tokio::spawn({
+                let signer = signer.clone();
+                let tenant_ca = tenant_ca.clone();
+                async move { signer.follow_ca_manager(&tenant_ca).await }
+            });
+            tenant_signers.insert(secret.clone(), signer);
+        }
 
-    // Initialize CA manager
-    let ca_manager = ca_manager::CaManager::new(
-        ca_secret_name,
-        ca_secret_namespace,
-    ).await?;
+        let cert_service = settings
+            .build(signer, namespace_signers, tenancy, tenant_signers)
+            .await?
+            .with_request_metrics(metrics.clone())
+            // Only reachable by the driver and root on the node
+            .with_trusted_callers();
+        issuance_settings = Some(cert_service.settings_handle());
+        let incoming = bind_socket(
+            &socket.display().to_string(),
+            &SocketPermissions { mode: 0o600, owner: None },
+        )?;
+        // Not drained on shutdown: renewals in flight still need it
+        tokio::spawn(async move {
+            if let Err(e) = Server::builder()
+                .add_service(proto::certservice::certificate_service_server::CertificateServiceServer::with_interceptor(
+                    cert_service,
+                    request_log::intercept,
+                ))
+                .serve_with_incoming(incoming)
+                .await
+            {
+                error!("Embedded certificate service error: {}", e);
+            }
+        });
+    }
 
     // Initialize certificate manager
-    let cert_manager = cert_manager::CertificateManager::new(
+    let mut cert_manager = cert_manager::CertificateManager::new(
         PathBuf::from(cert_base_path),
         cert_service_addr.clone(),
+    ).with_retry_policy(retry_policy)
+    .with_previous_versions(previous_versions);
+
+    // Fail fast while the certificate service is unreachable
+    if let Some(circuit_breaker) = &circuit_breaker {
+        let metrics = metrics.clone();
+        metrics.set(&metrics::CERT_SERVICE_CIRCUIT_OPEN, &[], 0.0);
+        let circuit_breaker = circuit_breaker.clone().with_listener(move |open| {
+            metrics.set(&metrics::CERT_SERVICE_CIRCUIT_OPEN, &[], if open { 1.0 } else { 0.0 });
+        });
+        cert_manager = cert_manager.with_circuit_breaker(circuit_breaker);
+    }
+
+    if let Some(token_file) = cert_service_token_file.filter(|_| cert_service_socket.is_none()) {
+        cert_manager = cert_manager.with_token_file(token_file);
+    }
+    if let Some(ca_file) = &cert_service_ca_file {
+        let ca_pem = std::fs::read_to_string(ca_file)
+            .context(format!("Failed to read CERT_SERVICE_CA_FILE {}", ca_file.display()))?;
+        cert_manager = cert_manager.with_tls_ca(ca_pem);
+    }
+
+    if let Some(secret) = key_encryption_secret {
+        let encryptor = key_encryption::KeyEncryptor::load(&secret, &key_encryption_secret_namespace, &node_id).await?;
+        cert_manager = cert_manager.with_key_encryption(encryptor, encrypt_keys);
+    }
+
+    // Resume monitoring of certificates mounted before a restart
+    match cert_manager.load_registry().await {
+        Ok(count) => info!("Loaded {} certificates from the persisted registry", count),
+        Err(e) => warn!("Failed to load persisted certificate registry: {}", e),
+    }
+    if !dev_mode {
+        match recovery::recover_certificates(&cert_manager, &PathBuf::from(&kubelet_pods_dir), &node_id).await {
+            Ok(0) => {}
+            Ok(count) => info!("Recovered {} mounted certificates", count),
+            Err(e) => warn!("Failed to recover mounted certificates: {}", e),
+        }
+    }
+
+    // Certificate lifecycle events are posted on the owning pods
+    let mut events = events::EventRecorder::new(node_id.clone());
+    if dev_mode {
+        events = events.log_only();
+    }
+    let annotator = pod_annotations::PodAnnotator::new(annotate_pods);
+    let bindings = cert_binding::BindingRecorder::new(certificate_bindings, node_id.clone());
+
+    if !metrics_addr.is_empty() {
+        let addr = metrics_addr.parse().context("Invalid METRICS_ADDR")?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(addr).await {
+                error!("Metrics endpoint error: {:#}", e);
+            }
+        });
+    }
+
+    // Warn as the CA approaches expiry
+    let mut ca_expiry = ca_expiry::CaExpiryMonitor::new(
+        ca_manager.clone(),
+        chrono::Duration::days(ca_expiry_warning_days),
+        metrics.clone(),
     );
+    if let Some(days) = ca_expiry_probe_days {
+        ca_expiry = ca_expiry.with_probe_window(chrono::Duration::days(days));
+    }
+    tokio::spawn({
+        let ca_expiry = ca_expiry.clone();
+        async move { ca_expiry.run().await }
+    });
+
+    // Sign on the node while the certificate service is down, if enabled
+    let local_signer = local_signing_fallback.then(|| {
+        local_signing::LocalSigner::new(
+            ca_manager.clone(),
+            chrono::Duration::seconds(local_signing_max_validity.max(60)),
+            local_signing_namespaces,
+            metrics.clone(),
+        )
+        .with_policy(local_policy.clone())
+    });
+
+    // Sign eligible volumes with an intermediate CA issued to this node during an outage, if enabled
+    let delegated_ca = delegated_ca_enabled.then(|| {
+        delegated_ca::DelegatedCa::new(cert_manager.clone(), ca_manager.clone(), node_id.clone(), metrics.clone())
+            .with_policy(local_policy.clone())
+    });
+    if let Some(delegated_ca) = &delegated_ca {
+        let delegated_ca = delegated_ca.clone();
+        tokio::spawn(async move { delegated_ca.run().await });
+    }
+
+    // Clean up volumes of pods that went away without NodeUnpublishVolume
+    if orphan_gc_interval > 0 && !dev_mode {
+        let collector = orphans::OrphanCollector::new(
+            cert_manager.clone(),
+            PathBuf::from(&kubelet_pods_dir),
+            node_id.clone(),
+            bindings.clone(),
+            metrics.clone(),
+            std::time::Duration::from_secs(orphan_gc_interval),
+        );
+        tokio::spawn(async move { collector.run().await });
+    }
+
+    // Clean up volumes of deleted pods right away, as kubelet may skip NodeUnpublishVolume,
+    // and cache the pods on the node for templates
+    let pod_cache = pod_watch.then(|| {
+        let pod_watcher = pod_watch::PodWatcher::new(
+            cert_manager.clone(),
+            node_id.clone(),
+            bindings.clone(),
+            revoke_on_pod_delete,
+        );
+        let pod_cache = pod_watcher.cache();
+        tokio::spawn(pod_watcher.run());
+        pod_cache
+    });
 
     // Initialize certificate monitor
-    let cert_monitor = CertificateMonitor::new(
+    let mut cert_monitor = CertificateMonitor::new(
         cert_manager.clone(),
         ca_manager.clone(),
-    );
+        events.clone(),
+        annotator.clone(),
+        metrics.clone(),
+    )
+    .with_settings(monitor_settings)
+    .with_certificate_watch(watch_certificates)
+    .with_bindings(bindings.clone())
+    .with_shutdown(shutdown.clone());
+    if let Some(local_signer) = &local_signer {
+        cert_monitor = cert_monitor.with_local_signer(local_signer.clone());
+    }
+    if let Some(delegated_ca) = &delegated_ca {
+        cert_monitor = cert_monitor.with_delegated_ca(delegated_ca.clone());
+    }
+    if let Some(notifier) = notifier {
+        cert_monitor = cert_monitor.with_notifier(notifier);
+    }
+
+    // Apply changes of the config file or on SIGHUP that do not need a restart
+    tokio::spawn({
+        let mut current = initial_config;
+        let monitor_settings = cert_monitor.settings_handle();
+        config::watch(move |mut config: DriverConfig| {
+            config.validate()?;
+            if let Some(issuance_settings) = &issuance_settings {
+                issuance_settings.send_replace(config.service.issuance_settings()?);
+            }
+            monitor_settings.send_replace(config.monitor_settings());
+            log_filter.set(config.log_level.as_deref())?;
+            if config.needs_restart(&current) {
+                warn!("Configuration reloaded; changed settings other than the log level, monitor and issuance settings take effect after a restart");
+            } else {
+                info!("Configuration reloaded");
+            }
+            current = config;
+            Ok(())
+        })
+    });
+
+    // On SIGHUP, besides the configuration, reload the CAs and run a monitor pass right
+    // away, so a rotated CA is picked up without waiting for the watch or a restart
+    tokio::spawn({
+        let ca_manager = ca_manager.clone();
+        let monitor_wakeup = cert_monitor.wakeup_handle();
+        async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    warn!("Failed to handle SIGHUP, not reloading the CA on it: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                match ca_manager.reload_ca().await {
+                    Ok(()) => info!("CA reloaded on SIGHUP"),
+                    Err(e) => warn!("Failed to reload the CA on SIGHUP, keeping the current one: {:#}", e),
+                }
+                monitor_wakeup.notify_one();
+            }
+        }
+    });
 
-    // Start certificate monitoring in background
-    let monitor_handle = tokio::spawn(async move {
-        if let Err(e) = cert_monitor.start().await {
-            error!("Certificate monitor error: {}", e);
+    // Start certificate monitoring in background, restarting it if it fails or panics
+    let monitor_heartbeat = cert_monitor.heartbeat();
+    let cert_monitor = Arc::new(cert_monitor);
+    let monitor_handle = tokio::spawn({
+        let cert_monitor = cert_monitor.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            loop {
+                let monitor = cert_monitor.clone();
+                match tokio::spawn(async move { monitor.start().await }).await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => error!("Certificate monitor error: {:#}", e),
+                    Err(e) => error!("Certificate monitor panicked: {}", e),
+                }
+                if shutdown.is_cancelled() {
+                    return;
+                }
+                metrics.inc(&metrics::MONITOR_RESTARTS, &[]);
+                warn!("Restarting certificate monitor in {}s", MONITOR_RESTART_DELAY.as_secs());
+                tokio::time::sleep(MONITOR_RESTART_DELAY).await;
+            }
         }
     });
 
+    // Put back certificate files that sidecars or others on the node delete or truncate
+    if watch_volumes {
+        let tamper_watch = TamperWatch::new(cert_manager.clone(), cert_monitor.clone(), events.clone(), metrics.clone());
+        tokio::spawn(tamper_watch.run());
+    }
+
+    // Keep the CA certificates of trust-only volumes current through CA rotations
+    if !dev_mode {
+        let updater = trust_bundle::TrustBundleUpdater::new(ca_manager.clone(), PathBuf::from(&kubelet_pods_dir), node_id.clone());
+        tokio::spawn(updater.run());
+    }
+
+    // Let node administrators force renewals with cacsictl
+    if let Some(socket) = admin_socket {
+        let admin_service = AdminService::new(cert_manager.clone(), cert_monitor);
+        let incoming = bind_socket(&socket.display().to_string(), &SocketPermissions { mode: 0o600, owner: None })?;
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = Server::builder()
+                .add_service(proto::admin::node_admin_server::NodeAdminServer::new(admin_service))
+                .serve_with_incoming_shutdown(incoming, shutdown.cancelled_owned())
+                .await
+            {
+                error!("Admin socket error: {}", e);
+            }
+        });
+    }
+
     // Create CSI services
-    let identity_service = IdentityService::new();
-    let node_service = NodeService::new(
+    let mut identity_service = IdentityService::new()
+        .with_ca_expiry(ca_expiry)
+        .with_monitor_heartbeat(monitor_heartbeat);
+    if let Some(circuit_breaker) = circuit_breaker {
+        identity_service = identity_service.with_circuit_breaker(circuit_breaker);
+    }
+    let mut node_service = NodeService::new(
         node_id,
-        cert_manager,
+        cert_manager.clone(),
         ca_manager,
         cluster_domain,
-    );
+        events,
+        annotator,
+    )
+    .with_require_tmpfs(require_tmpfs)
+    .with_metadata_labels(metadata_labels)
+    .with_publish_retry_deadline(publish_retry_deadline)
+    .with_bindings(bindings);
+    if dev_mode {
+        node_service = node_service.with_literal_pod_info();
+    }
+    if let Some(pod_cache) = pod_cache {
+        node_service = node_service.with_pod_cache(pod_cache);
+    }
+    if let Some(local_signer) = local_signer {
+        node_service = node_service.with_local_signer(local_signer);
+    }
+    if let Some(delegated_ca) = delegated_ca {
+        node_service = node_service.with_delegated_ca(delegated_ca);
+    }
 
-    // Parse socket path
-    let socket_path = socket_path
-        .strip_prefix("unix://")
-        .unwrap_or(&socket_path);
+    // Start gRPC server
+    let router = Server::builder()
+        .add_service(reflection_service()?)
+        .add_service(proto::csi::identity_server::IdentityServer::new(identity_service))
+        .add_service(proto::csi::node_server::NodeServer::new(node_service));
+    let served = serve(router, &csi_endpoint, &socket_permissions, shutdown.clone());
+    let registered = async {
+        match kubelet_registration_path {
+            Some(path) => register(path, &plugin_registration_dir, shutdown.clone()).await,
+            None => Ok(()),
+        }
+    };
+    drain(
+        async {
+            // The CSI socket is bound when `served` is first polled, before the kubelet learns about it
+            tokio::try_join!(served, registered)?;
+            // The monitor stops once its renewals in flight have finished
+            monitor_handle.await.ok();
+            Ok(())
+        },
+        &shutdown,
+        shutdown_timeout,
+    )
+    .await?;
+
+    // Record the latest renewal state for the next start
+    if let Err(e) = cert_manager.save_registry().await {
+        warn!("Failed to save certificate registry: {:#}", e);
+    }
+
+    info!("CSI driver shutdown complete");
+    Ok(())
+}
 
-    // Remove existing socket file if it exists
-    let _ = std::fs::remove_file(socket_path);
+/// Serve the CSI services on `endpoint` until `shutdown` is cancelled
+///
+/// `tcp://<addr>:<port>` listens on TCP, for test rigs and csi-sanity runs that cannot
+/// share a socket with the driver; anything else is a Unix socket path, optionally
+/// prefixed with `unix://`. On shutdown no new connections are accepted, and this
+/// returns once the requests in flight have completed.
+async fn serve(
+    router: Router,
+    endpoint: &str,
+    socket_permissions: &SocketPermissions,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let shutdown = shutdown.cancelled_owned();
 
-    // Create socket directory if it doesn't exist
-    if let Some(parent) = PathBuf::from(socket_path).parent() {
-        std::fs::create_dir_all(parent)?;
+    match endpoint.strip_prefix("tcp://") {
+        Some(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .context(format!("Failed to listen on {}", addr))?;
+            warn!("CSI driver listening on tcp://{}; the endpoint is unauthenticated, use TCP only for testing", listener.local_addr()?);
+            router
+                .serve_with_incoming_shutdown(tokio_stream::wrappers::TcpListenerStream::new(listener), shutdown)
+                .await?;
+        }
+        None => router.serve_with_incoming_shutdown(bind_socket(endpoint, socket_permissions)?, shutdown).await?,
     }
 
-    // Create UDS listener
-    let uds = tokio::net::UnixListener::bind(socket_path)?;
-    let uds_stream = tokio_stream::wrappers::UnixListenerStream::new(uds);
+    Ok(())
+}
 
-    info!("CSI driver listening on {}", socket_path);
+/// Serve the kubelet plugin registration API until `shutdown` is cancelled
+///
+/// The registration socket is left behind on shutdown, so the kubelet keeps the
+/// driver registered across restarts and the next start replaces it.
+async fn register(kubelet_registration_path: String, registration_dir: &str, shutdown: CancellationToken) -> Result<()> {
+    let socket_path = RegistrationService::socket_path(std::path::Path::new(registration_dir));
+    let incoming = bind_socket(&socket_path.display().to_string(), &SocketPermissions::default())?;
 
-    // Start gRPC server
     Server::builder()
-        .add_service(proto::csi::identity_server::IdentityServer::new(identity_service))
-        .add_service(proto::csi::node_server::NodeServer::new(node_service))
-        .serve_with_incoming_shutdown(uds_stream, async {
-            signal::ctrl_c().await.ok();
-            info!("Received shutdown signal");
-        })
+        .add_service(proto::pluginregistration::registration_server::RegistrationServer::new(
+            RegistrationService::new(kubelet_registration_path),
+        ))
+        .serve_with_incoming_shutdown(incoming, shutdown.cancelled_owned())
         .await?;
 
-    // Wait for monitor to finish
-    monitor_handle.abort();
-
-    info!("CSI driver shutdown complete");
     Ok(())
 }
+
+/// Run `work` to completion, giving up `timeout` after `shutdown` was cancelled
+async fn drain(
+    work: impl std::future::Future<Output = Result<()>>,
+    shutdown: &CancellationToken,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(timeout).await;
+    };
+
+    tokio::select! {
+        result = work => result,
+        _ = deadline => {
+            warn!("Work in flight did not finish within {:?} of the shutdown signal, exiting anyway", timeout);
+            Ok(())
+        }
+    }
+}
+
+/// gRPC server reflection for the CSI services, so grpcurl works on nodes without the proto files
+fn reflection_service() -> Result<
+    tonic_reflection::server::ServerReflectionServer<impl tonic_reflection::server::ServerReflection>,
+> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::csi::FILE_DESCRIPTOR_SET)
+        .build()
+        .context("Failed to build gRPC reflection service")
+}
@@ -0,0 +1,295 @@
+use anyhow::{Result, Context};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Kind of a metric in the Prometheus text format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+/// A metric family exported by the driver
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+}
+
+/// Renewals attempted by the certificate monitor, by `result`
+pub const RENEWALS: Metric = Metric {
+    name: "cacsi_renewals_total",
+    help: "Certificate renewals attempted by the certificate monitor",
+    kind: MetricKind::Counter,
+};
+
+/// Failed renewals by `severity` (`warning`, or `critical` close to expiry)
+pub const RENEWAL_FAILURES: Metric = Metric {
+    name: "cacsi_renewal_failures_total",
+    help: "Failed certificate renewals by how close the certificate was to expiring",
+    kind: MetricKind::Counter,
+};
+
+/// Consecutive failed renewals per `cert_id`, removed once a renewal succeeds
+pub const CONSECUTIVE_RENEWAL_FAILURES: Metric = Metric {
+    name: "cacsi_certificate_consecutive_renewal_failures",
+    help: "Consecutive failed renewals of a certificate",
+    kind: MetricKind::Gauge,
+};
+
+/// Expiry of each monitored certificate, by `cert_id`
+pub const CERTIFICATE_EXPIRY: Metric = Metric {
+    name: "cacsi_certificate_expiry_timestamp_seconds",
+    help: "Expiry of a monitored certificate as a unix timestamp",
+    kind: MetricKind::Gauge,
+};
+
+/// Last time the certificate monitor reconciled its renewal schedule
+pub const MONITOR_LAST_PASS: Metric = Metric {
+    name: "cacsi_monitor_last_pass_timestamp_seconds",
+    help: "Last time the certificate monitor reconciled its renewal schedule, as a unix timestamp",
+    kind: MetricKind::Gauge,
+};
+
+/// Restarts of the certificate monitor after it failed or panicked
+pub const MONITOR_RESTARTS: Metric = Metric {
+    name: "cacsi_monitor_restarts_total",
+    help: "Restarts of the certificate monitor after it failed or panicked",
+    kind: MetricKind::Counter,
+};
+
+/// Whether calls to the certificate service currently fail fast
+pub const CERT_SERVICE_CIRCUIT_OPEN: Metric = Metric {
+    name: "cacsi_cert_service_circuit_open",
+    help: "1 while calls to the certificate service fail fast after repeated connection failures",
+    kind: MetricKind::Gauge,
+};
+
+/// Revoked certificates found on the node, by the `action` taken
+pub const REVOKED_CERTIFICATES: Metric = Metric {
+    name: "cacsi_revoked_certificates_total",
+    help: "Mounted certificates found to be revoked, by the action taken",
+    kind: MetricKind::Counter,
+};
+
+/// Volumes of pods no longer on the node that were unregistered and removed
+pub const ORPHANED_VOLUMES: Metric = Metric {
+    name: "cacsi_orphaned_volumes_collected_total",
+    help: "Certificate volumes of pods no longer on the node that were cleaned up",
+    kind: MetricKind::Counter,
+};
+
+/// Certificates signed on the node while the certificate service was down, by `operation` and `result`
+pub const LOCALLY_SIGNED: Metric = Metric {
+    name: "cacsi_locally_signed_certificates_total",
+    help: "Certificates signed on the node because the certificate service was unavailable",
+    kind: MetricKind::Counter,
+};
+
+/// Certificates signed with the node intermediate CA, by `operation` and `result`
+pub const DELEGATED_SIGNED: Metric = Metric {
+    name: "cacsi_delegated_certificates_total",
+    help: "Certificates signed on the node with its intermediate CA",
+    kind: MetricKind::Counter,
+};
+
+/// Expiry of the intermediate CA the node currently signs with
+pub const NODE_INTERMEDIATE_EXPIRY: Metric = Metric {
+    name: "cacsi_node_intermediate_expiry_timestamp_seconds",
+    help: "Expiry of the node's intermediate CA as a unix timestamp",
+    kind: MetricKind::Gauge,
+};
+
+/// Expiry of the CA certificate
+pub const CA_EXPIRY: Metric = Metric {
+    name: "cacsi_ca_expiry_timestamp_seconds",
+    help: "Expiry of the CA certificate as a unix timestamp",
+    kind: MetricKind::Gauge,
+};
+
+/// Certificates whose files did not match the registry, by `kind`
+pub const CERTIFICATE_DRIFT: Metric = Metric {
+    name: "cacsi_certificate_drift_total",
+    help: "Registered certificates whose files on disk did not match the registry",
+    kind: MetricKind::Counter,
+};
+
+/// Volumes whose certificate files were deleted or truncated, by the `action` taken
+pub const TAMPERED_VOLUMES: Metric = Metric {
+    name: "cacsi_tampered_volumes_total",
+    help: "Volumes whose certificate files were deleted or truncated behind the driver's back",
+    kind: MetricKind::Counter,
+};
+
+/// Calls answered by the certificate service, by `method` and status `code`
+pub const CERT_SERVICE_REQUESTS: Metric = Metric {
+    name: "cacsi_cert_service_requests_total",
+    help: "Calls answered by the certificate service",
+    kind: MetricKind::Counter,
+};
+
+/// Time the certificate service spent answering calls, by `method`
+pub const CERT_SERVICE_REQUEST_SECONDS: Metric = Metric {
+    name: "cacsi_cert_service_request_duration_seconds_total",
+    help: "Seconds the certificate service spent answering calls",
+    kind: MetricKind::Counter,
+};
+
+struct Family {
+    help: &'static str,
+    kind: MetricKind,
+    /// Rendered label set -> value
+    samples: BTreeMap<String, f64>,
+}
+
+/// Metrics of the CSI driver or the certificate service, exported in the Prometheus text format
+#[derive(Clone, Default)]
+pub struct Metrics {
+    families: Arc<Mutex<BTreeMap<&'static str, Family>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter
+    pub fn inc(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        self.update(metric, labels, |value| *value += 1.0);
+    }
+
+    /// Add `value` to a counter
+    pub fn add(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |current| *current += value);
+    }
+
+    /// Set a gauge
+    pub fn set(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |current| *current = value);
+    }
+
+    /// Remove a series, e.g. of a certificate that is no longer monitored
+    pub fn remove(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        let mut families = self.families.lock().unwrap();
+        if let Some(family) = families.get_mut(metric.name) {
+            family.samples.remove(&format_labels(labels));
+        }
+    }
+
+    fn update(&self, metric: &Metric, labels: &[(&str, &str)], apply: impl FnOnce(&mut f64)) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            help: metric.help,
+            kind: metric.kind,
+            samples: BTreeMap::new(),
+        });
+        apply(family.samples.entry(format_labels(labels)).or_insert(0.0));
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut output = String::new();
+
+        for (name, family) in families.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, family.help);
+            let _ = writeln!(output, "# TYPE {} {}", name, family.kind.as_str());
+            for (labels, value) in &family.samples {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+
+        output
+    }
+
+    /// Serve the metrics on `/metrics` until the process exits
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        let make_service = make_service_fn(move |_| {
+            let metrics = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let metrics = metrics.clone();
+                    async move { Ok::<_, Infallible>(metrics.handle(request)) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)
+            .context(format!("Failed to bind metrics endpoint {}", addr))?
+            .serve(make_service);
+
+        info!("Serving metrics on http://{}/metrics", addr);
+        server.await.context("Metrics server failed")
+    }
+
+    fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET || request.uri().path() != "/metrics" {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+
+        let mut response = Response::new(Body::from(self.render()));
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        response
+    }
+}
+
+/// Render a label set as `{name="value",...}`, or nothing when empty
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let rendered: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let escaped = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, escaped)
+        })
+        .collect();
+
+    format!("{{{}}}", rendered.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_text_format() {
+        let metrics = Metrics::new();
+        metrics.inc(&RENEWALS, &[("result", "success")]);
+        metrics.inc(&RENEWALS, &[("result", "success")]);
+        metrics.set(&CERTIFICATE_EXPIRY, &[("cert_id", "ns/\"pod\"")], 1700000000.0);
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE cacsi_renewals_total counter"));
+        assert!(output.contains("cacsi_renewals_total{result=\"success\"} 2"));
+        assert!(output.contains("cacsi_certificate_expiry_timestamp_seconds{cert_id=\"ns/\\\"pod\\\"\"} 1700000000"));
+
+        metrics.remove(&CERTIFICATE_EXPIRY, &[("cert_id", "ns/\"pod\"")]);
+        assert!(!metrics.render().contains("1700000000"));
+    }
+}
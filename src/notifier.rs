@@ -0,0 +1,220 @@
+use anyhow::{Result, Context};
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::cert_manager::CertificateInfo;
+
+/// Consecutive failed renewals of a certificate before an alert is sent, unless set otherwise
+pub const DEFAULT_NOTIFY_FAILURE_THRESHOLD: u32 = 3;
+
+/// Hours before expiry an unrenewed certificate is alerted on, unless set otherwise
+pub const DEFAULT_NOTIFY_EXPIRY_HOURS: u64 = 24;
+
+/// Minimum time between two alerts of the same kind for the same certificate, unless set otherwise
+pub const DEFAULT_NOTIFY_COOLDOWN_SECONDS: u64 = 3600;
+
+/// How alerts are posted to the webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyFormat {
+    /// The alert as JSON
+    Generic,
+    /// A Slack incoming webhook message
+    Slack,
+}
+
+impl FromStr for NotifyFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "generic" => Ok(Self::Generic),
+            "slack" => Ok(Self::Slack),
+            other => Err(anyhow::anyhow!("Invalid notify format '{}' (expected generic or slack)", other)),
+        }
+    }
+}
+
+/// Why a certificate needs attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Renewal failed at least the failure threshold times in a row
+    RenewalFailing,
+    /// The certificate expires within the expiry window and was not renewed
+    ExpiringSoon,
+}
+
+/// A certificate that needs attention, as posted by the generic format
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub node_id: String,
+    pub certificate_id: String,
+    pub namespace: String,
+    pub pod: String,
+    /// Expiry as a unix timestamp
+    pub not_after: i64,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Alert {
+    /// One-line summary for chat messages
+    pub fn summary(&self) -> String {
+        let expiry = Utc
+            .timestamp_opt(self.not_after, 0)
+            .single()
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| self.not_after.to_string());
+        let what = match self.kind {
+            AlertKind::RenewalFailing => format!("renewal failed {} times in a row", self.consecutive_failures),
+            AlertKind::ExpiringSoon => "expires soon and was not renewed".to_string(),
+        };
+        let mut summary = format!(
+            "Certificate {} of pod {}/{} on node {}: {}, expires {}",
+            self.certificate_id, self.namespace, self.pod, self.node_id, what, expiry
+        );
+        if let Some(error) = &self.error {
+            summary.push_str(&format!(" (last error: {})", error));
+        }
+        summary
+    }
+}
+
+/// Posts alerts about certificates that keep failing to renew or are about to expire
+///
+/// Alerts of the same kind for the same certificate are sent at most once per cool-down;
+/// once a certificate is renewed, its next problem is alerted on right away.
+pub struct Notifier {
+    client: reqwest::Client,
+    url: String,
+    node_id: String,
+    format: NotifyFormat,
+    pub failure_threshold: u32,
+    pub expiry_window: Duration,
+    cooldown: Duration,
+    /// When each certificate was last alerted on, per kind
+    sent: Mutex<HashMap<(String, AlertKind), Instant>>,
+}
+
+impl Notifier {
+    pub fn new(
+        url: String,
+        node_id: String,
+        format: NotifyFormat,
+        failure_threshold: u32,
+        expiry_window: Duration,
+        cooldown: Duration,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .context("Failed to build notify webhook client")?;
+
+        Ok(Self {
+            client,
+            url,
+            node_id,
+            format,
+            failure_threshold: failure_threshold.max(1),
+            expiry_window,
+            cooldown,
+            sent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Post an alert about `cert_info` in the background, unless the certificate was alerted
+    /// on within the cool-down
+    pub fn notify(&self, kind: AlertKind, cert_info: &CertificateInfo, consecutive_failures: u32, error: Option<&str>) {
+        if !self.claim(&cert_info.cert_id, kind) {
+            return;
+        }
+
+        let alert = Alert {
+            kind,
+            node_id: self.node_id.clone(),
+            certificate_id: cert_info.cert_id.clone(),
+            namespace: cert_info.pod.namespace.clone(),
+            pod: cert_info.pod.name.clone(),
+            not_after: cert_info.not_after,
+            consecutive_failures,
+            error: error.map(str::to_string),
+        };
+        info!("Alerting on certificate {}: {:?}", alert.certificate_id, kind);
+
+        let request = match self.format {
+            NotifyFormat::Generic => self.client.post(&self.url).json(&alert),
+            NotifyFormat::Slack => self.client.post(&self.url).json(&serde_json::json!({ "text": alert.summary() })),
+        };
+        // Posted in the background so a slow webhook does not delay renewals
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+                warn!("Failed to post alert for certificate {}: {}", alert.certificate_id, e);
+            }
+        });
+    }
+
+    /// Forget the alerts of a certificate that was renewed or unregistered
+    pub fn resolve(&self, certificate_id: &str) {
+        self.sent.lock().unwrap().retain(|(cert_id, _), _| cert_id != certificate_id);
+    }
+
+    /// Record an alert as sent, or `false` if one of the same kind was sent within the cool-down
+    fn claim(&self, certificate_id: &str, kind: AlertKind) -> bool {
+        let mut sent = self.sent.lock().unwrap();
+        let key = (certificate_id.to_string(), kind);
+        if sent.get(&key).is_some_and(|sent_at| sent_at.elapsed() < self.cooldown) {
+            return false;
+        }
+        sent.insert(key, Instant::now());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown() {
+        let notifier = Notifier::new(
+            "http://127.0.0.1:1/alerts".to_string(),
+            "node-1".to_string(),
+            NotifyFormat::Slack,
+            3,
+            Duration::from_secs(3600),
+            Duration::from_secs(3600),
+        ).unwrap();
+
+        assert!(notifier.claim("default-web-tls", AlertKind::RenewalFailing));
+        assert!(!notifier.claim("default-web-tls", AlertKind::RenewalFailing));
+        assert!(notifier.claim("default-web-tls", AlertKind::ExpiringSoon));
+        assert!(notifier.claim("default-api-tls", AlertKind::RenewalFailing));
+
+        notifier.resolve("default-web-tls");
+        assert!(notifier.claim("default-web-tls", AlertKind::RenewalFailing));
+
+        let alert = Alert {
+            kind: AlertKind::RenewalFailing,
+            node_id: "node-1".to_string(),
+            certificate_id: "default-web-tls".to_string(),
+            namespace: "default".to_string(),
+            pod: "web".to_string(),
+            not_after: 0,
+            consecutive_failures: 3,
+            error: Some("unavailable".to_string()),
+        };
+        assert_eq!(
+            alert.summary(),
+            "Certificate default-web-tls of pod default/web on node node-1: renewal failed 3 times in a row, \
+             expires 1970-01-01T00:00:00+00:00 (last error: unavailable)"
+        );
+    }
+}
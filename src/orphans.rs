@@ -0,0 +1,185 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, debug, warn};
+
+use crate::cert_binding::BindingRecorder;
+use crate::cert_manager::{CertificateInfo, CertificateManager};
+use crate::metrics::{self, Metrics};
+use crate::recovery::{find_mounted_volumes, pods_on_node, MountedVolume};
+
+/// Seconds between orphan collection passes, unless set otherwise
+pub const DEFAULT_ORPHAN_GC_INTERVAL: u64 = 600;
+
+/// Unregisters certificates and removes volume directories whose pods are gone
+///
+/// NodeUnpublishVolume normally cleans up a volume, but a driver crash in the middle
+/// of it, or a pod deleted while the driver was down, leaves the registry entry and
+/// the key on disk behind for good. A volume is only collected once two passes in a
+/// row found it orphaned, so pods created after the pod list was read are left alone.
+pub struct OrphanCollector {
+    cert_manager: CertificateManager,
+    pods_dir: PathBuf,
+    node_id: String,
+    bindings: BindingRecorder,
+    metrics: Metrics,
+    interval: Duration,
+    /// Mount paths found orphaned by the previous pass
+    suspects: Mutex<HashSet<String>>,
+}
+
+impl OrphanCollector {
+    pub fn new(
+        cert_manager: CertificateManager,
+        pods_dir: PathBuf,
+        node_id: String,
+        bindings: BindingRecorder,
+        metrics: Metrics,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            cert_manager,
+            pods_dir,
+            node_id,
+            bindings,
+            metrics,
+            interval: interval.max(Duration::from_secs(1)),
+            suspects: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Collect orphans every interval
+    pub async fn run(&self) {
+        info!("Collecting orphaned certificate volumes every {}s", self.interval.as_secs());
+        loop {
+            tokio::time::sleep(self.interval).await;
+            match self.collect().await {
+                Ok(0) => {}
+                Ok(count) => info!("Cleaned up {} orphaned certificate volumes", count),
+                Err(e) => warn!("Failed to collect orphaned certificate volumes: {:#}", e),
+            }
+        }
+    }
+
+    /// Run one pass, returning the number of volumes cleaned up
+    pub async fn collect(&self) -> Result<usize> {
+        // Read before the registry and the disk, so volumes published meanwhile look orphaned
+        // at worst in this pass and are spared by the next one
+        let pods = pods_on_node(&self.node_id).await?;
+        let volumes = match find_mounted_volumes(&self.pods_dir) {
+            Ok(volumes) => volumes,
+            Err(e) => {
+                debug!("Only checking registered certificates: {:#}", e);
+                Vec::new()
+            }
+        };
+
+        let orphans = find_orphans(&self.cert_manager.get_all_certificates(), &volumes, &pods);
+        let confirmed: Vec<String> = {
+            let mut suspects = self.suspects.lock().unwrap();
+            let confirmed = orphans.intersection(&suspects).cloned().collect();
+            *suspects = orphans;
+            confirmed
+        };
+
+        for mount_path in &confirmed {
+            warn!("Cleaning up orphaned certificate volume {}", mount_path);
+            for cert_info in self.cert_manager.unregister_certificate(mount_path).await {
+                self.bindings.remove(&cert_info.pod, &cert_info.cert_id).await;
+            }
+            match tokio::fs::remove_dir_all(mount_path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove orphaned volume {}: {}", mount_path, e),
+            }
+            self.metrics.inc(&metrics::ORPHANED_VOLUMES, &[]);
+        }
+
+        Ok(confirmed.len())
+    }
+}
+
+/// Mount paths of registered certificates and on-disk volumes whose pods are no longer on the node
+///
+/// `pods` maps the UID of each pod on the node to its namespace and name. Registered
+/// certificates without a pod UID, issued by older versions, are matched by name.
+/// Registered certificates whose volume directory is gone are orphans as well.
+pub fn find_orphans(
+    registered: &[CertificateInfo],
+    volumes: &[MountedVolume],
+    pods: &HashMap<String, (String, String)>,
+) -> HashSet<String> {
+    let names: HashSet<(&str, &str)> = pods
+        .values()
+        .map(|(namespace, name)| (namespace.as_str(), name.as_str()))
+        .collect();
+
+    let registered_orphans = registered.iter().filter(|cert_info| {
+        let pod_gone = match &cert_info.pod.uid {
+            Some(uid) => !pods.contains_key(uid),
+            None => !names.contains(&(cert_info.pod.namespace.as_str(), cert_info.pod.name.as_str())),
+        };
+        pod_gone || !Path::new(&cert_info.mount_path).exists()
+    });
+    let volume_orphans = volumes.iter().filter(|volume| {
+        !pods.contains_key(&volume.pod_uid) && Path::new(&volume.mount_path).exists()
+    });
+
+    registered_orphans
+        .map(|cert_info| cert_info.mount_path.clone())
+        .chain(volume_orphans.map(|volume| volume.mount_path.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::k8s_client::PodRef;
+    use crate::reload::ReloadStrategy;
+
+    #[test]
+    fn test_find_orphans() {
+        let dir = TempDir::new("orphans").unwrap();
+        let mount = |name: &str| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let registered = |mount_path: String, name: &str, uid: Option<&str>| CertificateInfo {
+            cert_id: format!("default-{}-csi-abc", name),
+            mount_path,
+            pod: PodRef { namespace: "default".to_string(), name: name.to_string(), uid: uid.map(str::to_string) },
+            reload_strategy: ReloadStrategy::None,
+            not_before: 0,
+            not_after: 0,
+            last_renewal_error: None,
+            local_request: None,
+        };
+
+        let pods = HashMap::from([
+            ("uid-web".to_string(), ("default".to_string(), "web".to_string())),
+            ("uid-legacy".to_string(), ("default".to_string(), "legacy".to_string())),
+        ]);
+        let certificates = [
+            registered(mount("web"), "web", Some("uid-web")),
+            registered(mount("legacy"), "legacy", None),
+            registered(mount("deleted"), "deleted", Some("uid-deleted")),
+            registered(dir.join("unmounted").to_string_lossy().to_string(), "web", Some("uid-web")),
+        ];
+        let volumes = [
+            MountedVolume { pod_uid: "uid-web".to_string(), volume_id: "csi-abc".to_string(), mount_path: mount("web") },
+            MountedVolume { pod_uid: "uid-gone".to_string(), volume_id: "csi-def".to_string(), mount_path: mount("leaked") },
+        ];
+
+        let orphans = find_orphans(&certificates, &volumes, &pods);
+
+        let expected: HashSet<String> = ["deleted", "unmounted", "leaked"]
+            .iter()
+            .map(|name| dir.join(name).to_string_lossy().to_string())
+            .collect();
+        assert_eq!(orphans, expected);
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{Result, Context};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, Patch, PatchParams};
+use tracing::{debug, warn};
+use x509_parser::pem::parse_x509_pem;
+
+use crate::k8s_client::PodRef;
+
+/// Annotation holding the expiry (RFC 3339) of the pod's certificate
+pub const NOT_AFTER_ANNOTATION: &str = "cacsi.io/not-after";
+
+/// Annotation holding the serial number (hex) of the pod's certificate
+pub const SERIAL_ANNOTATION: &str = "cacsi.io/serial";
+
+/// Annotation bumped on rotation for volumes using `reload_strategy: annotation`
+pub const ROTATED_AT_ANNOTATION: &str = "cacsi.io/rotated-at";
+
+/// Records certificate expiry and serial as annotations on the owning pod
+///
+/// Like events, annotations are best effort and never fail issuance or renewal.
+/// Pods with several certificate volumes carry the values of the most recently
+/// issued or renewed certificate.
+#[derive(Clone)]
+pub struct PodAnnotator {
+    enabled: bool,
+}
+
+impl PodAnnotator {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Annotate `pod` with the expiry and serial of `cert_pem`
+    pub async fn record_certificate(&self, pod: &PodRef, cert_pem: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Err(e) = self.try_record_certificate(pod, cert_pem).await {
+            warn!("Failed to annotate pod {}/{}: {}", pod.namespace, pod.name, e);
+        }
+    }
+
+    /// Bump the rotation annotation so reloader-style controllers restart the workload
+    ///
+    /// Unlike the expiry annotations this is requested per volume, so it ignores `ANNOTATE_PODS`.
+    pub async fn mark_rotated(&self, pod: &PodRef) -> Result<()> {
+        let rotated_at = chrono::Utc::now().to_rfc3339();
+        self.patch_annotations(pod, serde_json::json!({ ROTATED_AT_ANNOTATION: rotated_at })).await
+    }
+
+    async fn try_record_certificate(&self, pod: &PodRef, cert_pem: &str) -> Result<()> {
+        let (serial, not_after) = certificate_serial_and_expiry(cert_pem)?;
+
+        self.patch_annotations(pod, serde_json::json!({
+            NOT_AFTER_ANNOTATION: not_after,
+            SERIAL_ANNOTATION: serial,
+        })).await?;
+
+        debug!("Annotated pod {}/{} with serial {} expiring {}", pod.namespace, pod.name, serial, not_after);
+
+        Ok(())
+    }
+
+    async fn patch_annotations(&self, pod: &PodRef, annotations: serde_json::Value) -> Result<()> {
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": annotations
+            }
+        });
+
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let pods: Api<Pod> = Api::namespaced(client, &pod.namespace);
+        pods.patch(&pod.name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context("Failed to patch pod annotations")?;
+
+        Ok(())
+    }
+}
+
+/// Extract the serial number (lowercase hex) and expiry (RFC 3339) from a PEM certificate
+fn certificate_serial_and_expiry(cert_pem: &str) -> Result<(String, String)> {
+    let (_, pem) = parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate PEM: {}", e))?;
+    let cert = pem.parse_x509()
+        .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))?;
+
+    let serial: String = cert.raw_serial().iter().map(|b| format!("{:02x}", b)).collect();
+    let not_after = chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .map(|dt| dt.to_rfc3339())
+        .ok_or_else(|| anyhow::anyhow!("Certificate expiry out of range"))?;
+
+    Ok((serial, not_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair, SerialNumber};
+
+    #[test]
+    fn test_certificate_serial_and_expiry() {
+        let mut params = CertificateParams::new(vec!["web".to_string()]).unwrap();
+        params.serial_number = Some(SerialNumber::from_slice(&[0x0a, 0xbc, 0x01]));
+        params.not_after = time::macros::datetime!(2030-01-02 03:04:05 UTC);
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let (serial, not_after) = certificate_serial_and_expiry(&cert.pem()).unwrap();
+        assert_eq!(serial, "0abc01");
+        assert_eq!(not_after, "2030-01-02T03:04:05+00:00");
+    }
+}
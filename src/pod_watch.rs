@@ -0,0 +1,163 @@
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::runtime::reflector::{self, store::Writer};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::Api;
+use std::collections::BTreeSet;
+use tracing::{info, debug, warn};
+
+use crate::cert_binding::BindingRecorder;
+use crate::cert_manager::{CertificateInfo, CertificateManager};
+use crate::k8s_client::PodCache;
+use crate::proto::certservice::RevocationReason;
+
+/// Cleans up the certificates of pods deleted from this node as soon as they are gone
+///
+/// Kubelet sometimes skips NodeUnpublishVolume, e.g. when a pod is force-deleted, which
+/// would leave the certificate registered for renewal and its key on disk. Deletions
+/// missed while the watch was down are left to the orphan collector. The pods seen by
+/// the watch are kept in a [`PodCache`] for NodePublishVolume.
+pub struct PodWatcher {
+    cert_manager: CertificateManager,
+    node_id: String,
+    bindings: BindingRecorder,
+    /// Also have the certificate service revoke the certificates of deleted pods
+    revoke: bool,
+    /// Fills the cache, taken by `run`
+    writer: Option<Writer<Pod>>,
+    cache: PodCache,
+}
+
+impl PodWatcher {
+    pub fn new(cert_manager: CertificateManager, node_id: String, bindings: BindingRecorder, revoke: bool) -> Self {
+        let (store, writer) = reflector::store();
+        Self {
+            cert_manager,
+            node_id,
+            bindings,
+            revoke,
+            writer: Some(writer),
+            cache: PodCache::new(store),
+        }
+    }
+
+    /// Cache of the pods on this node, filled once `run` is watching
+    pub fn cache(&self) -> PodCache {
+        self.cache.clone()
+    }
+
+    /// Watch the pods on this node until the watch ends
+    pub async fn run(mut self) {
+        let client = match crate::k8s_client::get_client().await {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Not watching pods, failed to create Kubernetes client: {:#}", e);
+                return;
+            }
+        };
+
+        let pods: Api<Pod> = Api::all(client);
+        let config = watcher::Config::default().fields(&format!("spec.nodeName={}", self.node_id));
+        let writer = self.writer.take().expect("pod watch already running");
+        let mut events = watcher(pods, config).default_backoff().reflect(writer).boxed();
+
+        info!("Watching pods on node {} for deletions", self.node_id);
+        loop {
+            match events.try_next().await {
+                Ok(Some(watcher::Event::Delete(pod))) => self.pod_deleted(pod).await,
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(e) => warn!("Error watching pods on node {}: {}", self.node_id, e),
+            }
+        }
+    }
+
+    /// Unregister the certificates of a deleted pod and remove their volumes
+    async fn pod_deleted(&self, pod: Pod) {
+        let metadata = pod.metadata;
+        let (Some(namespace), Some(name)) = (metadata.namespace, metadata.name) else {
+            return;
+        };
+
+        let mount_paths = mount_paths_of(
+            &self.cert_manager.get_all_certificates(),
+            &namespace,
+            &name,
+            metadata.uid.as_deref(),
+        );
+        if mount_paths.is_empty() {
+            return;
+        }
+
+        info!("Pod {}/{} was deleted, cleaning up {} certificate volumes", namespace, name, mount_paths.len());
+        for mount_path in mount_paths {
+            for cert_info in self.cert_manager.unregister_certificate(&mount_path).await {
+                self.bindings.remove(&cert_info.pod, &cert_info.cert_id).await;
+                if self.revoke && cert_info.local_request.is_none() {
+                    match self
+                        .cert_manager
+                        .revoke_certificate(&cert_info.cert_id, RevocationReason::CessationOfOperation)
+                        .await
+                    {
+                        Ok(()) => info!("Revoked certificate {} of deleted pod", cert_info.cert_id),
+                        Err(e) => warn!("Failed to revoke certificate {} of deleted pod: {:#}", cert_info.cert_id, e),
+                    }
+                }
+            }
+            match tokio::fs::remove_dir_all(&mount_path).await {
+                Ok(()) => debug!("Removed volume {}", mount_path),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove volume {} of deleted pod: {}", mount_path, e),
+            }
+        }
+    }
+}
+
+/// Mount paths of the registered certificates of a pod
+///
+/// Certificates registered with a pod UID only match that UID, so a pod recreated with
+/// the same name keeps its volumes; older registrations without one match by name.
+pub fn mount_paths_of(registered: &[CertificateInfo], namespace: &str, name: &str, uid: Option<&str>) -> BTreeSet<String> {
+    registered
+        .iter()
+        .filter(|cert_info| cert_info.pod.namespace == namespace && cert_info.pod.name == name)
+        .filter(|cert_info| match (&cert_info.pod.uid, uid) {
+            (Some(registered_uid), Some(uid)) => registered_uid == uid,
+            _ => true,
+        })
+        .map(|cert_info| cert_info.mount_path.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::k8s_client::PodRef;
+    use crate::reload::ReloadStrategy;
+
+    #[test]
+    fn test_mount_paths_of() {
+        let registered = |mount_path: &str, name: &str, uid: Option<&str>| CertificateInfo {
+            cert_id: format!("default-{}-csi-abc", name),
+            mount_path: mount_path.to_string(),
+            pod: PodRef { namespace: "default".to_string(), name: name.to_string(), uid: uid.map(str::to_string) },
+            reload_strategy: ReloadStrategy::None,
+            not_before: 0,
+            not_after: 0,
+            last_renewal_error: None,
+            local_request: None,
+        };
+        let certificates = [
+            registered("/pods/uid-1/certs", "web", Some("uid-1")),
+            registered("/pods/uid-1/client", "web", Some("uid-1")),
+            registered("/pods/uid-2/certs", "web", Some("uid-2")),
+            registered("/pods/legacy/certs", "web", None),
+            registered("/pods/uid-3/certs", "api", Some("uid-3")),
+        ];
+
+        let paths: Vec<String> = mount_paths_of(&certificates, "default", "web", Some("uid-1")).into_iter().collect();
+        assert_eq!(paths, ["/pods/legacy/certs", "/pods/uid-1/certs", "/pods/uid-1/client"]);
+        assert!(mount_paths_of(&certificates, "other", "web", Some("uid-1")).is_empty());
+        assert_eq!(mount_paths_of(&certificates, "default", "web", None).len(), 4);
+    }
+}
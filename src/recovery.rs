@@ -0,0 +1,221 @@
+use anyhow::{Result, Context};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{info, debug, warn};
+
+use crate::cert_manager::{certificate_validity, CertificateManager};
+use crate::csi::identity::PLUGIN_NAME;
+use crate::csi::mode::{self, DUAL_CERTIFICATES};
+use crate::encoding;
+use crate::k8s_client::PodRef;
+use crate::reload::ReloadStrategy;
+use crate::trust_bundle;
+
+/// Directory kubelet uses for CSI volumes inside each pod directory
+const CSI_VOLUMES_DIR: &str = "volumes/kubernetes.io~csi";
+
+/// The parts of kubelet's per-volume `vol_data.json` needed to identify our volumes
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VolumeData {
+    driver_name: String,
+    volume_handle: String,
+}
+
+/// A certificate volume found on disk
+#[derive(Debug)]
+pub struct MountedVolume {
+    pub pod_uid: String,
+    pub volume_id: String,
+    pub mount_path: String,
+}
+
+/// Re-register certificates that are still mounted after a driver restart
+///
+/// The renewal registry only lives in memory, so without this a restart would
+/// silently stop renewal for every mounted certificate. Volumes are found through
+/// kubelet's `vol_data.json` files; pod names are resolved from the pods scheduled
+/// on this node. Volumes missing from the persisted registry fall back to
+/// `reload_strategy: none`.
+pub async fn recover_certificates(
+    cert_manager: &CertificateManager,
+    pods_dir: &Path,
+    node_id: &str,
+) -> Result<usize> {
+    // Trust-only volumes have no certificate to renew, dual volumes one in each subdirectory
+    let volumes: Vec<MountedVolume> = find_mounted_volumes(pods_dir)?
+        .into_iter()
+        .filter(|volume| !trust_bundle::is_trust_only(&volume.mount_path))
+        .flat_map(split_dual)
+        .collect();
+    if volumes.is_empty() {
+        return Ok(0);
+    }
+
+    info!("Found {} mounted certificate volumes, re-registering", volumes.len());
+
+    let pods = pods_on_node(node_id).await?;
+
+    let mut recovered = 0;
+    for volume in volumes {
+        let Some((namespace, name)) = pods.get(&volume.pod_uid) else {
+            warn!("Skipping volume {}: pod {} is no longer on this node", volume.volume_id, volume.pod_uid);
+            continue;
+        };
+
+        let cert_path = Path::new(&volume.mount_path).join("tls.crt");
+        let cert_pem = match encoding::read_certificate(&volume.mount_path).await {
+            Ok(pem) => pem,
+            Err(e) => {
+                warn!("Skipping volume {}: failed to read {}: {}", volume.volume_id, cert_path.display(), e);
+                continue;
+            }
+        };
+
+        let (not_before, not_after) = match certificate_validity(&cert_pem) {
+            Ok(validity) => validity,
+            Err(e) => {
+                warn!("Skipping volume {}: {}", volume.volume_id, e);
+                continue;
+            }
+        };
+
+        // Already restored from the persisted registry, which also knows the reload strategy;
+        // matched by mount path, as certificates issued by older versions have IDs without the pod UID
+        if cert_manager.find_by_mount_path(&volume.mount_path).is_some() {
+            continue;
+        }
+
+        let pod = PodRef {
+            namespace: namespace.clone(),
+            name: name.clone(),
+            uid: Some(volume.pod_uid),
+        };
+        cert_manager.register_certificate(
+            pod.certificate_id(&volume.volume_id),
+            volume.mount_path,
+            pod,
+            ReloadStrategy::None,
+            not_before,
+            not_after,
+            None,
+        ).await;
+        recovered += 1;
+    }
+
+    Ok(recovered)
+}
+
+/// Find this driver's volumes below kubelet's pods directory
+pub fn find_mounted_volumes(pods_dir: &Path) -> Result<Vec<MountedVolume>> {
+    let mut volumes = Vec::new();
+
+    let pod_dirs = std::fs::read_dir(pods_dir)
+        .context(format!("Failed to read kubelet pods directory {}", pods_dir.display()))?;
+
+    for pod_dir in pod_dirs.flatten() {
+        let pod_uid = pod_dir.file_name().to_string_lossy().to_string();
+        let Ok(volume_dirs) = std::fs::read_dir(pod_dir.path().join(CSI_VOLUMES_DIR)) else {
+            continue;
+        };
+
+        for volume_dir in volume_dirs.flatten() {
+            let vol_data_path = volume_dir.path().join("vol_data.json");
+            let Ok(contents) = std::fs::read_to_string(&vol_data_path) else {
+                continue;
+            };
+
+            let vol_data: VolumeData = match serde_json::from_str(&contents) {
+                Ok(data) => data,
+                Err(e) => {
+                    debug!("Ignoring unreadable {}: {}", vol_data_path.display(), e);
+                    continue;
+                }
+            };
+
+            if vol_data.driver_name != PLUGIN_NAME {
+                continue;
+            }
+
+            volumes.push(MountedVolume {
+                pod_uid: pod_uid.clone(),
+                volume_id: vol_data.volume_handle,
+                mount_path: volume_dir.path().join("mount").to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    Ok(volumes)
+}
+
+/// The certificates of a `mode: dual` volume as volumes of their own, the way it publishes them
+fn split_dual(volume: MountedVolume) -> Vec<MountedVolume> {
+    if !mode::is_dual(&volume.mount_path) {
+        return vec![volume];
+    }
+    DUAL_CERTIFICATES
+        .iter()
+        .map(|(directory, _)| MountedVolume {
+            pod_uid: volume.pod_uid.clone(),
+            volume_id: format!("{}-{}", volume.volume_id, directory),
+            mount_path: Path::new(&volume.mount_path).join(directory).to_string_lossy().to_string(),
+        })
+        .collect()
+}
+
+/// Map pod UID to (namespace, name) for the pods scheduled on `node_id`
+pub async fn pods_on_node(node_id: &str) -> Result<HashMap<String, (String, String)>> {
+    let client = crate::k8s_client::get_client()
+        .await
+        .context("Failed to create Kubernetes client")?;
+
+    let pods: Api<Pod> = Api::all(client);
+    let params = ListParams::default().fields(&format!("spec.nodeName={}", node_id));
+    let list = crate::k8s_client::with_retries(|| pods.list(&params))
+        .await
+        .context(format!("Failed to list pods on node {}", node_id))?;
+
+    Ok(list
+        .items
+        .into_iter()
+        .filter_map(|pod| {
+            let metadata = pod.metadata;
+            Some((metadata.uid?, (metadata.namespace?, metadata.name?)))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_find_mounted_volumes() {
+        let pods_dir = TempDir::new("recovery").unwrap();
+
+        let ours = pods_dir.join("uid-1").join(CSI_VOLUMES_DIR).join("certs");
+        std::fs::create_dir_all(&ours).unwrap();
+        std::fs::write(
+            ours.join("vol_data.json"),
+            format!(r#"{{"driverName":"{}","volumeHandle":"csi-abc","specVolID":"certs"}}"#, PLUGIN_NAME),
+        ).unwrap();
+
+        let other = pods_dir.join("uid-2").join(CSI_VOLUMES_DIR).join("secrets");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(
+            other.join("vol_data.json"),
+            r#"{"driverName":"secrets-store.csi.k8s.io","volumeHandle":"csi-def"}"#,
+        ).unwrap();
+
+        let volumes = find_mounted_volumes(&pods_dir).unwrap();
+
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].pod_uid, "uid-1");
+        assert_eq!(volumes[0].volume_id, "csi-abc");
+        assert!(volumes[0].mount_path.ends_with("certs/mount"));
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+use crate::k8s_client::PodRef;
+use crate::pod_annotations::PodAnnotator;
+
+/// File written to the volume for `reload_strategy: sentinel`
+pub const SENTINEL_FILE: &str = ".reload";
+
+/// How an application is told that its certificate was rotated
+///
+/// Many applications only read certificates at startup, so a volume can opt
+/// into a signal after every renewal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReloadStrategy {
+    /// Only the certificate files change
+    #[default]
+    None,
+    /// Rewrite a sentinel file next to the certificate
+    Sentinel,
+    /// Bump an annotation on the pod for reloader-style controllers
+    Annotation,
+}
+
+impl FromStr for ReloadStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "sentinel" => Ok(Self::Sentinel),
+            "annotation" => Ok(Self::Annotation),
+            other => Err(format!("reload_strategy must be annotation, sentinel or none, got '{}'", other)),
+        }
+    }
+}
+
+impl ReloadStrategy {
+    /// Prepare the volume after issuance, so applications can watch the sentinel from the start
+    pub async fn on_issue(&self, mount_path: &str) -> Result<()> {
+        match self {
+            ReloadStrategy::Sentinel => write_sentinel(mount_path).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Signal the application after its certificate was renewed
+    ///
+    /// Failures are logged; the renewed certificate is already in place.
+    pub async fn on_renew(&self, pod: &PodRef, mount_path: &str, annotator: &PodAnnotator) {
+        let result = match self {
+            ReloadStrategy::None => return,
+            ReloadStrategy::Sentinel => write_sentinel(mount_path).await,
+            ReloadStrategy::Annotation => annotator.mark_rotated(pod).await,
+        };
+
+        match result {
+            Ok(()) => info!("Signalled certificate rotation to {}/{} ({:?})", pod.namespace, pod.name, self),
+            Err(e) => warn!("Failed to signal certificate rotation to {}/{}: {}", pod.namespace, pod.name, e),
+        }
+    }
+}
+
+/// Replace the sentinel in one rename, so watchers see a single complete change
+async fn write_sentinel(mount_path: &str) -> Result<()> {
+    let path = Path::new(mount_path).join(SENTINEL_FILE);
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, format!("{}\n", chrono::Utc::now().to_rfc3339()))
+        .await
+        .context(format!("Failed to write reload sentinel {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, &path)
+        .await
+        .context(format!("Failed to replace reload sentinel {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_parse_reload_strategy() {
+        assert_eq!("none".parse::<ReloadStrategy>().unwrap(), ReloadStrategy::None);
+        assert_eq!("sentinel".parse::<ReloadStrategy>().unwrap(), ReloadStrategy::Sentinel);
+        assert_eq!("annotation".parse::<ReloadStrategy>().unwrap(), ReloadStrategy::Annotation);
+        assert!("Sentinel".parse::<ReloadStrategy>().is_err());
+        assert_eq!(ReloadStrategy::default(), ReloadStrategy::None);
+    }
+
+    #[tokio::test]
+    async fn test_sentinel_is_swapped_on_renewal() {
+        let dir = TempDir::new("reload").unwrap();
+        let mount_path = dir.to_str().unwrap();
+        let sentinel = dir.join(SENTINEL_FILE);
+        let pod = PodRef { namespace: "default".to_string(), name: "web-0".to_string(), uid: None };
+        let annotator = PodAnnotator::new(false);
+
+        // Nothing to watch without the sentinel strategy
+        for strategy in [ReloadStrategy::None, ReloadStrategy::Annotation] {
+            strategy.on_issue(mount_path).await.unwrap();
+        }
+        ReloadStrategy::None.on_renew(&pod, mount_path, &annotator).await;
+        assert!(!sentinel.exists());
+
+        ReloadStrategy::Sentinel.on_issue(mount_path).await.unwrap();
+        let issued = (std::fs::read_to_string(&sentinel).unwrap(), std::fs::metadata(&sentinel).unwrap().ino());
+        assert!(chrono::DateTime::parse_from_rfc3339(issued.0.trim()).is_ok(), "{}", issued.0);
+
+        // Renewal replaces the file instead of rewriting it in place, and leaves nothing else behind
+        ReloadStrategy::Sentinel.on_renew(&pod, mount_path, &annotator).await;
+        let renewed = (std::fs::read_to_string(&sentinel).unwrap(), std::fs::metadata(&sentinel).unwrap().ino());
+        assert_ne!(renewed.0, issued.0);
+        assert_ne!(renewed.1, issued.1);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
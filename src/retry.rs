@@ -0,0 +1,217 @@
+use rand::Rng;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::{Code, Status};
+use tracing::{info, warn};
+
+use crate::error_details::{ErrorInfo, ErrorReason};
+
+/// Retry settings for certificate service calls
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (1-based): exponential with equal jitter
+    ///
+    /// A random delay between half and all of the capped exponential one. Jitter keeps
+    /// the nodes of a cluster from retrying in lockstep after a certificate service
+    /// rollout; the fixed half keeps a retry from following right on the failure.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let ceiling = exponential.min(self.max_backoff);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// How long the circuit stays open the first time, doubled each time it opens again in a row
+const CIRCUIT_INITIAL_OPEN: Duration = Duration::from_secs(5);
+
+/// Callback told whether the circuit is open whenever that changes
+type CircuitListener = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// Fails certificate service calls fast while the service is unreachable
+///
+/// After `failure_threshold` connection failures in a row the circuit opens and calls
+/// fail right away with `Unavailable`, instead of each waiting for its own connect
+/// timeout. Once the open period is over calls go through again (half-open); the
+/// first success closes the circuit, another failure opens it for twice as long, up
+/// to `max_open`.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<CircuitState>>,
+    failure_threshold: u32,
+    max_open: Duration,
+    listener: Option<CircuitListener>,
+}
+
+#[derive(Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    /// Times the circuit opened since it was last closed
+    openings: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, max_open: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CircuitState::default())),
+            failure_threshold: failure_threshold.max(1),
+            max_open: max_open.max(CIRCUIT_INITIAL_OPEN),
+            listener: None,
+        }
+    }
+
+    /// Call `listener` with whether the circuit is open whenever it opens or closes
+    pub fn with_listener(mut self, listener: impl Fn(bool) + Send + Sync + 'static) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// `Err` with the time left while the circuit is open
+    pub fn check(&self) -> Result<(), Duration> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(open_until) if open_until > Instant::now() => Err(open_until - Instant::now()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether calls currently fail fast
+    pub fn is_open(&self) -> bool {
+        self.check().is_err()
+    }
+
+    /// The service answered, so it is reachable again
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        let was_open = state.openings > 0;
+        *state = CircuitState::default();
+        drop(state);
+
+        if was_open {
+            info!("Certificate service reachable again, circuit closed");
+            self.notify(false);
+        }
+    }
+
+    /// The service could not be reached
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures < self.failure_threshold {
+            return;
+        }
+
+        let open_for = CIRCUIT_INITIAL_OPEN
+            .saturating_mul(2u32.saturating_pow(state.openings))
+            .min(self.max_open);
+        state.openings += 1;
+        state.open_until = Some(Instant::now() + open_for);
+        let failures = state.consecutive_failures;
+        drop(state);
+
+        warn!(
+            "Certificate service unreachable {} times in a row, failing calls fast for {:?}",
+            failures, open_for
+        );
+        self.notify(true);
+    }
+
+    fn notify(&self, open: bool) {
+        if let Some(listener) = &self.listener {
+            listener(open);
+        }
+    }
+}
+
+/// Whether a failed call may succeed when retried
+///
+/// Only errors caused by the service being unreachable or overloaded are retried;
+/// rejected requests (policy, validation) fail immediately.
+pub fn is_transient(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+/// Like [`is_transient`], but also checks the reason in the status details
+///
+/// A tenant at its certificate quota gets RESOURCE_EXHAUSTED like a rate-limited caller,
+/// but retrying does not help it.
+pub fn is_transient_status(status: &Status) -> bool {
+    let reason = ErrorInfo::from_status(status).and_then(|info| info.reason());
+    is_transient(status.code()) && reason != Some(ErrorReason::QuotaExceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+
+        assert!(policy.backoff(30) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let circuit_breaker = CircuitBreaker::new(2, Duration::from_secs(60)).with_listener({
+            let changes = changes.clone();
+            move |open| changes.lock().unwrap().push(open)
+        });
+
+        circuit_breaker.record_failure();
+        assert!(!circuit_breaker.is_open());
+        circuit_breaker.record_failure();
+        let retry_in = circuit_breaker.check().unwrap_err();
+        assert!(retry_in > Duration::from_secs(4) && retry_in <= CIRCUIT_INITIAL_OPEN);
+
+        // Failing again after the open period opens it for twice as long
+        circuit_breaker.state.lock().unwrap().open_until = Some(Instant::now());
+        circuit_breaker.record_failure();
+        assert!(circuit_breaker.check().unwrap_err() > CIRCUIT_INITIAL_OPEN);
+
+        circuit_breaker.record_success();
+        assert!(!circuit_breaker.is_open());
+        assert_eq!(*changes.lock().unwrap(), [true, true, false]);
+    }
+
+    #[test]
+    fn test_only_transient_codes_are_retried() {
+        assert!(is_transient(Code::Unavailable));
+        assert!(is_transient(Code::DeadlineExceeded));
+        assert!(!is_transient(Code::PermissionDenied));
+        assert!(!is_transient(Code::InvalidArgument));
+        assert!(is_transient_status(&ErrorReason::RateLimited.status(Code::ResourceExhausted, "slow down")));
+        assert!(!is_transient_status(&ErrorReason::QuotaExceeded.status(Code::ResourceExhausted, "quota")));
+    }
+}
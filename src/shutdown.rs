@@ -0,0 +1,20 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+/// Wait for SIGTERM, which Kubernetes sends to stop a pod, or SIGINT
+pub async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM, only stopping on Ctrl-C: {}", e);
+            tokio::signal::ctrl_c().await.ok();
+            info!("Received SIGINT, shutting down");
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+    }
+}
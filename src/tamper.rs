@@ -0,0 +1,276 @@
+use anyhow::{Result, Context};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tracing::{debug, error, info, warn};
+
+use crate::cert_manager::{CertificateManager, CERTIFICATE_FILES};
+use crate::cert_monitor::CertificateMonitor;
+use crate::events::EventRecorder;
+use crate::metrics::{self, Metrics};
+
+/// How often volumes registered or unregistered meanwhile are watched or dropped
+const RESYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Events in a volume directory that may leave a certificate file deleted or truncated
+const WATCHED_EVENTS: AddWatchFlags = AddWatchFlags::IN_DELETE
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_MODIFY)
+    .union(AddWatchFlags::IN_CLOSE_WRITE);
+
+/// Inotify instance registered with the tokio reactor
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Puts back certificate files that something else on the node deleted or truncated
+///
+/// Sidecars sharing a volume, or cleaning up emptyDirs they think they own, sometimes
+/// wipe `tls.crt` and `tls.key`. The driver watches the directories of registered
+/// volumes and writes the files back as it last wrote them, or re-issues the
+/// certificate when it no longer has them, e.g. after a restart.
+pub struct TamperWatch {
+    cert_manager: CertificateManager,
+    monitor: Arc<CertificateMonitor>,
+    events: EventRecorder,
+    metrics: Metrics,
+}
+
+impl TamperWatch {
+    pub fn new(
+        cert_manager: CertificateManager,
+        monitor: Arc<CertificateMonitor>,
+        events: EventRecorder,
+        metrics: Metrics,
+    ) -> Self {
+        Self { cert_manager, monitor, events, metrics }
+    }
+
+    /// Watch volumes until the driver stops
+    pub async fn run(self) {
+        if let Err(e) = self.watch().await {
+            error!("Stopped watching certificate volumes for deleted files: {:#}", e);
+        }
+    }
+
+    async fn watch(&self) -> Result<()> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .context("Failed to initialize inotify")?;
+        let inotify = AsyncFd::new(InotifyFd(inotify)).context("Failed to register inotify")?;
+        let mut watches = HashMap::new();
+        info!("Watching certificate volumes for deleted or truncated files");
+
+        loop {
+            self.sync_watches(&inotify.get_ref().0, &mut watches);
+
+            let events = tokio::select! {
+                ready = inotify.readable() => {
+                    match ready.context("Failed to wait for inotify events")?.try_io(|fd| Ok(fd.get_ref().0.read_events()?)) {
+                        Ok(events) => events.context("Failed to read inotify events")?,
+                        Err(_would_block) => continue,
+                    }
+                }
+                _ = tokio::time::sleep(RESYNC_INTERVAL) => continue,
+            };
+
+            for mount_path in touched_volumes(&events, &mut watches) {
+                self.check_volume(&mount_path).await;
+            }
+        }
+    }
+
+    /// Watch the directories of registered volumes and stop watching unregistered ones
+    fn sync_watches(&self, inotify: &Inotify, watches: &mut HashMap<WatchDescriptor, String>) {
+        let registered: HashSet<String> = self
+            .cert_manager
+            .get_all_certificates()
+            .into_iter()
+            .map(|cert_info| cert_info.mount_path)
+            .collect();
+
+        let unregistered: Vec<WatchDescriptor> = watches
+            .iter()
+            .filter(|(_, mount_path)| !registered.contains(*mount_path))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in unregistered {
+            watches.remove(&wd);
+            // Fails when the directory is already gone, which dropped the watch
+            let _ = inotify.rm_watch(wd);
+        }
+
+        let watched: HashSet<String> = watches.values().cloned().collect();
+        for mount_path in registered.difference(&watched) {
+            match inotify.add_watch(mount_path.as_str(), WATCHED_EVENTS) {
+                Ok(wd) => {
+                    watches.insert(wd, mount_path.clone());
+                }
+                Err(e) => debug!("Failed to watch volume {}: {}", mount_path, e),
+            }
+        }
+    }
+
+    /// Restore the certificate files of a volume if they were deleted or truncated
+    async fn check_volume(&self, mount_path: &str) {
+        let Some(cert_info) = self.cert_manager.find_by_mount_path(mount_path) else {
+            return;
+        };
+
+        let (damaged, restored) = {
+            // Publishing and renewals write the files while holding the lock, and volumes
+            // are unregistered before their files are removed
+            let _locked = self.cert_manager.lock_certificate(&cert_info.cert_id).await;
+            if self.cert_manager.find_by_mount_path(mount_path).is_none() || !Path::new(mount_path).is_dir() {
+                return;
+            }
+            let damaged = damaged_files(&self.cert_manager, mount_path).await;
+            if damaged.is_empty() {
+                return;
+            }
+            let restored = restore_files(&self.cert_manager, &damaged).await;
+            (damaged, restored)
+        };
+
+        let files = damaged
+            .iter()
+            .filter_map(|path| path.file_name().and_then(OsStr::to_str))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let (action, message) = match restored {
+            Ok(()) => {
+                warn!("Certificate files {} of {} were deleted or truncated, restored them", files, cert_info.cert_id);
+                ("restored", "restored them".to_string())
+            }
+            Err(e) => {
+                warn!("Certificate files {} of {} were deleted or truncated, re-issuing it: {:#}", files, cert_info.cert_id, e);
+                match self.monitor.renew_certificate(&cert_info, false).await {
+                    Ok(()) => ("reissued", "re-issued the certificate".to_string()),
+                    Err(e) => {
+                        let error = format!("{:#}", e);
+                        error!("Failed to re-issue certificate {}: {}", cert_info.cert_id, error);
+                        ("failed", format!("failed to re-issue the certificate: {}", error))
+                    }
+                }
+            }
+        };
+
+        self.metrics.inc(&metrics::TAMPERED_VOLUMES, &[("action", action)]);
+        self.events.tampered(&cert_info.pod, &cert_info.cert_id, &files, &message).await;
+    }
+}
+
+/// Volumes with a certificate file in `events`, dropping watches the kernel removed
+fn touched_volumes(events: &[InotifyEvent], watches: &mut HashMap<WatchDescriptor, String>) -> BTreeSet<String> {
+    let mut touched = BTreeSet::new();
+    for event in events {
+        // The directory was removed, or the watch with it
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            watches.remove(&event.wd);
+            continue;
+        }
+        let certificate_file = event
+            .name
+            .as_deref()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| CERTIFICATE_FILES.contains(&name));
+        if let (true, Some(mount_path)) = (certificate_file, watches.get(&event.wd)) {
+            touched.insert(mount_path.clone());
+        }
+    }
+    touched
+}
+
+/// Certificate files of a volume that are missing, empty, or shorter than the driver wrote them
+///
+/// Files replaced with other content are left to the certificate monitor, which
+/// compares the certificate on disk with the registry.
+pub async fn damaged_files(cert_manager: &CertificateManager, mount_path: &str) -> Vec<PathBuf> {
+    let mut damaged = Vec::new();
+    for file in CERTIFICATE_FILES {
+        let path = Path::new(mount_path).join(file);
+        let written_len = cert_manager.written_file(&path).map_or(1, |written| written.contents.len() as u64);
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.len() >= written_len.max(1) => {}
+            Ok(_) => damaged.push(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => damaged.push(path),
+            Err(e) => debug!("Failed to check {}: {}", path.display(), e),
+        }
+    }
+    damaged
+}
+
+/// Write damaged files back as the driver last wrote them, with their mode and group
+///
+/// Fails without writing anything when the driver kept no copy of one of them.
+pub async fn restore_files(cert_manager: &CertificateManager, damaged: &[PathBuf]) -> Result<()> {
+    let written = damaged
+        .iter()
+        .map(|path| {
+            cert_manager
+                .written_file(path)
+                .map(|written| (path, written))
+                .context(format!("No copy of {} was kept", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (path, written) in written {
+        tokio::fs::write(path, written.contents.as_slice())
+            .await
+            .context(format!("Failed to write {}", path.display()))?;
+        std::os::unix::fs::chown(path, None, Some(written.gid))
+            .context(format!("Failed to restore the group of {}", path.display()))?;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(written.mode))
+            .await
+            .context(format!("Failed to restore the mode of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_restore_deleted_and_truncated_files() {
+        let base_path = TempDir::new("tamper").unwrap();
+        let mount_path = base_path.join("mount");
+        std::fs::create_dir_all(&mount_path).unwrap();
+        let cert_path = mount_path.join("tls.crt");
+        let key_path = mount_path.join("tls.key");
+        std::fs::write(&cert_path, "certificate").unwrap();
+        std::fs::write(&key_path, "key").unwrap();
+        std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o440)).unwrap();
+        let mount_path = mount_path.to_string_lossy().to_string();
+
+        let manager = CertificateManager::new(base_path.to_path_buf(), String::new());
+        assert_eq!(damaged_files(&manager, &mount_path).await.len(), 0);
+        manager.remember_certificate_files(&mount_path).await;
+
+        std::fs::write(&cert_path, "cert").unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+        let damaged = damaged_files(&manager, &mount_path).await;
+        assert_eq!(damaged, [cert_path.clone(), key_path.clone()]);
+
+        restore_files(&manager, &damaged).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&cert_path).unwrap(), "certificate");
+        assert_eq!(std::fs::read_to_string(&key_path).unwrap(), "key");
+        assert_eq!(std::fs::metadata(&key_path).unwrap().permissions().mode() & 0o777, 0o440);
+        assert_eq!(damaged_files(&manager, &mount_path).await.len(), 0);
+
+        // Nothing to restore from once the volume is unregistered, e.g. after a restart
+        manager.unregister_certificate(&mount_path).await;
+        std::fs::remove_file(&key_path).unwrap();
+        assert!(restore_files(&manager, &damaged_files(&manager, &mount_path).await).await.is_err());
+    }
+}
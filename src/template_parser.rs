@@ -1,10 +1,23 @@
 use anyhow::{Result, anyhow};
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use tracing::debug;
 
 /// Parse and resolve template strings with pod metadata/spec placeholders
 /// Supports syntax like: {metadata.namespace}, {spec.serviceAccountName}, {metadata.name}
+///
+/// [`TemplateParser::resolve_with`] takes further sections, e.g. `node` for
+/// {node.labels.topology.kubernetes.io/zone} or `serviceaccount` for
+/// {serviceaccount.annotations.eks.amazonaws.com/role-arn}.
+///
+/// A placeholder may end in a pipeline of functions applied in order, e.g.
+/// {metadata.name|trunc:20|lower} or {metadata.labels.team|default:unknown}:
+/// - `default:<value>` uses `<value>` when the field is missing
+/// - `lower`, `upper` change the case
+/// - `trunc:<n>` keeps the first `n` characters
+/// - `sha256:<n>` replaces the value with the first `n` hex digits of its SHA-256 (all 64 without `n`)
+/// - `dns_safe` lowercases and replaces characters not allowed in DNS labels with `-`
 pub struct TemplateParser {
     template_regex: Regex,
 }
@@ -33,6 +46,12 @@ impl TemplateParser {
         pod_metadata: &HashMap<String, String>,
         pod_spec: &HashMap<String, String>,
     ) -> Result<String> {
+        self.resolve_with(template, &[("metadata", pod_metadata), ("spec", pod_spec)])
+    }
+
+    /// Resolve a template string against named sections of fields, e.g.
+    /// `[("metadata", &pod_metadata), ("spec", &pod_spec), ("node", &node_info)]`
+    pub fn resolve_with(&self, template: &str, sections: &[(&str, &HashMap<String, String>)]) -> Result<String> {
         let mut result = template.to_string();
         
         debug!("Resolving template: {}", template);
@@ -41,7 +60,7 @@ impl TemplateParser {
         for captures in self.template_regex.captures_iter(template) {
             if let Some(placeholder) = captures.get(1) {
                 let placeholder_str = placeholder.as_str();
-                let replacement = self.resolve_placeholder(placeholder_str, pod_metadata, pod_spec)?;
+                let replacement = self.resolve_placeholder(placeholder_str, sections)?;
                 
                 // Replace {placeholder} with the resolved value
                 result = result.replace(&format!("{{{}}}", placeholder_str), &replacement);
@@ -53,35 +72,113 @@ impl TemplateParser {
         Ok(result)
     }
 
-    /// Resolve a single placeholder like "metadata.namespace" or "spec.serviceAccountName"
-    fn resolve_placeholder(
-        &self,
-        placeholder: &str,
-        pod_metadata: &HashMap<String, String>,
-        pod_spec: &HashMap<String, String>,
-    ) -> Result<String> {
-        let parts: Vec<&str> = placeholder.split('.').collect();
-        
-        if parts.len() < 2 {
-            return Err(anyhow!("Invalid placeholder format: {}. Expected format: metadata.field or spec.field", placeholder));
+    /// Resolve a single placeholder like "metadata.namespace" or "spec.serviceAccountName",
+    /// followed by optional `|function:argument` stages
+    fn resolve_placeholder(&self, placeholder: &str, sections: &[(&str, &HashMap<String, String>)]) -> Result<String> {
+        self.evaluate(placeholder, sections)?
+            .ok_or_else(|| missing_field(placeholder_path(placeholder)))
+    }
+
+    /// Value of a placeholder after its functions, or `None` if the field is missing
+    /// without a default
+    fn evaluate(&self, placeholder: &str, sections: &[(&str, &HashMap<String, String>)]) -> Result<Option<String>> {
+        let mut stages = placeholder.split('|');
+        let path = stages.next().unwrap_or_default().trim();
+        let mut value = self.lookup(path, sections)?;
+
+        for stage in stages {
+            let (function, argument) = match stage.split_once(':') {
+                Some((function, argument)) => (function.trim(), Some(argument)),
+                None => (stage.trim(), None),
+            };
+            match function {
+                "default" => {
+                    if value.is_none() {
+                        value = Some(argument.unwrap_or_default().to_string());
+                    }
+                }
+                // A missing field stays missing, so a later default still applies; the
+                // function is still checked so typos do not hide behind a default
+                function => {
+                    let applied = apply_function(function, argument, value.clone().unwrap_or_default())
+                        .map_err(|e| anyhow!("{} in {{{}}}", e, placeholder))?;
+                    value = value.map(|_| applied);
+                }
+            }
         }
-        
-        let section = parts[0];
-        let field = parts[1..].join(".");
-        
-        match section {
-            "metadata" => {
-                pod_metadata.get(&field)
-                    .cloned()
-                    .ok_or_else(|| anyhow!("Metadata field not found: {}", field))
+
+        Ok(value)
+    }
+
+    /// Value of a field like "metadata.namespace", or `None` if the pod does not have it
+    fn lookup(&self, path: &str, sections: &[(&str, &HashMap<String, String>)]) -> Result<Option<String>> {
+        let Some((section, field)) = path.split_once('.') else {
+            return Err(anyhow!("Invalid placeholder format: {}. Expected format: metadata.field or spec.field", path));
+        };
+
+        match sections.iter().find(|(name, _)| *name == section) {
+            Some((_, fields)) => Ok(fields.get(field).cloned()),
+            None => {
+                let supported: Vec<&str> = sections.iter().map(|(name, _)| *name).collect();
+                Err(anyhow!("Unknown section: {}. Supported sections: {}", section, supported.join(", ")))
             }
-            "spec" => {
-                pod_spec.get(&field)
-                    .cloned()
-                    .ok_or_else(|| anyhow!("Spec field not found: {}", field))
+        }
+    }
+
+    /// Check if a string references fields of `section`, e.g. {node.name} for "node"
+    pub fn references_section(&self, text: &str, section: &str) -> bool {
+        self.template_regex
+            .captures_iter(text)
+            .filter_map(|captures| captures.get(1))
+            .any(|placeholder| placeholder.as_str().trim_start().split_once('.').is_some_and(|(name, _)| name == section))
+    }
+
+    /// Check a template against the given sections, reporting every problem in one error
+    ///
+    /// Problems are missing fields without a default, unknown sections and functions,
+    /// invalid function arguments, unmatched braces and control characters, in the
+    /// template or in the field values.
+    pub fn validate(&self, template: &str, sections: &[(&str, &HashMap<String, String>)]) -> Result<()> {
+        problems_error(self.problems(template, sections, true))
+    }
+
+    /// Check a template without field values, for callers that cannot fetch them yet
+    /// (e.g. an admission webhook checking volume attributes before the pod is scheduled)
+    ///
+    /// Reports the same problems as [`TemplateParser::validate`] except missing fields.
+    pub fn validate_syntax(&self, template: &str, sections: &[&str]) -> Result<()> {
+        let empty = HashMap::new();
+        let sections: Vec<(&str, &HashMap<String, String>)> = sections.iter().map(|name| (*name, &empty)).collect();
+        problems_error(self.problems(template, &sections, false))
+    }
+
+    fn problems(&self, template: &str, sections: &[(&str, &HashMap<String, String>)], check_fields: bool) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut literal_start = 0;
+        for placeholder in self.template_regex.find_iter(template) {
+            stray_braces(template, literal_start..placeholder.start(), &mut problems);
+            literal_start = placeholder.end();
+
+            let placeholder = &template[placeholder.start() + 1..placeholder.end() - 1];
+            match self.evaluate(placeholder, sections) {
+                Ok(Some(value)) if check_fields => {
+                    if let Some(c) = value.chars().find(|c| c.is_control()) {
+                        problems.push(format!("{{{}}} resolves to a value with control character {:?}", placeholder, c));
+                    }
+                }
+                Ok(None) if check_fields => problems.push(missing_field(placeholder_path(placeholder)).to_string()),
+                Ok(_) => {}
+                Err(e) => problems.push(e.to_string()),
             }
-            _ => Err(anyhow!("Unknown section: {}. Supported sections: metadata, spec", section)),
         }
+        stray_braces(template, literal_start..template.len(), &mut problems);
+
+        for (position, c) in template.char_indices().filter(|(_, c)| c.is_control()) {
+            problems.push(format!("Control character {:?} at position {}", c, position));
+        }
+
+        problems
     }
 
     /// Check if a string contains template placeholders
@@ -90,6 +187,87 @@ impl TemplateParser {
     }
 }
 
+/// Field path of a placeholder, without its functions
+fn placeholder_path(placeholder: &str) -> &str {
+    placeholder.split('|').next().unwrap_or_default().trim()
+}
+
+/// Record braces in `template[range]`, which is outside any placeholder
+fn stray_braces(template: &str, range: std::ops::Range<usize>, problems: &mut Vec<String>) {
+    for (offset, c) in template[range.clone()].char_indices().filter(|(_, c)| *c == '{' || *c == '}') {
+        problems.push(format!("Unmatched '{}' at position {}", c, range.start + offset));
+    }
+}
+
+/// One error listing all problems found in a template
+fn problems_error(problems: Vec<String>) -> Result<()> {
+    match problems.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("{}", problems[0])),
+        count => Err(anyhow!("{} problems: {}", count, problems.join("; "))),
+    }
+}
+
+/// Apply a pipeline function other than `default` to a resolved value
+fn apply_function(function: &str, argument: Option<&str>, value: String) -> Result<String> {
+    let length = |default: Option<usize>| -> Result<usize> {
+        match argument.map(str::trim) {
+            Some(argument) => argument
+                .parse()
+                .ok()
+                .filter(|length| *length > 0)
+                .ok_or_else(|| anyhow!("Invalid length '{}' for template function '{}'", argument, function)),
+            None => default.ok_or_else(|| anyhow!("Template function '{}' needs a length, e.g. {}:20", function, function)),
+        }
+    };
+
+    match function {
+        "lower" => Ok(value.to_lowercase()),
+        "upper" => Ok(value.to_uppercase()),
+        "trunc" => Ok(value.chars().take(length(None)?).collect()),
+        "sha256" => {
+            let digest: String = Sha256::digest(value.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+            Ok(digest.chars().take(length(Some(digest.len()))?).collect())
+        }
+        "dns_safe" => Ok(dns_safe(&value)),
+        other => Err(anyhow!("Unknown template function '{}'", other)),
+    }
+}
+
+/// Lowercase `value` and replace runs of characters not allowed in DNS labels with `-`,
+/// trimming labels to 63 characters without leading or trailing hyphens
+pub fn dns_safe(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split('.')
+        .map(|label| {
+            let mut safe = String::new();
+            for c in label.chars() {
+                if c.is_ascii_alphanumeric() {
+                    safe.push(c);
+                } else if !safe.ends_with('-') {
+                    safe.push('-');
+                }
+            }
+            let safe: String = safe.trim_start_matches('-').chars().take(63).collect();
+            safe.trim_end_matches('-').to_string()
+        })
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Error for a field the pod does not have, e.g. "metadata.labels.team"
+fn missing_field(path: &str) -> anyhow::Error {
+    match path.split_once('.') {
+        Some(("metadata", field)) => anyhow!("Metadata field not found: {}", field),
+        Some(("spec", field)) => anyhow!("Spec field not found: {}", field),
+        Some(("node", field)) => anyhow!("Node field not found: {}", field),
+        Some(("serviceaccount", field)) => anyhow!("ServiceAccount field not found: {}", field),
+        _ => anyhow!("Field not found: {}", path),
+    }
+}
+
 impl Default for TemplateParser {
     fn default() -> Self {
         Self::new().expect("Failed to create default TemplateParser")
@@ -151,6 +329,89 @@ mod tests {
         assert!(!parser.has_templates("no-templates-here"));
     }
 
+    #[test]
+    fn test_default_value() {
+        let parser = TemplateParser::new().unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("labels.team".to_string(), "payments".to_string());
+        let spec = HashMap::new();
+
+        let resolve = |template| parser.resolve(template, &metadata, &spec);
+        assert_eq!(resolve("{metadata.labels.team|default:unknown}").unwrap(), "payments");
+        assert_eq!(resolve("{metadata.labels.tier|default:unknown}").unwrap(), "unknown");
+        assert_eq!(resolve("x{metadata.labels.tier|default:}").unwrap(), "x");
+        assert!(resolve("{metadata.labels.tier}").is_err());
+        assert!(resolve("{metadata.labels.tier|fallback:x}").is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        let parser = TemplateParser::new().unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("name".to_string(), "Web_App-7d9f8b6c5-x2k4p".to_string());
+        let spec = HashMap::new();
+
+        let resolve = |template| parser.resolve(template, &metadata, &spec);
+        assert_eq!(resolve("{metadata.name|trunc:7|lower}").unwrap(), "web_app");
+        assert_eq!(resolve("{metadata.name|upper}").unwrap(), "WEB_APP-7D9F8B6C5-X2K4P");
+        assert_eq!(resolve("{metadata.name|dns_safe}").unwrap(), "web-app-7d9f8b6c5-x2k4p");
+        assert_eq!(resolve("{metadata.name|sha256:8}").unwrap().len(), 8);
+        assert_eq!(resolve("{metadata.name|sha256}").unwrap().len(), 64);
+        assert_eq!(resolve("{metadata.labels.team|upper|default:none}").unwrap(), "none");
+        assert!(resolve("{metadata.name|trunc}").is_err());
+        assert!(resolve("{metadata.name|trunc:0}").is_err());
+        assert_eq!(dns_safe("--Ünïcode Name!.example..COM-"), "n-code-name.example.com");
+    }
+
+    #[test]
+    fn test_node_section() {
+        let parser = TemplateParser::new().unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("name".to_string(), "web".to_string());
+        let spec = HashMap::new();
+        let mut node = HashMap::new();
+        node.insert("name".to_string(), "worker-1".to_string());
+        node.insert("labels.topology.kubernetes.io/zone".to_string(), "eu-west-1a".to_string());
+
+        let template = "{metadata.name}.{node.labels.topology.kubernetes.io/zone}";
+        let sections = [("metadata", &metadata), ("spec", &spec), ("node", &node)];
+        assert_eq!(parser.resolve_with(template, &sections).unwrap(), "web.eu-west-1a");
+        assert!(parser.references_section(template, "node"));
+        assert!(!parser.references_section("{metadata.labels.node}", "node"));
+        // Without node information the section is unknown
+        assert!(parser.resolve(template, &metadata, &spec).is_err());
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        let parser = TemplateParser::new().unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("name".to_string(), "web".to_string());
+        let spec = HashMap::new();
+        let sections = [("metadata", &metadata), ("spec", &spec)];
+
+        assert!(parser.validate("{metadata.name|upper}.{metadata.labels.team|default:none}", &sections).is_ok());
+
+        let template = "{metadata.labels.team}.{metadata.name|lwr}.{pod.name}}\u{7}";
+        let error = parser.validate(template, &sections).unwrap_err().to_string();
+        assert!(error.starts_with("5 problems: "), "{}", error);
+        for problem in [
+            "Metadata field not found: labels.team",
+            "Unknown template function 'lwr' in {metadata.name|lwr}",
+            "Unknown section: pod",
+            "Unmatched '}' at position",
+            "Control character",
+        ] {
+            assert!(error.contains(problem), "{} missing from {}", problem, error);
+        }
+
+        // Without field values only the missing field is not a problem
+        let error = parser.validate_syntax(template, &["metadata", "spec"]).unwrap_err().to_string();
+        assert!(error.starts_with("4 problems: "), "{}", error);
+        // Functions are checked even when a default replaces the missing field
+        assert!(parser.resolve("{metadata.labels.team|lwr|default:x}", &metadata, &spec).is_err());
+    }
+
     #[test]
     fn test_invalid_placeholder() {
         let parser = TemplateParser::new().unwrap();
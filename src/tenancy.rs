@@ -0,0 +1,323 @@
+use anyhow::{Result, Context};
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace};
+use kube::Api;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::cert_service::policy::{PolicyRuleSpec, PolicyRules};
+
+/// Namespace label naming the tenant of a namespace, unless set otherwise
+pub const DEFAULT_TENANT_LABEL: &str = "cacsi.k8s.io/tenant";
+
+/// ConfigMap key holding the tenant definitions
+const TENANTS_KEY: &str = "tenants.yaml";
+
+/// How long the tenant of a namespace is remembered before its labels are read again
+const NAMESPACE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Longest serial prefix in bytes, leaving 16 random bytes of the 20-byte serial
+pub const MAX_SERIAL_PREFIX_BYTES: usize = 4;
+
+/// A tenant as written in the tenants ConfigMap
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantSpec {
+    /// CA secret in the CA secret namespace signing the tenant's certificates; the default CA when unset
+    #[serde(default)]
+    pub ca_secret: Option<String>,
+    /// Hex bytes every serial number issued to the tenant starts with
+    #[serde(default)]
+    pub serial_prefix: Option<String>,
+    /// Most unexpired, unrevoked certificates the tenant may hold at once
+    #[serde(default)]
+    pub max_certificates: Option<usize>,
+    /// DNS domains only this tenant may request names in
+    #[serde(default)]
+    pub dns_domains: Vec<String>,
+    /// Policy rules applied to the tenant's requests after the global ones
+    #[serde(default)]
+    pub rules: Vec<PolicyRuleSpec>,
+}
+
+/// A group of namespaces, e.g. a business unit, isolated from the other tenants
+pub struct Tenant {
+    pub name: String,
+    pub ca_secret: Option<String>,
+    pub serial_prefix: Vec<u8>,
+    pub max_certificates: Option<usize>,
+    /// Lowercase, without a leading dot or wildcard label
+    pub dns_domains: Vec<String>,
+    pub rules: PolicyRules,
+}
+
+impl Tenant {
+    fn new(name: String, spec: TenantSpec) -> Result<Self> {
+        let serial_prefix = match &spec.serial_prefix {
+            Some(prefix) => parse_serial_prefix(prefix)
+                .map_err(|reason| anyhow::anyhow!("Tenant {} has an invalid serial_prefix '{}': {}", name, prefix, reason))?,
+            None => Vec::new(),
+        };
+        let dns_domains = spec
+            .dns_domains
+            .iter()
+            .map(|domain| domain.trim().trim_start_matches("*.").trim_start_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        let rules = PolicyRules::compile(spec.rules).context(format!("Invalid policy rules of tenant {}", name))?;
+
+        Ok(Self {
+            name,
+            ca_secret: spec.ca_secret.filter(|secret| !secret.is_empty()),
+            serial_prefix,
+            max_certificates: spec.max_certificates,
+            dns_domains,
+            rules,
+        })
+    }
+
+    /// Whether `name` (a DNS name, possibly a wildcard) is in one of the tenant's DNS domains
+    pub fn owns_name(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        self.dns_domains.iter().any(|domain| in_domain(&name, domain))
+    }
+
+    /// Whether the host of `uri`, e.g. the trust domain of a SPIFFE ID, is in one of the tenant's DNS domains
+    pub fn owns_uri(&self, uri: &str) -> bool {
+        uri_host(uri).is_some_and(|host| self.owns_name(host))
+    }
+}
+
+/// Tenants defined in a ConfigMap, and which tenant each namespace belongs to
+///
+/// A namespace belongs to the tenant named by its tenant label; namespaces without
+/// the label belong to no tenant and are served as before.
+pub struct Tenancy {
+    label: String,
+    configmap_name: String,
+    configmap_namespace: String,
+    tenants: RwLock<HashMap<String, Arc<Tenant>>>,
+    /// Tenant label value of recently seen namespaces
+    namespaces: Mutex<HashMap<String, (Option<String>, Instant)>>,
+}
+
+impl Tenancy {
+    pub async fn new(label: String, configmap_name: String, configmap_namespace: String) -> Result<Self> {
+        let tenancy = Self {
+            label,
+            configmap_name,
+            configmap_namespace,
+            tenants: RwLock::new(HashMap::new()),
+            namespaces: Mutex::new(HashMap::new()),
+        };
+
+        tenancy.reload().await?;
+
+        Ok(tenancy)
+    }
+
+    /// Tenancy with fixed tenants and namespace labels, without Kubernetes
+    pub fn in_memory(tenants_yaml: &str, namespaces: &[(&str, &str)]) -> Result<Self> {
+        let never = Instant::now() + Duration::from_secs(365 * 24 * 3600);
+        Ok(Self {
+            label: DEFAULT_TENANT_LABEL.to_string(),
+            configmap_name: String::new(),
+            configmap_namespace: String::new(),
+            tenants: RwLock::new(parse_tenants(tenants_yaml)?),
+            namespaces: Mutex::new(
+                namespaces
+                    .iter()
+                    .map(|(namespace, tenant)| (namespace.to_string(), (Some(tenant.to_string()), never)))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Re-read the tenants from the ConfigMap, keeping the current ones if any is invalid
+    pub async fn reload(&self) -> Result<()> {
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+
+        let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.configmap_namespace);
+        let configmap = crate::k8s_client::with_retries(|| configmaps.get(&self.configmap_name))
+            .await
+            .context(format!("Failed to get tenants ConfigMap {}/{}", self.configmap_namespace, self.configmap_name))?;
+
+        let tenants_yaml = configmap
+            .data
+            .as_ref()
+            .and_then(|data| data.get(TENANTS_KEY))
+            .ok_or_else(|| anyhow::anyhow!("Tenants ConfigMap missing {}", TENANTS_KEY))?;
+        let tenants = parse_tenants(tenants_yaml)?;
+
+        info!("Loaded {} tenants from {}/{}", tenants.len(), self.configmap_namespace, self.configmap_name);
+        *self.tenants.write().await = tenants;
+
+        Ok(())
+    }
+
+    /// Namespace label naming the tenant of a namespace
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub async fn tenants(&self) -> Vec<Arc<Tenant>> {
+        self.tenants.read().await.values().cloned().collect()
+    }
+
+    /// The tenant of `namespace`, or `None` when the namespace is not labeled with one
+    ///
+    /// Fails when the label names a tenant that is not defined, so such namespaces
+    /// get nothing rather than the default CA and policy.
+    pub async fn tenant_of(&self, namespace: &str) -> Result<Option<Arc<Tenant>>> {
+        let Some(name) = self.tenant_label(namespace).await? else {
+            return Ok(None);
+        };
+
+        match self.tenants.read().await.get(&name) {
+            Some(tenant) => Ok(Some(tenant.clone())),
+            None => Err(anyhow::anyhow!("Namespace {} belongs to tenant '{}', which is not defined", namespace, name)),
+        }
+    }
+
+    async fn tenant_label(&self, namespace: &str) -> Result<Option<String>> {
+        if namespace.is_empty() {
+            return Ok(None);
+        }
+        if let Some((tenant, read_at)) = self.namespaces.lock().unwrap().get(namespace) {
+            if read_at.elapsed() < NAMESPACE_CACHE_TTL {
+                return Ok(tenant.clone());
+            }
+        }
+
+        let client = crate::k8s_client::get_client()
+            .await
+            .context("Failed to create Kubernetes client")?;
+        let namespaces: Api<Namespace> = Api::all(client);
+        let object = crate::k8s_client::with_retries(|| namespaces.get(namespace))
+            .await
+            .context(format!("Failed to get namespace {}", namespace))?;
+
+        let tenant = object
+            .metadata
+            .labels
+            .and_then(|mut labels| labels.remove(&self.label))
+            .filter(|tenant| !tenant.is_empty());
+        debug!("Namespace {} belongs to tenant {:?}", namespace, tenant);
+
+        self.namespaces.lock().unwrap().insert(namespace.to_string(), (tenant.clone(), Instant::now()));
+        Ok(tenant)
+    }
+}
+
+/// Parse the tenant definitions, rejecting serial prefixes and DNS domains shared by two tenants
+fn parse_tenants(tenants_yaml: &str) -> Result<HashMap<String, Arc<Tenant>>> {
+    let specs: HashMap<String, TenantSpec> = serde_yaml::from_str(tenants_yaml)
+        .context("Invalid tenants YAML")?;
+
+    let mut tenants: Vec<Tenant> = specs
+        .into_iter()
+        .map(|(name, spec)| Tenant::new(name, spec))
+        .collect::<Result<_>>()?;
+    tenants.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for (index, tenant) in tenants.iter().enumerate() {
+        for other in &tenants[index + 1..] {
+            let (a, b) = (&tenant.serial_prefix, &other.serial_prefix);
+            if !a.is_empty() && !b.is_empty() && (a.starts_with(b) || b.starts_with(a)) {
+                anyhow::bail!("Tenants {} and {} have overlapping serial prefixes", tenant.name, other.name);
+            }
+            for domain in &tenant.dns_domains {
+                if let Some(shared) = other.dns_domains.iter().find(|d| in_domain(domain, d) || in_domain(d, domain)) {
+                    anyhow::bail!("Tenants {} and {} both own DNS domain {} ({})", tenant.name, other.name, domain, shared);
+                }
+            }
+        }
+    }
+
+    Ok(tenants.into_iter().map(|tenant| (tenant.name.clone(), Arc::new(tenant))).collect())
+}
+
+/// Whether `name` is `domain` or a name below it
+fn in_domain(name: &str, domain: &str) -> bool {
+    name == domain || name.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Host of `uri`, without user info or port; `None` for URIs without an authority
+fn uri_host(uri: &str) -> Option<&str> {
+    let (_, rest) = uri.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// Parse a serial prefix of up to [`MAX_SERIAL_PREFIX_BYTES`] hex bytes
+///
+/// The first byte must be between `01` and `7f`, so serials stay positive and
+/// minimally encoded.
+fn parse_serial_prefix(prefix: &str) -> Result<Vec<u8>, String> {
+    let prefix = prefix.trim().trim_start_matches("0x");
+    if prefix.is_empty() || !prefix.len().is_multiple_of(2) || prefix.len() / 2 > MAX_SERIAL_PREFIX_BYTES {
+        return Err(format!("expected 1 to {} hex bytes", MAX_SERIAL_PREFIX_BYTES));
+    }
+    if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("not hexadecimal".to_string());
+    }
+    let bytes: Vec<u8> = (0..prefix.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&prefix[i..i + 2], 16).unwrap())
+        .collect();
+    if !(0x01..=0x7f).contains(&bytes[0]) {
+        return Err("the first byte must be between 01 and 7f".to_string());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tenants() {
+        let tenancy = Tenancy::in_memory(
+            r#"
+payments:
+  ca_secret: payments-ca
+  serial_prefix: "0a"
+  max_certificates: 2
+  dns_domains: [payments.example.com]
+  rules:
+    - name: short-lived
+      expression: request.validity_days <= 30
+search:
+  dns_domains: ["*.search.example.com"]
+"#,
+            &[("payments-prod", "payments"), ("orphan", "unknown")],
+        ).unwrap();
+
+        let payments = tenancy.tenant_of("payments-prod").await.unwrap().unwrap();
+        assert_eq!(payments.ca_secret.as_deref(), Some("payments-ca"));
+        assert_eq!(payments.serial_prefix, [0x0a]);
+        assert_eq!(payments.rules.len(), 1);
+        assert!(payments.owns_name("*.api.payments.example.com"));
+        assert!(!payments.owns_name("notpayments.example.com"));
+        assert!(payments.owns_uri("spiffe://payments.example.com/ns/payments-prod/sa/web"));
+        assert!(payments.owns_uri("https://user@API.payments.example.com:8443/path"));
+        assert!(!payments.owns_uri("spiffe://cluster.local/ns/payments-prod/sa/web"));
+        assert!(!payments.owns_uri("urn:payments.example.com"));
+        assert!(tenancy.tenant_of("orphan").await.is_err());
+        assert!(tenancy.tenant_of("").await.unwrap().is_none());
+
+        assert!(parse_tenants("a:\n  serial_prefix: \"0a\"\nb:\n  serial_prefix: \"0a01\"\n").is_err());
+        assert!(parse_tenants("a:\n  dns_domains: [example.com]\nb:\n  dns_domains: [x.example.com]\n").is_err());
+        assert!(parse_serial_prefix("80").is_err());
+        assert!(parse_serial_prefix("0102030405").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{Result, Context};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+use yasna::models::ObjectIdentifier;
+use yasna::Tag;
+
+use crate::ca_manager::CaManager;
+use crate::recovery::{find_mounted_volumes, pods_on_node};
+
+/// CA certificates in a trust-only volume
+pub const CA_FILE: &str = "ca.crt";
+
+/// The CA certificates as a PKCS#12 truststore, for `truststore: "true"`
+pub const TRUSTSTORE_FILE: &str = "truststore.p12";
+
+/// Password of the truststore, the one Java uses for its own `cacerts`
+///
+/// A truststore holds no secrets; the password only protects its integrity.
+pub const TRUSTSTORE_PASSWORD: &str = "changeit";
+
+/// Iterations of the key derivation for the truststore MAC, as OpenSSL uses
+const MAC_ITERATIONS: u64 = 2048;
+
+/// id-data (RFC 5652)
+const OID_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 1];
+/// certBag (RFC 7292)
+const OID_CERT_BAG: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 3];
+/// x509Certificate (RFC 2985)
+const OID_X509_CERTIFICATE: &[u64] = &[1, 2, 840, 113549, 1, 9, 22, 1];
+/// friendlyName (RFC 2985), which Java uses as the alias
+const OID_FRIENDLY_NAME: &[u64] = &[1, 2, 840, 113549, 1, 9, 20];
+/// Oracle's trustedKeyUsage, without which Java skips certificate bags that have no key
+const OID_JAVA_TRUSTED_KEY_USAGE: &[u64] = &[2, 16, 840, 1, 113894, 746875, 1, 1];
+/// anyExtendedKeyUsage (RFC 5280)
+const OID_ANY_EXTENDED_KEY_USAGE: &[u64] = &[2, 5, 29, 37, 0];
+/// id-sha256 (RFC 5754)
+const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+
+/// Whether a volume was published with `mode: trust-only`: it has CA certificates and
+/// no certificate of its own
+pub fn is_trust_only(mount_path: &str) -> bool {
+    let path = Path::new(mount_path);
+    path.join(CA_FILE).exists() && !path.join("tls.crt").exists()
+}
+
+/// Write the CA certificates to a trust-only volume, and the truststore if asked for
+///
+/// Files are replaced atomically and readable by everyone, as they hold no secrets.
+pub async fn write(mount_path: &str, ca_pem: &str, truststore: bool) -> Result<()> {
+    let mut files = vec![(CA_FILE, ca_pem.as_bytes().to_vec())];
+    if truststore {
+        files.push((TRUSTSTORE_FILE, pkcs12_truststore(ca_pem, TRUSTSTORE_PASSWORD)?));
+    }
+
+    for (file, contents) in files {
+        let path = Path::new(mount_path).join(file);
+        let tmp_path = Path::new(mount_path).join(format!(".{}.tmp", file));
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .context(format!("Failed to write {}", tmp_path.display()))?;
+        tokio::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o444))
+            .await
+            .context(format!("Failed to set the mode of {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .context(format!("Failed to replace {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// A PKCS#12 file holding the CA certificates as trusted entries, with a SHA-256 MAC
+///
+/// The certificates are not encrypted. Java (8u301, 11.0.12 and later), keytool and
+/// OpenSSL read it.
+pub fn pkcs12_truststore(ca_pem: &str, password: &str) -> Result<Vec<u8>> {
+    let certificates: Vec<pem::Pem> = pem::parse_many(ca_pem)
+        .context("Failed to parse CA PEM")?
+        .into_iter()
+        .filter(|block| block.tag() == "CERTIFICATE")
+        .collect();
+    if certificates.is_empty() {
+        anyhow::bail!("No CA certificate in PEM");
+    }
+
+    let safe_contents = yasna::construct_der(|writer| {
+        writer.write_sequence_of(|writer| {
+            for (index, certificate) in certificates.iter().enumerate() {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_oid(&oid(OID_CERT_BAG));
+                    writer.next().write_tagged(Tag::context(0), |writer| {
+                        writer.write_sequence(|writer| {
+                            writer.next().write_oid(&oid(OID_X509_CERTIFICATE));
+                            writer.next().write_tagged(Tag::context(0), |writer| writer.write_bytes(certificate.contents()));
+                        })
+                    });
+                    writer.next().write_set_of(|writer| {
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(&oid(OID_FRIENDLY_NAME));
+                            writer.next().write_set_of(|writer| writer.next().write_bmp_string(&format!("ca-{}", index)));
+                        });
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(&oid(OID_JAVA_TRUSTED_KEY_USAGE));
+                            writer.next().write_set_of(|writer| writer.next().write_oid(&oid(OID_ANY_EXTENDED_KEY_USAGE)));
+                        });
+                    });
+                });
+            }
+        })
+    });
+
+    // One unencrypted ContentInfo holding the certificate bags
+    let auth_safe = yasna::construct_der(|writer| {
+        writer.write_sequence_of(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer.next().write_oid(&oid(OID_DATA));
+                writer.next().write_tagged(Tag::context(0), |writer| writer.write_bytes(&safe_contents));
+            });
+        })
+    });
+
+    let salt: [u8; 8] = rand::random();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key(password, &salt, MAC_ITERATIONS))
+        .expect("HMAC accepts keys of any length");
+    mac.update(&auth_safe);
+    let digest = mac.finalize().into_bytes();
+
+    Ok(yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_u8(3);
+            writer.next().write_sequence(|writer| {
+                writer.next().write_oid(&oid(OID_DATA));
+                writer.next().write_tagged(Tag::context(0), |writer| writer.write_bytes(&auth_safe));
+            });
+            writer.next().write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_oid(&oid(OID_SHA256));
+                        writer.next().write_null();
+                    });
+                    writer.next().write_bytes(&digest);
+                });
+                writer.next().write_bytes(&salt);
+                writer.next().write_u64(MAC_ITERATIONS);
+            });
+        })
+    }))
+}
+
+fn oid(components: &[u64]) -> ObjectIdentifier {
+    ObjectIdentifier::from_slice(components)
+}
+
+/// HMAC-SHA256 key for the MAC of a PKCS#12 file (RFC 7292, appendix B.2, with ID 3)
+///
+/// The key is as long as one SHA-256 output, so the first block of the derivation is all of it.
+fn mac_key(password: &str, salt: &[u8], iterations: u64) -> [u8; 32] {
+    const BLOCK_LEN: usize = 64;
+    let fill = |input: &[u8]| -> Vec<u8> {
+        input.iter().cycle().take(input.len().div_ceil(BLOCK_LEN) * BLOCK_LEN).copied().collect()
+    };
+
+    // The password as a BMPString with a terminating NUL
+    let password: Vec<u8> = password.encode_utf16().chain([0]).flat_map(u16::to_be_bytes).collect();
+    let mut digest = Sha256::new()
+        .chain_update([3u8; BLOCK_LEN])
+        .chain_update(fill(salt))
+        .chain_update(fill(&password))
+        .finalize();
+    for _ in 1..iterations {
+        digest = Sha256::digest(digest);
+    }
+    digest.into()
+}
+
+/// Rewrites the CA certificates of trust-only volumes when the CA changes, e.g. after a rotation
+///
+/// Trust-only volumes are not registered for renewal. They are found through kubelet's
+/// pods directory instead, so they are kept current across driver restarts too.
+pub struct TrustBundleUpdater {
+    ca_manager: CaManager,
+    pods_dir: PathBuf,
+    node_id: String,
+}
+
+impl TrustBundleUpdater {
+    pub fn new(ca_manager: CaManager, pods_dir: PathBuf, node_id: String) -> Self {
+        Self { ca_manager, pods_dir, node_id }
+    }
+
+    /// Update the volumes on every CA change until the driver stops
+    pub async fn run(self) {
+        let mut ca_changes = self.ca_manager.subscribe();
+        while ca_changes.changed().await.is_ok() {
+            match self.update_volumes().await {
+                Ok(0) => {}
+                Ok(count) => info!("Updated the CA certificates of {} trust-only volumes", count),
+                Err(e) => warn!("Failed to update the CA certificates of trust-only volumes: {:#}", e),
+            }
+        }
+    }
+
+    /// Write the current CA certificates to every trust-only volume, returning how many were updated
+    pub async fn update_volumes(&self) -> Result<usize> {
+        let volumes: Vec<_> = find_mounted_volumes(&self.pods_dir)?
+            .into_iter()
+            .filter(|volume| is_trust_only(&volume.mount_path))
+            .collect();
+        if volumes.is_empty() {
+            return Ok(0);
+        }
+
+        let pods = pods_on_node(&self.node_id).await?;
+        let mut updated = 0;
+        for volume in volumes {
+            let Some((namespace, _)) = pods.get(&volume.pod_uid) else {
+                continue;
+            };
+            let ca_pem = self.ca_manager
+                .for_namespace(namespace)
+                .await?
+                .get_ca_cert()
+                .await
+                .context(format!("Failed to get the CA of namespace {}", namespace))?;
+            let truststore = Path::new(&volume.mount_path).join(TRUSTSTORE_FILE).exists();
+            match write(&volume.mount_path, &ca_pem, truststore).await {
+                Ok(()) => updated += 1,
+                Err(e) => debug!("Failed to update the CA certificates of volume {}: {:#}", volume.volume_id, e),
+            }
+        }
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    #[tokio::test]
+    async fn test_write_trust_bundle() {
+        let ca_pem: String = ["Test CA", "Next CA"]
+            .into_iter()
+            .map(|name| {
+                let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+                params.distinguished_name.push(DnType::CommonName, name);
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                params.self_signed(&KeyPair::generate().unwrap()).unwrap().pem()
+            })
+            .collect();
+
+        let dir = TempDir::new("trust").unwrap();
+        let mount_path = dir.to_string_lossy().to_string();
+        assert!(!is_trust_only(&mount_path));
+
+        write(&mount_path, &ca_pem, true).await.unwrap();
+        assert!(is_trust_only(&mount_path));
+        assert_eq!(std::fs::read_to_string(dir.join(CA_FILE)).unwrap(), ca_pem);
+        assert_eq!(std::fs::metadata(dir.join(CA_FILE)).unwrap().permissions().mode() & 0o777, 0o444);
+
+        // Both CA certificates are in the truststore, and the MAC checks out
+        let pfx = std::fs::read(dir.join(TRUSTSTORE_FILE)).unwrap();
+        let (auth_safe, digest, salt, iterations) = yasna::parse_der(&pfx, |reader| {
+            reader.read_sequence(|reader| {
+                assert_eq!(reader.next().read_u8()?, 3);
+                let auth_safe = reader.next().read_sequence(|reader| {
+                    reader.next().read_oid()?;
+                    reader.next().read_tagged(Tag::context(0), |reader| reader.read_bytes())
+                })?;
+                let (digest, salt, iterations) = reader.next().read_sequence(|reader| {
+                    let digest = reader.next().read_sequence(|reader| {
+                        reader.next().read_der()?;
+                        reader.next().read_bytes()
+                    })?;
+                    Ok((digest, reader.next().read_bytes()?, reader.next().read_u64()?))
+                })?;
+                Ok((auth_safe, digest, salt, iterations))
+            })
+        })
+        .unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key(TRUSTSTORE_PASSWORD, &salt, iterations)).unwrap();
+        mac.update(&auth_safe);
+        mac.verify_slice(&digest).unwrap();
+        for certificate in pem::parse_many(&ca_pem).unwrap() {
+            assert!(auth_safe.windows(certificate.contents().len()).any(|window| window == certificate.contents()));
+        }
+
+        std::fs::write(dir.join("tls.crt"), "certificate").unwrap();
+        assert!(!is_trust_only(&mount_path));
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use x509_parser::pem::parse_x509_pem;
+
+use crate::cert_binding::{status_from_pem, CertificateBindingStatus};
+
+/// File in each volume describing its certificate
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// What `metadata.json` holds: the certificate on the volume and how its last renewal went
+///
+/// Lets applications and people inspect the mounted identity without parsing ASN.1.
+/// The certificate and renewal fields are those of the volume's CertificateBinding.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeMetadata {
+    pub certificate_id: String,
+    /// Distinguished name of the issuing CA
+    pub issuer: String,
+    #[serde(flatten)]
+    pub certificate: CertificateBindingStatus,
+}
+
+impl VolumeMetadata {
+    /// Describe `cert_pem`, just issued (`renewal: false`) or renewed
+    pub fn from_pem(cert_id: &str, cert_pem: &str, renewal: bool) -> Result<Self> {
+        let (_, pem) = parse_x509_pem(cert_pem.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to parse certificate PEM: {}", e))?;
+        let cert = pem.parse_x509()
+            .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))?;
+
+        let mut certificate = status_from_pem(cert_pem)?;
+        certificate.last_renewal_result = Some(if renewal { "Renewed" } else { "Issued" }.to_string());
+        certificate.last_renewal_time = Some(chrono::Utc::now().to_rfc3339());
+        Ok(Self {
+            certificate_id: cert_id.to_string(),
+            issuer: cert.issuer().to_string(),
+            certificate,
+        })
+    }
+
+    /// Read the metadata of a volume
+    pub async fn read(mount_path: &str) -> Result<Self> {
+        let path = Path::new(mount_path).join(METADATA_FILE);
+        let contents = tokio::fs::read(&path)
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&contents).context(format!("Failed to parse {}", path.display()))
+    }
+
+    /// Write the metadata to a volume, replacing the previous file atomically
+    pub async fn write(&self, mount_path: &str) -> Result<()> {
+        let path = Path::new(mount_path).join(METADATA_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        let contents = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .context(format!("Failed to write {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .context(format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair};
+
+    #[tokio::test]
+    async fn test_volume_metadata_round_trip() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.distinguished_name.push(DnType::CommonName, "Test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let issuer = Issuer::new(ca_params, &ca_key);
+
+        let mut params = CertificateParams::new(vec!["web.default.svc".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "web");
+        let cert = params.signed_by(&KeyPair::generate().unwrap(), &issuer).unwrap();
+
+        let dir = TempDir::new("metadata").unwrap();
+        let mount_path = dir.to_string_lossy().to_string();
+
+        let metadata = VolumeMetadata::from_pem("default-web-vol", &cert.pem(), true).unwrap();
+        metadata.write(&mount_path).await.unwrap();
+        let read = VolumeMetadata::read(&mount_path).await.unwrap();
+        assert_eq!(read, metadata);
+        assert_eq!(read.issuer, "CN=Test CA");
+        assert_eq!(read.certificate.common_name.as_deref(), Some("web"));
+        assert_eq!(read.certificate.dns_names, ["web.default.svc"]);
+        assert_eq!(read.certificate.last_renewal_result.as_deref(), Some("Renewed"));
+
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.join(METADATA_FILE)).unwrap()).unwrap();
+        assert_eq!(json["certificateId"], "default-web-vol");
+        assert!(json["notAfter"].is_string());
+    }
+}